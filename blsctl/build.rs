@@ -0,0 +1,34 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Generate shell completions for packaging
+//!
+//! When `BLSCTL_COMPLETIONS_DIR` is set, completion scripts for bash, zsh, fish
+//! and elvish are written there.
+
+use std::{env, io};
+
+use clap::CommandFactory;
+use clap_complete::{Shell, generate_to};
+use fs_err as fs;
+
+// Only the command definition is needed here
+#[allow(dead_code)]
+#[path = "src/cli.rs"]
+mod cli;
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+    println!("cargo:rerun-if-env-changed=BLSCTL_COMPLETIONS_DIR");
+
+    let Some(dir) = env::var_os("BLSCTL_COMPLETIONS_DIR") else {
+        return Ok(());
+    };
+    fs::create_dir_all(&dir)?;
+    let mut command = cli::Cli::command();
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Elvish] {
+        generate_to(shell, &mut command, "blsctl", &dir)?;
+    }
+    Ok(())
+}
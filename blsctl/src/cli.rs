@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Command line definition, shared with `build.rs` to generate shell completions
+//!
+//! blsforme types are taken as strings and parsed in `main`, so that the build
+//! script needn't depend on blsforme.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+/// Boot Loader Specification compatible kernel/initrd/cmdline management
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Override base path for all boot management operations, otherwise taken from
+    /// `BLSFORME_ROOT` or `CBM_ROOT`
+    #[arg(short, long, global = true)]
+    pub path: Option<PathBuf>,
+
+    /// Force running in image mode (scripting integration)
+    #[arg(short, long, global = true)]
+    pub image: bool,
+
+    /// Do not allow updating EFI vars
+    #[arg(short, long, global = true)]
+    pub no_efi_update: bool,
+
+    /// Emit versioned, machine readable JSON on stdout for `list-kernels`, `status`,
+    /// `audit`, `get-timeout` and `version`. Logs remain on stderr
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Print what would change without writing anything. Exits with 1 if changes are pending
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Override the EFI architecture (i.e. `aa64`), for cross-architecture images
+    #[arg(long, global = true)]
+    pub arch: Option<String>,
+
+    /// Seconds to wait for another instance to finish modifying `$BOOT`
+    #[arg(long, global = true, default_value_t = 30)]
+    pub lock_timeout: u64,
+
+    /// Use this directory as the ESP instead of discovering the partition, i.e. for image builds
+    #[arg(long, global = true)]
+    pub esp_path: Option<PathBuf>,
+
+    /// Where kernels and loader entries are installed
+    #[arg(long, global = true, value_enum, default_value_t = Layout::Auto, conflicts_with = "boot_dir")]
+    pub layout: Layout,
+
+    /// Install kernels and loader entries to this directory beneath the root (i.e. `boot`),
+    /// bypassing partition discovery and root device probing. Only suitable for GRUB and
+    /// image generation, with `root=` given via cmdline.d
+    #[arg(long, global = true)]
+    pub boot_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+/// Selects `$BOOT` among the discovered partitions
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Layout {
+    /// XBOOTLDR when present, otherwise the ESP
+    Auto,
+
+    /// Always the ESP, even when an XBOOTLDR partition is present
+    Esp,
+
+    /// Always XBOOTLDR, failing if there isn't one
+    Xbootldr,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Print version and build information, then exit
+    Version {
+        /// Also report the firmware and the installed systemd-boot (requires root)
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Report currently running kernel as successfully booting
+    ReportBooted,
+
+    /// Remove specified kernel from the system
+    RemoveKernel {
+        /// Kernel version to remove
+        version: String,
+
+        /// Also remove the kernel from the rootfs
+        #[arg(long)]
+        purge: bool,
+
+        /// Permit removal of the running kernel
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Mount the `$BOOT` directories
+    MountBoot {
+        /// Unmount again once finished, rather than leaving `$BOOT` mounted
+        #[arg(long)]
+        temporary: bool,
+    },
+
+    /// Configure the `$BOOT` directories for next boot
+    Update {
+        /// Keep at most this many kernels on `$BOOT`, pruning the oldest
+        #[arg(long)]
+        max_kernels: Option<usize>,
+
+        /// Replace the installed systemd-boot even if it is newer than the rootfs copy
+        #[arg(long)]
+        force_bootloader: bool,
+
+        /// Install the fallback bootloader and default entry even when sharing `$BOOT`
+        /// with another distribution
+        #[arg(long)]
+        take_ownership: bool,
+
+        /// Also set the bootloader timeout, as for `set-timeout`
+        #[arg(long)]
+        timeout: Option<String>,
+    },
+
+    /// Set the bootloader timeout value, in seconds or one of
+    /// `menu-force`, `menu-hidden` or `menu-disabled`
+    SetTimeout { timeout: String },
+
+    /// Retrieve the bootloader timeout value
+    GetTimeout,
+
+    /// Update `loader.conf` settings, leaving any others untouched
+    SetLoader {
+        /// Console resolution: a mode number, `auto`, `max` or `keep`
+        #[arg(long)]
+        console_mode: Option<String>,
+
+        /// Allow editing the kernel cmdline from the menu
+        #[arg(long)]
+        editor: Option<bool>,
+
+        /// Show automatic entries (Windows, EFI shell, etc)
+        #[arg(long)]
+        auto_entries: Option<bool>,
+
+        /// Show the automatic Windows Boot Manager entry
+        #[arg(long)]
+        auto_windows: Option<bool>,
+
+        /// Show the "Reboot into firmware" entry
+        #[arg(long)]
+        auto_firmware: Option<bool>,
+
+        /// Beep when the menu is shown
+        #[arg(long)]
+        beep: Option<bool>,
+    },
+
+    /// Set the kernel that will be used at next boot
+    ///
+    /// Accepts an entry ID, a kernel version (or unique prefix of one), a variant
+    /// name, or `default` to boot the newest kernel
+    SetKernel { kernel: String },
+
+    /// List kernels on `$BOOT`
+    ListKernels,
+
+    /// Report on the boot environment, kernels and bootloader configuration
+    Status {
+        /// Re-read all installed kernels and initrds, reporting any that differ from the rootfs
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Report loader entries and kernel directories on `$BOOT` that appear orphaned,
+    /// including those of other installations
+    Audit {
+        /// Remove the given orphans, as listed by a previous audit
+        #[arg(long, value_name = "PATH")]
+        prune: Vec<PathBuf>,
+    },
+
+    /// Print a completion script for the given shell
+    Completions { shell: Shell },
+}
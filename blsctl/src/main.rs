@@ -6,69 +6,33 @@
 //! replacement for Solus.
 
 use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{IsTerminal, stderr, stdout},
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
-use blsforme::{BootJSON, Configuration, Entry, Manager, Root, Schema, os_release::OsRelease};
-use clap::{Parser, Subcommand};
+use blsforme::{
+    AssetReport, BootLayout, Configuration, Entry, Firmware, Kernel, Manager, MountIntent, Orphan, RemoveOptions, Root,
+    Schema, StatusReport, VersionInfo,
+    bootloader::{ConsoleMode, LoaderConf, Timeout},
+    os_release::OsRelease,
+};
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use clap_complete::Shell;
+use cli::{Cli, Commands, Layout};
 use color_eyre::{Section, eyre::eyre};
 use fs_err as fs;
+use nix::sys::utsname::uname;
 
 use pretty_env_logger::formatted_builder;
+use serde::Serialize;
 
-/// Boot Loader Specification compatible kernel/initrd/cmdline management
-#[derive(Parser, Debug)]
-#[command(version, about)]
-struct Cli {
-    /// Override base path for all boot management operations
-    #[arg(short, long, global = true)]
-    path: Option<PathBuf>,
-
-    /// Force running in image mode (scripting integration)
-    #[arg(short, long, global = true)]
-    image: bool,
-
-    /// Do not allow updating EFI vars
-    #[arg(short, long, global = true)]
-    no_efi_update: bool,
-
-    #[command(subcommand)]
-    command: Commands,
-}
-
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Print version and exit
-    Version,
-
-    /// Report currently running kernel as successfully booting
-    ReportBooted,
-
-    /// Remove specified kernel from the system
-    RemoveKernel,
-
-    /// Mount the `$BOOT` directories
-    MountBoot,
-
-    /// Configure the `$BOOT` directories for next boot
-    Update,
-
-    /// Set the bootloader timeout value
-    SetTimeout { timeout: u64 },
-
-    /// Retrieve the bootloader timeout value
-    GetTimeout,
-
-    /// Set the kernel that will be used at next boot
-    SetKernel { kernel: String },
-
-    /// List kernels on `$BOOT`
-    ListKernels,
-
-    /// Status information (debugging)
-    Status,
-}
+mod cli;
+mod progress;
+mod status;
 
 fn scan_os_release(root: impl AsRef<Path>) -> color_eyre::Result<OsRelease> {
     let root = root.as_ref();
@@ -112,94 +76,569 @@ fn scan_os_info(root: impl AsRef<Path>) -> color_eyre::Result<os_info::OsInfo> {
     ))
 }
 
-/// Query the schema we need to use for pre BLS schema installations
-fn query_schema(os_release: OsRelease) -> color_eyre::Result<Schema> {
-    match os_release.id.as_str() {
-        "solus" => {
-            if os_release.version.name.as_ref().is_some_and(|v| v.starts_with("4.")) {
-                log::trace!("Legacy schema due to Solus 4 installation");
-                Ok(Schema::Legacy {
-                    namespace: "com.solus-project",
-                    os_release: Box::new(os_release),
-                })
-            } else {
-                Ok(Schema::Blsforme {
-                    os_release: Box::new(os_release),
-                })
-            }
+/// Determine the schema in use for the given root
+fn discover_schema(config: &Configuration) -> color_eyre::Result<Schema> {
+    if let Ok(os_info) = scan_os_info(config.root.path()) {
+        Ok(Schema::OsInfo {
+            os_info: Box::new(os_info),
+        })
+    } else {
+        Ok(Schema::from_os_release(scan_os_release(config.root.path())?))
+    }
+}
+
+/// Discover all kernels available in the rootfs
+fn discover_kernels(config: &Configuration, schema: &Schema) -> color_eyre::Result<Vec<Kernel>> {
+    let paths = glob::glob(&format!("{}/usr/lib/kernel/*", config.root.path().display()))?
+        .chain(glob::glob(&format!(
+            "{}/usr/lib/kernel/*/*",
+            config.root.path().display()
+        ))?)
+        .filter_map(|f| f.ok());
+    Ok(schema.discover_system_kernels(paths)?)
+}
+
+/// Print the versions of blsctl and blsforme, and optionally of the installed bootloader
+fn version(config: &Configuration, verbose: bool, json: bool) -> color_eyre::Result<()> {
+    let library = blsforme::version_info();
+    // This is purely informational, so never fail because of the system
+    let installed = verbose
+        .then(|| {
+            installed_bootloaders(config)
+                .inspect_err(|e| log::warn!("Unable to inspect the installed bootloader: {e:#}"))
+                .ok()
+        })
+        .flatten();
+
+    if json {
+        let (firmware, bootloaders) = installed.unzip();
+        return print_json(VersionJson {
+            blsctl: env!("CARGO_PKG_VERSION"),
+            blsforme: library,
+            firmware,
+            installed_bootloaders: bootloaders,
+        });
+    }
+
+    let list = |items: &[&str]| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
         }
-        "clear-linux-os" => {
-            log::trace!("Legacy schema due to Clear Linux OS installation");
-            Ok(Schema::Legacy {
-                namespace: "org.clearlinux",
-                os_release: Box::new(os_release),
-            })
+    };
+    println!("blsctl {}", env!("CARGO_PKG_VERSION"));
+    println!("blsforme {}", library.version);
+    println!("Schemas: {}", list(&library.schemas));
+    println!("Bootloaders: {}", list(&library.bootloaders));
+    println!("Architectures: {}", list(&library.architectures));
+    println!("Features: {}", list(&library.features));
+    if let Some((firmware, bootloaders)) = installed {
+        println!("Firmware: {firmware}");
+        for bootloader in bootloaders {
+            let version = bootloader.version.as_deref().unwrap_or("unknown version");
+            println!("Installed: {} ({version})", bootloader.path.display());
         }
-        _ => Ok(Schema::Blsforme {
-            os_release: Box::new(os_release),
-        }),
     }
+
+    Ok(())
 }
 
-fn inspect_root(config: &Configuration) -> color_eyre::Result<()> {
+/// The firmware and bootloaders installed to the ESP
+fn installed_bootloaders(config: &Configuration) -> color_eyre::Result<(Firmware, Vec<AssetReport>)> {
+    check_permissions()?;
+
+    let system = System::discover(config)?;
+    let manager = system.manager(config)?;
+    let _parts = manager.mount_partitions(MountIntent::ReadOnly)?;
+    let report = manager.status(&system.schema);
+    Ok((report.firmware, report.installed_bootloaders))
+}
+
+fn inspect_root(config: &Configuration, verify: bool, json: bool) -> color_eyre::Result<()> {
     if let Err(e) = check_permissions() {
         log::error!("{e:#}");
         return Ok(());
     }
 
-    let schema = if let Ok(os_info) = scan_os_info(config.root.path()) {
-        Schema::OsInfo {
-            os_info: Box::new(os_info),
+    let system = System::discover(config)?;
+    log::info!("Kernels: {:?}", system.kernels);
+
+    // Query the manager
+    let manager = system.manager(config)?;
+
+    // Report whatever we can, even when `$BOOT` can't be mounted
+    let _parts = manager
+        .mount_partitions(MountIntent::ReadOnly)
+        .inspect_err(|e| log::warn!("Failed to mount $BOOT: {e}"))
+        .unwrap_or_default();
+
+    let report = manager.status(&system.schema);
+    let mismatches = if verify {
+        Some(manager.verify(&system.schema)?)
+    } else {
+        None
+    };
+
+    if json {
+        return print_json(StatusJson { report, mismatches });
+    }
+
+    status::print_report(&report, mismatches.as_deref());
+    if let Some(mismatches) = mismatches.filter(|m| !m.is_empty()) {
+        return Err(eyre!("{} installed files failed verification", mismatches.len()));
+    }
+
+    Ok(())
+}
+
+/// Bootloader assets available in the rootfs
+fn bootloader_assets(config: &Configuration) -> color_eyre::Result<Vec<PathBuf>> {
+    // systemd-boot, and shim for Secure Boot where available
+    let mut assets = vec![];
+    for pattern in ["usr/lib*/systemd/boot/efi/*.efi", "usr/lib*/shim/*.efi"] {
+        assets.extend(glob::glob(&format!("{}/{pattern}", config.root.path().display()))?.filter_map(|f| f.ok()));
+    }
+    Ok(assets)
+}
+
+/// Everything discovered in the rootfs, shared by `status` and `update`
+struct System {
+    schema: Schema,
+    kernels: Vec<Kernel>,
+    assets: Vec<PathBuf>,
+}
+
+impl System {
+    /// Discover the OS, its kernels and bootloader assets
+    fn discover(config: &Configuration) -> color_eyre::Result<Self> {
+        let schema = discover_schema(config)?;
+        let kernels = discover_kernels(config, &schema)?;
+        let assets = bootloader_assets(config)?;
+        Ok(Self {
+            schema,
+            kernels,
+            assets,
+        })
+    }
+
+    /// Entries for all kernels, with their cmdline snippets loaded
+    fn entries(&self, config: &Configuration) -> color_eyre::Result<Vec<Entry<'_>>> {
+        let mut entries = self.kernels.iter().map(Entry::new).collect::<Vec<_>>();
+        for entry in entries.iter_mut() {
+            entry.load_cmdline_snippets(config)?;
         }
+        Ok(entries)
+    }
+
+    /// Manager for all entries and bootloader assets
+    fn manager<'a>(&'a self, config: &'a Configuration) -> color_eyre::Result<Manager<'a>> {
+        Ok(Manager::new(config)?
+            .with_entries(self.entries(config)?.into_iter())
+            .with_bootloader_assets(self.assets.clone()))
+    }
+}
+
+/// Install the rootfs kernels and bootloader to `$BOOT`
+fn update(
+    config: &Configuration,
+    max_kernels: Option<usize>,
+    force_bootloader: bool,
+    take_ownership: bool,
+    timeout: Option<Timeout>,
+    efi_updates: bool,
+) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let system = System::discover(config)?;
+    if system.kernels.is_empty() {
+        return Err(eyre!(
+            "No kernels found in {}, refusing to update $BOOT",
+            config.root.path().join("usr/lib/kernel").display()
+        ));
+    }
+
+    let mut manager = system
+        .manager(config)?
+        .with_efi_updates(efi_updates)
+        .with_force_bootloader(force_bootloader)
+        .with_take_ownership(take_ownership);
+    if let Some(max) = max_kernels {
+        manager = manager.with_max_kernels(max);
+    }
+    if let Some(timeout) = timeout {
+        manager = manager.with_timeout(timeout);
+    }
+    if stderr().is_terminal() && !config.dry_run {
+        let progress = progress::Progress::default();
+        manager = manager.with_progress(Box::new(move |event| progress.render(event)));
+    }
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    manager.sync(&system.schema)?;
+
+    Ok(pending_changes(config, &manager))
+}
+
+/// Report orphans on `$BOOT`, removing those selected with `--prune`
+fn audit(config: &Configuration, prune: &[PathBuf], json: bool) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?;
+    let intent = if prune.is_empty() {
+        MountIntent::ReadOnly
     } else {
-        let os_release = scan_os_release(config.root.path())?;
-        query_schema(os_release)?
+        MountIntent::ReadWrite
     };
+    let _parts = manager.mount_partitions(intent)?;
+    let report = manager.audit_boot(&schema)?;
 
-    let paths = glob::glob(&format!("{}/usr/lib/kernel/*", config.root.path().display()))?
-        .chain(glob::glob(&format!(
-            "{}/usr/lib/kernel/*/*",
-            config.root.path().display()
-        ))?)
-        .filter_map(|f| f.ok());
-    let mut kernels = schema.discover_system_kernels(paths)?;
-
-    // Future: Include other potential bootloader asset paths
-    let booty_bits = glob::glob(&format!(
-        "{}/usr/lib*/systemd/boot/efi/*.efi",
-        config.root.path().display()
-    ))?
-    .filter_map(|f| f.ok())
-    .collect::<Vec<_>>();
-
-    // If a boot JSON is provided, augment the records
-    for kernel in kernels.iter_mut() {
-        if let Some(json) = kernel
-            .extras
+    if !prune.is_empty() {
+        let selections = prune
+            .iter()
+            .map(|path| {
+                report
+                    .orphans
+                    .iter()
+                    .find(|o| o.path() == path)
+                    .cloned()
+                    .ok_or_else(|| eyre!("{} is not orphaned", path.display()))
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        manager.prune(&schema, &selections)?;
+        return Ok(pending_changes(config, &manager));
+    }
+
+    if json {
+        print_json(report)?;
+        return Ok(false);
+    }
+
+    if report.orphans.is_empty() {
+        println!("No orphans found on $BOOT");
+    }
+    for orphan in report.orphans.iter() {
+        let owner = if orphan.owned() { "" } else { " (other installation)" };
+        match orphan {
+            Orphan::DanglingEntry { path, missing, .. } => {
+                println!("dangling entry: {}{owner}", path.display());
+                for file in missing {
+                    println!("    missing: {file}");
+                }
+            }
+            Orphan::UnreferencedDir { path, .. } => println!("unreferenced:   {}{owner}", path.display()),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Version of the JSON output format, bumped on incompatible changes
+const JSON_VERSION: u32 = 1;
+
+/// Top-level JSON document, tagged with [`JSON_VERSION`]
+#[derive(Serialize)]
+struct JsonOutput<T> {
+    version: u32,
+    #[serde(flatten)]
+    data: T,
+}
+
+/// Print the given data as a versioned JSON document on stdout
+fn print_json(data: impl Serialize) -> color_eyre::Result<()> {
+    let output = JsonOutput {
+        version: JSON_VERSION,
+        data,
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// JSON representation of an installed kernel, for scripting
+#[derive(Serialize)]
+struct KernelJson<'a> {
+    #[serde(flatten)]
+    kernel: Kernel,
+    cmdline: Option<&'a str>,
+}
+
+impl<'a> KernelJson<'a> {
+    fn new(kernel: &Kernel, available: &[Kernel], entries: &'a [Entry<'static>]) -> Self {
+        Self {
+            kernel: Kernel {
+                variant: variant_of(kernel, available).map(str::to_string),
+                ..kernel.clone()
+            },
+            cmdline: cmdline_of(&kernel.version, entries),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct KernelsJson<'a> {
+    kernels: Vec<KernelJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct VersionJson {
+    blsctl: &'static str,
+    blsforme: VersionInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firmware: Option<Firmware>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed_bootloaders: Option<Vec<AssetReport>>,
+}
+
+#[derive(Serialize)]
+struct StatusJson {
+    #[serde(flatten)]
+    report: StatusReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mismatches: Option<Vec<PathBuf>>,
+}
+
+#[derive(Serialize)]
+struct TimeoutJson {
+    timeout: Option<Timeout>,
+}
+
+/// The cmdline recorded on `$BOOT` for the given kernel version
+fn cmdline_of<'a>(version: &str, entries: &'a [Entry<'static>]) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|e| e.kernel().version == version)
+        .and_then(|e| e.cmdline().first())
+        .map(|c| c.snippet.as_str())
+}
+
+/// Installed kernels don't record their variant, so borrow it from the rootfs
+fn variant_of<'a>(kernel: &'a Kernel, available: &'a [Kernel]) -> Option<&'a str> {
+    kernel.variant.as_deref().or_else(|| {
+        available
+            .iter()
+            .find(|k| k.version == kernel.version)
+            .and_then(|k| k.variant.as_deref())
+    })
+}
+
+/// List kernels available in the rootfs and/or installed to `$BOOT`
+///
+/// The running kernel is marked with an asterisk, unless we're operating
+/// in image mode where `uname -r` has no relation to the target.
+///
+/// In JSON mode only the installed kernels are emitted.
+fn list_kernels(config: &Configuration, json: bool) -> color_eyre::Result<()> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let available = discover_kernels(config, &schema)?;
+
+    let manager = Manager::new(config)?;
+    let parts = manager.mount_partitions(MountIntent::ReadOnly)?;
+    let installed = manager.installed_kernels(&schema, &parts)?;
+    let entries = manager.installed_entries(&schema)?;
+
+    if json {
+        let kernels = installed
             .iter()
-            .find(|e| matches!(e.kind, blsforme::AuxiliaryKind::BootJson))
-        {
-            let text = fs::read_to_string(&json.path)?;
-            let decoded = BootJSON::try_from(text.as_str())?;
-            kernel.variant = Some(decoded.variant.to_string());
+            .map(|kernel| KernelJson::new(kernel, &available, &entries))
+            .collect();
+        return print_json(KernelsJson { kernels });
+    }
+
+    // Merge both sets by version, preferring the installed record
+    let mut listing: BTreeMap<&str, (&Kernel, bool)> = BTreeMap::new();
+    for kernel in available.iter() {
+        listing.insert(&kernel.version, (kernel, false));
+    }
+    for kernel in installed.iter() {
+        listing.insert(&kernel.version, (kernel, true));
+    }
+
+    // Newest first
+    let mut listing = listing.into_iter().collect::<Vec<_>>();
+    listing.sort_by(|(_, (a, _)), (_, (b, _))| b.cmp(a));
+
+    let width = listing.iter().map(|(v, _)| v.len()).max().unwrap_or_default();
+    for (version, (kernel, is_installed)) in listing.iter() {
+        let marker = if config.root.is_native() && kernel.matches_running()? {
+            "*"
+        } else {
+            " "
+        };
+        let state = if *is_installed { "installed" } else { "available" };
+        println!(
+            "{marker} {version:<width$}  {:<10}  initrds: {}  {state}",
+            variant_of(kernel, &available).unwrap_or("-"),
+            kernel.initrd.len()
+        );
+        if let Some(cmdline) = cmdline_of(version, &entries) {
+            println!("  {:<width$}  options: {cmdline}", "");
         }
     }
-    log::info!("Kernels: {kernels:?}");
-    let mut entries = kernels.iter().map(Entry::new).collect::<Vec<_>>();
-    for entry in entries.iter_mut() {
-        entry.load_cmdline_snippets(config)?;
+
+    Ok(())
+}
+
+/// Report the running kernel as having booted successfully
+fn report_booted(config: &Configuration) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?;
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    let booted = match manager.mark_booted(&schema)? {
+        Some(version) => Some(version),
+        None if config.root.is_native() => Some(uname()?.release().to_string_lossy().to_string()),
+        None => None,
+    };
+    match booted {
+        Some(version) => {
+            manager.report_booted(&version)?;
+            log::info!("Reported kernel {version} as successfully booted");
+        }
+        None => log::warn!("Unable to determine the booted kernel"),
     }
 
-    // Query the manager
-    let manager = Manager::new(config)?
-        .with_entries(entries.into_iter())
-        .with_bootloader_assets(booty_bits);
-    let _parts = manager.mount_partitions()?;
-    eprintln!("manager = {manager:?}");
+    Ok(pending_changes(config, &manager))
+}
+
+/// Remove a kernel from `$BOOT`
+fn remove_kernel(config: &Configuration, version: &str, options: RemoveOptions) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?;
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    match manager.remove_kernel(&schema, version, options) {
+        Ok(()) => log::info!("Removed kernel {version}"),
+        Err(blsforme::Error::UnknownKernel { .. }) => {
+            log::warn!("Kernel {version} is not installed, nothing to remove")
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(pending_changes(config, &manager))
+}
+
+/// Set the default kernel for the next boot
+fn set_kernel(config: &Configuration, kernel: &str, efi_updates: bool) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?.with_efi_updates(efi_updates);
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    let kernel = (kernel != "default").then_some(kernel);
+    match manager.set_default_entry(&schema, kernel)? {
+        Some(entry_id) => log::info!("Default boot entry is now {entry_id}"),
+        None => log::info!("Default boot entry will be the newest kernel"),
+    }
+
+    Ok(pending_changes(config, &manager))
+}
+
+/// Set the bootloader menu timeout
+fn set_timeout(config: &Configuration, timeout: Timeout, efi_updates: bool) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?.with_efi_updates(efi_updates);
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    manager.set_timeout(&schema, timeout)?;
+
+    Ok(pending_changes(config, &manager))
+}
+
+/// Settings accepted by `set-loader`
+#[derive(Debug, Default)]
+struct LoaderSettings {
+    console_mode: Option<ConsoleMode>,
+    editor: Option<bool>,
+    auto_entries: Option<bool>,
+    auto_windows: Option<bool>,
+    auto_firmware: Option<bool>,
+    beep: Option<bool>,
+}
+
+impl LoaderSettings {
+    /// Apply the given settings only
+    fn apply(&self, conf: &mut LoaderConf) {
+        if let Some(mode) = self.console_mode {
+            conf.set_console_mode(mode);
+        }
+        if let Some(editor) = self.editor {
+            conf.set_editor(editor);
+        }
+        if let Some(auto_entries) = self.auto_entries {
+            conf.set_auto_entries(auto_entries);
+        }
+        if let Some(auto_windows) = self.auto_windows {
+            conf.set_auto_windows(auto_windows);
+        }
+        if let Some(auto_firmware) = self.auto_firmware {
+            conf.set_auto_firmware(auto_firmware);
+        }
+        if let Some(beep) = self.beep {
+            conf.set_beep(beep);
+        }
+    }
+}
+
+/// Update `loader.conf` with the given settings
+fn set_loader(config: &Configuration, settings: &LoaderSettings) -> color_eyre::Result<bool> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?;
+    let _parts = manager.mount_partitions(MountIntent::ReadWrite)?;
+    manager.update_loader_conf(&schema, |conf| settings.apply(conf))?;
+
+    Ok(pending_changes(config, &manager))
+}
+
+/// Print the bootloader menu timeout
+fn get_timeout(config: &Configuration, json: bool) -> color_eyre::Result<()> {
+    check_permissions()?;
+
+    let schema = discover_schema(config)?;
+    let manager = Manager::new(config)?;
+    let _parts = manager.mount_partitions(MountIntent::ReadOnly)?;
+    let timeout = manager.timeout(&schema)?;
+    if json {
+        return print_json(TimeoutJson { timeout });
+    }
+    match timeout {
+        Some(Timeout::Seconds(seconds)) => println!("Timeout: {seconds} seconds"),
+        Some(timeout) => println!("Timeout: {timeout}"),
+        None => println!("No timeout is currently configured"),
+    }
 
     Ok(())
 }
 
+/// Mount `$BOOT`, optionally leaving it mounted
+fn mount_boot(config: &Configuration, persistent: bool) -> color_eyre::Result<()> {
+    check_permissions()?;
+
+    let manager = Manager::new(config)?;
+    let mounts = manager.mount_boot(persistent)?;
+    for mount in mounts.iter() {
+        println!("Mounted {}", mount.path().display());
+    }
+
+    Ok(())
+}
+
+/// In dry-run mode print the planned changes, returning whether there are any
+fn pending_changes(config: &Configuration, manager: &Manager) -> bool {
+    let plan = manager.changes();
+    if config.dry_run && !plan.is_empty() {
+        print!("{plan}");
+        println!("Would update {} files", plan.updated_files());
+    }
+    !plan.is_empty()
+}
+
 /// Bail-out permission check for execution
 fn check_permissions() -> color_eyre::Result<()> {
     let euid = unsafe { nix::libc::geteuid() };
@@ -210,7 +649,27 @@ fn check_permissions() -> color_eyre::Result<()> {
     }
 }
 
+/// Print the completion script for the shell on stdout
+fn completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), env!("CARGO_BIN_NAME"), &mut stdout());
+}
+
+/// Parse a value taken as a string by [`Cli`], exiting as clap would when it's invalid
+fn parse_arg<T: FromStr<Err: fmt::Display>>(value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|e| Cli::command().error(ErrorKind::InvalidValue, e).exit())
+}
+
 fn main() -> color_eyre::Result<()> {
+    let res = Cli::parse();
+
+    // Completions are generated at packaging time, on a host that may not be set up
+    if let Commands::Completions { shell } = res.command {
+        completions(shell);
+        return Ok(());
+    }
+
     let host_os = scan_os_release("/").expect("Cannot determine running Linux distro");
     color_eyre::config::HookBuilder::default()
         .issue_url("https://github.com/AerynOS/blsforme/issues/new")
@@ -226,40 +685,97 @@ fn main() -> color_eyre::Result<()> {
         .parse_default_env()
         .init();
 
-    let res = Cli::parse();
+    // A path other than `/` implies image mode, as does `--image`. The root may also
+    // come from the environment, as with clr-boot-manager
+    let root = res.path.map(Root::new).unwrap_or_else(Root::from_env);
     let root = if res.image {
-        // forced image mode
-        Root::Image(res.path.unwrap_or("/".into()))
-    } else if let Some(path) = res.path {
-        // Path provided, native only if it is `/`
-        if path.as_path() == Path::new("/") {
-            Root::Native(path)
-        } else {
-            Root::Image(path)
-        }
+        Root::Image(root.path().clone())
     } else {
-        // Native operation
-        Root::Native("/".into())
+        root
     };
 
-    let config = Configuration { root, vfs: "/".into() };
+    let config = Configuration::builder()
+        .root(root)
+        .dry_run(res.dry_run)
+        .arch(res.arch.as_deref().map(parse_arg))
+        .lock_timeout(Duration::from_secs(res.lock_timeout))
+        .esp_path(res.esp_path)
+        .layout(match (res.boot_dir, res.layout) {
+            (Some(dir), _) => BootLayout::BootDirectory(dir),
+            (None, Layout::Auto) => BootLayout::Auto,
+            (None, Layout::Esp) => BootLayout::EspOnly,
+            (None, Layout::Xbootldr) => BootLayout::Xbootldr,
+        })
+        .build()?;
 
     log::trace!("Using configuration: {config:?}");
     log::info!("Inspecting root device: {}", config.root.path().display());
 
-    match res.command {
-        Commands::Version => todo!(),
-        Commands::ReportBooted => todo!(),
-        Commands::RemoveKernel => todo!(),
-        Commands::MountBoot => todo!(),
-        Commands::Update => todo!(),
-        Commands::SetTimeout { timeout: _ } => todo!(),
-        Commands::GetTimeout => todo!(),
-        Commands::SetKernel { kernel: _ } => todo!(),
-        Commands::ListKernels => todo!(),
-        Commands::Status => {
-            inspect_root(&config)?;
+    let changed = match res.command {
+        Commands::Version { verbose } => {
+            version(&config, verbose, res.json)?;
+            false
+        }
+        Commands::ReportBooted => report_booted(&config)?,
+        Commands::RemoveKernel { version, purge, force } => {
+            remove_kernel(&config, &version, RemoveOptions { purge, force })?
         }
+        Commands::MountBoot { temporary } => {
+            mount_boot(&config, !temporary)?;
+            false
+        }
+        Commands::Update {
+            max_kernels,
+            force_bootloader,
+            take_ownership,
+            timeout,
+        } => update(
+            &config,
+            max_kernels,
+            force_bootloader,
+            take_ownership,
+            timeout.as_deref().map(parse_arg),
+            !res.no_efi_update,
+        )?,
+        Commands::SetTimeout { timeout } => set_timeout(&config, parse_arg(&timeout), !res.no_efi_update)?,
+        Commands::GetTimeout => {
+            get_timeout(&config, res.json)?;
+            false
+        }
+        Commands::SetLoader {
+            console_mode,
+            editor,
+            auto_entries,
+            auto_windows,
+            auto_firmware,
+            beep,
+        } => set_loader(
+            &config,
+            &LoaderSettings {
+                console_mode: console_mode.as_deref().map(parse_arg),
+                editor,
+                auto_entries,
+                auto_windows,
+                auto_firmware,
+                beep,
+            },
+        )?,
+        Commands::SetKernel { kernel } => set_kernel(&config, &kernel, !res.no_efi_update)?,
+        Commands::ListKernels => {
+            list_kernels(&config, res.json)?;
+            false
+        }
+        Commands::Status { verify } => {
+            inspect_root(&config, verify, res.json)?;
+            false
+        }
+        Commands::Audit { prune } => audit(&config, &prune, res.json)?,
+        Commands::Completions { .. } => unreachable!("handled before configuration"),
+    };
+
+    // Allow CI to detect pending changes
+    if res.dry_run && changed {
+        std::process::exit(1);
     }
 
     Ok(())
@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Terminal progress display for `blsctl update`
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write, stderr},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use blsforme::{ProgressEvent, file_utils::format_size};
+
+/// Width of the progress bar, in characters
+const BAR_WIDTH: usize = 30;
+
+/// Renders progress events on stderr
+///
+/// Files copied in parallel all feed a single bar, so their updates never interleave.
+#[derive(Debug, Default)]
+pub struct Progress {
+    copies: Mutex<Copies>,
+}
+
+/// Files copied since the last step
+#[derive(Debug, Default)]
+struct Copies {
+    /// `(bytes_done, bytes_total)` per file
+    files: BTreeMap<PathBuf, (u64, u64)>,
+
+    /// Whether the bar is on screen without a trailing newline
+    drawing: bool,
+}
+
+impl Progress {
+    /// Render a progress event
+    pub fn render(&self, event: ProgressEvent) {
+        let mut copies = self.copies.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = stderr().lock();
+        let step = match event {
+            ProgressEvent::FileCopy {
+                path,
+                bytes_done,
+                bytes_total,
+            } => {
+                copies.files.insert(path, (bytes_done, bytes_total));
+                // Progress is purely cosmetic, so a failed write isn't worth reporting
+                let _ = copies.draw(&mut out);
+                return;
+            }
+            ProgressEvent::Discovery => "Inspecting $BOOT".to_string(),
+            ProgressEvent::BootloaderSync => "Updating bootloader".to_string(),
+            ProgressEvent::EntryInstall { entry_id } => format!("Installing {entry_id}"),
+            ProgressEvent::Cleanup => "Removing stale entries".to_string(),
+        };
+        let _ = copies.finish(&mut out).and_then(|_| writeln!(out, "{step}"));
+    }
+}
+
+impl Copies {
+    /// Draw the bar for all copies so far, ending the line once they're all complete
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (done, total) = self
+            .files
+            .values()
+            .fold((0, 0), |(done, total), (d, t)| (done + d, total + t));
+        let complete = self.files.values().filter(|(d, t)| d >= t).count();
+        write!(
+            out,
+            "\r  [{}] {} / {} ({complete}/{} files)",
+            bar(done, total),
+            format_size(done),
+            format_size(total),
+            self.files.len(),
+        )?;
+        self.drawing = complete < self.files.len();
+        if self.drawing { out.flush() } else { writeln!(out) }
+    }
+
+    /// End any bar on screen and start counting afresh
+    fn finish(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.files.clear();
+        if std::mem::take(&mut self.drawing) {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// A bar of [`BAR_WIDTH`] characters, filled in proportion to `done` of `total`
+fn bar(done: u64, total: u64) -> String {
+    let filled = if total == 0 {
+        BAR_WIDTH
+    } else {
+        ((done as f64 / total as f64 * BAR_WIDTH as f64) as usize).min(BAR_WIDTH)
+    };
+    format!("{}{}", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
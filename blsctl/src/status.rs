@@ -0,0 +1,209 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Human readable rendering of the [`StatusReport`]
+
+use std::{
+    fmt::Display,
+    io::{IsTerminal, stdout},
+    path::PathBuf,
+};
+
+use blsforme::{Firmware, PartitionReport, StatusReport, VarAccess, bootloader::Timeout, file_utils::format_size};
+use owo_colors::{OwoColorize, Style};
+
+/// Width of the label column
+const LABEL_WIDTH: usize = 16;
+
+/// Renders sections of aligned `label: value` lines, colored when stdout is a TTY
+struct Printer {
+    color: bool,
+}
+
+impl Printer {
+    fn paint(&self, text: impl Display, style: Style) -> String {
+        if self.color {
+            text.style(style).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn section(&self, title: &str) {
+        println!("{}", self.paint(title, Style::new().bold().underline()));
+    }
+
+    fn field(&self, label: &str, value: impl Display) {
+        let label = format!("{label}:");
+        println!(
+            "  {}  {value}",
+            self.paint(format!("{label:<LABEL_WIDTH$}"), Style::new().cyan())
+        );
+    }
+
+    /// Value, or a dimmed placeholder when missing
+    fn or_unknown(&self, value: Option<impl Display>) -> String {
+        match value {
+            Some(value) => value.to_string(),
+            None => self.paint("-", Style::new().dimmed()),
+        }
+    }
+
+    fn partition(&self, label: &str, partition: &PartitionReport) {
+        let device = match (&partition.device, &partition.model) {
+            (Some(device), Some(model)) => format!("{} ({model})", device.display()),
+            (device, _) => self.or_unknown(device.as_ref().map(|d| d.display())),
+        };
+        let mountpoint = match &partition.mountpoint {
+            Some(mountpoint) if partition.mounted => format!("{}", mountpoint.display()),
+            Some(mountpoint) => format!(
+                "{} {}",
+                mountpoint.display(),
+                self.paint("(not mounted)", Style::new().yellow())
+            ),
+            None => self.or_unknown(None::<&str>),
+        };
+        let capacity = match (partition.available, partition.size) {
+            (Some(available), Some(size)) => format!(" ({} free of {})", format_size(available), format_size(size)),
+            (None, Some(size)) => format!(" ({})", format_size(size)),
+            _ => String::new(),
+        };
+        self.field(label, format!("{device} at {mountpoint}{capacity}"));
+    }
+}
+
+/// Print the report as aligned, human readable sections
+pub fn print_report(report: &StatusReport, mismatches: Option<&[PathBuf]>) {
+    let printer = Printer {
+        color: stdout().is_terminal(),
+    };
+
+    printer.section("Firmware");
+    match report.firmware {
+        Firmware::Uefi => printer.field("Type", report.firmware),
+        Firmware::Bios => printer.field(
+            "Type",
+            format!(
+                "{} {}",
+                report.firmware,
+                printer.paint("(BIOS mode: limited functionality)", Style::new().yellow())
+            ),
+        ),
+    }
+    printer.field(
+        "EFI variables",
+        match report.efi_vars {
+            VarAccess::ReadWrite => "read-write".to_string(),
+            VarAccess::ReadOnly => printer.paint("read-only", Style::new().yellow()),
+            VarAccess::Unavailable => printer.paint("unavailable", Style::new().dimmed()),
+        },
+    );
+    printer.field("Secure Boot", if report.secure_boot { "enabled" } else { "disabled" });
+    println!();
+
+    printer.section("Boot partitions");
+    printer.partition("ESP", &report.esp);
+    printer.partition("XBOOTLDR", &report.xbootldr);
+    println!();
+
+    printer.section("Operating system");
+    printer.field("Name", report.os.display_name.as_deref().unwrap_or(&report.os.name));
+    printer.field("ID", &report.os.id);
+    printer.field("Schema", report.os.schema);
+    printer.field("Cmdline", report.cmdline.join(" "));
+    println!();
+
+    printer.section("Bootloader");
+    if report.bootloader_assets.is_empty() {
+        printer.field("Assets", printer.or_unknown(None::<&str>));
+    }
+    for asset in report.bootloader_assets.iter() {
+        let version = printer.or_unknown(asset.version.as_ref());
+        printer.field("Asset", format!("{} ({version})", asset.path.display()));
+    }
+    for installed in report.installed_bootloaders.iter() {
+        let version = printer.or_unknown(installed.version.as_ref());
+        printer.field("Installed", format!("{} ({version})", installed.path.display()));
+    }
+    printer.field("Shim", if report.shim { "installed" } else { "not installed" });
+    if let Some(fallback) = &report.fallback {
+        let owner = match (&fallback.version, fallback.owned) {
+            (_, true) => "ours".to_string(),
+            (Some(version), false) => printer.paint(format!("foreign, {version}"), Style::new().yellow()),
+            (None, false) => printer.paint("foreign", Style::new().yellow()),
+        };
+        printer.field("Fallback", format!("{} ({owner})", fallback.path.display()));
+    }
+    for entry in report.foreign_entries.iter() {
+        printer.field("Foreign entry", entry.display());
+    }
+    for vendor in report.esp_vendors.iter().filter(|v| v.kind.is_foreign()) {
+        printer.field("Other OS", format!("{} ({})", vendor.path.display(), vendor.kind));
+    }
+    if let Some(bootmgr) = &report.windows_boot_manager {
+        let visibility = if report.windows_hidden {
+            printer.paint("hidden by loader.conf", Style::new().yellow())
+        } else {
+            "shown".to_string()
+        };
+        printer.field("Windows", format!("{} ({visibility})", bootmgr.display()));
+    }
+    printer.field("Default entry", printer.or_unknown(report.default_entry.as_ref()));
+    let timeout = report.timeout.map(|t| match t {
+        Timeout::Seconds(seconds) => format!("{seconds} seconds"),
+        t => t.to_string(),
+    });
+    printer.field("Timeout", printer.or_unknown(timeout));
+    println!();
+
+    printer.section("Kernels");
+    let is_installed = |version: &str| report.installed_kernels.iter().any(|k| k.version == version);
+    let is_available = |version: &str| report.available_kernels.iter().any(|k| k.version == version);
+
+    // Newest first, listing each version once
+    let mut kernels = report
+        .available_kernels
+        .iter()
+        .chain(report.installed_kernels.iter())
+        .collect::<Vec<_>>();
+    kernels.sort_by(|a, b| b.cmp(a));
+    kernels.dedup_by(|a, b| a.version == b.version);
+    if kernels.is_empty() {
+        printer.field("Kernels", printer.or_unknown(None::<&str>));
+    }
+    for kernel in kernels {
+        let state = match (is_available(&kernel.version), is_installed(&kernel.version)) {
+            (true, true) => printer.paint("installed", Style::new().green()),
+            (true, false) => printer.paint("available", Style::new().yellow()),
+            _ => printer.paint("installed (not in rootfs)", Style::new().dimmed()),
+        };
+        printer.field(&kernel.version, state);
+    }
+    println!();
+
+    printer.section("Entries");
+    if report.installed_entries.is_empty() {
+        printer.field("Entries", printer.or_unknown(None::<&str>));
+    }
+    for entry in report.installed_entries.iter() {
+        printer.field(
+            entry.title.as_deref().unwrap_or(&entry.version),
+            printer.or_unknown(entry.cmdline.as_ref()),
+        );
+    }
+
+    if let Some(mismatches) = mismatches {
+        println!();
+        printer.section("Verification");
+        if mismatches.is_empty() {
+            printer.field(
+                "Result",
+                printer.paint("all installed kernels verified", Style::new().green()),
+            );
+        }
+        for path in mismatches {
+            printer.field("Mismatch", printer.paint(path.display(), Style::new().red()));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! CPU architecture handling for EFI assets
+
+use std::str::FromStr;
+
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(display("unknown architecture: {name}"))]
+pub struct UnknownArchitecture {
+    name: String,
+}
+
+/// EFI capable CPU architectures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    /// 64-bit x86 (`x64`)
+    X86_64,
+
+    /// 32-bit x86 (`ia32`)
+    X86,
+
+    /// 64-bit ARM (`aa64`)
+    Aarch64,
+
+    /// 32-bit ARM (`arm`)
+    Arm,
+
+    /// 64-bit RISC-V (`riscv64`)
+    Riscv64,
+
+    /// 64-bit LoongArch (`loongarch64`)
+    LoongArch64,
+}
+
+impl Architecture {
+    /// All known architectures
+    pub const ALL: [Self; 6] = [
+        Self::X86_64,
+        Self::X86,
+        Self::Aarch64,
+        Self::Arm,
+        Self::Riscv64,
+        Self::LoongArch64,
+    ];
+
+    /// The architecture we were built for, if EFI capable
+    pub fn native() -> Option<Self> {
+        match std::env::consts::ARCH {
+            "x86_64" => Some(Self::X86_64),
+            "x86" => Some(Self::X86),
+            "aarch64" => Some(Self::Aarch64),
+            "arm" => Some(Self::Arm),
+            "riscv64" => Some(Self::Riscv64),
+            "loongarch64" => Some(Self::LoongArch64),
+            _ => None,
+        }
+    }
+
+    /// The EFI name for this architecture, as used in binary names (i.e. `x64`)
+    pub fn efi_name(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "x64",
+            Self::X86 => "ia32",
+            Self::Aarch64 => "aa64",
+            Self::Arm => "arm",
+            Self::Riscv64 => "riscv64",
+            Self::LoongArch64 => "loongarch64",
+        }
+    }
+
+    /// systemd-boot binary and removable media fallback names for this architecture
+    pub fn efi_arch_filenames(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::X86_64 => ("systemd-bootx64.efi", "BOOTX64.EFI"),
+            Self::X86 => ("systemd-bootia32.efi", "BOOTIA32.EFI"),
+            Self::Aarch64 => ("systemd-bootaa64.efi", "BOOTAA64.EFI"),
+            Self::Arm => ("systemd-bootarm.efi", "BOOTARM.EFI"),
+            Self::Riscv64 => ("systemd-bootriscv64.efi", "BOOTRISCV64.EFI"),
+            Self::LoongArch64 => ("systemd-bootloongarch64.efi", "BOOTLOONGARCH64.EFI"),
+        }
+    }
+}
+
+impl FromStr for Architecture {
+    type Err = UnknownArchitecture;
+
+    /// Accepts both the kernel (`uname -m`) and EFI names
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" | "x64" => Ok(Self::X86_64),
+            "x86" | "i686" | "i386" | "ia32" => Ok(Self::X86),
+            "aarch64" | "arm64" | "aa64" => Ok(Self::Aarch64),
+            "arm" | "armv7l" => Ok(Self::Arm),
+            "riscv64" => Ok(Self::Riscv64),
+            "loongarch64" => Ok(Self::LoongArch64),
+            _ => UnknownArchitectureSnafu { name: s }.fail(),
+        }
+    }
+}
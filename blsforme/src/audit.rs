@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Cross-check loader entries against the files present on `$BOOT`
+//!
+//! Interrupted updates, manual edits and other installations sharing the ESP
+//! can leave entries pointing at missing files, or kernel directories that no
+//! entry references. An audit only reports these: deletion is left to
+//! [`crate::Manager::prune`] with a user-confirmed selection.
+
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::{bootloader::systemd_boot::entry_file::LoaderEntry, file_utils::PathExt};
+
+/// `EFI` subdirectories that never hold kernel directories
+const RESERVED_DIRS: [&str; 3] = ["boot", "linux", "systemd"];
+
+/// File name prefixes identifying a kernel directory in a foreign namespace
+const KERNEL_PREFIXES: [&str; 3] = ["vmlinuz", "linux", "kernel"];
+
+/// Findings of [`crate::Manager::audit_boot`]
+#[derive(Debug, Default, Serialize)]
+pub struct AuditReport {
+    /// Loader entries and kernel directories that appear to be orphaned
+    pub orphans: Vec<Orphan>,
+}
+
+/// A file or directory on `$BOOT` that is no longer usable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Orphan {
+    /// Loader entry referencing files that don't exist
+    DanglingEntry {
+        path: PathBuf,
+        missing: Vec<String>,
+        owned: bool,
+    },
+
+    /// Kernel directory not referenced by any loader entry
+    UnreferencedDir { path: PathBuf, owned: bool },
+}
+
+impl Orphan {
+    /// Path of the entry or directory
+    pub fn path(&self) -> &Path {
+        match self {
+            Orphan::DanglingEntry { path, .. } | Orphan::UnreferencedDir { path, .. } => path,
+        }
+    }
+
+    /// Whether it belongs to one of our namespaces, rather than another installation
+    pub fn owned(&self) -> bool {
+        match self {
+            Orphan::DanglingEntry { owned, .. } | Orphan::UnreferencedDir { owned, .. } => *owned,
+        }
+    }
+}
+
+/// Scan `boot_root` for orphans, using our namespaces and entry prefixes to determine ownership
+pub(crate) fn scan(
+    boot_root: &Path,
+    entries: &[(PathBuf, LoaderEntry)],
+    namespaces: &[String],
+    prefixes: &[String],
+) -> AuditReport {
+    let mut orphans = vec![];
+    let mut referenced = vec![];
+
+    for (path, entry) in entries {
+        let files = entry.linux.iter().chain(entry.efi.iter()).chain(entry.initrd.iter());
+        let mut missing = vec![];
+        for file in files {
+            let resolved = resolve(boot_root, file);
+            if !resolved.exists() {
+                missing.push(file.clone());
+            }
+            referenced.push(resolved);
+        }
+        if !missing.is_empty() {
+            let owned = path
+                .file_name()
+                .is_some_and(|n| prefixes.iter().any(|p| n.to_string_lossy().starts_with(p.as_str())));
+            orphans.push(Orphan::DanglingEntry {
+                path: path.clone(),
+                missing,
+                owned,
+            });
+        }
+    }
+
+    for (namespace, dir) in kernel_dirs(boot_root) {
+        let owned = namespaces.iter().any(|n| n.eq_ignore_ascii_case(&namespace));
+        if !owned && !is_kernel_dir(&dir) {
+            continue;
+        }
+        if !referenced.iter().any(|r| r.starts_with(&dir)) {
+            orphans.push(Orphan::UnreferencedDir { path: dir, owned });
+        }
+    }
+
+    AuditReport { orphans }
+}
+
+/// Resolve an entry path (i.e. `/EFI/aerynos/6.8.9/vmlinuz`) beneath `boot_root`
+pub(crate) fn resolve(boot_root: &Path, file: &str) -> PathBuf {
+    file.split('/')
+        .filter(|c| !c.is_empty())
+        .fold(boot_root.to_path_buf(), |path, component| {
+            path.join_insensitive(component)
+        })
+}
+
+/// All `EFI/$namespace/$dir` directories, alongside their namespace
+fn kernel_dirs(boot_root: &Path) -> Vec<(String, PathBuf)> {
+    let efi = boot_root.to_path_buf().join_insensitive("EFI");
+    let Ok(namespaces) = fs::read_dir(&efi) else {
+        return vec![];
+    };
+
+    let mut dirs = namespaces
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|n| !RESERVED_DIRS.contains(&n.to_ascii_lowercase().as_str()))
+        .flat_map(|namespace| {
+            let Ok(children) = fs::read_dir(efi.join(&namespace)) else {
+                return vec![];
+            };
+            children
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .map(|p| (namespace.clone(), p))
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs
+}
+
+/// Whether the directory contains a kernel image, to avoid flagging other vendors' files
+fn is_kernel_dir(dir: &Path) -> bool {
+    let Ok(children) = fs::read_dir(dir) else {
+        return false;
+    };
+    children.filter_map(|e| e.ok()).any(|e| {
+        let name = e.file_name().to_string_lossy().to_ascii_lowercase();
+        KERNEL_PREFIXES.iter().any(|p| name.starts_with(p))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::{Orphan, scan};
+    use crate::bootloader::systemd_boot::entry_file::LoaderEntry;
+
+    #[test]
+    fn test_scan() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let boot = root.path();
+        for dir in [
+            "EFI/aerynos/6.8.1",
+            "EFI/aerynos/6.8.2",
+            "EFI/fedora/6.9.0",
+            "EFI/Microsoft/Boot",
+        ] {
+            fs::create_dir_all(boot.join(dir)).unwrap();
+        }
+        fs::write(boot.join("EFI/aerynos/6.8.1/vmlinuz"), "").unwrap();
+        fs::write(boot.join("EFI/fedora/6.9.0/linux"), "").unwrap();
+        fs::write(boot.join("EFI/Microsoft/Boot/bootmgfw.efi"), "").unwrap();
+
+        let entries = [
+            (
+                boot.join("loader/entries/aerynos-6.8.1.conf"),
+                LoaderEntry::parse("linux /EFI/aerynos/6.8.1/vmlinuz\ninitrd /EFI/aerynos/6.8.1/initrd"),
+            ),
+            (
+                boot.join("loader/entries/aerynos-6.8.0.conf"),
+                LoaderEntry::parse("linux /efi/aerynos/6.8.0/vmlinuz"),
+            ),
+        ];
+        let report = scan(boot, &entries, &["aerynos".into()], &["aerynos".into()]);
+        assert_eq!(
+            report.orphans,
+            vec![
+                Orphan::DanglingEntry {
+                    path: boot.join("loader/entries/aerynos-6.8.1.conf"),
+                    missing: vec!["/EFI/aerynos/6.8.1/initrd".into()],
+                    owned: true,
+                },
+                Orphan::DanglingEntry {
+                    path: boot.join("loader/entries/aerynos-6.8.0.conf"),
+                    missing: vec!["/efi/aerynos/6.8.0/vmlinuz".into()],
+                    owned: true,
+                },
+                Orphan::UnreferencedDir {
+                    path: boot.join("EFI/aerynos/6.8.2"),
+                    owned: true,
+                },
+                Orphan::UnreferencedDir {
+                    path: boot.join("EFI/fedora/6.9.0"),
+                    owned: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Boot success and sync state tracking
+//!
+//! Successfully booted kernels are recorded in a small JSON file on `$BOOT`
+//! so that kernel removal can avoid deleting the last known-good kernel.
+//! The same file records the schema that produced the `$BOOT` layout, so that
+//! a later sync (or an audit) can tell when the OS namespace has changed.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt as _;
+
+use crate::{
+    Error, IoSnafu, JsonSnafu, Schema,
+    file_utils::{PathExt, Writer},
+};
+
+/// A single successful boot of a kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootRecord {
+    /// Time of the most recent successful boot (seconds since the UNIX epoch)
+    pub last_booted: u64,
+}
+
+/// The layout recorded by a sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// Kind of schema, i.e. `legacy`, `blsforme` or `os-info`
+    pub schema: String,
+
+    /// Namespace of the OS files on `$BOOT`
+    pub namespace: String,
+
+    /// Machine ID of the sysroot, when initialised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+
+    /// Time of the sync that first produced this layout (seconds since the UNIX epoch)
+    pub last_sync: u64,
+}
+
+impl SyncState {
+    /// State for a sync of the given schema at the given time
+    pub(crate) fn new(schema: &Schema, machine_id: Option<String>, time: SystemTime) -> Self {
+        Self {
+            schema: schema.kind().to_string(),
+            namespace: schema.os_namespace(),
+            machine_id,
+            last_sync: time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+        }
+    }
+
+    /// Whether the recorded layout belongs to a different namespace than the schema's
+    pub fn is_migration(&self, schema: &Schema) -> bool {
+        self.namespace != schema.os_namespace()
+    }
+}
+
+/// Persisted boot success and sync state
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootState {
+    /// Successful boots, keyed by kernel version (`uname -r`)
+    #[serde(default)]
+    pub kernels: BTreeMap<String, BootRecord>,
+
+    /// Layout of `$BOOT` as of the most recent sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SyncState>,
+}
+
+impl BootState {
+    /// Path to the state file within the given `$BOOT`
+    pub(crate) fn path(boot_root: &Path) -> PathBuf {
+        boot_root
+            .to_path_buf()
+            .join_insensitive("loader")
+            .join_insensitive("blsforme.json")
+    }
+
+    /// Load the state from `$BOOT`, returning an empty state if missing or corrupt
+    pub(crate) fn load(boot_root: &Path) -> Result<Self, Error> {
+        let path = Self::path(boot_root);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path).context(IoSnafu)?;
+        match serde_json::from_str(&text) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                log::warn!("Ignoring corrupt boot state {}: {e}", path.display());
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Atomically write the state back to `$BOOT`
+    pub(crate) fn save(&self, boot_root: &Path, writer: &Writer) -> Result<(), Error> {
+        let contents = serde_json::to_string_pretty(self).context(JsonSnafu)?;
+        writer.write(contents, Self::path(boot_root))?;
+        Ok(())
+    }
+
+    /// Record a successful boot of the given kernel version, as of now
+    pub fn record(&mut self, version: &str) {
+        let last_booted = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.kernels.insert(version.to_string(), BootRecord { last_booted });
+    }
+
+    /// Record the layout of a sync, returning whether it differs from the one recorded
+    ///
+    /// Only the schema, namespace and machine ID are compared, so that an unchanged
+    /// layout leaves the state (and `$BOOT`) untouched.
+    pub(crate) fn record_sync(&mut self, sync: SyncState) -> bool {
+        let unchanged = self.sync.as_ref().is_some_and(|s| {
+            s.schema == sync.schema && s.namespace == sync.namespace && s.machine_id == sync.machine_id
+        });
+        if !unchanged {
+            self.sync = Some(sync);
+        }
+        !unchanged
+    }
+
+    /// Forget the given kernel version
+    pub fn forget(&mut self, version: &str) -> bool {
+        self.kernels.remove(version).is_some()
+    }
+
+    /// Whether the kernel version has ever booted successfully
+    pub fn is_known_good(&self, version: &str) -> bool {
+        self.kernels.contains_key(version)
+    }
+
+    /// The most recently booted kernel version, if any
+    pub fn last_known_good(&self) -> Option<&str> {
+        self.kernels
+            .iter()
+            .max_by_key(|(_, r)| r.last_booted)
+            .map(|(v, _)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{BootState, SyncState};
+    use crate::{
+        Schema,
+        file_utils::{WriteMode, Writer},
+        os_release::OsRelease,
+    };
+
+    #[test]
+    fn test_sync_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let boot_root = dir.path();
+        assert_eq!(BootState::load(boot_root).unwrap().sync, None);
+
+        let schema = |text: &str| Schema::from_os_release(OsRelease::from_str(text).unwrap());
+        let solus = schema("NAME=Solus\nID=solus\nVERSION=4.5");
+        let then = UNIX_EPOCH + Duration::from_secs(1700000000);
+        let mut state = BootState::default();
+        state.record("6.8.2-25.desktop");
+        assert!(state.record_sync(SyncState::new(&solus, None, then)));
+        std::fs::create_dir(boot_root.join("loader")).unwrap();
+        state.save(boot_root, &Writer::new(WriteMode::default())).unwrap();
+
+        let mut state = BootState::load(boot_root).unwrap();
+        assert!(state.is_known_good("6.8.2-25.desktop"));
+        let sync = state.sync.clone().unwrap();
+        assert_eq!(sync.schema, "legacy");
+        assert_eq!(sync.namespace, "com.solus-project");
+        assert_eq!(sync.last_sync, 1700000000);
+        assert!(!sync.is_migration(&solus));
+        assert!(sync.is_migration(&schema("NAME=Solus\nID=solus\nVERSION=2025.01")));
+
+        // A later sync of the same layout changes nothing, not even the time
+        let later = then + Duration::from_secs(60);
+        assert!(!state.record_sync(SyncState::new(&solus, None, later)));
+        assert_eq!(state.sync.as_ref().unwrap().last_sync, 1700000000);
+        assert!(state.record_sync(SyncState::new(&solus, Some("abc".into()), later)));
+        assert_eq!(state.sync.unwrap().last_sync, 1700000060);
+    }
+}
@@ -5,25 +5,35 @@
 //! Boot environment tracking (ESP vs XBOOTLDR, etc)
 
 use std::{
-    collections::HashMap,
+    fmt,
+    io::Read as _,
     path::{Path, PathBuf},
 };
 
 use fs_err as fs;
-use gpt::{GptConfig, partition_types};
+use gpt::partition_types;
+use nix::{
+    sys::statvfs::statvfs,
+    unistd::{AccessFlags, access},
+};
+use serde::Serialize;
 use snafu::ResultExt as _;
-use topology::disk::probe::Probe;
+use topology::disk::{mounts::MountOption, probe::Probe};
 
 use crate::{
-    Configuration, Error, GptSnafu, IoSnafu, Root,
+    Configuration, Error, IoSnafu, NixSnafu,
     bootloader::systemd_boot::interface::{BootLoaderInterface, VariableName},
 };
 
+/// Vendor GUID of the UEFI global variables, i.e. `SecureBoot`
+const EFI_GLOBAL_VARIABLE: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
 /// Type of firmware detected
 ///
 /// By knowing the available firmware (effectively: is `efivarfs` mounted)
 /// we can detect full availability of UEFI features or legacy fallback.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Firmware {
     /// UEFI
     Uefi,
@@ -32,6 +42,102 @@ pub enum Firmware {
     Bios,
 }
 
+impl fmt::Display for Firmware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Firmware::Uefi => f.write_str("UEFI"),
+            Firmware::Bios => f.write_str("BIOS"),
+        }
+    }
+}
+
+impl Firmware {
+    /// Detect the host firmware via `sysfs`
+    pub(crate) fn detect(config: &Configuration) -> Self {
+        if config.vfs.join("sys").join("firmware").join("efi").exists() {
+            Firmware::Uefi
+        } else {
+            Firmware::Bios
+        }
+    }
+}
+
+/// Access to EFI variables via `efivarfs`
+///
+/// UEFI systems may still lack access, i.e. in containers and chroots, or with
+/// `efivarfs` mounted read-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VarAccess {
+    /// Variables can be read and written
+    ReadWrite,
+
+    /// Variables can only be read
+    ReadOnly,
+
+    /// `efivarfs` isn't mounted, or the firmware isn't UEFI
+    Unavailable,
+}
+
+impl VarAccess {
+    /// Probe the mount state and writability of `/sys/firmware/efi/efivars`
+    fn detect(probe: &Probe, firmware: Firmware, config: &Configuration) -> Self {
+        if firmware != Firmware::Uefi {
+            return VarAccess::Unavailable;
+        }
+        let efivars = config.vfs.join("sys").join("firmware").join("efi").join("efivars");
+        let Ok(efivars) = fs::canonicalize(&efivars) else {
+            return VarAccess::Unavailable;
+        };
+        let Some(mount) = probe
+            .mounts
+            .get_by_mountpoint(&efivars)
+            .filter(|m| m.filesystem == "efivarfs")
+        else {
+            return VarAccess::Unavailable;
+        };
+
+        let read_only = mount.options().any(|o| matches!(o, MountOption::Flag("ro")))
+            || access(&efivars, AccessFlags::W_OK).is_err();
+        if read_only {
+            VarAccess::ReadOnly
+        } else {
+            VarAccess::ReadWrite
+        }
+    }
+}
+
+/// Identifies the `$BOOT` partition for BIOS bootloaders
+#[derive(Debug, Clone)]
+pub struct BootDevice {
+    /// Disk holding `$BOOT`, where the bootloader is embedded
+    pub disk: PathBuf,
+
+    /// Filesystem UUID of `$BOOT`
+    pub uuid: Option<String>,
+
+    /// GRUB device name (i.e. `hd0,gpt2`), assuming the boot disk is the first BIOS disk
+    pub grub_device: Option<String>,
+}
+
+impl BootDevice {
+    fn new(probe: &Probe, disk: &Path, partition: &Path) -> Self {
+        let label = if probe.list_partitions(disk).is_ok() {
+            "gpt"
+        } else {
+            "msdos"
+        };
+        Self {
+            disk: disk.to_path_buf(),
+            uuid: probe
+                .get_device_superblock(partition)
+                .ok()
+                .and_then(|sb| sb.uuid().ok()),
+            grub_device: probe.get_partition_number(partition).map(|n| format!("hd0,{label}{n}")),
+        }
+    }
+}
+
 /// Helps access the boot environment, ie `$BOOT` and specific ESP
 #[derive(Debug)]
 pub struct BootEnvironment {
@@ -44,34 +150,48 @@ pub struct BootEnvironment {
     /// Firmware in use
     pub firmware: Firmware,
 
+    /// Access to EFI variables
+    pub efi_vars: VarAccess,
+
+    /// The legacy BIOS `$BOOT` partition, on native systems
+    pub boot_device: Option<BootDevice>,
+
     pub(crate) esp_mountpoint: Option<PathBuf>,
     pub(crate) xboot_mountpoint: Option<PathBuf>,
+
+    /// Total capacity of the ESP and XBOOTLDR, in bytes
+    pub(crate) esp_size: Option<u64>,
+    pub(crate) xbootldr_size: Option<u64>,
+
+    /// Model names of the disks holding the ESP and XBOOTLDR
+    pub(crate) esp_model: Option<String>,
+    pub(crate) xbootldr_model: Option<String>,
+
+    /// Whether the firmware booted with Secure Boot enforced
+    secure_boot: bool,
 }
 
 impl BootEnvironment {
     /// Return a new BootEnvironment for the given root
     pub fn new(probe: &Probe, disk_parent: Option<PathBuf>, config: &Configuration) -> Result<Self, Error> {
-        let firmware = if config.vfs.join("sys").join("firmware").join("efi").exists() {
-            Firmware::Uefi
-        } else {
-            Firmware::Bios
-        };
+        let firmware = Firmware::detect(config);
+        if config.has_boot_paths() {
+            return Ok(Self::from_boot_paths(firmware, config));
+        }
 
-        let mounts = probe
-            .mounts
-            .iter()
-            .filter_map(|m| Some((fs::canonicalize(m.device).ok()?, m)))
-            .collect::<HashMap<_, _>>();
-
-        let esp_from_bls = match config.root {
-            // For image mode, don't query BLS.
-            Root::Image(_) => None,
-            // Otherwise, query BLS first.
-            _ => Self::determine_esp_by_bls(&firmware, config).ok(),
+        let efi_vars = VarAccess::detect(probe, firmware, config);
+        log::trace!("EFI variable access: {efi_vars:?}");
+        let secure_boot = efi_vars != VarAccess::Unavailable && Self::read_secure_boot(config);
+
+        // For image mode, don't query BLS. Otherwise, query BLS first.
+        let esp_from_bls = if config.root.is_image() {
+            None
+        } else {
+            Self::determine_esp_by_bls(efi_vars, config).ok()
         };
 
         // If in image mode or if the BLS query failed, use raw discovery of the GPT device.
-        let esp = esp_from_bls.or_else(|| Self::determine_esp_by_gpt(&disk_parent?, config).ok());
+        let esp = esp_from_bls.or_else(|| Self::determine_esp_by_gpt(probe, disk_parent.as_ref()?, config).ok());
 
         // Make sure our config is sane!
         if firmware == Firmware::Uefi && esp.is_none() {
@@ -80,16 +200,55 @@ impl BootEnvironment {
         }
 
         let Some(esp_path) = &esp else {
+            // Legacy BIOS: find `$BOOT` by other means
+            let xbootldr = match (&firmware, &disk_parent) {
+                (Firmware::Bios, Some(disk_parent)) => {
+                    Self::determine_xbootldr_by_gpt(probe, disk_parent, config)
+                        .ok()
+                        .or_else(|| {
+                            // Only accept an already mounted boot partition, it may not be vfat
+                            Self::determine_boot_by_mbr(disk_parent)
+                                .ok()
+                                .filter(|p| probe.mounts.get_by_device(p).is_some())
+                        })
+                }
+                _ => None,
+            };
+            if let Some(path) = &xbootldr {
+                log::info!("Legacy boot partition: {}", path.display());
+            }
+            let xboot_mountpoint = xbootldr
+                .as_ref()
+                .and_then(|e| fs::canonicalize(probe.mounts.get_by_device(e)?.mountpoint).ok());
+
+            // Never touch the host's disks in image mode
+            let boot_device = match (&xbootldr, &disk_parent) {
+                (Some(partition), Some(disk)) if config.root.is_native() => {
+                    Some(BootDevice::new(probe, disk, partition))
+                }
+                _ => None,
+            };
+
             return Ok(Self {
-                xbootldr: None,
+                boot_device,
+                xbootldr_size: xbootldr.as_ref().and_then(|p| probe.get_device_size(p).ok()),
+                xbootldr_model: xbootldr.as_ref().and_then(|p| probe.get_device_model(p)),
+                xbootldr,
                 esp,
                 firmware,
-                xboot_mountpoint: None,
+                efi_vars,
+                xboot_mountpoint,
                 esp_mountpoint: None,
+                esp_size: None,
+                esp_model: None,
+                secure_boot,
             });
         };
 
-        let esp_mountpoint = mounts.get(esp_path).and_then(|m| fs::canonicalize(m.mountpoint).ok());
+        let esp_mountpoint = probe
+            .mounts
+            .get_by_device(esp_path)
+            .and_then(|m| fs::canonicalize(m.mountpoint).ok());
 
         // Report ESP and check for XBOOTLDR
         log::info!("EFI System Partition: {}", esp_path.display());
@@ -101,21 +260,69 @@ impl BootEnvironment {
 
         let xboot_mountpoint = xbootldr
             .as_ref()
-            .and_then(|e| fs::canonicalize(mounts.get(e)?.mountpoint).ok());
+            .and_then(|e| fs::canonicalize(probe.mounts.get_by_device(e)?.mountpoint).ok());
 
         Ok(Self {
+            boot_device: None,
+            esp_size: probe.get_device_size(esp_path).ok(),
+            xbootldr_size: xbootldr.as_ref().and_then(|p| probe.get_device_size(p).ok()),
+            esp_model: probe.get_device_model(esp_path),
+            secure_boot,
+            xbootldr_model: xbootldr.as_ref().and_then(|p| probe.get_device_model(p)),
             xbootldr,
             esp,
             firmware,
+            efi_vars,
             xboot_mountpoint,
             esp_mountpoint,
         })
     }
 
+    /// Use the configured boot directories as-is, without any devices or EFI variables
+    ///
+    /// An ESP implies a UEFI target, regardless of the host firmware. A boot directory
+    /// layout is applied later, when selecting the mountpoints.
+    pub(crate) fn from_boot_paths(firmware: Firmware, config: &Configuration) -> Self {
+        if let Some(path) = &config.esp_path {
+            log::info!("Using ESP directory: {}", path.display());
+        }
+        Self {
+            xbootldr: None,
+            esp: None,
+            firmware: if config.esp_path.is_some() {
+                Firmware::Uefi
+            } else {
+                firmware
+            },
+            efi_vars: VarAccess::Unavailable,
+            boot_device: None,
+            esp_mountpoint: config.esp_path.clone(),
+            xboot_mountpoint: None,
+            esp_size: None,
+            xbootldr_size: None,
+            esp_model: None,
+            xbootldr_model: None,
+            secure_boot: false,
+        }
+    }
+
+    /// Read the `SecureBoot` variable: 4 bytes of attributes, then `1` when enabled
+    fn read_secure_boot(config: &Configuration) -> bool {
+        let var = config
+            .vfs
+            .join("sys")
+            .join("firmware")
+            .join("efi")
+            .join("efivars")
+            .join(format!("SecureBoot-{EFI_GLOBAL_VARIABLE}"));
+        fs::read(var).is_ok_and(|data| data.get(4) == Some(&1))
+    }
+
     /// If UEFI we can ask BootLoaderProtocol for help to find out the ESP device.
-    fn determine_esp_by_bls(firmware: &Firmware, config: &Configuration) -> Result<PathBuf, Error> {
-        // UEFI only tyvm
-        if let Firmware::Bios = *firmware {
+    fn determine_esp_by_bls(efi_vars: VarAccess, config: &Configuration) -> Result<PathBuf, Error> {
+        // UEFI only tyvm, and only with access to the variables
+        if efi_vars == VarAccess::Unavailable {
+            log::debug!("EFI variables unavailable, discovering the ESP via GPT");
             return Err(Error::Unsupported);
         }
 
@@ -126,43 +333,73 @@ impl BootEnvironment {
     }
 
     /// Determine ESP by searching relative GPT
-    fn determine_esp_by_gpt(disk_parent: &Path, config: &Configuration) -> Result<PathBuf, Error> {
+    fn determine_esp_by_gpt(probe: &Probe, disk_parent: &Path, config: &Configuration) -> Result<PathBuf, Error> {
         log::trace!("Finding ESP on device: {disk_parent:?}");
-        let table = GptConfig::new().writable(false).open(disk_parent).context(GptSnafu)?;
-        let (_, esp) = table
-            .partitions()
-            .iter()
-            .find(|(_, p)| p.part_type_guid == partition_types::EFI)
-            .ok_or(Error::NoEsp)?;
-        let path = config
-            .vfs
-            .join("dev")
-            .join("disk")
-            .join("by-partuuid")
-            .join(esp.part_guid.as_hyphenated().to_string());
-        fs::canonicalize(path).context(IoSnafu)
+        Self::find_partition_by_type(probe, disk_parent, &partition_types::EFI, config)?.ok_or(Error::NoEsp)
     }
 
     /// Discover an XBOOTLDR partition *relative* to wherever the ESP is
     fn discover_xbootldr(probe: &Probe, esp: &PathBuf, config: &Configuration) -> Result<PathBuf, Error> {
         let parent = probe.get_device_parent(esp).ok_or(Error::Unsupported)?;
+        Self::determine_xbootldr_by_gpt(probe, &parent, config)
+    }
+
+    /// Determine XBOOTLDR by searching the GPT of the given disk
+    fn determine_xbootldr_by_gpt(probe: &Probe, parent: &Path, config: &Configuration) -> Result<PathBuf, Error> {
         log::trace!("Finding XBOOTLDR on device: {parent:?}");
-        let table = GptConfig::new().writable(false).open(parent).context(GptSnafu)?;
-        let (_, esp) = table
-            .partitions()
-            .iter()
-            .find(|(_, p)| p.part_type_guid == partition_types::FREEDESK_BOOT)
-            .ok_or(Error::NoXbootldr)?;
+        Self::find_partition_by_type(probe, parent, &partition_types::FREEDESK_BOOT, config)?.ok_or(Error::NoXbootldr)
+    }
+
+    /// Resolve the device of the first partition on the disk with the given type
+    fn find_partition_by_type(
+        probe: &Probe,
+        disk: &Path,
+        kind: &partition_types::Type,
+        config: &Configuration,
+    ) -> Result<Option<PathBuf>, Error> {
+        let kind = kind.guid.hyphenated().to_string();
+        let Some(partition) = probe
+            .list_partitions(disk)?
+            .into_iter()
+            .find(|p| p.part_type_guid == kind)
+        else {
+            return Ok(None);
+        };
         let path = config
             .vfs
             .join("dev")
             .join("disk")
             .join("by-partuuid")
-            .join(esp.part_guid.as_hyphenated().to_string());
-        fs::canonicalize(path).context(IoSnafu)
+            .join(partition.part_guid);
+        Ok(Some(fs::canonicalize(path).context(IoSnafu)?))
+    }
+
+    /// Determine the legacy boot partition from the active flag in the MBR
+    fn determine_boot_by_mbr(disk_parent: &Path) -> Result<PathBuf, Error> {
+        let mut mbr = [0u8; 512];
+        fs::File::open(disk_parent)
+            .and_then(|mut f| f.read_exact(&mut mbr))
+            .context(IoSnafu)?;
+        if mbr[510..] != [0x55, 0xAA] {
+            return Err(Error::Unsupported);
+        }
+
+        let index = mbr[446..510]
+            .chunks_exact(16)
+            .position(|entry| entry[0] == 0x80)
+            .ok_or(Error::Unsupported)?;
+
+        // `/dev/sda` -> `/dev/sda1`, `/dev/nvme0n1` -> `/dev/nvme0n1p1`
+        let disk = disk_parent.to_string_lossy();
+        let separator = if disk.ends_with(|c: char| c.is_ascii_digit()) {
+            "p"
+        } else {
+            ""
+        };
+        Ok(PathBuf::from(format!("{disk}{separator}{}", index + 1)))
     }
 
-    /// The so-called `$BOOT` partition (UEFI only at present)
+    /// The so-called `$BOOT` partition
     pub fn boot_partition(&self) -> Option<&PathBuf> {
         if let Some(part) = self.xbootldr.as_ref() {
             Some(part)
@@ -176,8 +413,168 @@ impl BootEnvironment {
         self.esp.as_ref()
     }
 
-    /// Return the XBOOTLDR partition (UEFI only)
+    /// Return the XBOOTLDR partition, or legacy boot partition on BIOS
     pub fn xbootldr(&self) -> Option<&PathBuf> {
         self.xbootldr.as_ref()
     }
+
+    /// Whether Secure Boot is enabled, false on BIOS or without access to EFI variables
+    pub fn secure_boot_enabled(&self) -> bool {
+        self.secure_boot
+    }
+
+    /// Free space on `$BOOT` (XBOOTLDR when mounted, otherwise the ESP), in bytes
+    pub fn available_space(&self) -> Result<u64, Error> {
+        Self::available_space_at(self.boot_mountpoint()?).context(NixSnafu)
+    }
+
+    /// Total capacity of `$BOOT` (XBOOTLDR when mounted, otherwise the ESP), in bytes
+    pub fn total_space(&self) -> Result<u64, Error> {
+        Self::total_space_at(self.boot_mountpoint()?).context(NixSnafu)
+    }
+
+    /// Space available to unprivileged users on the boot partition mounted at `mountpoint`
+    ///
+    /// For partitions mounted since probing, i.e. by [`crate::Manager::mount_partitions`].
+    pub(crate) fn available_space_at(mountpoint: &Path) -> Result<u64, nix::errno::Errno> {
+        let stat = statvfs(mountpoint)?;
+        Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+    }
+
+    /// Total capacity of the boot partition mounted at `mountpoint`, in bytes
+    pub(crate) fn total_space_at(mountpoint: &Path) -> Result<u64, nix::errno::Errno> {
+        let stat = statvfs(mountpoint)?;
+        Ok(stat.blocks() as u64 * stat.fragment_size() as u64)
+    }
+
+    /// Where `$BOOT` was mounted when the environment was probed
+    fn boot_mountpoint(&self) -> Result<&Path, Error> {
+        self.xboot_mountpoint
+            .as_deref()
+            .or(self.esp_mountpoint.as_deref())
+            .ok_or(Error::NoEsp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+    use topology::disk::{Builder, testing::MockBuilder};
+
+    use super::{BootEnvironment, EFI_GLOBAL_VARIABLE, Firmware, VarAccess};
+    use crate::{Configuration, Error, Root};
+
+    #[test]
+    fn test_boot_paths() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let esp = root.path().join("efi");
+        fs::create_dir(&esp).unwrap();
+        let config = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .esp_path(Some(esp.clone()))
+            .build()
+            .unwrap();
+
+        // No disk to discover partitions on, but none are needed
+        let probe = Builder::default().build().unwrap();
+        let env = BootEnvironment::new(&probe, None, &config).unwrap();
+        assert_eq!(env.firmware, Firmware::Uefi);
+        assert_eq!(env.efi_vars, VarAccess::Unavailable);
+        assert_eq!(env.esp(), None);
+        assert_eq!(env.esp_mountpoint, Some(esp));
+        assert!(env.available_space().is_ok());
+
+        let missing = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .esp_path(Some(root.path().join("boot")))
+            .build();
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_secure_boot() {
+        let vfs = tempfile::tempdir().expect("Failed to create tempdir");
+        let config = Configuration::builder().vfs(vfs.path()).build().unwrap();
+        assert!(!BootEnvironment::read_secure_boot(&config));
+
+        let efivars = vfs.path().join("sys/firmware/efi/efivars");
+        fs::create_dir_all(&efivars).unwrap();
+        let var = efivars.join(format!("SecureBoot-{EFI_GLOBAL_VARIABLE}"));
+        fs::write(&var, [0x06, 0, 0, 0, 1]).unwrap();
+        assert!(BootEnvironment::read_secure_boot(&config));
+        fs::write(&var, [0x06, 0, 0, 0, 0]).unwrap();
+        assert!(!BootEnvironment::read_secure_boot(&config));
+    }
+
+    #[test]
+    fn test_var_access() {
+        let vfs = tempfile::tempdir().expect("Failed to create tempdir");
+        let config = Configuration::builder().vfs(vfs.path()).build().unwrap();
+        let efivars = vfs.path().join("sys/firmware/efi/efivars");
+        fs::create_dir_all(&efivars).unwrap();
+        let efivars = fs::canonicalize(efivars).unwrap();
+        let mock = MockBuilder::new(vfs.path());
+
+        let probe = mock.clone().build().unwrap();
+        assert_eq!(
+            VarAccess::detect(&probe, Firmware::Bios, &config),
+            VarAccess::Unavailable
+        );
+        assert_eq!(
+            VarAccess::detect(&probe, Firmware::Uefi, &config),
+            VarAccess::Unavailable
+        );
+
+        let probe = mock
+            .clone()
+            .with_mount("efivarfs", &efivars, "efivarfs")
+            .build()
+            .unwrap();
+        assert_eq!(VarAccess::detect(&probe, Firmware::Uefi, &config), VarAccess::ReadWrite);
+        assert_eq!(
+            VarAccess::detect(&probe, Firmware::Bios, &config),
+            VarAccess::Unavailable
+        );
+
+        let probe = mock
+            .clone()
+            .with_mount_options("efivarfs", &efivars, "efivarfs", "ro,nosuid,nodev")
+            .build()
+            .unwrap();
+        assert_eq!(VarAccess::detect(&probe, Firmware::Uefi, &config), VarAccess::ReadOnly);
+
+        // Only efivarfs itself will do
+        let probe = mock.with_mount("tmpfs", &efivars, "tmpfs").build().unwrap();
+        assert_eq!(
+            VarAccess::detect(&probe, Firmware::Uefi, &config),
+            VarAccess::Unavailable
+        );
+    }
+
+    #[test]
+    fn test_space() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let esp = root.path().join("efi");
+        fs::create_dir(&esp).unwrap();
+        let config = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .esp_path(Some(esp.clone()))
+            .build()
+            .unwrap();
+
+        let env = BootEnvironment::from_boot_paths(Firmware::Uefi, &config);
+        let (available, total) = (env.available_space().unwrap(), env.total_space().unwrap());
+        assert!(available <= total);
+        assert_eq!(total, BootEnvironment::total_space_at(&esp).unwrap());
+        assert!(BootEnvironment::available_space_at(&root.path().join("missing")).is_err());
+
+        // Nothing to query without any boot partition
+        let config = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .build()
+            .unwrap();
+        let env = BootEnvironment::from_boot_paths(Firmware::Bios, &config);
+        assert!(matches!(env.available_space(), Err(Error::NoEsp)));
+        assert!(matches!(env.total_space(), Err(Error::NoEsp)));
+    }
 }
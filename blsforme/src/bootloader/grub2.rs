@@ -0,0 +1,203 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! GRUB2 management for legacy BIOS systems
+//!
+//! Kernels and BLS entries are installed to `$BOOT` exactly as for systemd-boot,
+//! with a `grub.cfg` generated from the same entries for GRUB builds lacking
+//! `blscfg` support. GRUB itself (`core.img`) is only installed when missing,
+//! leaving an existing installation to be updated through its configuration.
+
+use std::path::PathBuf;
+
+use snafu::ResultExt as _;
+
+use crate::{
+    BootDevice, Entry,
+    bootloader::{IoSnafu, SyncOptions, Timeout, WriteSnafu, systemd_boot},
+    file_utils::{PathExt, Writer},
+};
+
+/// GRUB2 bootloader, layered over the BLS entries of [`systemd_boot::Loader`]
+#[derive(Debug)]
+pub struct Loader<'a, 'b> {
+    /// Manages kernels and BLS entries on `$BOOT`
+    pub(super) bls: systemd_boot::Loader<'a, 'b>,
+
+    /// Identifies `$BOOT`, when known
+    device: Option<&'a BootDevice>,
+
+    writer: &'a Writer,
+}
+
+impl<'a, 'b> Loader<'a, 'b> {
+    pub(super) fn new(bls: systemd_boot::Loader<'a, 'b>, device: Option<&'a BootDevice>, writer: &'a Writer) -> Self {
+        Self { bls, device, writer }
+    }
+
+    /// GRUB directory on `$BOOT`, preferring an existing `grub2` (i.e. Fedora)
+    fn grub_dir(&self) -> PathBuf {
+        let boot_root = self.bls.boot_root().to_path_buf();
+        let grub2 = boot_root.join_insensitive("grub2");
+        if grub2.exists() {
+            grub2
+        } else {
+            boot_root.join_insensitive("grub")
+        }
+    }
+
+    /// Install `core.img` to the boot disk, unless GRUB is already installed
+    pub(super) fn sync(&self, options: &SyncOptions) -> Result<(), super::Error> {
+        self.bls.sync_timeout(options)?;
+
+        let Some(device) = self.device else {
+            log::warn!("Unable to determine the boot disk, not installing GRUB");
+            return Ok(());
+        };
+
+        let grub_dir = self.grub_dir();
+        let core = grub_dir.join_insensitive("i386-pc").join_insensitive("core.img");
+        if core.exists() && !options.force {
+            log::debug!("GRUB already installed: {}", core.display());
+            return Ok(());
+        }
+
+        let program = if grub_dir.ends_with("grub2") {
+            "grub2-install"
+        } else {
+            "grub-install"
+        };
+        let args = [
+            "--target=i386-pc".to_string(),
+            format!("--boot-directory={}", self.bls.boot_root().display()),
+            device.disk.to_string_lossy().to_string(),
+        ];
+        log::info!("Installing GRUB to {}", device.disk.display());
+        self.writer.exec(program, &args).context(IoSnafu)
+    }
+
+    /// Install the entries as BLS entries, then generate `grub.cfg` from them
+    pub(super) fn sync_entries(
+        &self,
+        cmdline: impl Iterator<Item = &'a str>,
+        entries: &[&Entry],
+    ) -> Result<(), super::Error> {
+        let cmdline = cmdline.collect::<Vec<_>>();
+        self.bls.sync_entries(cmdline.iter().copied(), entries)?;
+
+        // Newest first, UKIs can't be booted by GRUB on BIOS
+        let mut entries = entries.iter().filter(|e| !e.is_uki()).collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.kernel.cmp(&a.kernel));
+
+        let default = self.bls.default_entry()?;
+        let mut menu = String::new();
+        let mut default_index = 0;
+        for (index, entry) in entries.iter().enumerate() {
+            let id = self.bls.entry_id(entry);
+            if default.as_deref() == Some(&format!("{id}.conf")) {
+                default_index = index;
+            }
+            let (linux, initrds) = self.bls.boot_paths(entry)?;
+            menu.push_str(&menu_entry(
+                &self.bls.entry_title(entry),
+                &id,
+                self.device,
+                &linux,
+                &initrds,
+                &entry.effective_cmdline(&cmdline),
+            ));
+        }
+
+        let config = format!("{}{menu}", header(default_index, self.bls.timeout()?));
+        self.writer
+            .write(config, self.grub_dir().join_insensitive("grub.cfg"))
+            .context(WriteSnafu)?;
+        Ok(())
+    }
+}
+
+/// Quote a string for `grub.cfg`
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Global settings, mapping the `loader.conf` timeout onto GRUB's
+fn header(default: usize, timeout: Option<Timeout>) -> String {
+    let timeout = match timeout {
+        Some(Timeout::Seconds(seconds)) => format!("set timeout={seconds}"),
+        Some(Timeout::MenuForce) => "set timeout=-1".to_string(),
+        Some(Timeout::MenuHidden) => "set timeout_style=hidden\nset timeout=0".to_string(),
+        Some(Timeout::MenuDisabled) => "set timeout=0".to_string(),
+        None => "set timeout=5".to_string(),
+    };
+    format!("# Generated by blsforme, do not edit\nset default={default}\n{timeout}\n")
+}
+
+/// A `menuentry` block, locating `$BOOT` by filesystem UUID
+fn menu_entry(
+    title: &str,
+    id: &str,
+    device: Option<&BootDevice>,
+    linux: &str,
+    initrds: &[String],
+    cmdline: &str,
+) -> String {
+    let mut lines = vec![];
+    if let Some(grub_device) = device.and_then(|d| d.grub_device.as_deref()) {
+        lines.push(format!("set root={}", quote(grub_device)));
+    }
+    if let Some(uuid) = device.and_then(|d| d.uuid.as_deref()) {
+        lines.push(format!("search --no-floppy --fs-uuid --set=root {uuid}"));
+    }
+    lines.push(format!("linux {linux} {cmdline}"));
+    if !initrds.is_empty() {
+        lines.push(format!("initrd {}", initrds.join(" ")));
+    }
+    let body = lines.iter().map(|l| format!("    {l}\n")).collect::<String>();
+    format!("\nmenuentry {} --id {} {{\n{body}}}\n", quote(title), quote(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{header, menu_entry};
+    use crate::{BootDevice, bootloader::Timeout};
+
+    #[test]
+    fn test_grub_config() {
+        assert_eq!(
+            header(1, Some(Timeout::Seconds(3))),
+            "# Generated by blsforme, do not edit\nset default=1\nset timeout=3\n"
+        );
+
+        let device = BootDevice {
+            disk: PathBuf::from("/dev/sda"),
+            uuid: Some("1f5cb158-4a0e-48e2-a339-157d8133f05f".into()),
+            grub_device: Some("hd0,gpt2".into()),
+        };
+        let entry = menu_entry(
+            "Distro's OS (6.8.9)",
+            "distro-6.8.9",
+            Some(&device),
+            "/EFI/distro/6.8.9/vmlinuz",
+            &[
+                "/EFI/distro/6.8.9/amd-ucode.initrd".into(),
+                "/EFI/distro/6.8.9/initrd".into(),
+            ],
+            "root=UUID=abc rw",
+        );
+        assert_eq!(
+            entry,
+            r#"
+menuentry 'Distro'\''s OS (6.8.9)' --id 'distro-6.8.9' {
+    set root='hd0,gpt2'
+    search --no-floppy --fs-uuid --set=root 1f5cb158-4a0e-48e2-a339-157d8133f05f
+    linux /EFI/distro/6.8.9/vmlinuz root=UUID=abc rw
+    initrd /EFI/distro/6.8.9/amd-ucode.initrd /EFI/distro/6.8.9/initrd
+}
+"#
+        );
+    }
+}
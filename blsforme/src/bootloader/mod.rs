@@ -8,10 +8,24 @@ use std::path::{PathBuf, StripPrefixError};
 
 use snafu::Snafu;
 
-use crate::{Entry, Firmware, Kernel, Schema, manager::Mounts};
+use crate::{
+    Architecture, BootEnvironment, EfiAppEntry, Entry, EspSurvey, EspSurveyor, Firmware, Kernel, Schema,
+    audit::AuditReport,
+    file_utils::{ChangeDetection, Writer, format_size},
+    manager::Mounts,
+};
 
+#[cfg(feature = "grub2")]
+pub mod grub2;
 pub mod systemd_boot;
 
+pub use systemd_boot::{
+    entry_file::LoaderEntry,
+    loader_conf::{ConsoleMode, LoaderConf, SecureBootEnroll, Timeout},
+    pe_version::LoaderVersion,
+    secure_boot::SecureBootAssets,
+};
+
 /// Bootloader errors
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -24,14 +38,76 @@ pub enum Error {
     #[snafu(display("io: {source}"))]
     Io { source: std::io::Error },
 
+    #[snafu(display("{source}"))]
+    Write {
+        #[snafu(source(from(crate::Error, Box::new)))]
+        source: Box<crate::Error>,
+    },
+
     #[snafu(display("wip: {source}"))]
     Prefix { source: StripPrefixError },
+
+    #[snafu(display("unsupported with this firmware: {operation}"))]
+    Unsupported { operation: &'static str },
+
+    #[snafu(display("{firmware} boot management not yet implemented"))]
+    UnsupportedFirmware { firmware: Firmware },
+
+    #[snafu(display(
+        "insufficient space on {}: {} required, but only {} available",
+        path.display(),
+        format_size(*required),
+        format_size(*available)
+    ))]
+    InsufficientSpace {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+    },
+
+    #[snafu(display("failed to query free space on {}: {source}", path.display()))]
+    QuerySpace { path: PathBuf, source: nix::errno::Errno },
+
+    #[snafu(display("invalid EFI application name {name:?}, expected a plain file name"))]
+    InvalidEfiApp { name: String },
+
+    #[snafu(display("verification failed for {}: installed copy differs from source", path.display()))]
+    VerificationFailed { path: PathBuf },
+}
+
+/// Options controlling how the bootloader itself is installed
+#[derive(Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Replace the installed bootloader even when it is newer than ours
+    pub force: bool,
+
+    /// Name shim chain loads systemd-boot as (default: `grub$ARCH.efi`)
+    pub shim_loader: Option<String>,
+
+    /// Menu timeout to write to `loader.conf`, otherwise the existing one is preserved
+    pub timeout: Option<Timeout>,
+
+    /// Install the fallback bootloader and our default entry pattern even when
+    /// another distribution's entries and default are present on `$BOOT`, or
+    /// the fallback is a copy of the Windows Boot Manager
+    pub take_ownership: bool,
+
+    /// Classifies the vendor directories on the ESP
+    pub vendors: EspSurveyor,
 }
 
 #[derive(Debug)]
 pub enum Bootloader<'a, 'b> {
-    /// We really only support systemd-boot right now
+    /// systemd-boot, for UEFI systems
     Systemd(Box<systemd_boot::Loader<'a, 'b>>),
+
+    /// Legacy BIOS: BLS entries are maintained on `$BOOT`, but the
+    /// bootloader itself is left to the administrator
+    Bios(Box<systemd_boot::Loader<'a, 'b>>),
+
+    /// Legacy BIOS, with GRUB2 installed and configured by us
+    #[cfg(feature = "grub2")]
+    Grub2(Box<grub2::Loader<'a, 'b>>),
 }
 
 impl<'a, 'b> Bootloader<'a, 'b> {
@@ -40,38 +116,202 @@ impl<'a, 'b> Bootloader<'a, 'b> {
         schema: &'a Schema,
         assets: &'b [PathBuf],
         mounts: &'a Mounts,
-        firmware: &Firmware,
+        boot_env: &'a BootEnvironment,
+        arch: Option<Architecture>,
+        writer: &'a Writer,
+        detection: ChangeDetection,
     ) -> Result<Self, Error> {
-        match firmware {
-            Firmware::Uefi => Ok(Bootloader::Systemd(Box::new(systemd_boot::Loader::new(
-                schema, assets, mounts,
-            )?))),
-            Firmware::Bios => unimplemented!(),
+        let bls = Box::new(systemd_boot::Loader::new(
+            schema, assets, mounts, arch, writer, detection,
+        )?);
+        match boot_env.firmware {
+            Firmware::Uefi => Ok(Bootloader::Systemd(bls)),
+            #[cfg(feature = "grub2")]
+            Firmware::Bios => Ok(Bootloader::Grub2(Box::new(grub2::Loader::new(
+                *bls,
+                boot_env.boot_device.as_ref(),
+                writer,
+            )))),
+            #[cfg(not(feature = "grub2"))]
+            Firmware::Bios => Ok(Bootloader::Bios(bls)),
         }
     }
 
-    /// Sync bootloader to BOOT dir
-    pub fn sync(&self) -> Result<(), Error> {
+    /// Name and own entries by the given machine ID
+    pub(crate) fn with_machine_id(mut self, machine_id: Option<&'a str>) -> Self {
+        match &mut self {
+            Bootloader::Systemd(s) | Bootloader::Bios(s) => s.machine_id = machine_id,
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => g.bls.machine_id = machine_id,
+        }
+        self
+    }
+
+    /// Remove stale entries before installing new ones, when otherwise short on space
+    pub(crate) fn with_cleanup_first(mut self, cleanup_first: bool) -> Self {
+        match &mut self {
+            Bootloader::Systemd(s) | Bootloader::Bios(s) => s.cleanup_first = cleanup_first,
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => g.bls.cleanup_first = cleanup_first,
+        }
+        self
+    }
+
+    /// Install the given EFI applications during [`Bootloader::sync`], or leave them untouched with `None`
+    pub(crate) fn with_efi_apps(mut self, efi_apps: Option<&'b [EfiAppEntry]>) -> Self {
+        match &mut self {
+            Bootloader::Systemd(s) | Bootloader::Bios(s) => s.efi_apps = efi_apps,
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => g.bls.efi_apps = efi_apps,
+        }
+        self
+    }
+
+    /// The loader managing BLS entries and kernels on `$BOOT`
+    fn bls(&self) -> &systemd_boot::Loader<'a, 'b> {
         match &self {
-            Bootloader::Systemd(s) => s.sync(),
+            Bootloader::Systemd(s) | Bootloader::Bios(s) => s,
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => &g.bls,
         }
     }
 
-    pub fn sync_entries(
-        &self,
-        cmdline: impl Iterator<Item = &'a str>,
-        entries: &[Entry],
-        excluded_snippets: impl Iterator<Item = &'a str>,
-    ) -> Result<(), Error> {
+    /// Sync bootloader to BOOT dir, never downgrading it unless forced
+    pub fn sync(&self, options: &SyncOptions) -> Result<(), Error> {
         match &self {
-            Bootloader::Systemd(s) => s.sync_entries(cmdline, entries, excluded_snippets),
+            Bootloader::Systemd(s) => s.sync(options),
+            Bootloader::Bios(_) => UnsupportedFirmwareSnafu {
+                firmware: Firmware::Bios,
+            }
+            .fail(),
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => g.sync(options),
         }
     }
 
-    /// Grab the installed entries
-    pub fn installed_kernels(&self) -> Result<Vec<Kernel>, Error> {
+    pub fn sync_entries(&self, cmdline: impl Iterator<Item = &'a str>, entries: &[&Entry]) -> Result<(), Error> {
         match &self {
-            Bootloader::Systemd(s) => s.installed_kernels(),
+            Bootloader::Systemd(s) | Bootloader::Bios(s) => s.sync_entries(cmdline, entries),
+            #[cfg(feature = "grub2")]
+            Bootloader::Grub2(g) => g.sync_entries(cmdline, entries),
         }
     }
+
+    /// Retrieve the configured menu timeout
+    pub fn timeout(&self) -> Result<Option<Timeout>, Error> {
+        self.bls().timeout()
+    }
+
+    /// Set the menu timeout
+    pub fn set_timeout(&self, timeout: Timeout) -> Result<(), Error> {
+        self.bls().set_timeout(timeout)
+    }
+
+    /// Current `loader.conf`, empty when it doesn't exist yet
+    pub fn loader_conf(&self) -> Result<LoaderConf, Error> {
+        self.bls().loader_conf()
+    }
+
+    /// Modify `loader.conf`, preserving anything left unchanged
+    pub fn update_loader_conf(&self, update: impl FnOnce(&mut LoaderConf)) -> Result<(), Error> {
+        self.bls().update_loader_conf(update)
+    }
+
+    /// All type #1 loader entries on `$BOOT`, including foreign ones
+    pub fn installed_entries(&self) -> Vec<(PathBuf, LoaderEntry)> {
+        self.bls().installed_entries()
+    }
+
+    /// Installed files of the given entries that are missing or differ from their source
+    pub fn verify_entries(&self, entries: &[&Entry]) -> Result<Vec<PathBuf>, Error> {
+        self.bls().verify_entries(entries)
+    }
+
+    /// Report loader entries and kernel directories on `$BOOT` that appear orphaned
+    pub fn audit(&self) -> AuditReport {
+        self.bls().audit()
+    }
+
+    /// Paths of all loader entries owned by us
+    pub fn loader_entry_files(&self) -> Vec<PathBuf> {
+        self.bls().owned_loader_files()
+    }
+
+    /// Remove the given kernel version from the boot partition
+    ///
+    /// Returns `false` if the kernel wasn't found
+    pub fn remove_kernel(&self, version: &str) -> Result<bool, Error> {
+        self.bls().remove_kernel(version)
+    }
+
+    /// Mark the given entry as successfully booted
+    ///
+    /// Returns `false` if the entry isn't managed by us
+    pub fn bless_entry(&self, entry_id: &str) -> Result<bool, Error> {
+        self.bls().bless_entry(entry_id)
+    }
+
+    /// Determine whether the entry ID corresponds to the given kernel version
+    pub fn entry_matches_version(&self, entry_id: &str, version: &str) -> bool {
+        self.bls().entry_matches_version(entry_id, version)
+    }
+
+    /// IDs of all installed entries managed by us
+    pub fn installed_entry_ids(&self) -> Vec<String> {
+        self.bls().installed_entry_ids()
+    }
+
+    /// Pin the default boot entry
+    pub fn set_default_entry(&self, entry_id: &str) -> Result<(), Error> {
+        self.bls().set_default_entry(entry_id)
+    }
+
+    /// Installed bootloader binaries on the ESP
+    pub fn installed_bootloaders(&self, options: &SyncOptions) -> Result<Vec<PathBuf>, Error> {
+        match &self {
+            Bootloader::Systemd(s) => s.installed_bootloaders(options),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// The removable media fallback (i.e. `EFI/Boot/BOOTX64.EFI`), and whether it's ours
+    pub fn fallback_bootloader(&self, options: &SyncOptions) -> Option<(PathBuf, bool)> {
+        match &self {
+            Bootloader::Systemd(s) => s.fallback_bootloader(options),
+            _ => None,
+        }
+    }
+
+    /// Loader entries on `$BOOT` belonging to other installations
+    pub fn foreign_entries(&self) -> Vec<PathBuf> {
+        self.bls().foreign_entries()
+    }
+
+    /// Classify the vendor directories on the ESP, when mounted
+    pub fn esp_survey(&self, options: &SyncOptions) -> Option<EspSurvey> {
+        self.bls().esp_survey(&options.vendors)
+    }
+
+    /// Whether systemd-boot is installed to be chain loaded by shim
+    pub fn shim_installed(&self, options: &SyncOptions) -> bool {
+        match &self {
+            Bootloader::Systemd(s) => s.shim_installed(options),
+            _ => false,
+        }
+    }
+
+    /// The default entry configured in `loader.conf`
+    pub fn default_entry(&self) -> Result<Option<String>, Error> {
+        self.bls().default_entry()
+    }
+
+    /// Restore the default boot entry selection to "newest wins"
+    pub fn reset_default_entry(&self) -> Result<(), Error> {
+        self.bls().reset_default_entry()
+    }
+
+    /// Grab the installed entries
+    pub fn installed_kernels(&self) -> Result<Vec<Kernel>, Error> {
+        self.bls().installed_kernels()
+    }
 }
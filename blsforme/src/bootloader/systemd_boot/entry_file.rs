@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Type #1 loader entry (`loader/entries/*.conf`) handling
+//!
+//! Known keys from the Boot Loader Specification are exposed as fields, while
+//! anything else (i.e. `grub_users` on Fedora) is preserved as-is. Comments are
+//! not retained, and keys are written back in the order the specification
+//! lists them.
+
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+/// A parsed type #1 loader entry
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoaderEntry {
+    /// Human readable title for the menu
+    pub title: Option<String>,
+
+    /// Version string, used for sorting
+    pub version: Option<String>,
+
+    /// Machine ID of the installation owning this entry
+    pub machine_id: Option<String>,
+
+    /// Key used for sorting, ahead of the version
+    pub sort_key: Option<String>,
+
+    /// Path to the kernel image, relative to the partition root
+    pub linux: Option<String>,
+
+    /// Path to an EFI binary, relative to the partition root
+    pub efi: Option<String>,
+
+    /// Paths to initrds, in load order
+    pub initrd: Vec<String>,
+
+    /// Kernel command line, one element per `options` line
+    pub options: Vec<String>,
+
+    /// Path to a devicetree blob
+    pub devicetree: Option<String>,
+
+    /// Devicetree overlays
+    pub devicetree_overlay: Option<String>,
+
+    /// EFI architecture this entry is for, ie `x64`
+    pub architecture: Option<String>,
+
+    /// Unknown keys, preserved in their original order
+    pub extra: Vec<(String, String)>,
+}
+
+impl LoaderEntry {
+    /// Parse an entry, tolerating unknown keys
+    pub fn parse(text: &str) -> Self {
+        let Ok(entry) = text.parse();
+        entry
+    }
+
+    /// The complete kernel command line
+    pub fn cmdline(&self) -> String {
+        self.options.join(" ")
+    }
+
+    /// The image booted by this entry, whether `linux` or `efi`
+    pub fn image(&self) -> Option<&str> {
+        self.linux.as_deref().or(self.efi.as_deref())
+    }
+}
+
+impl FromStr for LoaderEntry {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entry = Self::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim().to_string();
+            match key {
+                "title" => entry.title = Some(value),
+                "version" => entry.version = Some(value),
+                "machine-id" => entry.machine_id = Some(value),
+                "sort-key" => entry.sort_key = Some(value),
+                "linux" => entry.linux = Some(value),
+                "efi" => entry.efi = Some(value),
+                "initrd" => entry.initrd.push(value),
+                "options" => entry.options.push(value),
+                "devicetree" => entry.devicetree = Some(value),
+                "devicetree-overlay" => entry.devicetree_overlay = Some(value),
+                "architecture" => entry.architecture = Some(value),
+                _ => entry.extra.push((key.to_string(), value)),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+impl Display for LoaderEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let single = [
+            ("title", &self.title),
+            ("version", &self.version),
+            ("machine-id", &self.machine_id),
+            ("sort-key", &self.sort_key),
+            ("linux", &self.linux),
+            ("efi", &self.efi),
+        ];
+        for (key, value) in single {
+            if let Some(value) = value {
+                writeln!(f, "{key} {value}")?;
+            }
+        }
+        for initrd in self.initrd.iter() {
+            writeln!(f, "initrd {initrd}")?;
+        }
+        for options in self.options.iter() {
+            writeln!(f, "options {options}")?;
+        }
+        let single = [
+            ("devicetree", &self.devicetree),
+            ("devicetree-overlay", &self.devicetree_overlay),
+            ("architecture", &self.architecture),
+        ];
+        for (key, value) in single {
+            if let Some(value) = value {
+                writeln!(f, "{key} {value}")?;
+            }
+        }
+        for (key, value) in self.extra.iter() {
+            writeln!(f, "{key} {value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoaderEntry;
+
+    #[test]
+    fn test_fedora() {
+        let text = "title Fedora Linux (6.8.9-300.fc40.x86_64) 40 (Workstation Edition)
+version 6.8.9-300.fc40.x86_64
+linux /vmlinuz-6.8.9-300.fc40.x86_64
+initrd /initramfs-6.8.9-300.fc40.x86_64.img $tuned_initrd
+options root=UUID=2a78a4da-f110-4441-8839-dbd97ab87cda ro rootflags=subvol=root rhgb quiet $tuned_params
+grub_users $grub_users
+grub_arg --unrestricted
+grub_class fedora
+";
+        let entry = LoaderEntry::parse(text);
+        assert_eq!(entry.version.as_deref(), Some("6.8.9-300.fc40.x86_64"));
+        assert_eq!(entry.image(), Some("/vmlinuz-6.8.9-300.fc40.x86_64"));
+        assert_eq!(entry.extra.len(), 3);
+        assert_eq!(entry.to_string(), text);
+    }
+
+    #[test]
+    fn test_arch() {
+        let text = "# Managed by hand
+title   Arch Linux
+sort-key arch
+linux   /vmlinuz-linux
+initrd  /intel-ucode.img
+initrd  /initramfs-linux.img
+options root=PARTUUID=6e5a3f0c-0d4b-4e1a-9d6f-3c1b2a4d5e6f rw
+options quiet
+devicetree /dtbs/rockchip/rk3399-rockpro64.dtb
+architecture aa64
+";
+        let entry = LoaderEntry::parse(text);
+        assert_eq!(entry.initrd, ["/intel-ucode.img", "/initramfs-linux.img"]);
+        assert_eq!(
+            entry.cmdline(),
+            "root=PARTUUID=6e5a3f0c-0d4b-4e1a-9d6f-3c1b2a4d5e6f rw quiet"
+        );
+        assert_eq!(entry.architecture.as_deref(), Some("aa64"));
+        assert!(entry.extra.is_empty());
+        assert_eq!(LoaderEntry::parse(&entry.to_string()), entry);
+    }
+}
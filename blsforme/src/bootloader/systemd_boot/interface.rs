@@ -11,6 +11,7 @@
 use std::{
     fmt::Display,
     io,
+    os::fd::AsRawFd,
     path::{self, Path, PathBuf},
     string::FromUtf16Error,
 };
@@ -33,6 +34,15 @@ pub struct BootLoaderInterface {
 /// The well known vendor UUID for the Boot Loader Interface
 pub const UUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
 
+/// Attributes for variables we write: NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS
+const VARIABLE_ATTRIBUTES: u32 = 0x07;
+
+/// `FS_IMMUTABLE_FL` from `linux/fs.h`, set by default on efivarfs files
+const FS_IMMUTABLE_FL: nix::libc::c_long = 0x10;
+
+nix::ioctl_read!(fs_ioc_getflags, b'f', 1, nix::libc::c_long);
+nix::ioctl_write_ptr!(fs_ioc_setflags, b'f', 2, nix::libc::c_long);
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("failed to decode UTF16 string: {source}"))]
@@ -46,6 +56,7 @@ pub enum Error {
 }
 
 /// Variables that are currently exposed via efivars
+#[derive(Debug, Clone, Copy)]
 pub enum VariableName {
     TimeInitUSec,
     TimeExecUSec,
@@ -124,12 +135,55 @@ impl BootLoaderInterface {
         String::from_utf16(&raw).context(Utf16DecodingSnafu)
     }
 
+    /// Write a UCS2 string to efivars
+    pub fn set_ucs2_string(&self, var: VariableName, value: &str) -> Result<(), Error> {
+        let path = self.join_var(var);
+        let mut raw = VARIABLE_ATTRIBUTES.to_le_bytes().to_vec();
+        raw.extend(value.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+
+        if path.exists() {
+            clear_immutable(&path);
+        }
+        fs::write(path, raw).context(IoSnafu)
+    }
+
+    /// Remove a variable from efivars, if it exists
+    pub fn remove_variable(&self, var: VariableName) -> Result<(), Error> {
+        let path = self.join_var(var);
+        if !path.exists() {
+            return Ok(());
+        }
+        clear_immutable(&path);
+        fs::remove_file(path).context(IoSnafu)
+    }
+
     /// Generate root path for the variable
     fn join_var(&self, var: VariableName) -> PathBuf {
         self.efi_dir.join(format!("{var}-{UUID}"))
     }
 }
 
+/// efivarfs marks variables immutable to prevent accidental deletion, so
+/// clear the flag before writing. This is best effort as mocked roots are
+/// unlikely to support the ioctl, and a genuine failure will surface on write.
+fn clear_immutable(path: &Path) {
+    let Ok(file) = fs::File::open(path) else {
+        return;
+    };
+    let mut flags: nix::libc::c_long = 0;
+    // SAFETY: The fd is valid for the lifetime of `file` and `flags` outlives the calls
+    let result = unsafe { fs_ioc_getflags(file.as_raw_fd(), &mut flags) }.and_then(|_| {
+        if flags & FS_IMMUTABLE_FL == 0 {
+            return Ok(0);
+        }
+        flags &= !FS_IMMUTABLE_FL;
+        unsafe { fs_ioc_setflags(file.as_raw_fd(), &flags) }
+    });
+    if let Err(e) = result {
+        log::trace!("Unable to clear immutable flag on {}: {e}", path.display());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -0,0 +1,491 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! `loader.conf` handling
+//!
+//! We only manage a handful of keys within `loader/loader.conf`, so this
+//! representation preserves everything else (comments, unknown keys) as-is
+//! to avoid clobbering any administrator changes.
+
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+use serde::{Serialize, Serializer};
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid value for {key}: {value}"))]
+    InvalidValue { key: &'static str, value: String },
+}
+
+/// Menu timeout, per `loader.conf(5)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    /// Show the menu for the given number of seconds. `0` skips the menu
+    Seconds(u64),
+
+    /// Always show the menu, without a timeout
+    MenuForce,
+
+    /// Hide the menu unless a key is pressed
+    MenuHidden,
+
+    /// Never show the menu
+    MenuDisabled,
+}
+
+impl FromStr for Timeout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "menu-force" => Ok(Self::MenuForce),
+            "menu-hidden" => Ok(Self::MenuHidden),
+            "menu-disabled" => Ok(Self::MenuDisabled),
+            value => value.parse().map(Self::Seconds).map_err(|_| Error::InvalidValue {
+                key: "timeout",
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timeout::Seconds(seconds) => write!(f, "{seconds}"),
+            Timeout::MenuForce => f.write_str("menu-force"),
+            Timeout::MenuHidden => f.write_str("menu-hidden"),
+            Timeout::MenuDisabled => f.write_str("menu-disabled"),
+        }
+    }
+}
+
+/// Seconds serialize as a number, the menu modes as their `loader.conf` value
+impl Serialize for Timeout {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Timeout::Seconds(seconds) => serializer.serialize_u64(*seconds),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+/// Console resolution mode, per `loader.conf(5)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleMode {
+    /// Firmware specific mode number
+    Mode(u32),
+
+    /// Pick a suitable mode automatically
+    Auto,
+
+    /// Highest mode supported by the firmware
+    Max,
+
+    /// Keep the firmware provided mode
+    Keep,
+}
+
+impl FromStr for ConsoleMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(Self::Auto),
+            "max" => Ok(Self::Max),
+            "keep" => Ok(Self::Keep),
+            value => value.parse().map(Self::Mode).map_err(|_| Error::InvalidValue {
+                key: "console-mode",
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for ConsoleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsoleMode::Mode(mode) => write!(f, "{mode}"),
+            ConsoleMode::Auto => f.write_str("auto"),
+            ConsoleMode::Max => f.write_str("max"),
+            ConsoleMode::Keep => f.write_str("keep"),
+        }
+    }
+}
+
+/// Secure Boot key enrollment behaviour, per `loader.conf(5)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureBootEnroll {
+    /// Never enroll keys
+    Off,
+
+    /// Offer keys for manual enrollment from the menu
+    Manual,
+
+    /// Enroll automatically when safe to do so (ie in a VM)
+    IfSafe,
+
+    /// Always enroll automatically
+    Force,
+}
+
+impl FromStr for SecureBootEnroll {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "off" => Ok(Self::Off),
+            "manual" => Ok(Self::Manual),
+            "if-safe" => Ok(Self::IfSafe),
+            "force" => Ok(Self::Force),
+            value => Err(Error::InvalidValue {
+                key: "secure-boot-enroll",
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for SecureBootEnroll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecureBootEnroll::Off => f.write_str("off"),
+            SecureBootEnroll::Manual => f.write_str("manual"),
+            SecureBootEnroll::IfSafe => f.write_str("if-safe"),
+            SecureBootEnroll::Force => f.write_str("force"),
+        }
+    }
+}
+
+/// Parse a boolean as systemd-boot does
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "1" | "yes" | "y" | "true" | "t" | "on" => Some(true),
+        "0" | "no" | "n" | "false" | "f" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Render a boolean in loader.conf style
+fn format_bool(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+/// Keys with typed accessors on [`LoaderConf`]
+const KNOWN_KEYS: [&str; 10] = [
+    "default",
+    "timeout",
+    "console-mode",
+    "editor",
+    "auto-entries",
+    "auto-windows",
+    "auto-firmware",
+    "beep",
+    "secure-boot-enroll",
+    "reboot-for-bitlocker",
+];
+
+/// A single line within `loader.conf`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// A `key value` setting
+    Setting { key: String, value: String },
+
+    /// Comments and blank lines, preserved verbatim
+    Verbatim(String),
+}
+
+/// Parsed `loader.conf` file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoaderConf {
+    lines: Vec<Line>,
+}
+
+impl LoaderConf {
+    /// Return the value for the given key, if set
+    ///
+    /// As with systemd-boot, the last occurrence wins.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|l| match l {
+            Line::Setting { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Set the value for the given key, replacing the first occurrence in place
+    /// and dropping any duplicates. New keys are appended.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        let mut replaced = false;
+        self.lines.retain_mut(|l| match l {
+            Line::Setting { key: k, value: v } if k == key => {
+                if replaced {
+                    false
+                } else {
+                    *v = value.clone();
+                    replaced = true;
+                    true
+                }
+            }
+            _ => true,
+        });
+        if !replaced {
+            self.lines.push(Line::Setting {
+                key: key.to_string(),
+                value,
+            });
+        }
+    }
+
+    /// Remove all occurrences of the given key
+    pub fn remove(&mut self, key: &str) {
+        self.lines
+            .retain(|l| !matches!(l, Line::Setting { key: k, .. } if k == key));
+    }
+
+    /// Settings without a typed accessor, in file order
+    pub fn extras(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Setting { key, value } if !KNOWN_KEYS.contains(&key.as_str()) => Some((key.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Default entry pattern, without any quoting
+    pub fn default_entry(&self) -> Option<&str> {
+        self.get("default").map(|d| d.trim_matches('"'))
+    }
+
+    /// Set the default entry pattern
+    pub fn set_default_entry(&mut self, pattern: &str) {
+        self.set("default", format!("\"{pattern}\""));
+    }
+
+    /// Menu timeout
+    pub fn timeout(&self) -> Option<Timeout> {
+        self.get("timeout")?.parse().ok()
+    }
+
+    /// Set the menu timeout
+    pub fn set_timeout(&mut self, timeout: Timeout) {
+        self.set("timeout", timeout.to_string());
+    }
+
+    /// Console resolution mode
+    pub fn console_mode(&self) -> Option<ConsoleMode> {
+        self.get("console-mode")?.parse().ok()
+    }
+
+    /// Set the console resolution mode
+    pub fn set_console_mode(&mut self, mode: ConsoleMode) {
+        self.set("console-mode", mode.to_string());
+    }
+
+    /// Whether the kernel cmdline editor is enabled
+    pub fn editor(&self) -> Option<bool> {
+        parse_bool(self.get("editor")?)
+    }
+
+    /// Enable or disable the kernel cmdline editor
+    pub fn set_editor(&mut self, editor: bool) {
+        self.set("editor", format_bool(editor));
+    }
+
+    /// Whether automatic entries (Windows, EFI shell, etc) are shown
+    pub fn auto_entries(&self) -> Option<bool> {
+        parse_bool(self.get("auto-entries")?)
+    }
+
+    /// Show or hide automatic entries
+    pub fn set_auto_entries(&mut self, auto_entries: bool) {
+        self.set("auto-entries", format_bool(auto_entries));
+    }
+
+    /// Whether the automatic Windows Boot Manager entry is shown
+    pub fn auto_windows(&self) -> Option<bool> {
+        parse_bool(self.get("auto-windows")?)
+    }
+
+    /// Show or hide the automatic Windows Boot Manager entry
+    pub fn set_auto_windows(&mut self, auto_windows: bool) {
+        self.set("auto-windows", format_bool(auto_windows));
+    }
+
+    /// Whether the Windows Boot Manager would be unreachable from the menu
+    ///
+    /// Either its automatic entry is disabled, or the menu itself is.
+    pub fn hides_windows(&self) -> bool {
+        self.auto_windows() == Some(false)
+            || self.auto_entries() == Some(false)
+            || self.timeout() == Some(Timeout::MenuDisabled)
+    }
+
+    /// Whether the "Reboot into firmware" entry is shown
+    pub fn auto_firmware(&self) -> Option<bool> {
+        parse_bool(self.get("auto-firmware")?)
+    }
+
+    /// Show or hide the "Reboot into firmware" entry
+    pub fn set_auto_firmware(&mut self, auto_firmware: bool) {
+        self.set("auto-firmware", format_bool(auto_firmware));
+    }
+
+    /// Whether to beep when the menu is shown
+    pub fn beep(&self) -> Option<bool> {
+        parse_bool(self.get("beep")?)
+    }
+
+    /// Enable or disable the menu beep
+    pub fn set_beep(&mut self, beep: bool) {
+        self.set("beep", format_bool(beep));
+    }
+
+    /// Secure Boot key enrollment behaviour
+    pub fn secure_boot_enroll(&self) -> Option<SecureBootEnroll> {
+        self.get("secure-boot-enroll")?.parse().ok()
+    }
+
+    /// Set the Secure Boot key enrollment behaviour
+    pub fn set_secure_boot_enroll(&mut self, enroll: SecureBootEnroll) {
+        self.set("secure-boot-enroll", enroll.to_string());
+    }
+
+    /// Whether to reboot when BitLocker would otherwise require recovery
+    pub fn reboot_for_bitlocker(&self) -> Option<bool> {
+        parse_bool(self.get("reboot-for-bitlocker")?)
+    }
+
+    /// Enable or disable rebooting for BitLocker
+    pub fn set_reboot_for_bitlocker(&mut self, reboot: bool) {
+        self.set("reboot-for-bitlocker", format_bool(reboot));
+    }
+}
+
+impl FromStr for LoaderConf {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = s
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    return Line::Verbatim(line.to_string());
+                }
+                match trimmed.split_once(char::is_whitespace) {
+                    Some((key, value)) => Line::Setting {
+                        key: key.to_string(),
+                        value: value.trim().to_string(),
+                    },
+                    None => Line::Setting {
+                        key: trimmed.to_string(),
+                        value: String::new(),
+                    },
+                }
+            })
+            .collect();
+        Ok(Self { lines })
+    }
+}
+
+impl Display for LoaderConf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.lines.iter() {
+            match line {
+                Line::Setting { key, value } if value.is_empty() => writeln!(f, "{key}")?,
+                Line::Setting { key, value } => writeln!(f, "{key} {value}")?,
+                Line::Verbatim(text) => writeln!(f, "{text}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleMode, LoaderConf, SecureBootEnroll, Timeout};
+
+    #[test]
+    fn test_preserve_unknown() {
+        let Ok(mut conf) = "# Managed by hand\ndefault \"aerynos*\"\nconsole-mode max\n".parse::<LoaderConf>();
+        assert_eq!(conf.timeout(), None);
+        assert_eq!(conf.console_mode(), Some(ConsoleMode::Max));
+        conf.set_timeout(Timeout::Seconds(0));
+        conf.set_default_entry("aerynos*");
+        assert_eq!(conf.timeout(), Some(Timeout::Seconds(0)));
+        assert_eq!(
+            conf.to_string(),
+            "# Managed by hand\ndefault \"aerynos*\"\nconsole-mode max\ntimeout 0\n"
+        );
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        let Ok(conf) = "timeout menu-force\neditor no\nauto-entries 1\n".parse::<LoaderConf>();
+        assert_eq!(conf.timeout(), Some(Timeout::MenuForce));
+        assert_eq!(conf.editor(), Some(false));
+        assert_eq!(conf.auto_entries(), Some(true));
+        assert_eq!(conf.default_entry(), None);
+        assert!(!conf.hides_windows());
+
+        let Ok(conf) = "auto-entries yes\nauto-windows no\n".parse::<LoaderConf>();
+        assert_eq!(conf.auto_windows(), Some(false));
+        assert!(conf.hides_windows());
+
+        assert_eq!(serde_json::to_string(&Timeout::Seconds(5)).unwrap(), "5");
+        assert_eq!(serde_json::to_string(&Timeout::MenuForce).unwrap(), "\"menu-force\"");
+    }
+
+    #[test]
+    fn test_real_samples() {
+        // As shipped by bootctl, Fedora and Arch respectively
+        let samples = [
+            "#timeout 3\n#console-mode keep\n",
+            "default 9fd3d4e5a2914fb59a70f5c3e2a2d7f4-*\ntimeout 5\nconsole-mode auto\nreboot-for-bitlocker 1\n",
+            "default  arch.conf\ntimeout  4\nconsole-mode max\neditor   no\n\n# Keep the firmware entry\nauto-firmware yes\nsecure-boot-enroll manual\nlinux-only yes\n",
+        ];
+        for sample in samples {
+            let Ok(conf) = sample.parse::<LoaderConf>();
+            let Ok(reparsed) = conf.to_string().parse::<LoaderConf>();
+            assert_eq!(conf, reparsed);
+        }
+
+        let Ok(mut conf) = samples[0].parse::<LoaderConf>();
+        assert_eq!(conf.timeout(), None);
+        assert_eq!(conf.to_string(), samples[0]);
+        conf.set_default_entry("aerynos*");
+        assert_eq!(conf.to_string(), format!("{}default \"aerynos*\"\n", samples[0]));
+
+        let Ok(mut conf) = samples[2].parse::<LoaderConf>();
+        assert_eq!(conf.default_entry(), Some("arch.conf"));
+        assert_eq!(conf.editor(), Some(false));
+        assert_eq!(conf.extras().collect::<Vec<_>>(), vec![("linux-only", "yes")]);
+        conf.set_editor(true);
+        conf.set_console_mode(ConsoleMode::Keep);
+        assert_eq!(
+            conf.to_string(),
+            "default arch.conf\ntimeout 4\nconsole-mode keep\neditor yes\n\n# Keep the firmware entry\nauto-firmware yes\nsecure-boot-enroll manual\nlinux-only yes\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "default aerynos*\ntimeout 5\nconsole-mode auto\neditor yes\nauto-entries no\nauto-firmware no\nbeep on\nsecure-boot-enroll if-safe\nreboot-for-bitlocker yes\n";
+        let Ok(mut conf) = text.parse::<LoaderConf>();
+        assert_eq!(conf.to_string(), text);
+        assert_eq!(conf.auto_firmware(), Some(false));
+        assert_eq!(conf.beep(), Some(true));
+        assert_eq!(conf.secure_boot_enroll(), Some(SecureBootEnroll::IfSafe));
+        assert_eq!(conf.reboot_for_bitlocker(), Some(true));
+
+        // Only the default changes during a sync
+        conf.set_default_entry("aerynos-*");
+        assert_eq!(
+            conf.to_string(),
+            text.replace("default aerynos*", "default \"aerynos-*\"")
+        );
+        assert_eq!(conf.timeout(), Some(Timeout::Seconds(5)));
+    }
+}
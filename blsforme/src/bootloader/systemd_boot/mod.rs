@@ -4,61 +4,167 @@
 
 //! systemd-boot management and interfaces
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use fs_err as fs;
-use snafu::{OptionExt as _, ResultExt as _};
+use snafu::{OptionExt as _, ResultExt as _, ensure};
 
 use crate::{
-    Entry, Kernel, Schema,
-    bootloader::{IoSnafu, MissingFileSnafu, MissingMountSnafu, PrefixSnafu},
-    file_utils::{PathExt, changed_files, copy_atomic_vfat},
+    Architecture, AuxiliaryFile, AuxiliaryKind, BootEnvironment, EfiAppEntry, Entry, EspSurvey, EspSurveyor, Kernel,
+    ProgressEvent, Schema,
+    audit::{self, AuditReport},
+    bootloader::{
+        InsufficientSpaceSnafu, InvalidEfiAppSnafu, IoSnafu, MissingFileSnafu, MissingMountSnafu, PrefixSnafu,
+        QuerySpaceSnafu, SyncOptions, UnsupportedSnafu, VerificationFailedSnafu, WriteSnafu,
+    },
+    entry::{EFI_TOOLS_DIR, efi_app_prefix},
+    file_utils::{ChangeDetection, PathExt, Writer, changed_files, format_size, tree_size},
+    hash_cache::HashCache,
     manager::Mounts,
 };
 
+pub mod entry_file;
 pub mod interface;
+pub mod loader_conf;
+pub mod pe_version;
+pub mod secure_boot;
+
+use entry_file::LoaderEntry;
+use loader_conf::{LoaderConf, Timeout};
+use pe_version::LoaderVersion;
+use secure_boot::SecureBootAssets;
+
+/// Upper bound on concurrent installs, as `$BOOT` is typically slow flash storage
+#[cfg(feature = "par")]
+const COPY_THREADS: usize = 4;
+
+/// Strip the `.conf` suffix and any boot counter (`+$left[-$done]`) from an entry ID
+fn strip_boot_counter(entry_id: &str) -> &str {
+    let stem = entry_id.strip_suffix(".conf").unwrap_or(entry_id);
+    match stem.rsplit_once('+') {
+        Some((base, counter)) if !counter.is_empty() && counter.chars().all(|c| c.is_ascii_digit() || c == '-') => base,
+        _ => stem,
+    }
+}
+
+/// Initrds of the entry in load order, [`AuxiliaryKind::Microcode`] first
+fn ordered_initrds<'e>(entry: &'e Entry) -> Vec<&'e AuxiliaryFile> {
+    let mut initrds = entry.kernel.initrd.iter().collect::<Vec<_>>();
+    initrds.sort_by_key(|i| (i.kind != AuxiliaryKind::Microcode, i.priority));
+    initrds
+}
 
 /// systemd specific bootloader behaviours
-/// NOTE: Currently secure boot is NOT supported (or fbx64)
+///
+/// Secure Boot is supported via shim, when available in the assets. See [`secure_boot`].
 #[derive(Debug)]
 pub struct Loader<'a, 'b> {
-    /// system configuration
-    #[allow(dead_code)]
+    /// Bootloader binaries (and shim, when present) to install
     assets: &'b [PathBuf],
     mounts: &'a Mounts,
 
     schema: &'a Schema,
     boot_root: PathBuf,
+
+    /// Explicitly requested EFI architecture
+    arch: Option<Architecture>,
+
+    /// All modifications go through here
+    writer: &'a Writer,
+
+    /// Hashes of files installed to `$BOOT`
+    hashes: HashCache,
+
+    /// Our machine ID, when entries are named by it
+    pub(super) machine_id: Option<&'a str>,
+
+    /// Remove stale entries before installing when otherwise short on space
+    pub(super) cleanup_first: bool,
+
+    /// EFI applications installed alongside the kernels, or `None` to leave them untouched
+    pub(super) efi_apps: Option<&'b [EfiAppEntry]>,
 }
 
-#[derive(Debug)]
+/// Entries on `$BOOT` due for removal
+#[derive(Debug, Default)]
+struct StaleEntries {
+    confs: Vec<PathBuf>,
+    kernels: Vec<PathBuf>,
+    ukis: Vec<PathBuf>,
+}
+
+impl StaleEntries {
+    /// Total size of all files to be removed
+    fn size(&self) -> u64 {
+        self.confs
+            .iter()
+            .chain(self.ukis.iter())
+            .chain(self.kernels.iter())
+            .map(|path| tree_size(path))
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
 struct InstallResult {
-    /// The `.conf` file that was written (absolute)
-    loader_conf: String,
+    /// The `.conf` file that was written (absolute), for type #1 entries
+    loader_conf: Option<String>,
 
-    // The kernel path that was installed (absolute)
-    kernel_dir: String,
+    // The kernel path that was installed (absolute), for type #1 entries
+    kernel_dir: Option<String>,
+
+    /// The unified kernel image that was installed (absolute), for type #2 entries
+    uki: Option<String>,
+
+    /// Whether anything on disk was (or would be) modified
+    modified: bool,
 }
 
 impl<'a, 'b> Loader<'a, 'b> {
     /// Construct a new systemd boot loader manager
-    pub(super) fn new(schema: &'a Schema, assets: &'b [PathBuf], mounts: &'a Mounts) -> Result<Self, super::Error> {
-        let boot_root = mounts
-            .xbootldr
-            .clone()
-            .or_else(|| mounts.esp.clone())
-            .context(MissingMountSnafu {
-                description: "ESP (/efi)",
-            })?;
+    pub(super) fn new(
+        schema: &'a Schema,
+        assets: &'b [PathBuf],
+        mounts: &'a Mounts,
+        arch: Option<Architecture>,
+        writer: &'a Writer,
+        detection: ChangeDetection,
+    ) -> Result<Self, super::Error> {
+        let boot_root = mounts.boot_root().cloned().context(MissingMountSnafu {
+            description: "ESP (/efi)",
+        })?;
 
         Ok(Self {
             schema,
             assets,
             mounts,
+            hashes: HashCache::load(&boot_root, detection),
             boot_root,
+            arch,
+            writer,
+            machine_id: None,
+            cleanup_first: false,
+            efi_apps: None,
         })
     }
 
+    /// Determine the EFI architecture to install for
+    ///
+    /// An explicit override always wins, then the native architecture if
+    /// its assets are available, and finally whichever architecture the
+    /// available assets are for.
+    fn efi_architecture(&self) -> Option<Architecture> {
+        if let Some(arch) = self.arch {
+            return Some(arch);
+        }
+        let available = |arch: &Architecture| self.assets.iter().any(|p| p.ends_with(arch.efi_arch_filenames().0));
+        let native = Architecture::native();
+        native
+            .filter(available)
+            .or_else(|| Architecture::ALL.into_iter().find(available))
+            .or(native)
+    }
+
     /// Get the kernel directory for a specific entry
     fn get_kernel_dir(&self, entry: &Entry) -> PathBuf {
         let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
@@ -67,89 +173,727 @@ impl<'a, 'b> Loader<'a, 'b> {
             .join_insensitive(effective_schema.os_namespace())
     }
 
-    /// Sync bootloader to ESP (not XBOOTLDR..)
-    pub(super) fn sync(&self) -> Result<(), super::Error> {
-        let x64_efi = self
+    /// Where the bootloader is installed on the ESP, paired with the source asset
+    ///
+    /// When shim is available it becomes the removable media fallback, chain loading
+    /// systemd-boot from the same directory.
+    fn bootloader_targets(&self, options: &SyncOptions) -> Result<Vec<(PathBuf, PathBuf)>, super::Error> {
+        let arch = self.efi_architecture().context(UnsupportedSnafu {
+            operation: "EFI binaries for this architecture",
+        })?;
+        let (asset_name, fallback_name) = arch.efi_arch_filenames();
+        log::debug!("using EFI architecture: {arch:?}");
+        let main_efi = self
             .assets
             .iter()
-            .find(|p| p.ends_with("systemd-bootx64.efi"))
-            .context(MissingFileSnafu {
-                filename: "systemd-bootx64.efi",
-            })?;
-        log::debug!("discovered main efi asset: {}", x64_efi.display());
+            .find(|p| p.ends_with(asset_name))
+            .context(MissingFileSnafu { filename: asset_name })?;
+        log::debug!("discovered main efi asset: {}", main_efi.display());
 
         let esp = self.mounts.esp.as_ref().context(MissingMountSnafu {
             description: "ESP (/efi)",
         })?;
-        // Copy systemd-bootx64.efi into these locations
-        let targets = vec![
-            (
-                x64_efi.clone(),
-                esp.join_insensitive("EFI")
-                    .join_insensitive("Boot")
-                    .join_insensitive("BOOTX64.EFI"),
-            ),
-            (
-                x64_efi.clone(),
-                esp.join_insensitive("EFI")
-                    .join_insensitive("systemd")
-                    .join_insensitive("systemd-bootx64.efi"),
-            ),
-        ];
+        let boot_dir = esp.join_insensitive("EFI").join_insensitive("Boot");
+
+        let mut targets = match SecureBootAssets::detect(self.assets, arch) {
+            Some(shim) => {
+                log::debug!("discovered shim asset: {}", shim.shim.display());
+                let loader_name = Self::shim_loader_name(options, arch);
+                let mut targets = vec![
+                    (shim.shim, boot_dir.join_insensitive(fallback_name)),
+                    (main_efi.clone(), boot_dir.join_insensitive(loader_name)),
+                ];
+                if let Some(mok_manager) = shim.mok_manager {
+                    targets.push((
+                        mok_manager,
+                        boot_dir.join_insensitive(SecureBootAssets::mok_manager_name(arch)),
+                    ));
+                }
+                targets
+            }
+            None => vec![(main_efi.clone(), boot_dir.join_insensitive(fallback_name))],
+        };
+        targets.push((
+            main_efi.clone(),
+            esp.join_insensitive("EFI")
+                .join_insensitive("systemd")
+                .join_insensitive(asset_name),
+        ));
+        Ok(targets)
+    }
+
+    /// Name of the systemd-boot binary chain loaded by shim
+    fn shim_loader_name(options: &SyncOptions, arch: Architecture) -> String {
+        options
+            .shim_loader
+            .clone()
+            .unwrap_or_else(|| SecureBootAssets::default_loader_name(arch))
+    }
+
+    /// Installed bootloader binaries on the ESP
+    pub(super) fn installed_bootloaders(&self, options: &SyncOptions) -> Result<Vec<PathBuf>, super::Error> {
+        Ok(self
+            .bootloader_targets(options)?
+            .into_iter()
+            .map(|(_, dest)| dest)
+            .filter(|dest| dest.exists())
+            .collect())
+    }
+
+    /// The removable media fallback, and whether it's identical to the binary we'd install there
+    pub(super) fn fallback_bootloader(&self, options: &SyncOptions) -> Option<(PathBuf, bool)> {
+        let (_, fallback_name) = self.efi_architecture()?.efi_arch_filenames();
+        let esp = self.mounts.esp.as_ref()?;
+        let fallback = esp
+            .join_insensitive("EFI")
+            .join_insensitive("Boot")
+            .join_insensitive(fallback_name);
+        if !fallback.exists() {
+            return None;
+        }
+        let owned = self.bootloader_targets(options).is_ok_and(|targets| {
+            targets
+                .into_iter()
+                .any(|(source, dest)| dest == fallback && changed_files(&[(source, dest.clone())]).is_empty())
+        });
+        Some((fallback, owned))
+    }
+
+    /// Classify the vendor directories on the ESP, when mounted
+    pub(super) fn esp_survey(&self, surveyor: &EspSurveyor) -> Option<EspSurvey> {
+        let esp = self.mounts.esp.as_ref()?;
+        Some(surveyor.survey(esp, &self.owned_namespaces()))
+    }
+
+    /// Whether systemd-boot is installed to be chain loaded by shim
+    pub(super) fn shim_installed(&self, options: &SyncOptions) -> bool {
+        let (Some(arch), Some(esp)) = (self.efi_architecture(), self.mounts.esp.as_ref()) else {
+            return false;
+        };
+        let loader = esp
+            .join_insensitive("EFI")
+            .join_insensitive("Boot")
+            .join_insensitive(Self::shim_loader_name(options, arch));
+        LoaderVersion::from_file(loader).is_some()
+    }
+
+    /// Whether the bootloader at `dest` should be replaced by `source`
+    ///
+    /// We never downgrade an installed systemd-boot unless forced, as the ESP may
+    /// be shared with a distribution shipping a newer systemd.
+    fn should_update_bootloader(source: &Path, dest: &Path, force: bool) -> bool {
+        let installed = LoaderVersion::from_file(dest);
+        let available = LoaderVersion::from_file(source);
+        let update = match (&available, &installed) {
+            _ if force => true,
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(available), Some(installed)) => available > installed,
+        };
+        let describe = |v: &Option<LoaderVersion>| v.as_ref().map_or("unknown".to_string(), |v| v.to_string());
+        log::info!(
+            "{} {}: installed {}, available {}",
+            if update { "Updating" } else { "Keeping" },
+            dest.display(),
+            describe(&installed),
+            describe(&available),
+        );
+        update
+    }
+
+    /// Sync bootloader to ESP (not XBOOTLDR..)
+    ///
+    /// Installed copies of systemd-boot are only replaced by a newer version, unless forced.
+    pub(super) fn sync(&self, options: &SyncOptions) -> Result<(), super::Error> {
+        // Leave the fallback and default alone when another distribution has entries here
+        let shared = !options.take_ownership && !self.foreign_entries().is_empty();
+        if shared {
+            match self.foreign_default()? {
+                Some(default) => log::warn!(
+                    "$BOOT is shared with another installation (default entry {default}), only updating our entries"
+                ),
+                None => log::warn!("$BOOT is shared with another installation, only updating our entries"),
+            }
+        }
+        let fallback_dir = self
+            .mounts
+            .esp
+            .as_ref()
+            .map(|esp| esp.join_insensitive("EFI").join_insensitive("Boot"));
+
+        // Windows installs a copy of its boot manager as the fallback, leave it be
+        let survey = self.esp_survey(&options.vendors);
+        let windows_fallback = !options.take_ownership
+            && survey
+                .as_ref()
+                .and_then(|s| s.windows_boot_manager.clone())
+                .zip(self.fallback_bootloader(options))
+                .is_some_and(|(bootmgr, (fallback, _))| changed_files(&[(bootmgr, fallback)]).is_empty());
+        if windows_fallback {
+            log::warn!("The fallback bootloader is the Windows Boot Manager, not replacing it");
+        }
+        let keep_fallback = shared || windows_fallback;
 
-        for (source, dest) in changed_files(targets.as_slice()) {
-            copy_atomic_vfat(source, dest).context(IoSnafu)?;
+        // Copy systemd-boot (and shim) into these locations
+        let targets = self
+            .bootloader_targets(options)?
+            .into_iter()
+            .filter(|(_, dest)| !keep_fallback || fallback_dir.as_ref().is_none_or(|d| !dest.starts_with(d)))
+            .filter(|(source, dest)| {
+                LoaderVersion::from_file(source).is_none()
+                    || Self::should_update_bootloader(source, dest, options.force)
+            })
+            .collect::<Vec<_>>();
+
+        self.copy_changed(&targets)?;
+
+        // Update the loader.conf file with default entry pattern based on namespace,
+        // unless pinned to one of our entries that still exists
+        let loader_conf = self.loader_conf()?;
+        let pinned = loader_conf.default_entry().filter(|d| {
+            !d.contains('*')
+                && self
+                    .installed_entry_ids()
+                    .iter()
+                    .any(|id| d.strip_suffix(".conf") == Some(id))
+        });
+        if let Some(pinned) = pinned {
+            log::debug!("Preserving pinned default entry: {pinned}");
+        } else if shared {
+            log::info!("Preserving the default entry of the other installation");
+        } else {
+            self.update_loader_conf(|conf| conf.set_default_entry(&self.default_pattern()))?;
+        }
+        self.sync_timeout(options)?;
+        if let Some(bootmgr) = survey.and_then(|s| s.windows_boot_manager) {
+            if self.loader_conf()?.hides_windows() {
+                log::warn!(
+                    "{} is present, but hidden from the menu by loader.conf",
+                    bootmgr.display()
+                );
+            }
         }
+        self.sync_efi_apps()?;
+        self.hashes.save(self.writer).context(WriteSnafu)?;
+
+        Ok(())
+    }
+
+    /// Install the requested EFI applications and their entries, removing any others we installed
+    ///
+    /// Nothing is installed or removed unless applications were configured.
+    fn sync_efi_apps(&self) -> Result<(), super::Error> {
+        let Some(efi_apps) = self.efi_apps else {
+            return Ok(());
+        };
+        let tools_dir = self
+            .boot_root
+            .join_insensitive("EFI")
+            .join_insensitive(self.schema.os_namespace())
+            .join_insensitive(EFI_TOOLS_DIR);
+
+        let mut confs = vec![];
+        let mut binaries = vec![];
+        for app in efi_apps {
+            let is_file_name = Path::new(&app.install_name).file_name() == Some(app.install_name.as_ref());
+            ensure!(
+                is_file_name,
+                InvalidEfiAppSnafu {
+                    name: &app.install_name
+                }
+            );
+
+            let binary = tools_dir.join_insensitive(&app.install_name);
+            self.copy_changed(&[(app.source_path.clone(), binary.clone())])?;
 
-        // Write the loader.conf file with default entry pattern based on namespace
-        let loader_conf_dir = self.boot_root.join_insensitive("loader");
-        let loader_conf_path = loader_conf_dir.join_insensitive("loader.conf");
-        if !loader_conf_dir.exists() {
-            fs::create_dir_all(loader_conf_dir).context(IoSnafu)?;
+            let conf = self
+                .boot_root
+                .join_insensitive("loader")
+                .join_insensitive("entries")
+                .join_insensitive(format!("{}.conf", self.efi_app_id(app)));
+            self.writer
+                .write(self.generate_efi_app_entry(app), &conf)
+                .context(WriteSnafu)?;
+
+            confs.push(conf);
+            binaries.push(binary);
         }
 
-        // Create a default pattern that matches all entries for our namespace
-        let namespace = self.schema.os_namespace();
-        let default_pattern = format!("default \"{namespace}*\"\n");
-        fs::write(loader_conf_path, default_pattern).context(IoSnafu)?;
+        for conf in self.owned_efi_app_files().iter().filter(|f| !confs.contains(f)) {
+            log::info!("Removing stale EFI application entry: {conf:?}");
+            self.writer.remove_file(conf).context(IoSnafu)?;
+        }
+        let installed = fs::read_dir(&tools_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && !binaries.contains(p));
+        for binary in installed {
+            log::info!("Removing stale EFI application: {binary:?}");
+            self.writer.remove_file(&binary).context(IoSnafu)?;
+        }
 
         Ok(())
     }
 
+    /// Loader entry ID of the application, prefixed by our machine ID when set
+    fn efi_app_id(&self, app: &EfiAppEntry) -> String {
+        match self.machine_id {
+            Some(machine_id) => format!("{machine_id}-{}", app.id(self.schema)),
+            None => app.id(self.schema),
+        }
+    }
+
+    /// Type #1 entry booting the application via the `efi` key
+    fn generate_efi_app_entry(&self, app: &EfiAppEntry) -> String {
+        let machine_id = self
+            .machine_id
+            .map(|id| format!("\nmachine-id {id}"))
+            .unwrap_or_default();
+        format!(
+            "title {}{machine_id}\nsort-key {EFI_TOOLS_DIR}\nefi /EFI/{}/{EFI_TOOLS_DIR}/{}\n",
+            app.title,
+            self.schema.os_namespace(),
+            app.install_name
+        )
+    }
+
+    /// Loader entries of EFI applications installed by us
+    fn owned_efi_app_files(&self) -> Vec<PathBuf> {
+        let prefix = efi_app_prefix(self.schema);
+        let loader_dir = self.boot_root.join_insensitive("loader").join_insensitive("entries");
+        let Ok(entries) = fs::read_dir(&loader_dir) else {
+            return vec![];
+        };
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let id = match self.machine_id {
+                    Some(machine_id) => name.strip_prefix(machine_id).and_then(|n| n.strip_prefix('-')),
+                    None => Some(name.as_ref()),
+                };
+                id.is_some_and(|id| id.starts_with(&prefix) && id.ends_with(".conf")) && self.owned_by_machine(path)
+            })
+            .collect()
+    }
+
+    /// Write the requested menu timeout to `loader.conf`, if any
+    pub(super) fn sync_timeout(&self, options: &SyncOptions) -> Result<(), super::Error> {
+        match options.timeout {
+            Some(timeout) => self.update_loader_conf(|conf| conf.set_timeout(timeout)),
+            None => Ok(()),
+        }
+    }
+
+    /// The default entry pattern matching all entries for our namespace
+    fn default_pattern(&self) -> String {
+        match self.machine_id {
+            Some(machine_id) => format!("{machine_id}-{}*", self.schema.os_namespace()),
+            None => format!("{}*", self.schema.os_namespace()),
+        }
+    }
+
+    /// IDs (minus the `.conf` suffix) of all loader entries owned by us
+    pub(super) fn installed_entry_ids(&self) -> Vec<String> {
+        let mut ids = self
+            .owned_loader_files()
+            .iter()
+            .filter_map(|f| Some(f.file_name()?.to_string_lossy().strip_suffix(".conf")?.to_string()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// Pin the default boot entry in `loader.conf` to the given entry ID
+    pub(super) fn set_default_entry(&self, entry_id: &str) -> Result<(), super::Error> {
+        let mut conf = self.loader_conf()?;
+        conf.set_default_entry(&format!("{entry_id}.conf"));
+        self.write_loader_conf(&conf)
+    }
+
+    /// Restore the default "newest wins" pattern in `loader.conf`
+    pub(super) fn reset_default_entry(&self) -> Result<(), super::Error> {
+        let mut conf = self.loader_conf()?;
+        conf.set_default_entry(&self.default_pattern());
+        self.write_loader_conf(&conf)
+    }
+
+    /// Path to `loader.conf` on the boot partition
+    fn loader_conf_path(&self) -> PathBuf {
+        self.boot_root
+            .join_insensitive("loader")
+            .join_insensitive("loader.conf")
+    }
+
+    /// Load the current `loader.conf`, or an empty one if it doesn't exist yet
+    pub(super) fn loader_conf(&self) -> Result<LoaderConf, super::Error> {
+        let path = self.loader_conf_path();
+        if !path.exists() {
+            return Ok(LoaderConf::default());
+        }
+        let Ok(conf) = fs::read_to_string(path).context(IoSnafu)?.parse();
+        Ok(conf)
+    }
+
+    /// Write `loader.conf` back to the boot partition
+    fn write_loader_conf(&self, conf: &LoaderConf) -> Result<(), super::Error> {
+        self.writer
+            .write(conf.to_string(), self.loader_conf_path())
+            .context(WriteSnafu)?;
+        Ok(())
+    }
+
+    /// Apply changes to `loader.conf`, only writing it back when modified
+    pub(super) fn update_loader_conf(&self, update: impl FnOnce(&mut LoaderConf)) -> Result<(), super::Error> {
+        let mut conf = self.loader_conf()?;
+        let original = conf.clone();
+        update(&mut conf);
+        if conf != original {
+            self.write_loader_conf(&conf)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the menu timeout from `loader.conf`
+    pub(super) fn timeout(&self) -> Result<Option<Timeout>, super::Error> {
+        Ok(self.loader_conf()?.timeout())
+    }
+
+    /// Retrieve the default entry from `loader.conf`
+    pub(super) fn default_entry(&self) -> Result<Option<String>, super::Error> {
+        Ok(self.loader_conf()?.default_entry().map(str::to_string))
+    }
+
+    /// Set the menu timeout in `loader.conf`, preserving all other keys
+    pub(super) fn set_timeout(&self, timeout: Timeout) -> Result<(), super::Error> {
+        let mut conf = self.loader_conf()?;
+        conf.set_timeout(timeout);
+        self.write_loader_conf(&conf)
+    }
+
     pub(super) fn sync_entries(
         &self,
         cmdline: impl Iterator<Item = &'a str>,
-        entries: &[Entry],
-        excluded_snippets: impl Iterator<Item = &'a str>,
+        entries: &[&Entry],
     ) -> Result<(), super::Error> {
-        let base_cmdline = cmdline.map(str::to_string).collect::<Vec<_>>();
-        let exclusions = excluded_snippets.map(str::to_string).collect::<Vec<_>>();
-        let mut installed_entries = vec![];
-        for entry in entries {
-            let entry_cmdline = entry
-                .cmdline
-                .iter()
-                .filter(|c| !exclusions.contains(&c.name))
-                .map(|c| c.snippet.clone())
-                .collect::<Vec<_>>();
-            let full_cmdline = base_cmdline
-                .iter()
-                .chain(entry_cmdline.iter())
-                .cloned()
-                .collect::<Vec<_>>();
+        self.check_space(entries)?;
 
-            let installed = self.install(&full_cmdline.join(" "), entry)?;
-            installed_entries.push(installed);
-        }
+        let base_cmdline = cmdline.collect::<Vec<_>>();
+        let install = |entry: &&Entry| self.install(&entry.effective_cmdline(&base_cmdline), entry);
+
+        // Install in a stable order so that logs and reproducible images don't depend on the caller
+        let mut entries = entries.to_vec();
+        entries.sort_by(|a, b| a.kernel.cmp(&b.kernel));
+
+        // Each entry installs to its own files, so entries (and their files) may be
+        // installed concurrently
+        #[cfg(feature = "par")]
+        let installed_entries = {
+            use rayon::prelude::*;
+            let run = || entries.par_iter().map(install).collect::<Result<Vec<_>, _>>();
+            match rayon::ThreadPoolBuilder::new().num_threads(COPY_THREADS).build() {
+                Ok(pool) => pool.install(run)?,
+                Err(e) => {
+                    log::warn!("Failed to create thread pool, using the global pool: {e}");
+                    run()?
+                }
+            }
+        };
+        #[cfg(not(feature = "par"))]
+        let installed_entries = entries.iter().map(install).collect::<Result<Vec<_>, _>>()?;
+
+        log::debug!(
+            "{} of {} entries modified",
+            installed_entries.iter().filter(|e| e.modified).count(),
+            installed_entries.len()
+        );
 
+        self.writer.progress(ProgressEvent::Cleanup);
         self.cleanup_stale_entries(&installed_entries)?;
+        self.hashes.save(self.writer).context(WriteSnafu)?;
 
         Ok(())
     }
 
-    /// Clean up stale loader configs and kernel directories
+    /// Ensure `$BOOT` has room for all files about to be copied, before anything is written
+    ///
+    /// Stale entries are only removed once the new ones are installed, so their space
+    /// doesn't count unless [`Loader::cleanup_first`] permits removing them up front.
+    fn check_space(&self, entries: &[&Entry]) -> Result<(), super::Error> {
+        let required = self.required_space(entries)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let available = BootEnvironment::available_space_at(&self.boot_root).context(QuerySpaceSnafu {
+            path: self.boot_root.clone(),
+        })?;
+        self.ensure_space(entries, available, required)
+    }
+
+    /// Fail unless `required` bytes fit into `available`, removing stale entries first when permitted
+    fn ensure_space(&self, entries: &[&Entry], available: u64, required: u64) -> Result<(), super::Error> {
+        log::trace!("install requires {required} bytes, {available} available");
+        if available >= required {
+            return Ok(());
+        }
+
+        if self.cleanup_first {
+            let planned = entries
+                .iter()
+                .map(|e| self.planned_result(e))
+                .collect::<Result<Vec<_>, _>>()?;
+            let stale = self.stale_entries(&planned);
+            let reclaimable = stale.size();
+            if available + reclaimable >= required {
+                log::warn!(
+                    "Insufficient space on {}, removing stale entries first to free {}",
+                    self.boot_root.display(),
+                    format_size(reclaimable)
+                );
+                self.remove_stale(&stale);
+                return Ok(());
+            }
+        }
+
+        InsufficientSpaceSnafu {
+            path: self.boot_root.clone(),
+            available,
+            required,
+        }
+        .fail()
+    }
+
+    /// Net bytes required to install the entries: every file needing a copy, less the
+    /// installed copies it replaces, plus room to stage the largest of them
+    fn required_space(&self, entries: &[&Entry]) -> Result<u64, super::Error> {
+        let size = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or_default();
+        let (mut added, mut replaced, mut staging) = (0, 0, 0);
+        for entry in entries {
+            let changeset = self.changeset(entry)?;
+            for (source, dest) in self.hashes.changed_files(&changeset) {
+                let source_size = size(source);
+                added += source_size;
+                replaced += size(dest);
+                staging = staging.max(source_size);
+            }
+        }
+        Ok(if added == 0 {
+            0
+        } else {
+            added.saturating_sub(replaced) + staging
+        })
+    }
+
+    /// The result of installing the entry, without installing it
+    fn planned_result(&self, entry: &Entry) -> Result<InstallResult, super::Error> {
+        if entry.is_type2() {
+            return Ok(InstallResult {
+                uki: Some(self.entry_file(entry).to_string_lossy().to_string()),
+                ..Default::default()
+            });
+        }
+        let changeset = self.changeset(entry)?;
+        let kernel_dir = changeset[0].1.parent().context(MissingFileSnafu {
+            filename: "vmlinuz parent",
+        })?;
+        Ok(InstallResult {
+            loader_conf: Some(self.entry_file(entry).to_string_lossy().to_string()),
+            kernel_dir: Some(kernel_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// The loader config (type #1) or UKI (type #2) installed for this entry
+    fn entry_file(&self, entry: &Entry) -> PathBuf {
+        let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
+        if entry.is_type2() {
+            self.boot_root
+                .join_insensitive("EFI")
+                .join_insensitive("Linux")
+                .join_insensitive(format!("{}.efi", entry.id(effective_schema)))
+        } else {
+            self.boot_root
+                .join_insensitive("loader")
+                .join_insensitive("entries")
+                .join_insensitive(format!("{}.conf", entry.id(effective_schema)))
+        }
+    }
+
+    /// Clean up stale loader configs, kernel directories and UKIs
     fn cleanup_stale_entries(&self, installed_entries: &[InstallResult]) -> Result<(), super::Error> {
-        let all_namespaces = match self.schema {
+        self.remove_stale(&self.stale_entries(installed_entries));
+        Ok(())
+    }
+
+    /// Remove the given stale entries
+    fn remove_stale(&self, stale: &StaleEntries) {
+        fn refs(paths: &[PathBuf]) -> Vec<&PathBuf> {
+            paths.iter().collect()
+        }
+        self.remove_entries(&refs(&stale.confs), &refs(&stale.kernels), &refs(&stale.ukis));
+    }
+
+    /// Loader configs, kernel directories and UKIs not belonging to the given installed entries
+    fn stale_entries(&self, installed_entries: &[InstallResult]) -> StaleEntries {
+        let loader_files = self.owned_loader_files();
+        let kernel_dirs = self.owned_kernel_dirs();
+        let ukis = self.owned_ukis();
+
+        let obsolete_loader_confs = loader_files
+            .iter()
+            .filter(|f| {
+                !installed_entries
+                    .iter()
+                    .any(|e| e.loader_conf.as_deref() == Some(&f.to_string_lossy()))
+            })
+            .collect::<Vec<_>>();
+
+        // Other installations of our OS may share kernel directories with us
+        let foreign_files = self
+            .installed_entries()
+            .into_iter()
+            .filter(|(path, _)| !loader_files.contains(path))
+            .flat_map(|(_, entry)| {
+                entry
+                    .linux
+                    .iter()
+                    .chain(entry.initrd.iter())
+                    .map(|f| audit::resolve(&self.boot_root, f))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let obsolete_kernels = kernel_dirs
+            .iter()
+            .filter(|f| {
+                !installed_entries
+                    .iter()
+                    .any(|e| e.kernel_dir.as_deref() == Some(&f.to_string_lossy()))
+                    && !foreign_files.iter().any(|file| file.starts_with(f))
+            })
+            .collect::<Vec<_>>();
+
+        let obsolete_ukis = ukis
+            .iter()
+            .filter(|f| {
+                !installed_entries
+                    .iter()
+                    .any(|e| e.uki.as_deref() == Some(&f.to_string_lossy()))
+            })
+            .collect::<Vec<_>>();
+
+        StaleEntries {
+            confs: obsolete_loader_confs.into_iter().cloned().collect(),
+            kernels: obsolete_kernels.into_iter().cloned().collect(),
+            ukis: obsolete_ukis.into_iter().cloned().collect(),
+        }
+    }
+
+    /// Remove a specific kernel version from the boot partition
+    ///
+    /// Returns `false` if nothing matching the version was found
+    pub(super) fn remove_kernel(&self, version: &str) -> Result<bool, super::Error> {
+        let loader_files = self.owned_loader_files();
+        let confs = loader_files
+            .iter()
+            .filter(|f| {
+                f.file_name()
+                    .is_some_and(|n| self.entry_matches_version(&n.to_string_lossy(), version))
+            })
+            .collect::<Vec<_>>();
+
+        let kernel_dirs = self.owned_kernel_dirs();
+        let trees = kernel_dirs
+            .iter()
+            .filter(|d| d.file_name().is_some_and(|n| n == version))
+            .collect::<Vec<_>>();
+
+        // Legacy schemas install flat `kernel-*`/`initrd-*` files into the namespace directory
+        let legacy_files = match self.schema {
+            Schema::Legacy { .. } => self
+                .owned_namespace_dirs()
+                .iter()
+                .filter_map(|d| fs::read_dir(d).ok())
+                .flatten()
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.is_file()
+                        && p.file_name()
+                            .map(|n| n.to_string_lossy())
+                            .is_some_and(|n| n.ends_with(&format!(".{version}")))
+                })
+                .collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        let ukis = self.owned_ukis();
+        let files = legacy_files
+            .iter()
+            .chain(ukis.iter().filter(|u| {
+                u.file_stem()
+                    .is_some_and(|n| self.entry_matches_version(&n.to_string_lossy(), version))
+            }))
+            .collect::<Vec<_>>();
+
+        if confs.is_empty() && trees.is_empty() && files.is_empty() {
+            return Ok(false);
+        }
+
+        self.remove_entries(&confs, &trees, &files);
+
+        Ok(true)
+    }
+
+    /// Determine whether the entry ID (or filename) belongs to us and the given kernel version
+    ///
+    /// Entry IDs are `$prefix-$version` with an optional `-$state_id` suffix
+    pub(super) fn entry_matches_version(&self, entry_id: &str, version: &str) -> bool {
+        let stem = strip_boot_counter(entry_id);
+        self.owned_prefixes().iter().any(|prefix| {
+            stem.strip_prefix(prefix.as_str())
+                .and_then(|s| s.strip_prefix('-'))
+                .and_then(|s| s.strip_prefix(version))
+                .is_some_and(|rest| {
+                    rest.is_empty()
+                        || rest
+                            .strip_prefix('-')
+                            .is_some_and(|id| id.chars().all(|c| c.is_ascii_digit()))
+                })
+        })
+    }
+
+    /// Mark the given entry as successfully booted
+    ///
+    /// Entries using boot counting (`$id+$left-$done.conf`) are renamed to drop the
+    /// counter, in the same fashion as `systemd-bless-boot`. Returns `false` if the
+    /// entry isn't one of ours.
+    pub(super) fn bless_entry(&self, entry_id: &str) -> Result<bool, super::Error> {
+        let wanted = strip_boot_counter(entry_id);
+        let Some(path) = self.owned_loader_files().into_iter().find(|f| {
+            f.file_name()
+                .is_some_and(|n| strip_boot_counter(&n.to_string_lossy()) == wanted)
+        }) else {
+            return Ok(false);
+        };
+
+        let blessed = path.with_file_name(format!("{wanted}.conf"));
+        if blessed != path {
+            log::info!("Marking {} as successfully booted", blessed.display());
+            self.writer.rename(&path, &blessed).context(IoSnafu)?;
+        } else {
+            log::debug!("{} does not use boot counting", path.display());
+        }
+
+        Ok(true)
+    }
+
+    /// All namespaces owned by us on the boot partition, including former identities
+    fn owned_namespaces(&self) -> Vec<String> {
+        match self.schema {
             Schema::OsInfo { os_info } => {
                 // Include all former identities
                 let mut old_ids = os_info
@@ -163,9 +907,28 @@ impl<'a, 'b> Loader<'a, 'b> {
                 old_ids
             }
             _ => vec![self.schema.os_namespace()],
-        };
+        }
+    }
 
-        let all_prefixes = match self.schema {
+    /// All loader entry prefixes owned by us, including former identities
+    ///
+    /// With a machine ID, the prefixed forms come first so they're matched in preference
+    /// to the unprefixed ones, which remain ours to migrate from.
+    fn owned_prefixes(&self) -> Vec<String> {
+        let prefixes = self.schema_prefixes();
+        match self.machine_id {
+            Some(machine_id) => prefixes
+                .iter()
+                .map(|p| format!("{machine_id}-{p}"))
+                .chain(prefixes.iter().cloned())
+                .collect(),
+            None => prefixes,
+        }
+    }
+
+    /// Loader entry prefixes for the schema, including former identities
+    fn schema_prefixes(&self) -> Vec<String> {
+        match self.schema {
             Schema::OsInfo { os_info } => {
                 // Include all former identities
                 let mut old_ids = os_info
@@ -180,77 +943,236 @@ impl<'a, 'b> Loader<'a, 'b> {
             }
             Schema::Legacy { os_release, .. } => vec![os_release.name.clone()],
             _ => vec![self.schema.os_id()],
+        }
+    }
+
+    /// Parse every loader entry on `$BOOT`, including those not owned by us
+    pub(super) fn installed_entries(&self) -> Vec<(PathBuf, LoaderEntry)> {
+        let loader_dir = self.boot_root.join_insensitive("loader").join_insensitive("entries");
+        let Ok(dir) = fs::read_dir(&loader_dir) else {
+            return vec![];
         };
 
+        let mut entries = dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("conf")))
+            .filter_map(|path| match fs::read_to_string(&path) {
+                Ok(text) => Some((path, LoaderEntry::parse(&text))),
+                Err(e) => {
+                    log::warn!("Skipping unreadable loader entry: {e}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+
+    /// Cross-check all loader entries against the files on `$BOOT`
+    pub(super) fn audit(&self) -> AuditReport {
+        audit::scan(
+            &self.boot_root,
+            &self.installed_entries(),
+            &self.owned_namespaces(),
+            &self.owned_prefixes(),
+        )
+    }
+
+    /// Find all loader files that match any of our prefixes
+    pub(super) fn owned_loader_files(&self) -> Vec<PathBuf> {
+        let all_prefixes = self.owned_prefixes();
         let loader_dir = self.boot_root.join_insensitive("loader").join_insensitive("entries");
 
-        // Find all loader files that match any of our prefixes
         let mut loader_files = Vec::new();
         if let Ok(entries) = fs::read_dir(&loader_dir) {
             for entry in entries.filter_map(|e| e.ok()) {
                 let file_name = entry.file_name().to_string_lossy().to_string();
-                if all_prefixes.iter().any(|prefix| file_name.starts_with(prefix)) {
+                if all_prefixes.iter().any(|prefix| file_name.starts_with(prefix))
+                    && self.owned_by_machine(&entry.path())
+                {
                     loader_files.push(entry.path());
                 }
             }
         }
+        loader_files
+    }
+
+    /// Loader entries belonging to other installations, i.e. another distribution sharing the ESP
+    pub(super) fn foreign_entries(&self) -> Vec<PathBuf> {
+        let owned = self.owned_loader_files();
+        let apps = self.owned_efi_app_files();
+        self.installed_entries()
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| !owned.contains(path) && !apps.contains(path))
+            .collect()
+    }
+
+    /// The `loader.conf` default, when it selects entries other than ours
+    fn foreign_default(&self) -> Result<Option<String>, super::Error> {
+        let prefixes = self.owned_prefixes();
+        let namespaces = self.owned_namespaces();
+        Ok(self.default_entry()?.filter(|default| {
+            !prefixes
+                .iter()
+                .chain(namespaces.iter())
+                .any(|p| default.starts_with(p.as_str()))
+        }))
+    }
+
+    /// Whether the loader entry belongs to this machine, rather than another installation of the same OS
+    ///
+    /// Only entries declaring a different `machine-id` are excluded.
+    fn owned_by_machine(&self, path: &Path) -> bool {
+        let Some(machine_id) = self.machine_id else {
+            return true;
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => LoaderEntry::parse(&text)
+                .machine_id
+                .is_none_or(|id| id.eq_ignore_ascii_case(machine_id)),
+            Err(_) => true,
+        }
+    }
+
+    /// Existing `EFI/$namespace` directories owned by us
+    fn owned_namespace_dirs(&self) -> Vec<PathBuf> {
+        self.owned_namespaces()
+            .iter()
+            .map(|namespace| self.boot_root.join_insensitive("EFI").join_insensitive(namespace))
+            .filter(|d| d.exists())
+            .collect()
+    }
 
-        // Check each namespace for kernel directories
+    /// Check each namespace for kernel directories
+    fn owned_kernel_dirs(&self) -> Vec<PathBuf> {
         let mut kernel_dirs = Vec::new();
-        for namespace in &all_namespaces {
-            let efi_dir = self.boot_root.join_insensitive("EFI").join_insensitive(namespace);
-            if efi_dir.exists() {
-                if let Ok(entries) = fs::read_dir(&efi_dir) {
-                    for entry in entries.filter_map(|e| e.ok()) {
-                        if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
-                            kernel_dirs.push(entry.path());
-                        }
+        for efi_dir in self.owned_namespace_dirs() {
+            if let Ok(entries) = fs::read_dir(&efi_dir) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    let is_tools = entry.file_name().eq_ignore_ascii_case(EFI_TOOLS_DIR);
+                    if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) && !is_tools {
+                        kernel_dirs.push(entry.path());
                     }
                 }
             }
         }
+        kernel_dirs
+    }
 
-        let obsolete_loader_confs = loader_files
-            .iter()
-            .filter(|f| !installed_entries.iter().any(|e| e.loader_conf == f.to_string_lossy()))
-            .collect::<Vec<_>>();
+    /// Type #2 entries (`EFI/Linux/*.efi`) that match any of our prefixes
+    fn owned_ukis(&self) -> Vec<PathBuf> {
+        let all_prefixes = self.owned_prefixes();
+        let uki_dir = self.boot_root.join_insensitive("EFI").join_insensitive("Linux");
 
-        let obsolete_kernels = kernel_dirs
-            .iter()
-            .filter(|f| !installed_entries.iter().any(|e| e.kernel_dir == f.to_string_lossy()))
-            .collect::<Vec<_>>();
+        let mut ukis = Vec::new();
+        if let Ok(entries) = fs::read_dir(&uki_dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                    continue;
+                };
+                let is_efi = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("efi"));
+                if is_efi
+                    && all_prefixes
+                        .iter()
+                        .any(|prefix| stem.starts_with(&format!("{prefix}-")))
+                {
+                    ukis.push(path);
+                }
+            }
+        }
+        ukis
+    }
 
-        for conf in obsolete_loader_confs.iter() {
+    /// Remove loader configs, kernel trees and loose files, logging any failures
+    fn remove_entries(&self, confs: &[&PathBuf], trees: &[&PathBuf], files: &[&PathBuf]) {
+        for conf in confs.iter() {
             log::info!("Removing stale loader config: {conf:?}");
-            if let Err(e) = fs::remove_file(conf) {
+            if let Err(e) = self.writer.remove_file(conf) {
                 log::error!("Failed to remove stale loader config {conf:?}: {e}")
             }
         }
 
-        for tree in obsolete_kernels.iter() {
+        for tree in trees.iter() {
             log::info!("Removing stale kernel tree: {tree:?}");
-            if let Err(e) = fs::remove_dir_all(tree) {
+            if let Err(e) = self.writer.remove_dir_all(tree) {
                 log::error!("Failed to remove stale kernel tree {tree:?}: {e}")
             }
         }
 
+        for file in files.iter() {
+            log::info!("Removing stale kernel file: {file:?}");
+            if let Err(e) = self.writer.remove_file(file) {
+                log::error!("Failed to remove stale kernel file {file:?}: {e}")
+            }
+        }
+    }
+
+    /// Copy all `(source, dest)` pairs whose contents differ
+    ///
+    /// Returns `true` if anything was (or would be) copied, only once every copy
+    /// has completed.
+    fn copy_changed(&self, files: &[(PathBuf, PathBuf)]) -> Result<bool, super::Error> {
+        let needs_writing = self.hashes.changed_files(files);
+        log::trace!("requires update: {needs_writing:?}");
+
+        #[cfg(feature = "par")]
+        {
+            use rayon::prelude::*;
+            needs_writing
+                .par_iter()
+                .try_for_each(|(source, dest)| self.copy_file(source, dest))?;
+        }
+        #[cfg(not(feature = "par"))]
+        for (source, dest) in needs_writing.iter() {
+            self.copy_file(source, dest)?;
+        }
+
+        Ok(!needs_writing.is_empty())
+    }
+
+    /// Copy a single file to `$BOOT`, verifying the installed copy
+    fn copy_file(&self, source: &Path, dest: &Path) -> Result<(), super::Error> {
+        self.writer.copy(source, dest).context(WriteSnafu)?;
+        if !self.writer.dry_run() {
+            let verified = self.hashes.copied(dest).context(IoSnafu)?;
+            ensure!(verified, VerificationFailedSnafu { path: dest });
+        }
         Ok(())
     }
 
-    /// Install a kernel to the ESP or XBOOTLDR, write a config for it
-    fn install(&self, cmdline: &str, entry: &Entry) -> Result<InstallResult, super::Error> {
+    /// Files to install for the given entry as `(source, dest)` pairs, starting with the image
+    fn changeset(&self, entry: &Entry) -> Result<Vec<(PathBuf, PathBuf)>, super::Error> {
+        let mut changeset = self.boot_changeset(entry)?;
         let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
+        let sysroot = entry.sysroot.clone().unwrap_or_default();
+        let kernel_dir = self.get_kernel_dir(entry);
+        let device_trees = entry
+            .device_trees()
+            .filter_map(|asset| {
+                Some((
+                    sysroot.join(&asset.path),
+                    kernel_dir.join_insensitive(entry.installed_asset_name(effective_schema, asset)?),
+                ))
+            })
+            .collect::<Vec<_>>();
+        log::trace!("with devicetrees: {device_trees:?}");
 
-        let loader_id = self
-            .boot_root
-            .join_insensitive("loader")
-            .join_insensitive("entries")
-            .join_insensitive(format!("{}.conf", entry.id(effective_schema)));
-        log::trace!("writing entry: {}", loader_id.display());
+        changeset.extend(device_trees);
+        Ok(changeset)
+    }
 
+    /// The image and initrds loaded at boot, as `(source, dest)` pairs
+    fn boot_changeset(&self, entry: &Entry) -> Result<Vec<(PathBuf, PathBuf)>, super::Error> {
         let sysroot = entry.sysroot.clone().unwrap_or_default();
+        let image = sysroot.join(&entry.kernel.image);
+        if entry.is_type2() {
+            return Ok(vec![(image, self.entry_file(entry))]);
+        }
 
-        // Get kernel directory for this specific entry
+        let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
         let kernel_dir = self.get_kernel_dir(entry);
 
         // vmlinuz primary path
@@ -259,11 +1181,10 @@ impl<'a, 'b> Loader<'a, 'b> {
                 .installed_kernel_name(effective_schema)
                 .context(MissingFileSnafu { filename: "vmlinuz" })?,
         );
-        // initrds requiring install
-        let initrds = entry
-            .kernel
-            .initrd
-            .iter()
+        // initrds requiring install, UKIs carry their own
+        let initrds = ordered_initrds(entry)
+            .into_iter()
+            .filter(|_| !entry.is_uki())
             .filter_map(|asset| {
                 Some((
                     sysroot.join(&asset.path),
@@ -274,19 +1195,44 @@ impl<'a, 'b> Loader<'a, 'b> {
         log::trace!("with kernel path: {}", vmlinuz.display());
         log::trace!("with initrds: {initrds:?}");
 
-        // build up the total changeset
-        let mut changeset = vec![(sysroot.join(&entry.kernel.image), vmlinuz.clone())];
+        let mut changeset = vec![(image, vmlinuz)];
         changeset.extend(initrds);
+        Ok(changeset)
+    }
 
-        // Determine which need copying now.
-        let needs_writing = changed_files(changeset.as_slice());
-        log::trace!("requires update: {needs_writing:?}");
+    /// Compare the installed files of each installed entry against their sources
+    ///
+    /// Returns the installed files that are missing or differ.
+    pub(super) fn verify_entries(&self, entries: &[&Entry]) -> Result<Vec<PathBuf>, super::Error> {
+        let mut mismatches = vec![];
+        for entry in entries.iter().filter(|e| self.entry_file(e).exists()) {
+            let changeset = self.changeset(entry)?;
+            mismatches.extend(changed_files(&changeset).into_iter().map(|(_, dest)| dest.clone()));
+        }
+        Ok(mismatches)
+    }
 
-        // Donate them to disk
-        for (source, dest) in needs_writing {
-            copy_atomic_vfat(source, dest).context(IoSnafu)?;
+    /// Install a kernel to the ESP or XBOOTLDR, write a config for it
+    fn install(&self, cmdline: &str, entry: &Entry) -> Result<InstallResult, super::Error> {
+        self.writer.progress(ProgressEvent::EntryInstall {
+            entry_id: self.entry_id(entry),
+        });
+        if entry.is_type2() {
+            return self.install_uki(entry);
         }
 
+        let loader_id = self.entry_file(entry);
+        log::trace!("writing entry: {}", loader_id.display());
+
+        // Get kernel directory for this specific entry
+        let kernel_dir = self.get_kernel_dir(entry);
+
+        let changeset = self.changeset(entry)?;
+        let vmlinuz = changeset[0].1.clone();
+
+        // Donate any changes to disk
+        let copied = self.copy_changed(&changeset)?;
+
         let asset_dir = kernel_dir
             .strip_prefix(&self.boot_root)
             .context(PrefixSnafu)?
@@ -295,39 +1241,101 @@ impl<'a, 'b> Loader<'a, 'b> {
         let loader_config = self.generate_entry(&asset_dir, cmdline, entry);
         log::trace!("loader config: {loader_config}");
 
-        let entry_dir = self.boot_root.join_insensitive("loader").join_insensitive("entries");
-        if !entry_dir.exists() {
-            fs::create_dir_all(entry_dir).context(IoSnafu)?;
+        let written = self.writer.write(loader_config, &loader_id).context(WriteSnafu)?;
+
+        Ok(InstallResult {
+            loader_conf: Some(loader_id.to_string_lossy().to_string()),
+            kernel_dir: Some(
+                vmlinuz
+                    .parent()
+                    .context(MissingFileSnafu {
+                        filename: "vmlinuz parent",
+                    })?
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            modified: copied || written,
+            ..Default::default()
+        })
+    }
+
+    /// Install a unified kernel image as a type #2 entry, in `EFI/Linux`
+    ///
+    /// The UKI carries its own cmdline and initrd, so no loader config is written.
+    fn install_uki(&self, entry: &Entry) -> Result<InstallResult, super::Error> {
+        let uki = self.entry_file(entry);
+        log::trace!("writing uki: {}", uki.display());
+
+        let copied = self.copy_changed(&self.changeset(entry)?)?;
+
+        Ok(InstallResult {
+            uki: Some(uki.to_string_lossy().to_string()),
+            modified: copied,
+            ..Default::default()
+        })
+    }
+
+    /// Menu title for the entry, i.e. `AerynOS (6.8.9-289.current)` or `AerynOS (6.8.2, lts)`,
+    /// unless the entry has its own title
+    pub(super) fn entry_title(&self, entry: &Entry) -> String {
+        if let Some(title) = entry.title() {
+            return title.to_string();
+        }
+        let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
+        let name = effective_schema
+            .os_display_name()
+            .unwrap_or_else(|| effective_schema.os_name());
+        match &entry.kernel.variant {
+            Some(variant) => format!("{name} ({}, {variant})", entry.kernel.version),
+            None => format!("{name} ({})", entry.kernel.version),
         }
+    }
 
-        let tracker = InstallResult {
-            loader_conf: loader_id.to_string_lossy().to_string(),
-            kernel_dir: vmlinuz
-                .parent()
-                .context(MissingFileSnafu {
-                    filename: "vmlinuz parent",
-                })?
-                .to_string_lossy()
-                .to_string(),
-        };
+    /// BLS `sort-key`, grouping entries by OS and then kernel variant
+    fn entry_sort_key(&self, entry: &Entry) -> String {
+        let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
+        match &entry.kernel.variant {
+            Some(variant) => format!("{}-{variant}", effective_schema.os_id()),
+            None => effective_schema.os_id(),
+        }
+    }
 
-        // TODO: Hash compare and dont obliterate!
-        fs::write(loader_id, loader_config).context(IoSnafu)?;
+    /// Entry ID, as used for the loader config file name
+    pub(super) fn entry_id(&self, entry: &Entry) -> String {
+        entry.id(entry.schema.as_ref().unwrap_or(self.schema))
+    }
+
+    /// Kernel image and initrds of a type #1 entry, relative to `$BOOT`
+    #[cfg(feature = "grub2")]
+    pub(super) fn boot_paths(&self, entry: &Entry) -> Result<(String, Vec<String>), super::Error> {
+        let mut paths = self
+            .boot_changeset(entry)?
+            .into_iter()
+            .map(|(_, dest)| {
+                dest.strip_prefix(&self.boot_root)
+                    .map(|p| format!("/{}", p.display()))
+                    .context(PrefixSnafu)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let image = paths.remove(0);
+        Ok((image, paths))
+    }
 
-        Ok(tracker)
+    /// The `$BOOT` mountpoint
+    #[cfg(feature = "grub2")]
+    pub(super) fn boot_root(&self) -> &Path {
+        &self.boot_root
     }
 
     /// Generate a usable loader config entry
     fn generate_entry(&self, asset_dir: &str, cmdline: &str, entry: &Entry) -> String {
         let effective_schema = entry.schema.as_ref().unwrap_or(self.schema);
 
-        let initrd = if entry.kernel.initrd.is_empty() {
+        let initrd = if entry.kernel.initrd.is_empty() || entry.is_uki() {
             "\n".to_string()
         } else {
-            let initrds = entry
-                .kernel
-                .initrd
-                .iter()
+            let initrds = ordered_initrds(entry)
+                .into_iter()
                 .filter_map(|asset| {
                     Some(format!(
                         "\ninitrd /{asset_dir}/{}",
@@ -337,16 +1345,30 @@ impl<'a, 'b> Loader<'a, 'b> {
                 .collect::<String>();
             format!("\n{initrds}")
         };
-        let title = if let Some(pretty) = effective_schema.os_display_name() {
-            format!("{pretty} ({})", entry.kernel.version)
-        } else {
-            format!("{} ({})", effective_schema.os_name(), entry.kernel.version)
-        };
+        let devicetree = entry
+            .device_tree()
+            .and_then(|asset| entry.installed_asset_name(effective_schema, asset))
+            .map(|name| format!("devicetree /{asset_dir}/{name}\n"))
+            .unwrap_or_default();
+        if entry.devicetree.is_some() && devicetree.is_empty() {
+            log::warn!("Selected devicetree not found for kernel {}", entry.kernel.version);
+        }
+        let title = self.entry_title(entry);
+        let sort_key = self.entry_sort_key(entry);
+        let version = &entry.kernel.version;
+        let machine_id = entry
+            .machine_id
+            .as_ref()
+            .map(|id| format!("\nmachine-id {id}"))
+            .unwrap_or_default();
         let vmlinuz = entry.installed_kernel_name(effective_schema).expect("linux go boom");
+        let key = if entry.is_uki() { "efi" } else { "linux" };
         format!(
-            r###"title {title}
-linux /{asset_dir}/{vmlinuz}{initrd}
-options {cmdline}
+            r###"title {title}{machine_id}
+sort-key {sort_key}
+version {version}
+{key} /{asset_dir}/{vmlinuz}{initrd}
+{devicetree}options {cmdline}
 "###
         )
     }
@@ -358,6 +1380,11 @@ options {cmdline}
             .join_insensitive("EFI")
             .join_insensitive(self.schema.os_namespace());
 
+        // Nothing installed yet
+        if !base_kernel_dir.exists() {
+            return Ok(vec![]);
+        }
+
         for entry in fs::read_dir(&base_kernel_dir).context(IoSnafu)? {
             let entry = entry.context(IoSnafu)?;
             if !entry.file_type().context(IoSnafu)?.is_dir() {
@@ -371,10 +1398,681 @@ options {cmdline}
             all_paths.extend(paths);
         }
 
-        if let Ok(kernels) = self.schema.discover_system_kernels(all_paths.iter()) {
-            Ok(kernels)
-        } else {
-            Ok(vec![])
+        let mut kernels = self
+            .schema
+            .discover_system_kernels(all_paths.iter())
+            .unwrap_or_default();
+
+        // Type #2 entries
+        for uki in self.owned_ukis() {
+            let Some(version) = uki.file_stem().and_then(|s| self.uki_version(&s.to_string_lossy())) else {
+                continue;
+            };
+            if kernels.iter().any(|k| k.version == version) {
+                continue;
+            }
+            kernels.push(Kernel {
+                version,
+                image: uki,
+                initrd: vec![],
+                extras: vec![],
+                variant: None,
+                boot_meta: None,
+            });
+        }
+
+        kernels.sort_by(|a, b| b.cmp(a));
+        Ok(kernels)
+    }
+
+    /// Recover the kernel version from the file stem of one of our UKIs
+    ///
+    /// UKIs are named by entry ID (`$prefix-$version[-$state_id]`). A trailing numeric
+    /// component is only taken as a state ID if what remains still looks like a
+    /// `$version-$release` pair.
+    fn uki_version(&self, stem: &str) -> Option<String> {
+        let stem = strip_boot_counter(stem);
+        let rest = self
+            .owned_prefixes()
+            .iter()
+            .find_map(|prefix| stem.strip_prefix(prefix.as_str())?.strip_prefix('-'))?;
+        match rest.rsplit_once('-') {
+            Some((version, state_id))
+                if version.contains('-') && !state_id.is_empty() && state_id.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Some(version.to_string())
+            }
+            _ => Some(rest.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use fs_err as fs;
+    use tempfile::TempDir;
+
+    use super::Loader;
+    use crate::{
+        Architecture, AuxiliaryKind, Configuration, EfiAppEntry, Entry, Kernel, Root, Schema,
+        bootloader::{Error, SyncOptions},
+        file_utils::{ChangeDetection, Writer},
+        manager::Mounts,
+        os_release::OsRelease,
+    };
+
+    /// An AerynOS tree in a tempdir, with `boot` as the ESP
+    struct Fixture {
+        tree: TempDir,
+        boot: PathBuf,
+        schema: Schema,
+        mounts: Mounts,
+        writer: Writer,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self::with_writer(Writer::default())
+        }
+
+        fn with_writer(writer: Writer) -> Self {
+            let tree = tempfile::tempdir().expect("Failed to create tempdir");
+            let boot = tree.path().join("boot");
+            fs::create_dir_all(&boot).unwrap();
+            Self {
+                schema: Schema::Blsforme {
+                    os_release: Box::new(OsRelease::from_str("ID=aerynos\nNAME=AerynOS").unwrap()),
+                },
+                mounts: Mounts {
+                    xbootldr: None,
+                    esp: Some(boot.clone()),
+                },
+                boot,
+                tree,
+                writer,
+            }
+        }
+
+        fn root(&self) -> &Path {
+            self.tree.path()
+        }
+
+        /// Create the kernel directory for `version` beneath `usr/lib/kernel`
+        fn kernel_dir(&self, version: &str) -> PathBuf {
+            let dir = self.root().join("usr/lib/kernel").join(version);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        /// Discover the kernels within the given kernel directories
+        fn kernels(&self, dirs: &[PathBuf]) -> Vec<Kernel> {
+            let paths = dirs.iter().flat_map(|dir| {
+                let mut paths = vec![dir.clone()];
+                paths.extend(fs::read_dir(dir).unwrap().filter_map(|e| e.ok()).map(|e| e.path()));
+                paths
+            });
+            self.schema.discover_system_kernels(paths).unwrap()
+        }
+
+        fn loader(&self) -> Loader<'_, '_> {
+            self.loader_for(&self.mounts)
+        }
+
+        /// A loader syncing to other mounts than our own
+        fn loader_for<'a>(&'a self, mounts: &'a Mounts) -> Loader<'a, 'a> {
+            Loader::new(
+                &self.schema,
+                &[],
+                mounts,
+                None,
+                &self.writer,
+                ChangeDetection::default(),
+            )
+            .unwrap()
+        }
+
+        /// A loader installing the given bootloader assets
+        fn loader_with_assets<'a>(&'a self, assets: &'a [PathBuf]) -> Loader<'a, 'a> {
+            Loader::new(
+                &self.schema,
+                assets,
+                &self.mounts,
+                Some(Architecture::X86_64),
+                &self.writer,
+                ChangeDetection::default(),
+            )
+            .unwrap()
+        }
+    }
+
+    /// Contents of every file beneath `root`, keyed by relative path
+    fn snapshot(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>) {
+        for entry in fs::read_dir(dir).unwrap().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                snapshot(root, &path, files);
+            } else {
+                files.insert(path.strip_prefix(root).unwrap().into(), fs::read(&path).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_reproducible_sync() {
+        let fixture = Fixture::new();
+        let dirs = ["6.8.2-25.desktop", "6.10.2-31.desktop", "6.9.1-30.desktop"]
+            .into_iter()
+            .map(|version| {
+                let dir = fixture.kernel_dir(version);
+                fs::write(dir.join("vmlinuz"), version).unwrap();
+                fs::write(dir.join("10-default.initrd"), "initrd").unwrap();
+                dir
+            })
+            .collect::<Vec<_>>();
+
+        let sync = |boot: &Path, reverse: bool, epoch: Option<SystemTime>| {
+            let kernels = fixture.kernels(&dirs);
+            let entries = kernels.iter().map(Entry::new).collect::<Vec<_>>();
+            let mut entries = entries.iter().collect::<Vec<_>>();
+            if reverse {
+                entries.reverse();
+            }
+
+            fs::create_dir_all(boot).unwrap();
+            let mounts = Mounts {
+                xbootldr: None,
+                esp: Some(boot.to_path_buf()),
+            };
+            // Independent of any `SOURCE_DATE_EPOCH` in the environment
+            let writer = Writer::default().with_reproducible(true).with_source_date_epoch(epoch);
+            let loader = Loader::new(&fixture.schema, &[], &mounts, None, &writer, ChangeDetection::default()).unwrap();
+            loader.sync_entries(["rw"].into_iter(), &entries).unwrap();
+
+            let mut files = BTreeMap::new();
+            snapshot(boot, boot, &mut files);
+            files
+        };
+
+        let first = fixture.root().join("first");
+        let second = fixture.root().join("second");
+        let files = sync(&first, false, None);
+        assert!(!files.is_empty());
+        assert_eq!(files, sync(&second, true, None));
+
+        // Copies carry the mtime of their source
+        let source = dirs[2].join("vmlinuz");
+        let dest = first.join("EFI/aerynos/6.9.1-30.desktop/vmlinuz");
+        assert_eq!(
+            fs::metadata(dest).unwrap().modified().unwrap(),
+            fs::metadata(source).unwrap().modified().unwrap()
+        );
+
+        // ..unless a fixed timestamp is given, which every file then takes
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let third = fixture.root().join("third");
+        sync(&third, false, Some(epoch));
+        for path in [
+            "EFI/aerynos/6.9.1-30.desktop/vmlinuz",
+            "loader/entries/aerynos-6.9.1-30.desktop.conf",
+        ] {
+            assert_eq!(fs::metadata(third.join(path)).unwrap().modified().unwrap(), epoch);
+        }
+    }
+
+    #[test]
+    fn test_machine_id_entries() {
+        let fixture = Fixture::new();
+        let ours = "0123456789abcdef0123456789abcdef";
+        let theirs = "fedcba9876543210fedcba9876543210";
+        let boot = &fixture.boot;
+        let entries_dir = boot.join("loader/entries");
+        fs::create_dir_all(&entries_dir).unwrap();
+        fs::write(
+            entries_dir.join("aerynos-6.8.0-20.desktop.conf"),
+            format!("title AerynOS\nmachine-id {theirs}\nlinux /EFI/aerynos/6.8.0-20.desktop/vmlinuz\n"),
+        )
+        .unwrap();
+        for version in ["6.8.0-20.desktop", "6.7.0-10.desktop"] {
+            let dir = boot.join("EFI/aerynos").join(version);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("vmlinuz"), version).unwrap();
+        }
+        fs::write(
+            entries_dir.join("aerynos-6.7.0-10.desktop.conf"),
+            "title AerynOS\nlinux /EFI/aerynos/6.7.0-10.desktop/vmlinuz\n",
+        )
+        .unwrap();
+
+        let kernel_dir = fixture.kernel_dir("6.8.2-25.desktop");
+        fs::write(kernel_dir.join("vmlinuz"), "vmlinuz").unwrap();
+        let kernels = fixture.kernels(&[kernel_dir]);
+        let entry = Entry::new(&kernels[0]).with_machine_id(ours);
+        assert_eq!(entry.id(&fixture.schema), format!("{ours}-aerynos-6.8.2-25.desktop"));
+
+        let mut loader = fixture.loader();
+        loader.machine_id = Some(ours);
+        loader.sync_entries(["rw"].into_iter(), &[&entry]).unwrap();
+
+        let conf = fs::read_to_string(entries_dir.join(format!("{ours}-aerynos-6.8.2-25.desktop.conf"))).unwrap();
+        assert!(conf.contains(&format!("\nmachine-id {ours}\n")));
+        assert_eq!(loader.default_pattern(), format!("{ours}-aerynos*"));
+
+        // Our unprefixed entry is replaced, the other installation's is left alone
+        assert!(!entries_dir.join("aerynos-6.7.0-10.desktop.conf").exists());
+        assert!(!boot.join("EFI/aerynos/6.7.0-10.desktop").exists());
+        assert!(entries_dir.join("aerynos-6.8.0-20.desktop.conf").exists());
+        assert!(boot.join("EFI/aerynos/6.8.0-20.desktop/vmlinuz").exists());
+    }
+
+    #[test]
+    fn test_failed_copy_skips_entry() {
+        let fixture = Fixture::new();
+        let kernel_dir = fixture.kernel_dir("6.8.2-25.desktop");
+
+        // Large, sparse assets to keep copies busy
+        for name in ["vmlinuz", "10-default.initrd", "20-extra.initrd"] {
+            fs::File::create(kernel_dir.join(name))
+                .unwrap()
+                .set_len(8 * 1024 * 1024)
+                .unwrap();
+        }
+        let kernels = fixture.kernels(std::slice::from_ref(&kernel_dir));
+        let entry = Entry::new(&kernels[0]);
+        let missing = kernel_dir.join("20-extra.initrd");
+        fs::remove_file(&missing).unwrap();
+
+        let loader = fixture.loader();
+        let err = loader.sync_entries(["rw"].into_iter(), &[&entry]).unwrap_err();
+        assert!(
+            matches!(&err, Error::Write { source } if matches!(&**source, crate::Error::VfatWrite { from, .. } if *from == missing)),
+            "unexpected error: {err}"
+        );
+
+        // The entry is only written once all of its files are in place
+        assert!(
+            !fixture
+                .boot
+                .join("loader/entries/aerynos-6.8.2-25.desktop.conf")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_excluded_snippets() {
+        let fixture = Fixture::new();
+        let kernel_root = fixture.root().join("usr/lib/kernel");
+        let dirs = ["6.8.2-25.lts", "6.9.1-30.desktop", "6.10.2-31.desktop"]
+            .into_iter()
+            .map(|version| {
+                let dir = fixture.kernel_dir(version);
+                fs::write(dir.join("vmlinuz"), version).unwrap();
+                dir
+            })
+            .collect::<Vec<_>>();
+        fs::write(
+            kernel_root.join("6.8.2-25.lts/excluded.cmdline.d"),
+            "# No splash for LTS\n20-splash.cmdline\n",
+        )
+        .unwrap();
+        fs::create_dir_all(kernel_root.join("cmdline.d")).unwrap();
+        fs::write(kernel_root.join("cmdline.d/00-quiet.cmdline"), "quiet").unwrap();
+        fs::write(kernel_root.join("cmdline.d/20-splash.cmdline"), "splash").unwrap();
+
+        let config = Configuration::builder()
+            .root(Root::Image(fixture.root().to_path_buf()))
+            .build()
+            .unwrap();
+        let kernels = fixture.kernels(&dirs);
+        let entries = kernels
+            .iter()
+            .map(|kernel| {
+                let entry = Entry::new(kernel);
+                let mut entry = if kernel.version == "6.10.2-31.desktop" {
+                    entry.with_excluded_snippet("00-quiet.cmdline")
+                } else {
+                    entry
+                };
+                entry.load_cmdline_snippets(&config).unwrap();
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        let loader = fixture.loader();
+        loader
+            .sync_entries(["rw"].into_iter(), &entries.iter().collect::<Vec<_>>())
+            .unwrap();
+
+        let options = |version: &str| {
+            let conf = fs::read_to_string(fixture.boot.join(format!("loader/entries/aerynos-{version}.conf"))).unwrap();
+            conf.lines().find(|l| l.starts_with("options ")).unwrap().to_string()
+        };
+        assert_eq!(options("6.8.2-25.lts"), "options rw quiet");
+        assert_eq!(options("6.9.1-30.desktop"), "options rw quiet splash");
+        assert_eq!(options("6.10.2-31.desktop"), "options rw splash");
+    }
+
+    #[test]
+    fn test_shared_esp() {
+        let fixture = Fixture::new();
+        let boot = &fixture.boot;
+        fs::create_dir_all(boot.join("loader/entries")).unwrap();
+        fs::create_dir_all(boot.join("EFI/Boot")).unwrap();
+        fs::write(boot.join("loader/entries/fedora-6.9.0.conf"), "linux /fedora/vmlinuz\n").unwrap();
+        fs::write(boot.join("loader/loader.conf"), "default fedora*\n").unwrap();
+        fs::write(boot.join("EFI/Boot/BOOTX64.EFI"), "fedora shim").unwrap();
+        let asset = fixture.root().join("systemd-bootx64.efi");
+        fs::write(&asset, "#### LoaderInfo: systemd-boot 257.5 ####").unwrap();
+        let assets = [asset];
+
+        let loader = fixture.loader_with_assets(&assets);
+        assert_eq!(
+            loader.foreign_entries(),
+            vec![boot.join("loader/entries/fedora-6.9.0.conf")]
+        );
+
+        // Only our own files are installed
+        let options = SyncOptions::default();
+        loader.sync(&options).unwrap();
+        assert_eq!(
+            fs::read_to_string(boot.join("EFI/Boot/BOOTX64.EFI")).unwrap(),
+            "fedora shim"
+        );
+        assert_eq!(loader.default_entry().unwrap().as_deref(), Some("fedora*"));
+        assert!(boot.join("EFI/systemd/systemd-bootx64.efi").exists());
+        assert_eq!(
+            loader.fallback_bootloader(&options),
+            Some((boot.join("EFI/Boot/BOOTX64.EFI"), false))
+        );
+
+        let options = SyncOptions {
+            take_ownership: true,
+            ..Default::default()
+        };
+        loader.sync(&options).unwrap();
+        assert_eq!(loader.default_entry().unwrap().as_deref(), Some("aerynos*"));
+        assert_eq!(
+            loader.fallback_bootloader(&options),
+            Some((boot.join("EFI/Boot/BOOTX64.EFI"), true))
+        );
+    }
+
+    #[test]
+    fn test_shared_esp_without_default() {
+        let fixture = Fixture::new();
+        let boot = &fixture.boot;
+        fs::create_dir_all(boot.join("loader/entries")).unwrap();
+        fs::create_dir_all(boot.join("EFI/Boot")).unwrap();
+        fs::write(boot.join("loader/entries/fedora-6.9.0.conf"), "linux /fedora/vmlinuz\n").unwrap();
+        fs::write(boot.join("loader/loader.conf"), "timeout 5\n").unwrap();
+        fs::write(boot.join("EFI/Boot/BOOTX64.EFI"), "fedora shim").unwrap();
+        let asset = fixture.root().join("systemd-bootx64.efi");
+        fs::write(&asset, "#### LoaderInfo: systemd-boot 257.5 ####").unwrap();
+        let assets = [asset];
+
+        // Foreign entries alone mean sharing, so no default is claimed
+        let loader = fixture.loader_with_assets(&assets);
+        loader.sync(&SyncOptions::default()).unwrap();
+        assert_eq!(loader.default_entry().unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(boot.join("EFI/Boot/BOOTX64.EFI")).unwrap(),
+            "fedora shim"
+        );
+    }
+
+    #[test]
+    fn test_windows_fallback() {
+        let fixture = Fixture::new();
+        let boot = &fixture.boot;
+        fs::create_dir_all(boot.join("EFI/Boot")).unwrap();
+        fs::create_dir_all(boot.join("EFI/Microsoft/Boot")).unwrap();
+        fs::write(boot.join("EFI/Microsoft/Boot/bootmgfw.efi"), "bootmgr").unwrap();
+        fs::write(boot.join("EFI/Boot/BOOTX64.EFI"), "bootmgr").unwrap();
+        let asset = fixture.root().join("systemd-bootx64.efi");
+        fs::write(&asset, "#### LoaderInfo: systemd-boot 257.5 ####").unwrap();
+        let assets = [asset];
+
+        let loader = fixture.loader_with_assets(&assets);
+        let options = SyncOptions::default();
+        let survey = loader.esp_survey(&options.vendors).unwrap();
+        assert_eq!(
+            survey.windows_boot_manager,
+            Some(boot.join("EFI/Microsoft/Boot/bootmgfw.efi"))
+        );
+
+        // The fallback and Windows itself are left alone, our default is still set
+        loader.sync(&options).unwrap();
+        assert_eq!(
+            fs::read_to_string(boot.join("EFI/Boot/BOOTX64.EFI")).unwrap(),
+            "bootmgr"
+        );
+        assert_eq!(
+            fs::read_to_string(boot.join("EFI/Microsoft/Boot/bootmgfw.efi")).unwrap(),
+            "bootmgr"
+        );
+        assert_eq!(loader.default_entry().unwrap().as_deref(), Some("aerynos*"));
+
+        let options = SyncOptions {
+            take_ownership: true,
+            ..Default::default()
+        };
+        loader.sync(&options).unwrap();
+        assert_eq!(
+            loader.fallback_bootloader(&options),
+            Some((boot.join("EFI/Boot/BOOTX64.EFI"), true))
+        );
+    }
+
+    #[test]
+    fn test_efi_apps() {
+        let fixture = Fixture::new();
+        let boot = &fixture.boot;
+        fs::create_dir_all(boot.join("EFI/aerynos/6.8.2-25.desktop")).unwrap();
+        let memtest = fixture.root().join("memtest86+.efi");
+        let shell = fixture.root().join("shellx64.efi");
+        fs::write(&memtest, "memtest").unwrap();
+        fs::write(&shell, "shell").unwrap();
+
+        let apps = [
+            EfiAppEntry::new("Memory Test", &memtest, "memtest86+.efi"),
+            EfiAppEntry::new("UEFI Shell", &shell, "shellx64.efi"),
+        ];
+        let mut loader = fixture.loader();
+        loader.efi_apps = Some(&apps);
+        loader.sync_efi_apps().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(boot.join("loader/entries/tools-aerynos-memtest86+.conf")).unwrap(),
+            "title Memory Test\nsort-key tools\nefi /EFI/aerynos/tools/memtest86+.efi\n"
+        );
+        assert!(boot.join("EFI/aerynos/tools/shellx64.efi").exists());
+
+        // Neither a kernel directory nor a kernel entry
+        let stale = loader.stale_entries(&[]);
+        assert_eq!(stale.kernels, vec![boot.join("EFI/aerynos/6.8.2-25.desktop")]);
+        assert!(stale.confs.is_empty());
+
+        // Dropped applications are removed
+        loader.efi_apps = Some(&apps[..1]);
+        loader.sync_efi_apps().unwrap();
+        assert!(!boot.join("loader/entries/tools-aerynos-shellx64.conf").exists());
+        assert!(!boot.join("EFI/aerynos/tools/shellx64.efi").exists());
+        assert!(boot.join("EFI/aerynos/tools/memtest86+.efi").exists());
+
+        // Without configured applications, those already installed are left alone
+        loader.efi_apps = None;
+        loader.sync_efi_apps().unwrap();
+        assert!(boot.join("loader/entries/tools-aerynos-memtest86+.conf").exists());
+        assert!(boot.join("EFI/aerynos/tools/memtest86+.efi").exists());
+
+        let escape = [EfiAppEntry::new("Escape", &shell, "../shellx64.efi")];
+        loader.efi_apps = Some(&escape);
+        assert!(matches!(loader.sync_efi_apps(), Err(Error::InvalidEfiApp { .. })));
+    }
+
+    #[test]
+    fn test_devicetree_entry() {
+        let fixture = Fixture::new();
+        let kernel_dir = fixture.kernel_dir("6.8.2-25.arm64");
+        fs::create_dir_all(kernel_dir.join("dtbs/rockchip")).unwrap();
+        fs::write(kernel_dir.join("vmlinuz"), "vmlinuz").unwrap();
+        fs::write(kernel_dir.join("dtbs/rockchip/rk3588-rock-5b.dtb"), "rock-5b").unwrap();
+        fs::write(kernel_dir.join("dtbs/rockchip/rk3588-orangepi-5.dtb"), "orangepi-5").unwrap();
+        let kernels = fixture.kernels(&[kernel_dir]);
+        let plain = Entry::new(&kernels[0]);
+        let entry = Entry::new(&kernels[0]).with_devicetree("rk3588-rock-5b.dtb");
+
+        let loader = fixture.loader();
+        assert!(
+            !loader
+                .generate_entry("EFI/aerynos", "rw", &plain)
+                .contains("devicetree")
+        );
+
+        loader.sync_entries(["rw"].into_iter(), &[&entry]).unwrap();
+        let conf = fs::read_to_string(fixture.boot.join("loader/entries/aerynos-6.8.2-25.arm64.conf")).unwrap();
+        assert!(conf.contains("\ndevicetree /EFI/aerynos/6.8.2-25.arm64/dtbs/rockchip/rk3588-rock-5b.dtb\n"));
+
+        // Every devicetree is installed, preserving its subpath
+        let installed = fixture.boot.join("EFI/aerynos/6.8.2-25.arm64/dtbs/rockchip");
+        assert!(installed.join("rk3588-rock-5b.dtb").exists());
+        assert!(installed.join("rk3588-orangepi-5.dtb").exists());
+    }
+
+    #[test]
+    fn test_microcode_entry() {
+        let fixture = Fixture::new();
+        let kernel_dir = fixture.kernel_dir("6.8.2-25.desktop");
+        for name in ["vmlinuz", "10-default.initrd", "amd-ucode.img"] {
+            fs::write(kernel_dir.join(name), name).unwrap();
         }
+        let firmware = fixture.root().join("usr/lib/firmware");
+        fs::create_dir_all(&firmware).unwrap();
+        fs::write(firmware.join("intel-ucode.img"), "intel-ucode").unwrap();
+
+        let kernels = fixture.kernels(&[kernel_dir]);
+        assert!(
+            kernels[0]
+                .initrd
+                .iter()
+                .any(|i| i.kind == AuxiliaryKind::Microcode && i.path.ends_with("amd-ucode.img"))
+        );
+        let entry = Entry::new(&kernels[0]).with_microcode(firmware.join("intel-ucode.img"));
+
+        let loader = fixture.loader();
+        loader.sync_entries(["rw"].into_iter(), &[&entry]).unwrap();
+        let conf = fs::read_to_string(fixture.boot.join("loader/entries/aerynos-6.8.2-25.desktop.conf")).unwrap();
+        assert_eq!(
+            conf.lines().filter(|l| l.starts_with("initrd ")).collect::<Vec<_>>(),
+            [
+                "initrd /EFI/aerynos/6.8.2-25.desktop/intel-ucode.img",
+                "initrd /EFI/aerynos/6.8.2-25.desktop/amd-ucode.img",
+                "initrd /EFI/aerynos/6.8.2-25.desktop/10-default.initrd",
+            ]
+        );
+        assert!(
+            fixture
+                .boot
+                .join("EFI/aerynos/6.8.2-25.desktop/intel-ucode.img")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_generate_entry() {
+        let fixture = Fixture::new();
+        let loader = fixture.loader();
+
+        let mut kernel = Kernel {
+            version: "6.8.2-25.lts".to_string(),
+            image: PathBuf::from("/usr/lib/kernel/6.8.2-25.lts/vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: None,
+            boot_meta: None,
+        };
+        assert_eq!(
+            loader.generate_entry("EFI/aerynos", "rw quiet", &Entry::new(&kernel)),
+            "title AerynOS (6.8.2-25.lts)\nsort-key aerynos\nversion 6.8.2-25.lts\n\
+             linux /EFI/aerynos/6.8.2-25.lts/vmlinuz\n\noptions rw quiet\n"
+        );
+
+        kernel.variant = Some("lts".to_string());
+        assert_eq!(
+            loader.generate_entry("EFI/aerynos", "rw quiet", &Entry::new(&kernel)),
+            "title AerynOS (6.8.2-25.lts, lts)\nsort-key aerynos-lts\nversion 6.8.2-25.lts\n\
+             linux /EFI/aerynos/6.8.2-25.lts/vmlinuz\n\noptions rw quiet\n"
+        );
+        assert!(
+            loader
+                .generate_entry("EFI/aerynos", "rw", &Entry::new(&kernel).with_title("Installer"))
+                .starts_with("title Installer\nsort-key aerynos-lts\n")
+        );
+    }
+
+    #[test]
+    fn test_required_space() {
+        let fixture = Fixture::new();
+        let kernel_dir = fixture.kernel_dir("6.8.2-25.desktop");
+        fs::write(kernel_dir.join("vmlinuz"), [0; 1000]).unwrap();
+        fs::write(kernel_dir.join("10-default.initrd"), [0; 500]).unwrap();
+        let kernels = fixture.kernels(std::slice::from_ref(&kernel_dir));
+        let entry = Entry::new(&kernels[0]);
+        let loader = fixture.loader();
+
+        // Everything is new, with room to stage the kernel
+        assert_eq!(loader.required_space(&[&entry]).unwrap(), 2500);
+        loader.sync_entries(["rw"].into_iter(), &[&entry]).unwrap();
+        assert_eq!(loader.required_space(&[&entry]).unwrap(), 0);
+
+        // Only the growth of a replaced file counts
+        fs::write(kernel_dir.join("10-default.initrd"), [1; 800]).unwrap();
+        assert_eq!(loader.required_space(&[&entry]).unwrap(), 1100);
+    }
+
+    #[test]
+    fn test_check_space() {
+        let fixture = Fixture::new();
+        let dirs = ["6.8.2-25.desktop", "6.9.1-30.desktop"]
+            .into_iter()
+            .map(|version| {
+                let dir = fixture.kernel_dir(version);
+                fs::write(dir.join("vmlinuz"), [0; 1000]).unwrap();
+                dir
+            })
+            .collect::<Vec<_>>();
+        let kernels = fixture.kernels(&dirs);
+        let (newer, older) = (Entry::new(&kernels[0]), Entry::new(&kernels[1]));
+        let boot = &fixture.boot;
+
+        let mut loader = fixture.loader();
+        assert!(loader.check_space(&[&older]).is_ok());
+        loader.sync_entries(["rw"].into_iter(), &[&older]).unwrap();
+
+        let err = loader.ensure_space(&[&newer], 500, 2000).unwrap_err();
+        assert!(
+            matches!(&err, Error::InsufficientSpace { path, available: 500, required: 2000 } if path == boot),
+            "unexpected error: {err}"
+        );
+        assert!(boot.join("EFI/aerynos/6.8.2-25.desktop/vmlinuz").exists());
+
+        // The older kernel is stale once only the newer is synced, freeing enough room
+        loader.cleanup_first = true;
+        loader.ensure_space(&[&newer], 1500, 2000).unwrap();
+        assert!(!boot.join("EFI/aerynos/6.8.2-25.desktop").exists());
+        assert!(!boot.join("loader/entries/aerynos-6.8.2-25.desktop.conf").exists());
+
+        let missing = Mounts {
+            xbootldr: None,
+            esp: Some(fixture.root().join("missing")),
+        };
+        let loader = fixture.loader_for(&missing);
+        assert!(matches!(loader.check_space(&[&newer]), Err(Error::QuerySpace { .. })));
     }
 }
@@ -0,0 +1,83 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Version detection for systemd-boot PE binaries
+//!
+//! systemd-boot embeds a `#### LoaderInfo: systemd-boot 257.5 ####` marker
+//! within its `.data` section, which we use to avoid downgrading a bootloader
+//! installed by a newer systemd (i.e. another distribution sharing the ESP).
+
+use std::{cmp::Ordering, fmt::Display, path::Path};
+
+use fs_err as fs;
+
+use crate::kernel::compare_local;
+
+/// Marker preceding the loader info string
+const MARKER: &[u8] = b"#### LoaderInfo: ";
+
+/// Marker following the loader info string
+const TERMINATOR: &[u8] = b" ####";
+
+/// Version information embedded in a systemd-boot binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoaderVersion {
+    /// Product name, i.e. `systemd-boot`
+    pub product: String,
+
+    /// Version string, i.e. `257.5`
+    pub version: String,
+}
+
+impl LoaderVersion {
+    /// Read the version from the PE binary at the given path
+    pub fn from_file(path: impl AsRef<Path>) -> Option<Self> {
+        Self::from_bytes(&fs::read(path.as_ref()).ok()?)
+    }
+
+    /// Scan the binary contents for the `LoaderInfo` marker
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let start = data.windows(MARKER.len()).position(|w| w == MARKER)? + MARKER.len();
+        let rest = &data[start..];
+        let end = rest.windows(TERMINATOR.len()).position(|w| w == TERMINATOR)?;
+        let info = std::str::from_utf8(&rest[..end]).ok()?.trim();
+        let (product, version) = info.rsplit_once(' ')?;
+        Some(Self {
+            product: product.to_string(),
+            version: version.to_string(),
+        })
+    }
+}
+
+impl PartialOrd for LoaderVersion {
+    /// Only versions of the same product are comparable
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self.product == other.product).then(|| compare_local(&self.version, &other.version))
+    }
+}
+
+impl Display for LoaderVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.product, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoaderVersion;
+
+    #[test]
+    fn test_loader_version() {
+        let new = LoaderVersion::from_bytes(b"MZ\0\0#### LoaderInfo: systemd-boot 257.5 ####\0\0").unwrap();
+        assert_eq!(new.product, "systemd-boot");
+        assert_eq!(new.version, "257.5");
+        assert_eq!(new.to_string(), "systemd-boot 257.5");
+        assert_eq!(LoaderVersion::from_bytes(b"MZ\0\0"), None);
+
+        let old = LoaderVersion::from_bytes(b"#### LoaderInfo: systemd-boot 255.10 ####").unwrap();
+        assert!(old < new);
+        let other = LoaderVersion::from_bytes(b"#### LoaderInfo: other-boot 300 ####").unwrap();
+        assert_eq!(other.partial_cmp(&new), None);
+    }
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Secure Boot shim layout
+//!
+//! When the distribution ships a signed shim, it becomes the removable media
+//! fallback (`EFI/Boot/BOOTX64.EFI`) and chain loads systemd-boot installed
+//! next to it under the name shim expects, with MokManager alongside.
+//! MOK enrollment itself is left to `mokutil`.
+
+use std::path::{Path, PathBuf};
+
+use crate::Architecture;
+
+/// Shim binaries discovered within the bootloader assets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecureBootAssets {
+    /// The shim itself, i.e. `shimx64.efi`
+    pub shim: PathBuf,
+
+    /// MokManager, i.e. `mmx64.efi`
+    pub mok_manager: Option<PathBuf>,
+
+    /// Shim fallback, i.e. `fbx64.efi`
+    ///
+    /// Not installed, as we don't register boot entries via `BOOT.CSV`.
+    pub fallback: Option<PathBuf>,
+}
+
+impl SecureBootAssets {
+    /// Detect shim assets for the given architecture, if shim is available at all
+    pub fn detect(assets: &[PathBuf], arch: Architecture) -> Option<Self> {
+        let find = |prefix: &str| {
+            let name = format!("{prefix}{}.efi", arch.efi_name());
+            assets.iter().find(|p| file_name_matches(p, &name)).cloned()
+        };
+        Some(Self {
+            shim: find("shim")?,
+            mok_manager: find("mm"),
+            fallback: find("fb"),
+        })
+    }
+
+    /// MokManager binary name expected by shim
+    pub fn mok_manager_name(arch: Architecture) -> String {
+        format!("mm{}.efi", arch.efi_name())
+    }
+
+    /// Default name shim chain loads, i.e. `grubx64.efi`
+    pub fn default_loader_name(arch: Architecture) -> String {
+        format!("grub{}.efi", arch.efi_name())
+    }
+}
+
+fn file_name_matches(path: &Path, name: &str) -> bool {
+    path.file_name()
+        .is_some_and(|f| f.to_string_lossy().eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::SecureBootAssets;
+    use crate::Architecture;
+
+    #[test]
+    fn test_detect() {
+        let boot = PathBuf::from("/usr/lib/systemd/boot/efi/systemd-bootx64.efi");
+        let shim = PathBuf::from("/usr/lib/shim/shimx64.efi");
+        let mm = PathBuf::from("/usr/lib/shim/mmx64.efi");
+        assert_eq!(
+            SecureBootAssets::detect(std::slice::from_ref(&boot), Architecture::X86_64),
+            None
+        );
+
+        let assets = SecureBootAssets::detect(&[boot, shim.clone(), mm.clone()], Architecture::X86_64).unwrap();
+        assert_eq!(assets.shim, shim);
+        assert_eq!(assets.mok_manager, Some(mm));
+        assert_eq!(assets.fallback, None);
+        assert_eq!(
+            SecureBootAssets::default_loader_name(Architecture::X86_64),
+            "grubx64.efi"
+        );
+    }
+}
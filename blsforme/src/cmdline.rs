@@ -0,0 +1,141 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Kernel command line assembly
+
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+/// Parameters where only one value may take effect, so the last one wins
+const EXCLUSIVE_KEYS: [&str; 2] = ["root", "resume"];
+
+/// Mutually exclusive flags, where the last one wins
+const EXCLUSIVE_FLAGS: [&str; 2] = ["ro", "rw"];
+
+/// A tokenized kernel command line
+///
+/// Parameters are split on whitespace, except within double quotes, so that
+/// values such as `foo="a b"` survive a round trip.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cmdline {
+    params: Vec<String>,
+}
+
+impl Cmdline {
+    /// All parameters, in order
+    pub fn params(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().map(String::as_str)
+    }
+
+    /// Value of the last `key=value` parameter with the given key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .rev()
+            .find_map(|p| p.split_once('=').filter(|(k, _)| *k == key).map(|(_, v)| v))
+    }
+
+    /// Remove all parameters with the given key, or flags with the given name
+    pub fn remove(&mut self, key: &str) {
+        self.params
+            .retain(|p| p.split_once('=').map_or(p.as_str(), |(k, _)| k) != key);
+    }
+
+    /// Append all parameters from another cmdline
+    pub fn extend(&mut self, other: Cmdline) {
+        self.params.extend(other.params);
+    }
+
+    /// Drop repeated parameters and resolve conflicting ones, keeping the last occurrence
+    ///
+    /// Conflicts are `ro` vs `rw`, and multiple `root=` or `resume=` values.
+    pub fn normalize(self) -> Self {
+        let mut seen = vec![];
+        let mut params: Vec<String> = vec![];
+        for param in self.params.into_iter().rev() {
+            let (identity, conflicting) = match param.split_once('=') {
+                Some((key, _)) if EXCLUSIVE_KEYS.contains(&key) => (key.to_string(), true),
+                None if EXCLUSIVE_FLAGS.contains(&param.as_str()) => (EXCLUSIVE_FLAGS.join("|"), true),
+                _ => (param.clone(), false),
+            };
+            match seen.iter().position(|i| *i == identity) {
+                Some(index) => {
+                    let kept = &params[index];
+                    if conflicting && *kept != param {
+                        log::warn!("Conflicting kernel parameter {param} is overridden by {kept}");
+                    } else {
+                        log::trace!("Dropping repeated kernel parameter {param}");
+                    }
+                }
+                None => {
+                    seen.push(identity);
+                    params.push(param);
+                }
+            }
+        }
+        params.reverse();
+        Self { params }
+    }
+}
+
+impl FromStr for Cmdline {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = vec![];
+        let mut current = String::new();
+        let mut quoted = false;
+        for c in s.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    current.push(c);
+                }
+                c if c.is_whitespace() && !quoted => {
+                    if !current.is_empty() {
+                        params.push(std::mem::take(&mut current));
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            params.push(current);
+        }
+        Ok(Self { params })
+    }
+}
+
+impl Display for Cmdline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.params.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cmdline;
+
+    #[test]
+    fn test_round_trip() {
+        let Ok(cmdline) = r#"root=UUID=abc  foo="a b"   quiet"#.parse::<Cmdline>();
+        assert_eq!(
+            cmdline.params().collect::<Vec<_>>(),
+            ["root=UUID=abc", r#"foo="a b""#, "quiet"]
+        );
+        assert_eq!(cmdline.to_string(), r#"root=UUID=abc foo="a b" quiet"#);
+        assert_eq!(cmdline.get("foo"), Some(r#""a b""#));
+        assert_eq!(cmdline.get("quiet"), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let Ok(cmdline) =
+            r#"root=UUID=abc rw quiet foo="a b" quiet splash ro resume=/dev/a root=UUID=def resume=/dev/b foo="a b""#
+                .parse::<Cmdline>();
+        assert_eq!(
+            cmdline.normalize().to_string(),
+            r#"quiet splash ro root=UUID=def resume=/dev/b foo="a b""#
+        );
+    }
+}
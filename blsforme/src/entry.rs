@@ -2,15 +2,35 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use fs_err as fs;
-use snafu::ResultExt as _;
+use snafu::{OptionExt as _, ResultExt as _, ensure};
 
-use crate::{AuxiliaryFile, Configuration, IoSnafu, Kernel, Schema, file_utils::cmdline_snippet};
+use crate::{
+    AuxiliaryFile, AuxiliaryKind, Cmdline, Configuration, InvalidLoaderEntrySnafu, IoSnafu, Kernel,
+    MissingKernelFilesSnafu, Schema, bootloader::LoaderEntry, file_utils::cmdline_snippet,
+};
 
-/// A cmdline entry is found in the `$sysroot/usr/lib/kernel/cmdline.d` directory
-#[derive(Debug)]
+/// `cmdline.d` drop-in directories within the sysroot, in increasing priority
+///
+/// A snippet replaces any same-named snippet from a lower priority directory,
+/// and masks it entirely when empty or symlinked to `/dev/null`.
+pub const CMDLINE_DIRS: [&str; 3] = [
+    "usr/lib/kernel/cmdline.d",
+    "etc/kernel/cmdline.d",
+    "run/kernel/cmdline.d",
+];
+
+/// Parameters describing the rootfs, replaced entirely when a snippet sets `root=`
+const ROOT_KEYS: [&str; 3] = ["root", "rootfstype", "rootflags"];
+
+/// A cmdline entry is found in one of the [`CMDLINE_DIRS`] within the sysroot
+#[derive(Debug, Clone)]
 pub struct CmdlineEntry {
     /// Name of the entry, i.e. `00-quiet.cmdline`
     pub name: String,
@@ -19,70 +39,222 @@ pub struct CmdlineEntry {
     pub snippet: String,
 }
 
+impl CmdlineEntry {
+    /// Priority from the numeric filename prefix, i.e. `50` for `50-quiet.cmdline`
+    pub fn priority(&self) -> Option<u32> {
+        let digits = self.name.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.name.len());
+        self.name[..digits].parse().ok()
+    }
+
+    /// Sort key: by priority, with unprefixed names last, then by name
+    fn sort_key(&self) -> (u32, &str) {
+        (self.priority().unwrap_or(u32::MAX), &self.name)
+    }
+}
+
 /// An entry corresponds to a single kernel, and may have a supplemental
 /// cmdline
 #[derive(Debug)]
 pub struct Entry<'a> {
-    pub(crate) kernel: &'a Kernel,
+    pub(crate) kernel: Cow<'a, Kernel>,
+
+    /// Title, when loaded from an existing loader config
+    pub(crate) title: Option<String>,
 
     pub(crate) sysroot: Option<PathBuf>,
 
     pub(crate) cmdline: Vec<CmdlineEntry>,
 
+    /// Names of snippets left out of this entry's cmdline
+    pub(crate) excluded_snippets: Vec<String>,
+
     /// Unique state ID for this entry
     pub(crate) state_id: Option<i32>,
 
+    /// Machine ID prefixing the entry ID, to share `$BOOT` with other installations
+    pub(crate) machine_id: Option<String>,
+
     /// Entry-specific schema for overriding the global schema
     pub(crate) schema: Option<Schema>,
+
+    /// Devicetree selected for the board, relative to the kernel directory
+    pub(crate) devicetree: Option<PathBuf>,
 }
 
 impl<'a> Entry<'a> {
     /// New entry for the given kernel
     pub fn new(kernel: &'a Kernel) -> Self {
         Self {
-            kernel,
+            kernel: Cow::Borrowed(kernel),
+            title: None,
             cmdline: vec![],
+            excluded_snippets: vec![],
             sysroot: None,
             state_id: None,
+            machine_id: None,
             schema: None,
+            devicetree: None,
         }
     }
 
+    /// Reconstruct an entry from an existing BLS type #1 loader config
+    ///
+    /// Paths within the returned kernel are relative to `$BOOT`, and the
+    /// `options` line is exposed as a single [`CmdlineEntry`].
+    pub fn from_loader_conf(path: &Path, schema: &Schema) -> Result<Entry<'static>, super::Error> {
+        let text = fs::read_to_string(path).context(IoSnafu)?;
+        let parsed = LoaderEntry::parse(&text);
+
+        let title = parsed.title.clone();
+        let image = parsed
+            .image()
+            .map(PathBuf::from)
+            .context(InvalidLoaderEntrySnafu { path, field: "linux" })?;
+        let initrd = parsed
+            .initrd
+            .iter()
+            .map(|i| AuxiliaryFile::new(i, AuxiliaryKind::InitRd))
+            .collect::<Vec<_>>();
+        let options = parsed.cmdline();
+
+        // We title entries `$name ($version)` or `$name ($version, $variant)`, otherwise
+        // rely on the `version` key or the `$version/vmlinuz` layout
+        let (from_title, variant) = title
+            .as_deref()
+            .and_then(|t| t.strip_suffix(')')?.rsplit_once(" ("))
+            .map(|(_, v)| match v.split_once(", ") {
+                Some((version, variant)) => (Some(version.to_string()), Some(variant.to_string())),
+                None => (Some(v.to_string()), None),
+            })
+            .unwrap_or_default();
+        let from_path = match schema {
+            Schema::Legacy { .. } => None,
+            _ => image
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|v| v.to_string_lossy().to_string()),
+        };
+        let version = parsed
+            .version
+            .clone()
+            .or(from_title)
+            .or(from_path)
+            .context(InvalidLoaderEntrySnafu { path, field: "version" })?;
+
+        let cmdline = if options.is_empty() {
+            vec![]
+        } else {
+            vec![CmdlineEntry {
+                name: "options".to_string(),
+                snippet: options,
+            }]
+        };
+
+        Ok(Entry {
+            kernel: Cow::Owned(Kernel {
+                version,
+                image,
+                initrd,
+                extras: vec![],
+                variant,
+                boot_meta: None,
+            }),
+            title,
+            sysroot: None,
+            cmdline,
+            excluded_snippets: vec![],
+            state_id: None,
+            machine_id: parsed.machine_id.clone(),
+            schema: None,
+            devicetree: None,
+        })
+    }
+
+    /// The kernel for this entry
+    pub fn kernel(&self) -> &Kernel {
+        &self.kernel
+    }
+
+    /// Title, if loaded from an existing loader config
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Cmdline snippets for this entry
+    pub fn cmdline(&self) -> &[CmdlineEntry] {
+        &self.cmdline
+    }
+
     /// Load cmdline snippets from the system root for this entry's sysroot
+    ///
+    /// Snippets shipped alongside the kernel come first, followed by the
+    /// `cmdline.d` drop-ins (see [`CMDLINE_DIRS`]), each sorted by their numeric
+    /// filename prefix and then filename, so that the assembled cmdline is stable
+    /// between runs regardless of directory order.
     pub fn load_cmdline_snippets(&mut self, config: &Configuration) -> Result<(), super::Error> {
         let sysroot = self.sysroot.clone().unwrap_or(config.root.path().into());
 
         // Load local cmdline snippets for this kernel entry
-        for snippet in self
+        let mut local = self
             .kernel
             .extras
             .iter()
             .filter(|e| matches!(e.kind, crate::AuxiliaryKind::Cmdline))
+            .filter_map(|e| {
+                let name = e.path.file_name()?.to_string_lossy().to_string();
+                let snippet = cmdline_snippet(sysroot.join(&e.path)).ok()?;
+                Some(CmdlineEntry { name, snippet })
+            })
+            .collect::<Vec<_>>();
+        local.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        self.cmdline.extend(local);
+
+        // Snippets the kernel asks to leave out, by whitespace separated name
+        for e in self
+            .kernel
+            .extras
+            .iter()
+            .filter(|e| matches!(e.kind, crate::AuxiliaryKind::ExcludedCmdline))
         {
-            if let Ok(cmdline) = cmdline_snippet(sysroot.join(&snippet.path)) {
-                self.cmdline.push(CmdlineEntry {
-                    name: snippet.path.file_name().unwrap().to_string_lossy().to_string(),
-                    snippet: cmdline,
-                });
+            match cmdline_snippet(sysroot.join(&e.path)) {
+                Ok(names) => self
+                    .excluded_snippets
+                    .extend(names.split_whitespace().map(str::to_string)),
+                Err(err) => log::warn!("Ignoring {}: {err}", e.path.display()),
             }
         }
 
-        // Globals
-        let cmdline_d = sysroot.join("usr").join("lib").join("kernel").join("cmdline.d");
-
-        if !cmdline_d.exists() {
-            return Ok(());
+        // Globals, where a same-named file in a later directory replaces the earlier one
+        let mut drop_ins = BTreeMap::new();
+        for dir in CMDLINE_DIRS {
+            let Ok(entries) = fs::read_dir(sysroot.join(dir)) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.extension().is_some_and(|e| e == "cmdline") {
+                    drop_ins.insert(entry.file_name().to_string_lossy().to_string(), path);
+                }
+            }
         }
 
-        let entries = fs::read_dir(&cmdline_d).context(IoSnafu)?;
-
-        for entry in entries.filter_map(Result::ok) {
-            let name = entry.file_name().to_string_lossy().to_string();
+        let mut globals = vec![];
+        for (name, path) in drop_ins {
+            if path.read_link().is_ok_and(|target| target == Path::new("/dev/null")) {
+                log::trace!("cmdline.d entry {name} is masked by {}", path.display());
+                continue;
+            }
             // Don't bomb out on invalid cmdline snippets
-            if let Ok(snippet) = cmdline_snippet(entry.path()) {
-                self.cmdline.push(CmdlineEntry { name, snippet });
+            match cmdline_snippet(&path) {
+                Ok(snippet) if snippet.trim().is_empty() => {
+                    log::trace!("cmdline.d entry {name} is masked by {}", path.display());
+                }
+                Ok(snippet) => globals.push(CmdlineEntry { name, snippet }),
+                Err(e) => log::warn!("Ignoring cmdline.d entry {}: {e}", path.display()),
             }
         }
+        globals.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        self.cmdline.extend(globals);
 
         Ok(())
     }
@@ -105,6 +277,14 @@ impl<'a> Entry<'a> {
         }
     }
 
+    /// With the given machine ID, named `$machine_id-$id-$version` per the BLS
+    pub fn with_machine_id(self, machine_id: impl Into<String>) -> Self {
+        Self {
+            machine_id: Some(machine_id.into()),
+            ..self
+        }
+    }
+
     /// With the given schema
     /// Used by moss to override the global schema
     pub fn with_schema(self, schema: Schema) -> Self {
@@ -114,6 +294,65 @@ impl<'a> Entry<'a> {
         }
     }
 
+    /// With the given title, replacing the generated `$name ($version)` title
+    pub fn with_title(self, title: impl Into<String>) -> Self {
+        Self {
+            title: Some(title.into()),
+            ..self
+        }
+    }
+
+    /// With the given devicetree, as a path beneath the kernel directory
+    /// (i.e. `dtbs/rockchip/rk3588-rock-5b.dtb`) or a bare file name
+    ///
+    /// Only the installer knows the board, so no devicetree is used unless selected.
+    pub fn with_devicetree(self, devicetree: impl Into<PathBuf>) -> Self {
+        Self {
+            devicetree: Some(devicetree.into()),
+            ..self
+        }
+    }
+
+    /// With a distribution-wide microcode initrd (i.e. `usr/lib/firmware/intel-ucode.img`)
+    /// not shipped with the kernel, resolved against the sysroot like the kernel's own files
+    ///
+    /// Microcode always loads ahead of the kernel's initrds.
+    pub fn with_microcode(self, path: impl Into<PathBuf>) -> Self {
+        let mut kernel = self.kernel.into_owned();
+        let microcode = AuxiliaryFile::new(path, AuxiliaryKind::Microcode);
+        kernel.initrd.insert(0, microcode);
+        Self {
+            kernel: Cow::Owned(kernel),
+            ..self
+        }
+    }
+
+    /// All devicetrees shipped with a type #1 entry's kernel, installed alongside it
+    pub(crate) fn device_trees(&self) -> impl Iterator<Item = &AuxiliaryFile> {
+        self.kernel
+            .extras
+            .iter()
+            .filter(|e| !self.is_type2() && matches!(e.kind, AuxiliaryKind::DeviceTree))
+    }
+
+    /// The discovered [`AuxiliaryKind::DeviceTree`] matching our selected devicetree
+    pub(crate) fn device_tree(&self) -> Option<&AuxiliaryFile> {
+        let selected = self.devicetree.as_ref()?;
+        self.device_trees()
+            .find(|e| self.asset_subpath(e) == *selected || e.path.file_name() == Some(selected.as_os_str()))
+    }
+
+    /// Path of the asset beneath the kernel directory, preserving any subdirectories
+    fn asset_subpath(&self, asset: &AuxiliaryFile) -> PathBuf {
+        self.kernel
+            .image
+            .parent()
+            .and_then(|dir| asset.path.strip_prefix(dir).ok())
+            .or_else(|| asset.path.file_name().map(Path::new))
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
     /// With the given cmdline entry
     /// Used by moss to inject a `moss.tx={}` parameter
     pub fn with_cmdline(self, entry: CmdlineEntry) -> Self {
@@ -122,6 +361,76 @@ impl<'a> Entry<'a> {
         Self { cmdline, ..self }
     }
 
+    /// Leave the named snippet (i.e. `20-splash.cmdline`) out of this entry's cmdline
+    pub fn with_excluded_snippet(self, name: impl Into<String>) -> Self {
+        let mut excluded_snippets = self.excluded_snippets;
+        excluded_snippets.push(name.into());
+        Self {
+            excluded_snippets,
+            ..self
+        }
+    }
+
+    /// The fully assembled cmdline: the `base` cmdline extended by our snippets,
+    /// skipping any snippets excluded by this entry
+    ///
+    /// Snippets setting `root=` take over from the probed rootfs, and repeated or
+    /// conflicting parameters are resolved per [`Cmdline::normalize`].
+    pub fn effective_cmdline(&self, base: &[&str]) -> String {
+        let Ok(mut cmdline) = base.join(" ").parse::<Cmdline>();
+        let snippets = self
+            .cmdline
+            .iter()
+            .filter(|c| !self.excluded_snippets.contains(&c.name))
+            .map(|c| c.snippet.as_str())
+            .collect::<Vec<_>>();
+        let Ok(snippets) = snippets.join(" ").parse::<Cmdline>();
+
+        if snippets.get("root").is_some() {
+            for key in ROOT_KEYS {
+                cmdline.remove(key);
+            }
+        }
+        cmdline.extend(snippets);
+        cmdline.normalize().to_string()
+    }
+
+    /// Whether this entry boots a unified kernel image
+    pub fn is_uki(&self) -> bool {
+        self.kernel.is_uki()
+    }
+
+    /// Approximate space required on `$BOOT` to install this entry, from the
+    /// size of the kernel image, any initrds and devicetrees
+    pub fn estimated_install_size(&self) -> u64 {
+        let sysroot = self.sysroot.clone().unwrap_or_default();
+        let initrds = self.kernel.initrd.iter().filter(|_| !self.is_uki()).map(|i| &i.path);
+        let device_trees = self.device_trees().map(|d| &d.path);
+        std::iter::once(&self.kernel.image)
+            .chain(initrds)
+            .chain(device_trees)
+            .filter_map(|path| fs::metadata(sysroot.join(path)).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Ensure the kernel image, initrds and extras all exist beneath `sysroot`
+    pub fn validate(&self, sysroot: &Path) -> Result<(), super::Error> {
+        let missing = std::iter::once(&self.kernel.image)
+            .chain(self.kernel.initrd.iter().map(|i| &i.path))
+            .chain(self.kernel.extras.iter().map(|e| &e.path))
+            .map(|path| sysroot.join(path))
+            .filter(|path| !path.exists())
+            .collect::<Vec<_>>();
+        ensure!(missing.is_empty(), MissingKernelFilesSnafu { missing });
+        Ok(())
+    }
+
+    /// Whether this entry is installed as a BLS type #2 entry, requiring no loader config
+    pub fn is_type2(&self) -> bool {
+        self.kernel.is_uki() && !self.kernel.efi_stub()
+    }
+
     /// Return an entry ID, suitable for `.conf` generation
     pub fn id(&self, schema: &Schema) -> String {
         // Prefer internal schema if available
@@ -131,6 +440,10 @@ impl<'a> Entry<'a> {
             Schema::Legacy { os_release, .. } => os_release.name.clone(),
             _ => effective_schema.os_id(),
         };
+        let id = match &self.machine_id {
+            Some(machine_id) => format!("{machine_id}-{id}"),
+            None => id,
+        };
         if let Some(state_id) = self.state_id.as_ref() {
             format!("{id}-{version}-{state_id}", version = &self.kernel.version)
         } else {
@@ -151,6 +464,11 @@ impl<'a> Entry<'a> {
                 .file_name()
                 .map(|f| f.to_string_lossy())
                 .map(|filename| format!("kernel-{filename}")),
+            _ if self.kernel.is_uki() => self
+                .kernel
+                .image
+                .file_name()
+                .map(|f| format!("{}/{}", self.kernel.version, f.to_string_lossy())),
             _ => Some(format!("{}/vmlinuz", self.kernel.version)),
         }
     }
@@ -163,7 +481,7 @@ impl<'a> Entry<'a> {
 
         match effective_schema {
             Schema::Legacy { .. } => match asset.kind {
-                crate::AuxiliaryKind::InitRd => asset
+                crate::AuxiliaryKind::InitRd | crate::AuxiliaryKind::Microcode => asset
                     .path
                     .file_name()
                     .map(|f| f.to_string_lossy())
@@ -173,10 +491,236 @@ impl<'a> Entry<'a> {
             _ => {
                 let filename = asset.path.file_name().map(|f| f.to_string_lossy())?;
                 match asset.kind {
-                    crate::AuxiliaryKind::InitRd => Some(format!("{}/{}", &self.kernel.version, filename)),
+                    crate::AuxiliaryKind::InitRd | crate::AuxiliaryKind::Microcode => {
+                        Some(format!("{}/{}", &self.kernel.version, filename))
+                    }
+                    crate::AuxiliaryKind::DeviceTree => Some(format!(
+                        "{}/{}",
+                        &self.kernel.version,
+                        self.asset_subpath(asset).display()
+                    )),
                     _ => None,
                 }
             }
         }
     }
 }
+
+/// Directory within `EFI/$namespace` holding installed [`EfiAppEntry`] binaries
+pub(crate) const EFI_TOOLS_DIR: &str = "tools";
+
+/// An EFI application (i.e. memtest86+ or a UEFI shell) booted via its own loader entry
+///
+/// The binary is installed to `EFI/$namespace/tools`, with a type #1 entry using
+/// the `efi` key. Applications no longer requested are removed on sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EfiAppEntry {
+    /// Menu title
+    pub title: String,
+
+    /// The `.efi` binary to install
+    pub source_path: PathBuf,
+
+    /// File name within `EFI/$namespace/tools`, also naming the loader entry
+    pub install_name: String,
+}
+
+impl EfiAppEntry {
+    /// New application, installed as `install_name`
+    pub fn new(title: impl Into<String>, source_path: impl Into<PathBuf>, install_name: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            source_path: source_path.into(),
+            install_name: install_name.into(),
+        }
+    }
+
+    /// Loader entry ID, i.e. `tools-aerynos-memtest86+`
+    ///
+    /// Unlike kernel entries this doesn't start with the namespace, so the
+    /// default entry pattern never selects an application.
+    pub fn id(&self, schema: &Schema) -> String {
+        let stem = Path::new(&self.install_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.install_name.clone());
+        format!("{}{stem}", efi_app_prefix(schema))
+    }
+}
+
+/// Prefix shared by the loader entry IDs of all [`EfiAppEntry`]s for the schema
+pub(crate) fn efi_app_prefix(schema: &Schema) -> String {
+    format!("{EFI_TOOLS_DIR}-{}-", schema.os_namespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::PathBuf, str::FromStr};
+
+    use fs_err as fs;
+
+    use super::{CmdlineEntry, Entry};
+    use crate::{AuxiliaryFile, AuxiliaryKind, Configuration, Error, Kernel, Root, Schema, os_release::OsRelease};
+
+    #[test]
+    fn test_cmdline_drop_ins() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let write = |dir: &str, name: &str, snippet: &str| {
+            let dir = root.path().join(dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(name), snippet).unwrap();
+        };
+        write("usr/lib/kernel/cmdline.d", "20-splash.cmdline", "splash");
+        write("usr/lib/kernel/cmdline.d", "00-quiet.cmdline", "# Quiet boot\nquiet");
+        write("usr/lib/kernel/cmdline.d", "10-console.cmdline", "console=tty0");
+        write("usr/lib/kernel/cmdline.d", "30-audit.cmdline", "audit=1");
+        write("usr/lib/kernel/cmdline.d", "README", "not a snippet");
+        write("etc/kernel/cmdline.d", "10-console.cmdline", "console=ttyS0");
+        write("etc/kernel/cmdline.d", "15-local.cmdline", "mitigations=off");
+        write("etc/kernel/cmdline.d", "20-splash.cmdline", "");
+        symlink("/dev/null", root.path().join("etc/kernel/cmdline.d/30-audit.cmdline")).unwrap();
+        write("run/kernel/cmdline.d", "15-local.cmdline", "mitigations=auto");
+        write("usr/lib/kernel/cmdline.d", "5-early.cmdline", "earlycon");
+        write("usr/lib/kernel/cmdline.d", "custom.cmdline", "nowatchdog");
+
+        let config = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .build()
+            .unwrap();
+        let kernel = Kernel {
+            version: "6.8.2-25.desktop".to_string(),
+            image: PathBuf::from("vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: None,
+            boot_meta: None,
+        };
+        let mut entry = Entry::new(&kernel);
+        entry.load_cmdline_snippets(&config).unwrap();
+        assert_eq!(
+            entry.cmdline.iter().map(|c| c.snippet.as_str()).collect::<Vec<_>>(),
+            ["quiet", "earlycon", "console=ttyS0", "mitigations=auto", "nowatchdog"]
+        );
+    }
+
+    #[test]
+    fn test_effective_cmdline() {
+        let kernel = Kernel {
+            version: "6.8.2-25.desktop".to_string(),
+            image: PathBuf::from("vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: None,
+            boot_meta: None,
+        };
+        let snippet = |name: &str, snippet: &str| CmdlineEntry {
+            name: name.to_string(),
+            snippet: snippet.to_string(),
+        };
+        let entry = Entry::new(&kernel)
+            .with_cmdline(snippet("00-quiet.cmdline", "quiet splash"))
+            .with_cmdline(snippet("10-ro.cmdline", "ro"))
+            .with_cmdline(snippet("20-debug.cmdline", "debug"));
+        let base = ["root=UUID=abc", "rootfstype=ext4", "quiet", "rw"];
+
+        assert_eq!(
+            entry.effective_cmdline(&base),
+            "root=UUID=abc rootfstype=ext4 quiet splash ro debug"
+        );
+
+        let entry = entry.with_excluded_snippet("20-debug.cmdline");
+        assert_eq!(
+            entry.effective_cmdline(&base),
+            "root=UUID=abc rootfstype=ext4 quiet splash ro"
+        );
+
+        // An explicit root replaces the probed rootfs entirely
+        let entry = entry.with_cmdline(snippet("30-root.cmdline", "root=LABEL=root"));
+        assert_eq!(entry.effective_cmdline(&base), "quiet splash ro root=LABEL=root");
+    }
+
+    #[test]
+    fn test_from_loader_conf() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let schema = Schema::Blsforme {
+            os_release: Box::new(OsRelease::from_str("ID=aerynos\nNAME=AerynOS").unwrap()),
+        };
+        let parse = |name: &str, text: &str| {
+            let path = root.path().join(name);
+            fs::write(&path, text).unwrap();
+            Entry::from_loader_conf(&path, &schema)
+        };
+
+        // Comments, blank lines and stray loader.conf keys are skipped
+        let entry = parse(
+            "titled.conf",
+            "# Managed by blsforme\n\ntitle AerynOS (6.8.2-25.desktop, desktop)\n  # indented comment\n\
+             default aerynos-*\ntimeout 5\nlinux /EFI/aerynos/6.8.2-25.desktop/vmlinuz\n\
+             initrd /EFI/aerynos/6.8.2-25.desktop/10-default.initrd\noptions root=UUID=abc quiet # trailing\n",
+        )
+        .unwrap();
+        assert_eq!(entry.title(), Some("AerynOS (6.8.2-25.desktop, desktop)"));
+        assert_eq!(entry.kernel().version, "6.8.2-25.desktop");
+        assert_eq!(entry.kernel().variant.as_deref(), Some("desktop"));
+        assert_eq!(
+            entry.kernel().image,
+            PathBuf::from("/EFI/aerynos/6.8.2-25.desktop/vmlinuz")
+        );
+        assert_eq!(entry.kernel().initrd.len(), 1);
+        assert_eq!(entry.cmdline()[0].snippet, "root=UUID=abc quiet # trailing");
+
+        // Without a title or version key the version comes from the kernel directory
+        let entry = parse(
+            "untitled.conf",
+            "linux /EFI/aerynos/6.9.1-30.desktop/vmlinuz\noptions\n",
+        )
+        .unwrap();
+        assert_eq!(entry.kernel().version, "6.9.1-30.desktop");
+        assert!(entry.cmdline().is_empty());
+
+        // Malformed entries name the missing key
+        let err = parse("empty.conf", "linux\n\ntitle\n").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidLoaderEntry { field: "version", .. }),
+            "unexpected error: {err}"
+        );
+        let err = parse("no-linux.conf", "title AerynOS (6.8.2-25.desktop)\ntimeout 5\n").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidLoaderEntry { field: "linux", .. }),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let dir = root.path().join("usr/lib/kernel/6.8.2-25.desktop");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("vmlinuz"), "vmlinuz").unwrap();
+        fs::write(dir.join("System.map"), "").unwrap();
+
+        let relative = PathBuf::from("usr/lib/kernel/6.8.2-25.desktop");
+        let mut kernel = Kernel {
+            version: "6.8.2-25.desktop".to_string(),
+            image: relative.join("vmlinuz"),
+            initrd: vec![],
+            extras: vec![AuxiliaryFile::new(
+                relative.join("System.map"),
+                AuxiliaryKind::SystemMap,
+            )],
+            variant: None,
+            boot_meta: None,
+        };
+        assert!(Entry::new(&kernel).validate(root.path()).is_ok());
+
+        kernel.initrd.push(AuxiliaryFile::new(
+            relative.join("10-default.initrd"),
+            AuxiliaryKind::InitRd,
+        ));
+        let err = Entry::new(&kernel).validate(root.path()).unwrap_err();
+        assert!(
+            matches!(&err, Error::MissingKernelFiles { missing } if *missing == [dir.join("10-default.initrd")]),
+            "unexpected error: {err}"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Survey of the vendor directories on the ESP
+//!
+//! Other operating systems and boot managers install themselves beneath `EFI/`.
+//! Each directory is classified so that dual-boot setups (notably the Windows
+//! Boot Manager) can be reported and respected during sync. Foreign directories
+//! are only ever read, never modified or removed.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::file_utils::PathExt;
+
+/// Classification of a vendor directory beneath `EFI/`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VendorKind {
+    /// One of our own namespaces
+    Owned,
+
+    /// systemd-boot, shared by every installation using it
+    SystemdBoot,
+
+    /// The removable media fallback, `EFI/Boot`
+    Fallback,
+
+    /// Windows Boot Manager
+    Windows,
+
+    /// macOS
+    Apple,
+
+    /// GRUB, typically installed by another distribution
+    Grub,
+
+    /// rEFInd boot manager
+    Refind,
+
+    /// Vendor named by a [`VendorRule`] registered via [`EspSurveyor::with_rule`]
+    Other(String),
+
+    /// Not recognised
+    Unknown,
+}
+
+impl VendorKind {
+    /// Whether the directory belongs to another OS or boot manager
+    pub fn is_foreign(&self) -> bool {
+        !matches!(self, VendorKind::Owned | VendorKind::SystemdBoot | VendorKind::Fallback)
+    }
+}
+
+impl Display for VendorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VendorKind::Owned => f.write_str("ours"),
+            VendorKind::SystemdBoot => f.write_str("systemd-boot"),
+            VendorKind::Fallback => f.write_str("fallback"),
+            VendorKind::Windows => f.write_str("Windows Boot Manager"),
+            VendorKind::Apple => f.write_str("macOS"),
+            VendorKind::Grub => f.write_str("GRUB"),
+            VendorKind::Refind => f.write_str("rEFInd"),
+            VendorKind::Other(name) => f.write_str(name),
+            VendorKind::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Identifies vendor directories by their name, or by a file within them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VendorRule {
+    /// Directory name beneath `EFI/`, matched case-insensitively
+    Dir { name: String, kind: VendorKind },
+
+    /// File name prefix within the directory, matched case-insensitively (i.e. `grub`)
+    File { prefix: String, kind: VendorKind },
+}
+
+impl VendorRule {
+    /// Match directories with the given name
+    pub fn dir(name: impl Into<String>, kind: VendorKind) -> Self {
+        Self::Dir {
+            name: name.into(),
+            kind,
+        }
+    }
+
+    /// Match directories containing a file with the given name prefix
+    pub fn file(prefix: impl Into<String>, kind: VendorKind) -> Self {
+        Self::File {
+            prefix: prefix.into(),
+            kind,
+        }
+    }
+
+    /// The kind of the directory, if matched
+    fn classify(&self, name: &str, files: &[String]) -> Option<&VendorKind> {
+        match self {
+            VendorRule::Dir { name: n, kind } => n.eq_ignore_ascii_case(name).then_some(kind),
+            VendorRule::File { prefix, kind } => {
+                let prefix = prefix.to_ascii_lowercase();
+                files.iter().any(|f| f.starts_with(&prefix)).then_some(kind)
+            }
+        }
+    }
+}
+
+/// A directory beneath `EFI/` on the ESP
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VendorDir {
+    /// Name of the directory
+    pub name: String,
+
+    /// Full path to the directory
+    pub path: PathBuf,
+
+    /// Classification of the directory
+    pub kind: VendorKind,
+}
+
+/// Findings of [`EspSurveyor::survey`]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct EspSurvey {
+    /// All vendor directories, sorted by name
+    pub vendors: Vec<VendorDir>,
+
+    /// The Windows Boot Manager (`EFI/Microsoft/Boot/bootmgfw.efi`), when present
+    pub windows_boot_manager: Option<PathBuf>,
+}
+
+impl EspSurvey {
+    /// Vendor directories belonging to other operating systems or boot managers
+    pub fn foreign(&self) -> impl Iterator<Item = &VendorDir> {
+        self.vendors.iter().filter(|v| v.kind.is_foreign())
+    }
+}
+
+/// Classifies the vendor directories on the ESP
+///
+/// The built-in rules recognise Microsoft, Apple, GRUB and rEFInd. Further
+/// vendors may be recognised via [`EspSurveyor::with_rule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EspSurveyor {
+    rules: Vec<VendorRule>,
+}
+
+impl Default for EspSurveyor {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                VendorRule::dir("Boot", VendorKind::Fallback),
+                VendorRule::dir("systemd", VendorKind::SystemdBoot),
+                VendorRule::dir("Microsoft", VendorKind::Windows),
+                VendorRule::dir("Apple", VendorKind::Apple),
+                VendorRule::dir("refind", VendorKind::Refind),
+                VendorRule::file("refind_", VendorKind::Refind),
+                VendorRule::file("grub", VendorKind::Grub),
+            ],
+        }
+    }
+}
+
+impl EspSurveyor {
+    /// Recognise another vendor, in preference to the existing rules
+    pub fn with_rule(self, rule: VendorRule) -> Self {
+        let rules = std::iter::once(rule).chain(self.rules).collect();
+        Self { rules }
+    }
+
+    /// Classify every directory beneath `EFI/` on the ESP
+    ///
+    /// Directories named after one of our `namespaces` are [`VendorKind::Owned`].
+    pub fn survey(&self, esp: &Path, namespaces: &[String]) -> EspSurvey {
+        let efi = esp.to_path_buf().join_insensitive("EFI");
+        let Ok(children) = fs::read_dir(&efi) else {
+            return EspSurvey::default();
+        };
+
+        let mut vendors = children
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                let kind = self.classify(&name, &e.path(), namespaces);
+                VendorDir {
+                    name,
+                    path: e.path(),
+                    kind,
+                }
+            })
+            .collect::<Vec<_>>();
+        vendors.sort_by_key(|v| v.name.to_lowercase());
+
+        let windows_boot_manager = vendors
+            .iter()
+            .filter(|v| v.kind == VendorKind::Windows)
+            .map(|v| v.path.join_insensitive("Boot").join_insensitive("bootmgfw.efi"))
+            .find(|p| p.exists());
+
+        EspSurvey {
+            vendors,
+            windows_boot_manager,
+        }
+    }
+
+    fn classify(&self, name: &str, path: &Path, namespaces: &[String]) -> VendorKind {
+        if namespaces.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            return VendorKind::Owned;
+        }
+        let files = fs::read_dir(path)
+            .map(|children| {
+                children
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().to_ascii_lowercase())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        self.rules
+            .iter()
+            .find_map(|rule| rule.classify(name, &files))
+            .cloned()
+            .unwrap_or(VendorKind::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::{EspSurveyor, VendorKind, VendorRule};
+
+    #[test]
+    fn test_survey() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let esp = root.path();
+        for dir in [
+            "EFI/aerynos",
+            "EFI/BOOT",
+            "EFI/systemd",
+            "EFI/Microsoft/Boot",
+            "EFI/fedora",
+            "EFI/refind",
+            "EFI/vendor",
+        ] {
+            fs::create_dir_all(esp.join(dir)).unwrap();
+        }
+        fs::write(esp.join("EFI/Microsoft/Boot/bootmgfw.efi"), "").unwrap();
+        fs::write(esp.join("EFI/fedora/grubx64.efi"), "").unwrap();
+
+        let kinds = |surveyor: &EspSurveyor| {
+            surveyor
+                .survey(esp, &["aerynos".into()])
+                .vendors
+                .into_iter()
+                .map(|v| (v.name, v.kind))
+                .collect::<Vec<_>>()
+        };
+        let surveyor = EspSurveyor::default();
+        assert_eq!(
+            kinds(&surveyor),
+            vec![
+                ("aerynos".into(), VendorKind::Owned),
+                ("BOOT".into(), VendorKind::Fallback),
+                ("fedora".into(), VendorKind::Grub),
+                ("Microsoft".into(), VendorKind::Windows),
+                ("refind".into(), VendorKind::Refind),
+                ("systemd".into(), VendorKind::SystemdBoot),
+                ("vendor".into(), VendorKind::Unknown),
+            ]
+        );
+
+        let survey = surveyor.survey(esp, &["aerynos".into()]);
+        assert_eq!(
+            survey.windows_boot_manager,
+            Some(esp.join("EFI/Microsoft/Boot/bootmgfw.efi"))
+        );
+        assert_eq!(survey.foreign().count(), 4);
+
+        let surveyor = surveyor.with_rule(VendorRule::dir("VENDOR", VendorKind::Other("Vendor OS".into())));
+        assert_eq!(
+            kinds(&surveyor)[6],
+            ("vendor".into(), VendorKind::Other("Vendor OS".into()))
+        );
+    }
+}
@@ -5,17 +5,26 @@
 //! File utilities shared between the blsforme APIs
 
 use std::{
+    fmt::Display,
     io,
     os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime},
 };
 
-use crate::{Error, IoSnafu};
+use crate::{
+    Cmdline, Error, IoSnafu, VfatWriteSnafu,
+    progress::{ProgressCallback, ProgressEvent, ProgressObserver},
+};
 use fs_err::{self as fs, File};
 use snafu::ResultExt as _;
 
 /// Case-insensitive path joining for FAT, respecting existing entries on the filesystem
 /// Note, this discards errors, so will require read permissions
+///
+/// Where multiple case variants exist (ie `EFI` and `efi`), an exact match is
+/// preferred, then a non-empty directory, then the first variant by byte order.
 pub trait PathExt<P: AsRef<Path>> {
     fn join_insensitive(&self, path: P) -> PathBuf;
 }
@@ -23,21 +32,157 @@ pub trait PathExt<P: AsRef<Path>> {
 impl<P: AsRef<Path>> PathExt<P> for PathBuf {
     fn join_insensitive(&self, path: P) -> PathBuf {
         let real_path: &Path = path.as_ref();
-        if let Ok(dir) = fs::read_dir(self) {
-            let entries = dir.filter_map(|e| e.ok()).filter_map(|p| {
-                let n = p.file_name();
-                n.into_string().ok()
-            });
-            for entry in entries {
-                if entry.to_lowercase() == real_path.to_string_lossy().to_lowercase() {
-                    return self.join(&entry);
-                }
+        let name = real_path.to_string_lossy();
+        let variants = case_variants(self, &name);
+        match variants.as_slice() {
+            [] => self.join(path),
+            [variant] => self.join(variant),
+            _ => {
+                let picked = preferred_variant(self, Some(&name), &variants);
+                log::warn!(
+                    "Multiple case variants of {name} in {}: {}. Using {picked}",
+                    self.display(),
+                    variants.join(", ")
+                );
+                self.join(picked)
+            }
+        }
+    }
+}
+
+/// All entries of `dir` matching `name` case-insensitively, sorted
+fn case_variants(dir: &Path, name: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let name = name.to_lowercase();
+    let mut variants = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|e| e.to_lowercase() == name)
+        .collect::<Vec<_>>();
+    variants.sort();
+    variants
+}
+
+/// Pick the variant to use from a sorted, non-empty set of case variants
+fn preferred_variant<'v>(dir: &Path, name: Option<&str>, variants: &'v [String]) -> &'v str {
+    let has_children = |v: &&String| fs::read_dir(dir.join(v)).is_ok_and(|mut d| d.next().is_some());
+    variants
+        .iter()
+        .find(|v| Some(v.as_str()) == name)
+        .or_else(|| variants.iter().find(has_children))
+        .unwrap_or(&variants[0])
+}
+
+/// A set of directories differing only by case, ie `EFI` and `efi`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseCollision {
+    /// The variant that [`PathExt::join_insensitive`] resolves to
+    pub preferred: PathBuf,
+
+    /// All other variants
+    pub duplicates: Vec<PathBuf>,
+}
+
+impl Display for CaseCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.preferred.display())?;
+        for duplicate in self.duplicates.iter() {
+            write!(f, " (duplicate: {})", duplicate.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl CaseCollision {
+    /// Move the contents of all duplicates into the preferred directory,
+    /// removing each duplicate once emptied
+    ///
+    /// Conflicting files are left in place with a warning.
+    pub fn merge(&self, writer: &Writer) -> io::Result<()> {
+        for duplicate in self.duplicates.iter() {
+            if merge_dir(duplicate, &self.preferred, writer)? {
+                writer.remove_dir_all(duplicate)?;
             }
         }
-        self.join(path)
+        Ok(())
     }
 }
 
+/// Move all of `source` into `dest`, returning `true` if nothing was left behind
+fn merge_dir(source: &Path, dest: &Path, writer: &Writer) -> io::Result<bool> {
+    let dest = dest.to_path_buf();
+    let mut hasher = blake3::Hasher::new();
+    let mut merged = true;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            merged = false;
+            continue;
+        };
+        let from = entry.path();
+        let target = dest.join_insensitive(&name);
+        if !target.exists() {
+            writer.rename(&from, target)?;
+        } else if from.is_dir() && target.is_dir() {
+            if merge_dir(&from, &target, writer)? {
+                writer.remove_dir_all(&from)?;
+            } else {
+                merged = false;
+            }
+        } else if from.is_file() && target.is_file() && files_identical(&mut hasher, &from, &target)? {
+            writer.remove_file(&from)?;
+        } else {
+            log::warn!("Not merging {}: conflicts with {}", from.display(), target.display());
+            merged = false;
+        }
+    }
+    Ok(merged)
+}
+
+/// Find all directories beneath `root` that differ only by case
+pub fn case_collisions(root: &Path) -> io::Result<Vec<CaseCollision>> {
+    let mut collisions = vec![];
+    let mut dirs = fs::read_dir(root)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    dirs.sort();
+
+    let mut seen = vec![];
+    for dir in dirs.iter() {
+        let lower = dir.to_lowercase();
+        if seen.contains(&lower) {
+            continue;
+        }
+        let variants = dirs
+            .iter()
+            .filter(|d| d.to_lowercase() == lower)
+            .cloned()
+            .collect::<Vec<_>>();
+        if variants.len() > 1 {
+            let preferred = preferred_variant(root, None, &variants);
+            collisions.push(CaseCollision {
+                preferred: root.join(preferred),
+                duplicates: variants
+                    .iter()
+                    .filter(|v| *v != preferred)
+                    .map(|v| root.join(v))
+                    .collect(),
+            });
+        }
+        seen.push(lower);
+    }
+
+    for dir in dirs.iter() {
+        collisions.extend(case_collisions(&root.join(dir))?);
+    }
+
+    Ok(collisions)
+}
+
 /// Compare two files with blake3 to see if they differ
 fn files_identical(hasher: &mut blake3::Hasher, a: &Path, b: &Path) -> io::Result<bool> {
     let fi_a = File::open(a)?;
@@ -85,15 +230,82 @@ pub fn changed_files(files: &[(PathBuf, PathBuf)]) -> Vec<(&PathBuf, &PathBuf)>
 /// Long story short we always set a temporary file name up,
 /// then delete the target file, and finally rename into place.
 /// This is to prevent various block corruption issues with vfat.
-pub fn copy_atomic_vfat(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io::Result<()> {
-    let source = source.as_ref();
+pub fn copy_atomic_vfat(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), Error> {
+    copy_atomic_vfat_with_progress(source, dest, |_, _| {})
+}
+
+/// [`copy_atomic_vfat`], reporting `(bytes_done, bytes_total)` after every
+/// [`PROGRESS_CHUNK`] bytes and once complete
+pub fn copy_atomic_vfat_with_progress(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    progress: impl FnMut(u64, u64),
+) -> Result<(), Error> {
+    let (source, dest) = (source.as_ref(), dest.as_ref());
+    File::open(source)
+        .and_then(|input| {
+            let total = input.metadata()?.len();
+            let mut reader = ProgressReader {
+                inner: input,
+                done: 0,
+                reported: 0,
+                total,
+                progress,
+            };
+            (reader.progress)(0, total);
+            write_vfat(&mut reader, dest)?;
+            if reader.reported != reader.done {
+                (reader.progress)(reader.done, total);
+            }
+            Ok(())
+        })
+        .context(VfatWriteSnafu { from: source, to: dest })
+}
+
+/// How often [`copy_atomic_vfat_with_progress`] reports progress
+pub const PROGRESS_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// Counts the bytes read, reporting them every [`PROGRESS_CHUNK`]
+struct ProgressReader<R, F> {
+    inner: R,
+    done: u64,
+    reported: u64,
+    total: u64,
+    progress: F,
+}
+
+impl<R: io::Read, F: FnMut(u64, u64)> io::Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.done += read as u64;
+        if self.done - self.reported >= PROGRESS_CHUNK {
+            self.reported = self.done;
+            (self.progress)(self.done, self.total);
+        }
+        Ok(read)
+    }
+}
+
+/// Write the given contents to dest file, using the same staging
+/// approach as [`copy_atomic_vfat`]
+pub fn write_atomic_vfat(contents: impl AsRef<[u8]>, dest: impl AsRef<Path>) -> Result<(), Error> {
     let dest = dest.as_ref();
+    write_vfat(&mut contents.as_ref(), dest).context(VfatWriteSnafu {
+        from: staging_path(dest),
+        to: dest,
+    })
+}
 
-    log::trace!("copy_atomic_vfat: {}", dest.display());
+/// Staging file for writes to `dest`
+fn staging_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".TmpWrite");
+    dest.with_file_name(name)
+}
 
-    // Staging path
-    let dest_temp = dest.with_extension(".TmpWrite");
-    let dest_exists = dest.exists();
+/// Stage the input and rename it into place, never leaving the staging file behind
+fn write_vfat(input: &mut impl io::Read, dest: &Path) -> io::Result<()> {
+    log::trace!("write_vfat: {}", dest.display());
 
     // Ensure leading path structure exists
     let dir_leading = dest
@@ -103,16 +315,34 @@ pub fn copy_atomic_vfat(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io:
         fs::create_dir_all(dir_leading)?;
     }
 
-    // open source/dest
+    // Staging path, discarding any left by an interrupted write
+    let dest_temp = staging_path(dest);
+    if dest_temp.exists() {
+        log::debug!("Removing stale staging file: {}", dest_temp.display());
+        fs::remove_file(&dest_temp)?;
+    }
+
+    let result = stage_and_rename(input, &dest_temp, dest);
+    if result.is_err() && dest_temp.exists() {
+        if let Err(e) = fs::remove_file(&dest_temp) {
+            log::warn!("Failed to clean up staging file: {e}");
+        }
+    }
+    result
+}
+
+fn stage_and_rename(input: &mut impl io::Read, dest_temp: &Path, dest: &Path) -> io::Result<()> {
+    let dest_exists = dest.exists();
+
+    // open dest
     let mut output = File::options()
         .truncate(true)
         .write(true)
         .create(true)
-        .open(&dest_temp)?;
-    let mut input = File::open(source)?;
+        .open(dest_temp)?;
 
     // Copy *contents* only
-    io::copy(&mut input, &mut output)?;
+    io::copy(input, &mut output)?;
     nix::unistd::syncfs(&output).map_err(|e| io::Error::from_raw_os_error(e as i32))?;
 
     // Remove original destination file
@@ -121,8 +351,17 @@ pub fn copy_atomic_vfat(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io:
         nix::unistd::syncfs(&output).map_err(|e| io::Error::from_raw_os_error(e as i32))?;
     }
 
-    // Rename into final location
-    fs::rename(dest_temp, dest)?;
+    // Rename into final location. Some VFAT implementations fail to rename over a
+    // destination that is still (or again) present, so remove it and retry once
+    if let Err(e) = std::fs::rename(dest_temp, dest) {
+        let retry = matches!(e.kind(), io::ErrorKind::AlreadyExists | io::ErrorKind::PermissionDenied);
+        if !retry || !dest.exists() {
+            return Err(e);
+        }
+        log::debug!("Retrying rename over {}: {e}", dest.display());
+        fs::remove_file(dest)?;
+        fs::rename(dest_temp, dest)?;
+    }
     nix::unistd::syncfs(&output).map_err(|e| io::Error::from_raw_os_error(e as i32))?;
 
     log::info!("Updated VFAT file: {}", dest.display());
@@ -130,18 +369,554 @@ pub fn copy_atomic_vfat(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io:
     Ok(())
 }
 
+/// Whether a [`Writer`] modifies the filesystem, or only plans the changes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Perform all changes
+    #[default]
+    Commit,
+
+    /// Record all changes without performing them
+    Plan,
+}
+
+/// How installed files are compared against their sources
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeDetection {
+    /// Trust cached hashes of installed files while their size and mtime are unchanged
+    #[default]
+    Metadata,
+
+    /// Always hash installed files, and verify each copy once written
+    Hash,
+}
+
+/// A single modification to the boot partitions (or EFI variables)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Copy a file into place
+    Copy { source: PathBuf, dest: PathBuf, size: u64 },
+
+    /// (Re)write a file with the given contents
+    Write { dest: PathBuf, contents: String },
+
+    /// Rename a file
+    Rename { from: PathBuf, to: PathBuf },
+
+    /// Remove a file
+    Remove { path: PathBuf },
+
+    /// Remove a directory tree
+    RemoveDir { path: PathBuf },
+
+    /// Set (or with no value, remove) an EFI variable
+    EfiVariable { name: String, value: Option<String> },
+
+    /// Run an external tool, i.e. `grub-install`
+    Exec { program: String, args: Vec<String> },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Copy { source, dest, size } => {
+                write!(f, "copy {} to {} ({size} bytes)", source.display(), dest.display())
+            }
+            Change::Write { dest, .. } => write!(f, "write {}", dest.display()),
+            Change::Rename { from, to } => write!(f, "rename {} to {}", from.display(), to.display()),
+            Change::Remove { path } => write!(f, "remove {}", path.display()),
+            Change::RemoveDir { path } => write!(f, "remove directory {}", path.display()),
+            Change::EfiVariable {
+                name,
+                value: Some(value),
+            } => write!(f, "set EFI variable {name} to {value}"),
+            Change::EfiVariable { name, value: None } => write!(f, "remove EFI variable {name}"),
+            Change::Exec { program, args } => write!(f, "run {program} {}", args.join(" ")),
+        }
+    }
+}
+
+/// The set of changes made, or planned, by a [`Writer`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncPlan {
+    pub changes: Vec<Change>,
+}
+
+impl SyncPlan {
+    /// Whether there's nothing to do
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Number of files copied or written
+    pub fn updated_files(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|c| matches!(c, Change::Copy { .. } | Change::Write { .. }))
+            .count()
+    }
+}
+
+impl Display for SyncPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in self.changes.iter() {
+            writeln!(f, "{change}")?;
+            if let Change::Write { contents, .. } = change {
+                for line in contents.lines() {
+                    writeln!(f, "    {line}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Performs all modifications to the boot partitions, recording each change
+///
+/// In [`WriteMode::Plan`] nothing is modified, allowing the exact same code
+/// paths to produce a [`SyncPlan`].
+#[derive(Debug, Default)]
+pub struct Writer {
+    mode: WriteMode,
+    reproducible: bool,
+    source_date_epoch: Option<SystemTime>,
+    changes: Mutex<Vec<Change>>,
+    progress: Option<ProgressObserver>,
+}
+
+impl Writer {
+    /// Construct a new writer with the given mode
+    pub fn new(mode: WriteMode) -> Self {
+        Self {
+            mode,
+            reproducible: false,
+            source_date_epoch: None,
+            changes: Mutex::default(),
+            progress: None,
+        }
+    }
+
+    /// A writer only planning changes, with the same timestamps as this one
+    pub(crate) fn planner(&self) -> Self {
+        Self {
+            mode: WriteMode::Plan,
+            reproducible: self.reproducible,
+            source_date_epoch: self.source_date_epoch,
+            changes: Mutex::default(),
+            progress: None,
+        }
+    }
+
+    /// Switch between planning and committing changes, keeping all other settings
+    pub fn with_mode(self, mode: WriteMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Report progress of copies (and other events) to the given callback
+    pub fn with_progress(self, callback: ProgressCallback) -> Self {
+        Self {
+            progress: Some(ProgressObserver::new(callback)),
+            ..self
+        }
+    }
+
+    /// Report a progress event, when observed
+    pub(crate) fn progress(&self, event: ProgressEvent) {
+        if let Some(observer) = &self.progress {
+            observer.emit(event);
+        }
+    }
+
+    /// Stamp written files with deterministic mtimes, for reproducible images
+    ///
+    /// Copies take the mtime of their source, and all files take `SOURCE_DATE_EPOCH`
+    /// when it is set. The variable is read here, once.
+    pub fn with_reproducible(self, reproducible: bool) -> Self {
+        Self {
+            reproducible,
+            source_date_epoch: source_date_epoch(),
+            ..self
+        }
+    }
+
+    /// Stamp reproducible files with the given time in place of `SOURCE_DATE_EPOCH`
+    pub fn with_source_date_epoch(self, source_date_epoch: Option<SystemTime>) -> Self {
+        Self {
+            source_date_epoch,
+            ..self
+        }
+    }
+
+    /// Fixed timestamp for written files, when reproducible
+    fn epoch(&self) -> Option<SystemTime> {
+        self.source_date_epoch.filter(|_| self.reproducible)
+    }
+
+    /// Current time, or `SOURCE_DATE_EPOCH` when reproducible and set
+    pub(crate) fn now(&self) -> SystemTime {
+        self.epoch().unwrap_or_else(SystemTime::now)
+    }
+
+    /// Whether we're only planning changes
+    pub fn dry_run(&self) -> bool {
+        self.mode == WriteMode::Plan
+    }
+
+    /// All changes made (or planned) so far
+    pub fn plan(&self) -> SyncPlan {
+        SyncPlan {
+            changes: self.changes.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+        }
+    }
+
+    /// Record a change, returning `true` if it should actually be performed
+    pub fn commit(&self, change: Change) -> bool {
+        if self.dry_run() {
+            log::debug!("[dry-run] would {change}");
+        }
+        self.changes.lock().unwrap_or_else(PoisonError::into_inner).push(change);
+        !self.dry_run()
+    }
+
+    /// Copy source file to dest file via [`copy_atomic_vfat`]
+    pub fn copy(&self, source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), Error> {
+        let (source, dest) = (source.as_ref(), dest.as_ref());
+        let change = Change::Copy {
+            source: source.to_path_buf(),
+            dest: dest.to_path_buf(),
+            size: fs::metadata(source).map(|m| m.len()).unwrap_or_default(),
+        };
+        if self.commit(change) {
+            copy_atomic_vfat_with_progress(source, dest, |bytes_done, bytes_total| {
+                self.progress(ProgressEvent::FileCopy {
+                    path: dest.to_path_buf(),
+                    bytes_done,
+                    bytes_total,
+                })
+            })?;
+            if self.reproducible {
+                let mtime = match self.epoch() {
+                    Some(mtime) => mtime,
+                    None => fs::metadata(source).and_then(|m| m.modified()).context(IoSnafu)?,
+                };
+                set_mtime(dest, mtime).context(IoSnafu)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write contents to dest file via [`write_atomic_vfat`], unless already identical
+    ///
+    /// Returns `true` if the file was (or, when planning, would be) modified.
+    pub fn write(&self, contents: impl AsRef<[u8]>, dest: impl AsRef<Path>) -> Result<bool, Error> {
+        let contents = contents.as_ref();
+        let dest = dest.as_ref();
+        if fs::read(dest).is_ok_and(|existing| existing == contents) {
+            log::debug!("Unchanged: {}", dest.display());
+            return Ok(false);
+        }
+        log::debug!("Updating: {}", dest.display());
+        let change = Change::Write {
+            dest: dest.to_path_buf(),
+            contents: String::from_utf8_lossy(contents).to_string(),
+        };
+        if self.commit(change) {
+            write_atomic_vfat(contents, dest)?;
+            if let Some(mtime) = self.epoch() {
+                set_mtime(dest, mtime).context(IoSnafu)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Rename a file
+    pub fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let change = Change::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
+        if self.commit(change) {
+            fs::rename(from, to)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a file
+    pub fn remove_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if self.commit(Change::Remove {
+            path: path.to_path_buf(),
+        }) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Run an external program, failing unless it exits successfully
+    pub fn exec(&self, program: &str, args: &[String]) -> io::Result<()> {
+        let change = Change::Exec {
+            program: program.to_string(),
+            args: args.to_vec(),
+        };
+        if self.commit(change) {
+            let status = std::process::Command::new(program).args(args).status()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("{program} failed: {status}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a directory tree
+    pub fn remove_dir_all(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if self.commit(Change::RemoveDir {
+            path: path.to_path_buf(),
+        }) {
+            fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Timestamp from `SOURCE_DATE_EPOCH`, if set and valid
+fn source_date_epoch() -> Option<SystemTime> {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse::<u64>().ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Set the modification time of the file at `path`
+fn set_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
+    File::options().write(true).open(path)?.file().set_modified(mtime)
+}
+
+/// Total size of the file, or all files beneath the directory
+pub fn tree_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| tree_size(&e.path())).sum())
+        .unwrap_or_default()
+}
+
+/// Render a size in bytes for humans, ie `12.3 MiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in UNITS.iter().skip(1) {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
 /// Read a cmdline snippet from a file, which supports comments (`#`)
-/// and concatenates lines into a single string.
+/// and joins the parameters of all lines into a single string.
+///
+/// Comments may take a whole line or trail the parameters, except within quoted values.
 pub fn cmdline_snippet(path: impl AsRef<Path>) -> Result<String, Error> {
     let path = path.as_ref();
     log::trace!("Reading cmdline snippet: {path:?}");
     let ret = fs::read_to_string(path)
         .context(IoSnafu)?
         .lines()
-        .map(|l| l.trim())
-        .filter(|l| !l.starts_with('#'))
+        .map(|line| {
+            let Ok(cmdline) = strip_comment(line).parse::<Cmdline>();
+            cmdline.to_string()
+        })
+        .filter(|params| !params.is_empty())
         .collect::<Vec<_>>()
-        .join(" ")
-        .to_string();
+        .join(" ");
     Ok(ret)
 }
+
+/// The line up to the first `#` outside of double quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use fs_err as fs;
+
+    use super::{
+        PROGRESS_CHUNK, PathExt, WriteMode, Writer, case_collisions, cmdline_snippet, copy_atomic_vfat,
+        copy_atomic_vfat_with_progress, staging_path,
+    };
+    use crate::Error;
+
+    /// ESP with both `EFI/Boot` and `efi/boot`, the latter holding the loader
+    fn esp() -> tempfile::TempDir {
+        let esp = tempfile::tempdir().expect("Failed to create tempdir");
+        fs::create_dir_all(esp.path().join("EFI/Boot")).unwrap();
+        fs::create_dir_all(esp.path().join("efi/boot")).unwrap();
+        fs::create_dir_all(esp.path().join("efi/aerynos")).unwrap();
+        fs::write(esp.path().join("efi/boot/BOOTX64.EFI"), "loader").unwrap();
+        esp
+    }
+
+    #[test]
+    fn test_join_insensitive() {
+        let esp = esp();
+        let root = esp.path().to_path_buf();
+        assert_eq!(root.join_insensitive("EFI"), root.join("EFI"));
+        assert_eq!(root.join_insensitive("efi"), root.join("efi"));
+        assert_eq!(root.join_insensitive("Efi"), root.join("EFI"));
+        assert_eq!(
+            root.join_insensitive("EFI").join_insensitive("BOOT"),
+            root.join("EFI/Boot")
+        );
+        assert_eq!(
+            root.join_insensitive("efi").join_insensitive("BOOT"),
+            root.join("efi/boot")
+        );
+        assert_eq!(root.join_insensitive("loader"), root.join("loader"));
+    }
+
+    #[test]
+    fn test_case_collisions() {
+        let esp = esp();
+        let root = esp.path();
+        let collisions = case_collisions(root).unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].preferred, root.join("EFI"));
+        assert_eq!(collisions[0].duplicates, [root.join("efi")]);
+
+        // Planning leaves everything in place
+        let writer = Writer::new(WriteMode::Plan);
+        collisions[0].merge(&writer).unwrap();
+        assert!(!writer.plan().is_empty());
+        assert!(root.join("efi").exists());
+
+        collisions[0].merge(&Writer::default()).unwrap();
+        assert!(!root.join("efi").exists());
+        assert!(root.join("EFI/Boot/BOOTX64.EFI").exists());
+        assert!(root.join("EFI/aerynos").is_dir());
+        assert!(case_collisions(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cmdline_snippet() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = dir.path().join("10-console.cmdline");
+        fs::write(
+            &path,
+            r#"# Serial console for the BMC
+console=ttyS0,115200n8   # primary
+  console=tty0
+
+# Keep the quoted value intact
+dyndbg="file drivers/usb/* +p #usb"  quiet
+#splash
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cmdline_snippet(&path).unwrap(),
+            r#"console=ttyS0,115200n8 console=tty0 dyndbg="file drivers/usb/* +p #usb" quiet"#
+        );
+
+        fs::write(&path, "# Only comments\n\n#quiet\n").unwrap();
+        assert_eq!(cmdline_snippet(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_copy_atomic_vfat() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let source = dir.path().join("vmlinuz");
+        let dest = dir.path().join("EFI/aerynos/vmlinuz");
+        let staging = staging_path(&dest);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&source, "new").unwrap();
+        fs::write(&dest, "old").unwrap();
+        fs::write(&staging, "stale").unwrap();
+
+        copy_atomic_vfat(&source, &dest).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(!staging.exists());
+
+        // Failures identify both files, and leave no staging file behind
+        let blocked = dir.path().join("EFI/aerynos/initrd");
+        fs::create_dir_all(blocked.join("occupied")).unwrap();
+        let err = copy_atomic_vfat(&source, &blocked).unwrap_err();
+        assert!(
+            matches!(&err, Error::VfatWrite { from, to, .. } if *from == source && *to == blocked),
+            "unexpected error: {err}"
+        );
+        assert!(!staging_path(&blocked).exists());
+
+        // The writer surfaces the same typed errors
+        let writer = Writer::default();
+        assert!(matches!(writer.copy(&source, &blocked), Err(Error::VfatWrite { .. })));
+        assert!(matches!(
+            writer.write("new", &blocked),
+            Err(Error::VfatWrite { to, .. }) if to == blocked
+        ));
+    }
+
+    #[test]
+    fn test_staging_path() {
+        let dir = std::path::Path::new("EFI/aerynos/6.8.2-25.arm64");
+        assert_eq!(
+            staging_path(&dir.join("10-default.initrd")),
+            dir.join("10-default.initrd.TmpWrite")
+        );
+
+        // Files differing only by extension are staged apart, as when copied in parallel
+        assert_ne!(
+            staging_path(&dir.join("board.initrd")),
+            staging_path(&dir.join("board.dtb"))
+        );
+        assert_eq!(staging_path(&dir.join("vmlinuz")), dir.join("vmlinuz.TmpWrite"));
+    }
+
+    #[test]
+    fn test_copy_progress() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let source = dir.path().join("initrd");
+        let total = PROGRESS_CHUNK * 2 + 10;
+        fs::write(&source, vec![0u8; total as usize]).unwrap();
+
+        let mut reports = vec![];
+        copy_atomic_vfat_with_progress(&source, dir.path().join("copy"), |done, size| {
+            assert_eq!(size, total);
+            reports.push(done);
+        })
+        .unwrap();
+        assert_eq!(reports, vec![0, PROGRESS_CHUNK, PROGRESS_CHUNK * 2, total]);
+    }
+
+    #[test]
+    fn test_planner() {
+        let epoch = UNIX_EPOCH + Duration::from_secs(1700000000);
+        let writer = Writer::new(WriteMode::Commit)
+            .with_reproducible(true)
+            .with_source_date_epoch(Some(epoch));
+        let planner = writer.planner();
+        assert!(planner.dry_run());
+        assert_eq!(planner.now(), epoch);
+        assert!(!Writer::new(WriteMode::Commit).planner().reproducible);
+    }
+}
@@ -0,0 +1,269 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Cached hashes of files installed to `$BOOT`
+//!
+//! Deciding whether a file needs copying means hashing both the source and
+//! the installed copy. The installed hashes are kept in a small JSON file on
+//! `$BOOT` and trusted for as long as the file's size and mtime are unchanged,
+//! avoiding re-reading every kernel and initrd from the VFAT partition.
+//! With [`ChangeDetection::Hash`] the cache is bypassed, installed copies are
+//! always re-read and each copy is verified once written.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use snafu::ResultExt as _;
+
+use crate::{
+    Error, JsonSnafu,
+    file_utils::{ChangeDetection, PathExt, Writer},
+};
+
+/// Hash of a single installed file, valid while its size and mtime are unchanged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHash {
+    size: u64,
+    mtime: i64,
+    hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Keyed by path relative to `$BOOT`
+    #[serde(default)]
+    files: BTreeMap<String, CachedHash>,
+
+    /// Source hashes for files we're about to copy, keyed by destination
+    #[serde(skip)]
+    pending: BTreeMap<PathBuf, String>,
+
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// Persistent cache of BLAKE3 hashes for files installed to `$BOOT`
+#[derive(Debug)]
+pub(crate) struct HashCache {
+    root: PathBuf,
+    detection: ChangeDetection,
+    state: Mutex<State>,
+}
+
+impl HashCache {
+    /// Path to the cache within the given `$BOOT`
+    fn path(boot_root: &Path) -> PathBuf {
+        boot_root
+            .to_path_buf()
+            .join_insensitive("loader")
+            .join_insensitive(".blsforme-hashes.json")
+    }
+
+    /// Load the cache from `$BOOT`, starting afresh if missing or unreadable
+    pub(crate) fn load(boot_root: &Path, detection: ChangeDetection) -> Self {
+        let path = Self::path(boot_root);
+        let state = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                log::warn!("Ignoring corrupt hash cache {}: {e}", path.display());
+                State::default()
+            }),
+            Err(_) => State::default(),
+        };
+        Self {
+            root: boot_root.to_path_buf(),
+            detection,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Find out which of the `(source, dest)` pairs differ in content
+    pub(crate) fn changed_files<'f>(&self, files: &'f [(PathBuf, PathBuf)]) -> Vec<(&'f PathBuf, &'f PathBuf)> {
+        files
+            .iter()
+            .filter(|(source, dest)| !self.identical(source, dest).unwrap_or(false))
+            .map(|(source, dest)| (source, dest))
+            .collect()
+    }
+
+    /// Compare the source against the installed copy, via the cache where possible
+    fn identical(&self, source: &Path, dest: &Path) -> io::Result<bool> {
+        let source_meta = fs::metadata(source)?;
+        let Ok(dest_meta) = fs::metadata(dest) else {
+            self.state().pending.insert(dest.to_path_buf(), hash_file(source)?);
+            return Ok(false);
+        };
+        let source_hash = hash_file(source)?;
+        if source_meta.size() != dest_meta.size() {
+            self.state().pending.insert(dest.to_path_buf(), source_hash);
+            return Ok(false);
+        }
+
+        let key = self.key(dest);
+        let cached = key
+            .as_ref()
+            .filter(|_| self.detection == ChangeDetection::Metadata)
+            .and_then(|key| {
+                self.state()
+                    .files
+                    .get(key)
+                    .filter(|c| c.size == dest_meta.size() && c.mtime == dest_meta.mtime())
+                    .map(|c| c.hash.clone())
+            });
+        let dest_hash = match cached {
+            Some(hash) => hash,
+            None => {
+                let hash = hash_file(dest)?;
+                if let Some(key) = key {
+                    let mut state = self.state();
+                    state.files.insert(
+                        key,
+                        CachedHash {
+                            size: dest_meta.size(),
+                            mtime: dest_meta.mtime(),
+                            hash: hash.clone(),
+                        },
+                    );
+                    state.dirty = true;
+                }
+                hash
+            }
+        };
+
+        if source_hash == dest_hash {
+            Ok(true)
+        } else {
+            self.state().pending.insert(dest.to_path_buf(), source_hash);
+            Ok(false)
+        }
+    }
+
+    /// Record that `dest` was just installed from the source passed to [`Self::changed_files`]
+    ///
+    /// With [`ChangeDetection::Hash`] the installed copy is re-read first, returning
+    /// `false` if it doesn't match the source.
+    pub(crate) fn copied(&self, dest: &Path) -> io::Result<bool> {
+        let Some(hash) = self.state().pending.remove(dest) else {
+            return Ok(true);
+        };
+        if self.detection == ChangeDetection::Hash && hash_file(dest)? != hash {
+            return Ok(false);
+        }
+        let (Some(key), Ok(meta)) = (self.key(dest), fs::metadata(dest)) else {
+            return Ok(true);
+        };
+        let mut state = self.state();
+        state.files.insert(
+            key,
+            CachedHash {
+                size: meta.size(),
+                mtime: meta.mtime(),
+                hash,
+            },
+        );
+        state.dirty = true;
+        Ok(true)
+    }
+
+    /// Persist the cache, dropping entries for files that no longer exist
+    ///
+    /// Nothing is written when only planning changes.
+    pub(crate) fn save(&self, writer: &Writer) -> Result<(), Error> {
+        if writer.dry_run() {
+            return Ok(());
+        }
+        let mut state = self.state();
+        let before = state.files.len();
+        state.files.retain(|key, _| self.root.join(key).exists());
+        if !state.dirty && state.files.len() == before {
+            return Ok(());
+        }
+
+        let contents = serde_json::to_string_pretty(&*state).context(JsonSnafu)?;
+        writer.write(contents, Self::path(&self.root))?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Cache key for the given path, if it lives on `$BOOT`
+    fn key(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|p| p.to_string_lossy().to_string())
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// BLAKE3 hash of the given file, in hex
+///
+/// The file is streamed in chunks rather than mapped, so a flaky VFAT
+/// read surfaces as an error instead of a fault.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::HashCache;
+    use crate::file_utils::{ChangeDetection, Writer};
+
+    #[test]
+    fn test_changed_files() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let boot = root.path().join("boot");
+        let source = root.path().join("vmlinuz");
+        let dest = boot.join("vmlinuz");
+        fs::create_dir_all(&boot).unwrap();
+        fs::write(&source, "kernel").unwrap();
+
+        let files = [(source.clone(), dest.clone())];
+        let cache = HashCache::load(&boot, ChangeDetection::Metadata);
+        assert_eq!(cache.changed_files(&files).len(), 1);
+        fs::copy(&source, &dest).unwrap();
+        assert!(cache.copied(&dest).unwrap());
+        assert!(cache.changed_files(&files).is_empty());
+        cache.save(&Writer::default()).unwrap();
+
+        // A fresh load trusts the cached hash of the installed copy
+        let cache = HashCache::load(&boot, ChangeDetection::Metadata);
+        assert!(cache.changed_files(&files).is_empty());
+        fs::write(&source, "update").unwrap();
+        assert_eq!(cache.changed_files(&files).len(), 1);
+    }
+
+    #[test]
+    fn test_verify_copy() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let boot = root.path().join("boot");
+        let source = root.path().join("vmlinuz");
+        let dest = boot.join("vmlinuz");
+        fs::create_dir_all(&boot).unwrap();
+        fs::write(&source, "kernel").unwrap();
+
+        let files = [(source.clone(), dest.clone())];
+        let cache = HashCache::load(&boot, ChangeDetection::Hash);
+        assert_eq!(cache.changed_files(&files).len(), 1);
+        fs::write(&dest, "kernal").unwrap();
+        assert!(!cache.copied(&dest).unwrap());
+
+        assert_eq!(cache.changed_files(&files).len(), 1);
+        fs::copy(&source, &dest).unwrap();
+        assert!(cache.copied(&dest).unwrap());
+        assert!(cache.changed_files(&files).is_empty());
+    }
+}
@@ -5,17 +5,26 @@
 //! Kernel abstraction
 
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
     path::{Path, PathBuf},
 };
 
-use serde::Deserialize;
+use fs_err as fs;
+use nix::sys::utsname::uname;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt as _;
 
-use crate::{Error, os_release::OsRelease};
+use crate::{Error, IoSnafu, NixSnafu, entry::EFI_TOOLS_DIR, file_utils::PathExt, os_release::OsRelease};
 use os_info::OsInfo;
 
+/// Namespaces of the clr-boot-manager based distributions, for [`Schema::Legacy`]
+const LEGACY_NAMESPACES: [&str; 2] = ["com.solus-project", "org.clearlinux"];
+
 /// Control kernel discovery mechanism
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Schema {
     /// Legacy (clr-boot-manager style) schema
     Legacy {
@@ -30,35 +39,118 @@ pub enum Schema {
     OsInfo { os_info: Box<OsInfo> },
 }
 
-/// `boot.json` deserialise support
+/// Owned form of [`Schema`], as a borrowed namespace can't be deserialized
+#[cfg(feature = "serde")]
 #[derive(Deserialize)]
-pub struct BootJSON<'a> {
+#[serde(rename_all = "kebab-case")]
+enum SchemaRepr {
+    Legacy {
+        os_release: Box<OsRelease>,
+        namespace: String,
+    },
+    Blsforme {
+        os_release: Box<OsRelease>,
+    },
+    OsInfo {
+        os_info: Box<OsInfo>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Schema {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match SchemaRepr::deserialize(deserializer)? {
+            SchemaRepr::Legacy { os_release, namespace } => Schema::Legacy {
+                os_release,
+                namespace: LEGACY_NAMESPACES
+                    .into_iter()
+                    .find(|n| *n == namespace)
+                    .ok_or_else(|| serde::de::Error::unknown_variant(&namespace, &LEGACY_NAMESPACES))?,
+            },
+            SchemaRepr::Blsforme { os_release } => Schema::Blsforme { os_release },
+            SchemaRepr::OsInfo { os_info } => Schema::OsInfo { os_info },
+        })
+    }
+}
+
+/// Schemas are equal when they identify the same OS in the same way: the namespace
+/// and OS ID for [`Schema::Legacy`], otherwise the [`Schema::os_id`]
+impl PartialEq for Schema {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Schema::Legacy { os_release, namespace },
+                Schema::Legacy {
+                    os_release: other_release,
+                    namespace: other_namespace,
+                },
+            ) => namespace == other_namespace && os_release.id == other_release.id,
+            (Schema::Blsforme { .. }, Schema::Blsforme { .. }) | (Schema::OsInfo { .. }, Schema::OsInfo { .. }) => {
+                self.os_id() == other.os_id()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Schema {}
+
+/// `boot.json` (de)serialise support
+///
+/// Unknown fields are ignored, so that newer kernel packages may extend the format.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BootJSON {
     /// Kernel's package name
-    #[serde(borrow)]
-    pub name: &'a str,
+    pub name: String,
 
     /// Kernel's version string (uname -r)
-    #[serde(borrow)]
-    pub version: &'a str,
+    pub version: String,
 
     /// Kernel's variant id
-    #[serde(borrow)]
-    pub variant: &'a str,
+    pub variant: String,
+
+    /// Kernel parameters provided by the kernel package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<Vec<String>>,
+
+    /// Companion packages required by the kernel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends: Option<Vec<String>>,
+
+    /// Whether the kernel is a unified kernel image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uki: Option<bool>,
 }
 
-impl<'a> TryFrom<&'a str> for BootJSON<'a> {
+impl TryFrom<&str> for BootJSON {
     type Error = serde_json::Error;
 
-    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
         serde_json::from_str::<Self>(value)
     }
 }
 
+impl BootJSON {
+    /// Read the `boot.json` at the given path, warning if it can't be used
+    fn load(path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path)
+            .inspect_err(|e| log::warn!("Unable to read {}: {e}", path.display()))
+            .ok()?;
+        Self::try_from(text.as_str())
+            .inspect_err(|e| log::warn!("Ignoring malformed {}: {e}", path.display()))
+            .ok()
+    }
+}
+
 /// A kernel is the primary bootable element that we care about, ie
 /// the vmlinuz file. It also comes with a set of auxiliary files
 /// that are required for a fully working system, but specifically
 /// dependent on that kernel version.
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+///
+/// Kernels are ordered by their [`KernelVersion`], so sorting a collection
+/// in reverse yields the newest kernel first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct Kernel {
     /// Matches the `uname -r` of the kernel, should be uniquely encoded by release/variant
     pub version: String,
@@ -74,15 +166,218 @@ pub struct Kernel {
 
     /// Recorded variant type
     pub variant: Option<String>,
+
+    /// Parsed `boot.json` shipped with the kernel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_meta: Option<BootJSON>,
+}
+
+impl Kernel {
+    /// Structured version of this kernel, for ordering
+    pub fn kernel_version(&self) -> KernelVersion {
+        KernelVersion::from(self.version.as_str())
+    }
+
+    /// Whether the image is a unified kernel image (`.efi`), bundling
+    /// the kernel, initrd and cmdline, or is flagged to boot as an EFI binary
+    pub fn is_uki(&self) -> bool {
+        self.image.extension().is_some_and(|e| e.eq_ignore_ascii_case("efi")) || self.efi_stub()
+    }
+
+    /// Whether this is the currently running kernel, according to `uname -r`
+    pub fn matches_running(&self) -> Result<bool, Error> {
+        let running = uname().context(NixSnafu)?;
+        Ok(self.matches_release(&running.release().to_string_lossy()))
+    }
+
+    /// Compare against a `uname -r` release, ignoring any trailing `+` build metadata
+    fn matches_release(&self, release: &str) -> bool {
+        release.trim_end_matches('+') == self.version.trim_end_matches('+')
+    }
+
+    /// Whether the kernel is flagged (via [`AuxiliaryKind::EfiStub`]) to boot from a
+    /// type #1 entry using the `efi` key, rather than as a type #2 entry
+    pub fn efi_stub(&self) -> bool {
+        self.extras.iter().any(|e| matches!(e.kind, AuxiliaryKind::EfiStub))
+    }
+
+    /// Identify microcode initrds by the given filename patterns, rather than
+    /// [`MICROCODE_INITRDS`], reordering the initrds to match
+    pub fn with_microcode_patterns(self, patterns: &[&str]) -> Self {
+        let mut initrd = self
+            .initrd
+            .into_iter()
+            .map(|i| i.with_microcode_patterns(patterns))
+            .collect::<Vec<_>>();
+        initrd.sort_by_key(initrd_order);
+        Self { initrd, ..self }
+    }
+}
+
+impl PartialOrd for Kernel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Kernel {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_versions(&self.version, &other.version)
+            .then_with(|| self.image.cmp(&other.image))
+            .then_with(|| self.initrd.cmp(&other.initrd))
+            .then_with(|| self.extras.cmp(&other.extras))
+            .then_with(|| self.variant.cmp(&other.variant))
+    }
+}
+
+/// Structured kernel version, ie `6.8.2-25.desktop`
+///
+/// The leading `major.minor.patch` components are compared numerically, so
+/// that `6.10` sorts after `6.9`. Missing components are treated as `0`. A
+/// directly following `-rcN` marks a pre-release, sorting before the release.
+/// Anything after that forms the `local` part, compared lexicographically
+/// with runs of digits compared numerically (`-100` sorts after `-25`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub rc: Option<u32>,
+    pub local: String,
+}
+
+impl KernelVersion {
+    /// Parse the version, unless it doesn't start with a number
+    ///
+    /// [`KernelVersion::from`] accepts anything, but unrecognised versions then
+    /// only order by their `local` part.
+    pub fn parse(version: &str) -> Option<Self> {
+        version
+            .starts_with(|c: char| c.is_ascii_digit())
+            .then(|| Self::from(version))
+    }
+}
+
+impl From<&str> for KernelVersion {
+    fn from(version: &str) -> Self {
+        let mut numbers = [0u32; 3];
+        let mut rest = version;
+        for (index, number) in numbers.iter_mut().enumerate() {
+            let candidate = if index == 0 { Some(rest) } else { rest.strip_prefix('.') };
+            let Some(candidate) = candidate else {
+                break;
+            };
+            let end = candidate.find(|c: char| !c.is_ascii_digit()).unwrap_or(candidate.len());
+            let Ok(value) = candidate[..end].parse() else {
+                break;
+            };
+            *number = value;
+            rest = &candidate[end..];
+        }
+
+        let rc = rest.strip_prefix("-rc").and_then(|candidate| {
+            let end = candidate.find(|c: char| !c.is_ascii_digit()).unwrap_or(candidate.len());
+            let value = candidate[..end].parse().ok()?;
+            rest = &candidate[end..];
+            Some(value)
+        });
+
+        let [major, minor, patch] = numbers;
+        Self {
+            major,
+            minor,
+            patch,
+            rc,
+            local: rest.to_string(),
+        }
+    }
+}
+
+impl Display for KernelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(rc) = self.rc {
+            write!(f, "-rc{rc}")?;
+        }
+        f.write_str(&self.local)
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.rc, other.rc) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+            .then_with(|| compare_local(&self.local, &other.local))
+    }
+}
+
+/// Compare two version strings via [`KernelVersion`]
+///
+/// Unrecognised versions are considered older, and ordered by name.
+pub(crate) fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (KernelVersion::parse(a), KernelVersion::parse(b)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => Ordering::Equal,
+    }
+    .then_with(|| a.cmp(b))
+}
+
+/// Compare the local part of two versions, treating runs of digits numerically
+pub(crate) fn compare_local(a: &str, b: &str) -> Ordering {
+    let mut a = version_chunks(a);
+    let mut b = version_chunks(b);
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                _ => x.cmp(y),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Split a version into alternating runs of digits and non-digits
+fn version_chunks(version: &str) -> impl Iterator<Item = &str> {
+    let mut rest = version;
+    std::iter::from_fn(move || {
+        let digit = rest.chars().next()?.is_ascii_digit();
+        let end = rest.find(|c: char| c.is_ascii_digit() != digit).unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
 }
 
 /// Denotes the kind of auxiliary file
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[serde(rename_all = "snake_case")]
 pub enum AuxiliaryKind {
     /// A cmdline snippet
     Cmdline,
 
     /// An initial ramdisk
+    #[serde(rename = "initrd")]
     InitRd,
 
     /// System.map file
@@ -93,24 +388,254 @@ pub enum AuxiliaryKind {
 
     /// The `boot.json` file
     BootJson,
+
+    /// The `efistub` marker, requesting the image is booted directly
+    /// as an EFI binary from a type #1 entry
+    EfiStub,
+
+    /// A flattened devicetree blob (`.dtb`), selected per board via [`crate::Entry::with_devicetree`]
+    DeviceTree,
+
+    /// The `excluded.cmdline.d` file, naming `cmdline.d` snippets to leave out for this kernel
+    ExcludedCmdline,
+
+    /// An early microcode initrd (i.e. `intel-ucode.img`), always loaded ahead of the other initrds
+    Microcode,
+}
+
+impl AuxiliaryKind {
+    /// Whether the file is loaded as an initrd
+    pub fn is_initrd(&self) -> bool {
+        matches!(self, Self::InitRd | Self::Microcode)
+    }
 }
 
 /// An additional file required to be shipped with the kernel,
 /// such as initrds, system maps, etc.
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct AuxiliaryFile {
     pub path: PathBuf,
     pub kind: AuxiliaryKind,
+
+    /// Load order, lowest first. Early microcode must precede all other initrds
+    pub priority: u8,
+}
+
+/// Default filename patterns identifying microcode initrds
+pub const MICROCODE_INITRDS: [&str; 1] = ["*ucode*"];
+
+/// Whether the file name is a microcode image, i.e. `intel-ucode.img` or `amd-ucode.img`
+fn is_microcode_image(file_name: &str) -> bool {
+    file_name.ends_with("-ucode.img")
+}
+
+/// Priority of microcode initrds
+const PRIORITY_MICROCODE: u8 = 0;
+
+/// Priority of all other auxiliary files
+const PRIORITY_DEFAULT: u8 = 100;
+
+impl AuxiliaryFile {
+    /// New auxiliary file, with microcode initrds identified by [`MICROCODE_INITRDS`]
+    pub fn new(path: impl Into<PathBuf>, kind: AuxiliaryKind) -> Self {
+        Self {
+            path: path.into(),
+            kind,
+            priority: PRIORITY_DEFAULT,
+        }
+        .with_microcode_patterns(&MICROCODE_INITRDS)
+    }
+
+    /// Recompute the priority, identifying microcode initrds by the given filename patterns
+    ///
+    /// Patterns are matched case-insensitively and support `*` wildcards.
+    /// [`AuxiliaryKind::Microcode`] files are always microcode.
+    pub fn with_microcode_patterns(self, patterns: &[&str]) -> Self {
+        let name = self
+            .path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let microcode = match self.kind {
+            AuxiliaryKind::Microcode => true,
+            AuxiliaryKind::InitRd => patterns.iter().any(|p| wildcard_match(&p.to_lowercase(), &name)),
+            _ => false,
+        };
+        Self {
+            priority: if microcode {
+                PRIORITY_MICROCODE
+            } else {
+                PRIORITY_DEFAULT
+            },
+            ..self
+        }
+    }
+}
+
+/// All `.dtb` files beneath `dir`, i.e. `dtbs/rockchip/rk3588-rock-5b.dtb`
+fn device_trees(dir: &Path) -> Vec<PathBuf> {
+    let Ok(children) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    children
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .flat_map(|path| {
+            if path.is_dir() {
+                device_trees(&path)
+            } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("dtb")) {
+                vec![path]
+            } else {
+                vec![]
+            }
+        })
+        .collect()
+}
+
+/// Match `name` against a pattern where `*` matches any run of characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Sort key for initrds: by priority, then stable name order
+fn initrd_order(initrd: &AuxiliaryFile) -> (u8, String) {
+    (initrd.priority, initrd.path.display().to_string().to_lowercase())
 }
 
 impl Schema {
+    /// The schema for an OS identified only by its os-release
+    ///
+    /// Solus 4 and Clear Linux OS (or their derivatives, per `ID_LIKE`) use the
+    /// [`Schema::Legacy`] layout of clr-boot-manager, all others [`Schema::Blsforme`].
+    pub fn from_os_release(os_release: OsRelease) -> Self {
+        let id = std::iter::once(os_release.id.as_str())
+            .chain(os_release.like_ids())
+            .find(|id| matches!(*id, "solus" | "clear-linux-os"));
+        let namespace = match id {
+            Some("solus") if os_release.version.name.as_ref().is_some_and(|v| v.starts_with("4.")) => {
+                log::trace!("Legacy schema due to Solus 4 installation");
+                Some(LEGACY_NAMESPACES[0])
+            }
+            Some("clear-linux-os") => {
+                log::trace!("Legacy schema due to Clear Linux OS installation");
+                Some(LEGACY_NAMESPACES[1])
+            }
+            _ => None,
+        };
+
+        let os_release = Box::new(os_release);
+        match namespace {
+            Some(namespace) => Schema::Legacy { os_release, namespace },
+            None => Schema::Blsforme { os_release },
+        }
+    }
+
     /// Given a set of kernel-like paths, yield all potential kernels within them
     /// This should be a set of `/usr/lib/kernel` paths. Use glob or appropriate to discover.
+    ///
+    /// Kernels are returned newest-first.
     pub fn discover_system_kernels(&self, paths: impl Iterator<Item = impl AsRef<Path>>) -> Result<Vec<Kernel>, Error> {
-        match &self {
+        let mut kernels = match &self {
             Schema::Legacy { namespace, .. } => Self::legacy_kernels(namespace, paths),
             Schema::Blsforme { .. } => Self::blsforme_kernels(paths),
             Schema::OsInfo { .. } => Self::blsforme_kernels(paths),
+        }?;
+        for kernel in kernels.iter().filter(|k| KernelVersion::parse(&k.version).is_none()) {
+            log::warn!("Unrecognised kernel version {}, ordering by name", kernel.version);
+        }
+        kernels.sort_by(|a, b| b.cmp(a));
+        Ok(kernels)
+    }
+
+    /// Reconstruct the kernels installed beneath `EFI/$namespace` on a boot partition,
+    /// independent of the bootloader in use. UKIs in `EFI/Linux` are not included.
+    ///
+    /// Kernels are returned newest-first, with paths on the boot partition.
+    pub fn discover_installed_kernels(&self, boot_root: &Path) -> Result<Vec<Kernel>, Error> {
+        let namespace_dir = boot_root
+            .to_path_buf()
+            .join_insensitive("EFI")
+            .join_insensitive(self.os_namespace());
+        if !namespace_dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let children = fs::read_dir(&namespace_dir)
+            .context(IoSnafu)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect::<Vec<_>>();
+
+        match self {
+            // Flat `kernel-$name` and `initrd-$name` files, named after the rootfs files
+            Schema::Legacy { namespace, .. } => {
+                let installed = children
+                    .into_iter()
+                    .filter_map(|path| {
+                        let name = path.file_name()?.to_str()?;
+                        let original = name.strip_prefix("kernel-").or_else(|| name.strip_prefix("initrd-"))?;
+                        Some((namespace_dir.join(original), path.clone()))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                let installed_path = |path: PathBuf| installed.get(&path).cloned().unwrap_or(path);
+                let mut kernels = Self::legacy_kernels(namespace, installed.keys())?
+                    .into_iter()
+                    .map(|kernel| Kernel {
+                        image: installed_path(kernel.image),
+                        initrd: kernel
+                            .initrd
+                            .into_iter()
+                            .map(|i| AuxiliaryFile {
+                                path: installed_path(i.path),
+                                ..i
+                            })
+                            .collect(),
+                        ..kernel
+                    })
+                    .collect::<Vec<_>>();
+                kernels.sort_by(|a, b| b.cmp(a));
+                Ok(kernels)
+            }
+            // `$version/` directories, as in `/usr/lib/kernel`
+            _ => {
+                let mut paths = vec![];
+                let is_tools = |p: &PathBuf| p.file_name().is_some_and(|n| n.eq_ignore_ascii_case(EFI_TOOLS_DIR));
+                for dir in children.into_iter().filter(|p| p.is_dir() && !is_tools(p)) {
+                    paths.extend(
+                        fs::read_dir(&dir)
+                            .context(IoSnafu)?
+                            .filter_map(|e| e.ok())
+                            .map(|e| e.path()),
+                    );
+                    paths.push(dir);
+                }
+                self.discover_system_kernels(paths.iter())
+            }
+        }
+    }
+
+    /// Kind of schema, i.e. `legacy`, `blsforme` or `os-info`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Schema::Legacy { .. } => "legacy",
+            Schema::Blsforme { .. } => "blsforme",
+            Schema::OsInfo { .. } => "os-info",
         }
     }
 
@@ -145,11 +670,12 @@ impl Schema {
     }
 
     /// Retrieve display name for the OS
-    /// This is the `PRETTY_NAME` field in os-release, used for display purposes
+    /// This is the `PRETTY_NAME` field in os-release (or `NAME` with `VERSION_CODENAME`),
+    /// used for display purposes
     pub fn os_display_name(&self) -> Option<String> {
         match self {
-            Schema::Legacy { os_release, .. } => os_release.meta.pretty_name.clone(),
-            Schema::Blsforme { os_release } => os_release.meta.pretty_name.clone(),
+            Schema::Legacy { os_release, .. } => os_release.display_name(),
+            Schema::Blsforme { os_release } => os_release.display_name(),
             Schema::OsInfo { os_info } => Some(os_info.metadata.identity.display.clone()),
         }
     }
@@ -184,6 +710,7 @@ impl Schema {
                                 initrd: vec![],
                                 extras: vec![],
                                 variant: Some(variant.to_string()),
+                                boot_meta: None,
                             },
                         );
                     }
@@ -214,29 +741,15 @@ impl Schema {
                     .ok_or(Error::InvalidFilesystem)?;
 
                 let aux = match filename {
-                    x if x == sysmap_file => Some(AuxiliaryFile {
-                        path: path.as_ref().into(),
-                        kind: AuxiliaryKind::SystemMap,
-                    }),
-                    x if x == cmdline_file => Some(AuxiliaryFile {
-                        path: path.as_ref().into(),
-                        kind: AuxiliaryKind::Cmdline,
-                    }),
-                    x if x == config_file => Some(AuxiliaryFile {
-                        path: path.as_ref().into(),
-                        kind: AuxiliaryKind::Config,
-                    }),
-                    x if x == initrd_file => Some(AuxiliaryFile {
-                        path: path.as_ref().into(),
-                        kind: AuxiliaryKind::InitRd,
-                    }),
+                    x if x == sysmap_file => Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::SystemMap)),
+                    x if x == cmdline_file => Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::Cmdline)),
+                    x if x == config_file => Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::Config)),
+                    x if x == initrd_file => Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::InitRd)),
+                    x if is_microcode_image(x) => Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::Microcode)),
                     x if x.starts_with(&initrd_file) => {
                         // Version dependent initrd
                         if x != initrd_file && x.split_once(&initrd_file).is_some() {
-                            Some(AuxiliaryFile {
-                                path: path.as_ref().into(),
-                                kind: AuxiliaryKind::InitRd,
-                            })
+                            Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::InitRd))
                         } else {
                             None
                         }
@@ -245,10 +758,7 @@ impl Schema {
                         // Version independent initrd
                         if let Some((_, r)) = x.split_once(&indep_initrd) {
                             if !r.contains('.') {
-                                Some(AuxiliaryFile {
-                                    path: path.as_ref().into(),
-                                    kind: AuxiliaryKind::InitRd,
-                                })
+                                Some(AuxiliaryFile::new(path.as_ref(), AuxiliaryKind::InitRd))
                             } else {
                                 None
                             }
@@ -260,7 +770,7 @@ impl Schema {
                 };
 
                 if let Some(aux_file) = aux {
-                    if matches!(aux_file.kind, AuxiliaryKind::InitRd) {
+                    if aux_file.kind.is_initrd() {
                         kernel.initrd.push(aux_file);
                     } else {
                         kernel.extras.push(aux_file);
@@ -268,9 +778,7 @@ impl Schema {
                 }
             }
 
-            kernel
-                .initrd
-                .sort_by_key(|i| i.path.display().to_string().to_lowercase());
+            kernel.initrd.sort_by_key(initrd_order);
             kernel
                 .extras
                 .sort_by_key(|e| e.path.display().to_string().to_lowercase());
@@ -296,10 +804,33 @@ impl Schema {
                         initrd: vec![],
                         extras: vec![],
                         variant: None,
+                        boot_meta: None,
                     },
                 ))
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<BTreeMap<_, _>>();
+
+        // Unified kernel images take precedence over a plain `vmlinuz` for the same version
+        for uki in all_paths
+            .iter()
+            .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("efi")))
+        {
+            let Some(version) = uki.parent().and_then(|p| p.file_name()?.to_str()) else {
+                continue;
+            };
+            log::trace!("discovered uki: {}", uki.display());
+            kernel_images
+                .entry(version.to_string())
+                .and_modify(|k| k.image = uki.clone())
+                .or_insert_with(|| Kernel {
+                    version: version.to_string(),
+                    image: uki.clone(),
+                    initrd: vec![],
+                    extras: vec![],
+                    variant: None,
+                    boot_meta: None,
+                });
+        }
 
         // Walk kernels, find matching assets
         for (version, kernel) in kernel_images.iter_mut() {
@@ -319,40 +850,49 @@ impl Schema {
                     .to_str()
                     .ok_or(Error::InvalidFilesystem)?;
                 let aux = match filename {
-                    "System.map" => Some(AuxiliaryFile {
-                        path: asset.clone(),
-                        kind: AuxiliaryKind::SystemMap,
-                    }),
-                    "boot.json" => Some(AuxiliaryFile {
-                        path: asset.clone(),
-                        kind: AuxiliaryKind::BootJson,
-                    }),
-                    "config" => Some(AuxiliaryFile {
-                        path: asset.clone(),
-                        kind: AuxiliaryKind::Config,
-                    }),
-                    _ if filename.ends_with(".initrd") => Some(AuxiliaryFile {
-                        path: asset.clone(),
-                        kind: AuxiliaryKind::InitRd,
-                    }),
-                    _ if filename.ends_with(".cmdline") => Some(AuxiliaryFile {
-                        path: asset.clone(),
-                        kind: AuxiliaryKind::Cmdline,
-                    }),
+                    "System.map" => Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::SystemMap)),
+                    "boot.json" => {
+                        kernel.boot_meta = BootJSON::load(asset);
+                        if let Some(meta) = &kernel.boot_meta {
+                            kernel.variant = Some(meta.variant.clone());
+                        }
+                        Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::BootJson))
+                    }
+                    "config" => Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::Config)),
+                    "efistub" => Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::EfiStub)),
+                    "excluded.cmdline.d" => Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::ExcludedCmdline)),
+                    "dtbs" if asset.is_dir() => {
+                        kernel.extras.extend(
+                            device_trees(asset)
+                                .into_iter()
+                                .map(|p| AuxiliaryFile::new(p, AuxiliaryKind::DeviceTree)),
+                        );
+                        None
+                    }
+                    _ if filename.ends_with(".dtb") => {
+                        Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::DeviceTree))
+                    }
+                    _ if filename.ends_with(".initrd") => {
+                        Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::InitRd))
+                    }
+                    _ if is_microcode_image(filename) => {
+                        Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::Microcode))
+                    }
+                    _ if filename.ends_with(".cmdline") => {
+                        Some(AuxiliaryFile::new(asset.clone(), AuxiliaryKind::Cmdline))
+                    }
                     _ => None,
                 };
 
                 if let Some(aux_file) = aux {
-                    if matches!(aux_file.kind, AuxiliaryKind::InitRd) {
+                    if aux_file.kind.is_initrd() {
                         kernel.initrd.push(aux_file);
                     } else {
                         kernel.extras.push(aux_file);
                     }
                 }
 
-                kernel
-                    .initrd
-                    .sort_by_key(|i| i.path.display().to_string().to_lowercase());
+                kernel.initrd.sort_by_key(initrd_order);
                 kernel
                     .extras
                     .sort_by_key(|e| e.path.display().to_string().to_lowercase());
@@ -365,9 +905,28 @@ impl Schema {
 
 #[cfg(test)]
 mod tests {
+    use std::{path::PathBuf, str::FromStr};
+
     use fs_err as fs;
 
-    use super::BootJSON;
+    use super::{AuxiliaryFile, AuxiliaryKind, BootJSON, Kernel, KernelVersion, Schema};
+    use crate::os_release::OsRelease;
+
+    #[test]
+    fn test_matches_release() {
+        let kernel = Kernel {
+            version: "6.12.5-1.desktop".into(),
+            image: PathBuf::from("vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: None,
+            boot_meta: None,
+        };
+        assert!(kernel.matches_release("6.12.5-1.desktop"));
+        assert!(kernel.matches_release("6.12.5-1.desktop+"));
+        assert!(!kernel.matches_release("6.12.5-1.lts"));
+        assert!(!kernel.matches_release("6.12.5-1"));
+    }
 
     #[test]
     fn test_boot_json() {
@@ -376,5 +935,270 @@ mod tests {
         assert_eq!(boot.name, "linux-desktop");
         assert_eq!(boot.variant, "desktop");
         assert_eq!(boot.version, "6.8.2-25.desktop");
+
+        let written = serde_json::to_string(&boot).unwrap();
+        assert_eq!(
+            written,
+            r#"{"name":"linux-desktop","version":"6.8.2-25.desktop","variant":"desktop"}"#
+        );
+        assert_eq!(BootJSON::try_from(written.as_str()).unwrap(), boot);
+
+        // Optional extras, ignoring fields we don't know about
+        let extended = BootJSON::try_from(
+            r#"{"name":"linux-lts","version":"6.6.30-12.lts","variant":"lts","cmdline":["nomodeset"],
+                "depends":["linux-firmware"],"uki":false,"future":{"key":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(extended.cmdline, Some(vec!["nomodeset".to_string()]));
+        assert_eq!(extended.depends, Some(vec!["linux-firmware".to_string()]));
+        assert_eq!(extended.uki, Some(false));
+
+        // Discovery attaches the metadata, and survives a malformed file
+        let tree = tempfile::tempdir().unwrap();
+        let schema = Schema::Blsforme {
+            os_release: Box::new(OsRelease::from_str("ID=aerynos\nNAME=AerynOS").unwrap()),
+        };
+        let mut paths = vec![];
+        for (version, json) in [("6.8.2-25.desktop", text.as_str()), ("6.9.1-30.desktop", "{")] {
+            let dir = tree.path().join(version);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("vmlinuz"), version).unwrap();
+            fs::write(dir.join("boot.json"), json).unwrap();
+            paths.extend([dir.clone(), dir.join("vmlinuz"), dir.join("boot.json")]);
+        }
+        let kernels = schema.discover_system_kernels(paths.iter()).unwrap();
+        let kernel = |version: &str| kernels.iter().find(|k| k.version == version).unwrap();
+        assert_eq!(kernel("6.8.2-25.desktop").boot_meta.as_ref(), Some(&boot));
+        assert_eq!(kernel("6.8.2-25.desktop").variant.as_deref(), Some("desktop"));
+        assert_eq!(kernel("6.9.1-30.desktop").boot_meta, None);
+        assert_eq!(kernel("6.9.1-30.desktop").variant, None);
+    }
+
+    #[test]
+    fn test_kernel_version() {
+        let version = KernelVersion::from("6.8.2-25.desktop");
+        assert_eq!((version.major, version.minor, version.patch), (6, 8, 2));
+        assert_eq!(version.local, "-25.desktop");
+        assert_eq!(KernelVersion::from("6.10").to_string(), "6.10.0");
+
+        let mut versions = [
+            "6.9.1-30.desktop",
+            "6.10.2-31.desktop",
+            "6.10.2-100.desktop",
+            "6.10-rc1",
+            "6.10.0-rc2-1.mainline",
+            "6.10.0-1.desktop",
+        ]
+        .into_iter()
+        .map(KernelVersion::from)
+        .collect::<Vec<_>>();
+        versions.sort_by(|a, b| b.cmp(a));
+        assert_eq!(
+            versions.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            [
+                "6.10.2-100.desktop",
+                "6.10.2-31.desktop",
+                "6.10.0-1.desktop",
+                "6.10.0-rc2-1.mainline",
+                "6.10.0-rc1",
+                "6.9.1-30.desktop"
+            ]
+        );
+
+        assert_eq!(KernelVersion::parse("custom"), None);
+        let kernel = |version: &str| Kernel {
+            version: version.into(),
+            image: PathBuf::from("vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: None,
+            boot_meta: None,
+        };
+        let mut kernels = [kernel("6.9.2"), kernel("custom"), kernel("6.10.1"), kernel("alpha")];
+        kernels.sort();
+        assert_eq!(
+            kernels.iter().map(|k| k.version.as_str()).collect::<Vec<_>>(),
+            ["alpha", "custom", "6.9.2", "6.10.1"]
+        );
+    }
+
+    #[test]
+    fn test_kernel_json() {
+        let kernel = Kernel {
+            version: "6.8.2-25.desktop".to_string(),
+            image: PathBuf::from("/usr/lib/kernel/6.8.2-25.desktop/vmlinuz"),
+            initrd: vec![AuxiliaryFile::new(
+                "/usr/lib/kernel/6.8.2-25.desktop/10-default.initrd",
+                AuxiliaryKind::InitRd,
+            )],
+            extras: vec![AuxiliaryFile::new(
+                "/usr/lib/kernel/6.8.2-25.desktop/boot.json",
+                AuxiliaryKind::BootJson,
+            )],
+            variant: Some("desktop".to_string()),
+            boot_meta: None,
+        };
+        let json = serde_json::to_value(&kernel).unwrap();
+        assert_eq!(json["image"], "/usr/lib/kernel/6.8.2-25.desktop/vmlinuz");
+        assert_eq!(json["initrd"][0]["kind"], "initrd");
+        #[cfg(feature = "serde")]
+        assert_eq!(serde_json::from_value::<Kernel>(json).unwrap(), kernel);
+    }
+
+    #[test]
+    fn test_installed_kernels() {
+        let boot = tempfile::tempdir().expect("Failed to create tempdir");
+        for (dir, files) in [
+            ("efi/aerynos/6.8.2-25.desktop", &["vmlinuz", "10-default.initrd"][..]),
+            ("efi/aerynos/6.9.1-30.lts", &["vmlinuz"][..]),
+            ("efi/aerynos/6.9.2-31.lts", &["10-default.initrd"][..]),
+        ] {
+            let dir = boot.path().join(dir);
+            fs::create_dir_all(&dir).unwrap();
+            for file in files {
+                fs::write(dir.join(file), file).unwrap();
+            }
+        }
+
+        let schema = Schema::Blsforme {
+            os_release: Box::new(OsRelease::from_str("ID=aerynos\nNAME=AerynOS").unwrap()),
+        };
+        let kernels = schema.discover_installed_kernels(boot.path()).unwrap();
+        assert_eq!(
+            kernels.iter().map(|k| k.version.as_str()).collect::<Vec<_>>(),
+            ["6.9.1-30.lts", "6.8.2-25.desktop"]
+        );
+        assert_eq!(
+            kernels[1].initrd[0].path,
+            boot.path().join("efi/aerynos/6.8.2-25.desktop/10-default.initrd")
+        );
+    }
+
+    #[test]
+    fn test_microcode_first() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let dir = root.path().join("6.8.2-25.desktop");
+        fs::create_dir_all(&dir).unwrap();
+        let names = ["vmlinuz", "amd-ucode.initrd", "dracut.initrd", "intel-ucode.initrd"];
+        for name in names {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let schema = Schema::Blsforme {
+            os_release: Box::new(OsRelease::from_str("ID=aerynos\nNAME=AerynOS").unwrap()),
+        };
+        let paths = names.iter().map(|n| dir.join(n));
+        let kernel = schema.discover_system_kernels(paths).unwrap().remove(0);
+        let initrds = |kernel: &super::Kernel| {
+            kernel
+                .initrd
+                .iter()
+                .map(|i| i.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            initrds(&kernel),
+            ["amd-ucode.initrd", "intel-ucode.initrd", "dracut.initrd"]
+        );
+
+        let kernel = kernel.with_microcode_patterns(&["intel-*"]);
+        assert_eq!(
+            initrds(&kernel),
+            ["intel-ucode.initrd", "amd-ucode.initrd", "dracut.initrd"]
+        );
+
+        // Microcode images are microcode whatever the patterns, in either schema
+        let names = [
+            "com.solus-project.current.6.8.2-25",
+            "initrd-com.solus-project.current.6.8.2-25",
+            "intel-ucode.img",
+        ];
+        for name in names {
+            fs::write(root.path().join(name), name).unwrap();
+        }
+        let legacy = Schema::Legacy {
+            os_release: Box::new(OsRelease::from_str("ID=solus\nNAME=Solus").unwrap()),
+            namespace: "com.solus-project",
+        };
+        let paths = names.iter().map(|n| root.path().join(n));
+        let kernel = legacy
+            .discover_system_kernels(paths)
+            .unwrap()
+            .remove(0)
+            .with_microcode_patterns(&[]);
+        assert_eq!(
+            initrds(&kernel),
+            ["intel-ucode.img", "initrd-com.solus-project.current.6.8.2-25"]
+        );
+        assert_eq!(kernel.initrd[0].kind, AuxiliaryKind::Microcode);
+    }
+
+    #[test]
+    fn test_from_os_release() {
+        let schema = |text: &str| Schema::from_os_release(OsRelease::from_str(text).unwrap());
+
+        assert!(matches!(
+            schema("NAME=Solus\nID=solus\nVERSION=4.5"),
+            Schema::Legacy {
+                namespace: "com.solus-project",
+                ..
+            }
+        ));
+        assert!(matches!(
+            schema("NAME=Solus\nID=solus\nVERSION=2025.01"),
+            Schema::Blsforme { .. }
+        ));
+        assert!(matches!(
+            schema("NAME=Clear\nID=clear-linux-os"),
+            Schema::Legacy {
+                namespace: "org.clearlinux",
+                ..
+            }
+        ));
+        assert!(matches!(
+            schema("NAME=Derived\nID=derived\nID_LIKE=clear-linux-os"),
+            Schema::Legacy {
+                namespace: "org.clearlinux",
+                ..
+            }
+        ));
+        assert!(matches!(schema("NAME=AerynOS\nID=aerynos"), Schema::Blsforme { .. }));
+    }
+
+    #[test]
+    fn test_schema_eq() {
+        let release = |text: &str| Box::new(OsRelease::from_str(text).unwrap());
+        let blsforme = |text: &str| Schema::Blsforme {
+            os_release: release(text),
+        };
+
+        // Only the identity matters
+        assert_eq!(
+            blsforme("ID=aerynos\nNAME=AerynOS"),
+            blsforme("ID=aerynos\nNAME=AerynOS\nVERSION_ID=2025.01")
+        );
+        assert_ne!(blsforme("ID=aerynos\nNAME=AerynOS"), blsforme("ID=solus\nNAME=Solus"));
+
+        let legacy = |namespace| Schema::Legacy {
+            os_release: release("ID=solus\nNAME=Solus"),
+            namespace,
+        };
+        assert_eq!(legacy("com.solus-project"), legacy("com.solus-project"));
+        assert_ne!(legacy("com.solus-project"), legacy("org.clearlinux"));
+        assert_ne!(legacy("com.solus-project"), blsforme("ID=solus\nNAME=Solus"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_schema_serde() {
+        let schema = Schema::from_os_release(OsRelease::from_str("NAME=Solus\nID=solus\nVERSION=4.5").unwrap());
+        let json = serde_json::to_string(&schema).unwrap();
+        let restored = serde_json::from_str::<Schema>(&json).unwrap();
+        assert_eq!(restored, schema);
+        assert_eq!(restored.kind(), "legacy");
+        assert_eq!(restored.os_namespace(), "com.solus-project");
+
+        let unknown = json.replace("com.solus-project", "org.example");
+        assert!(serde_json::from_str::<Schema>(&unknown).is_err());
     }
 }
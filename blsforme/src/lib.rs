@@ -2,22 +2,54 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bootloader::systemd_boot;
 use gpt::GptError;
-use snafu::Snafu;
+use snafu::{Snafu, ensure};
 
 mod kernel;
-pub use kernel::{AuxiliaryFile, AuxiliaryKind, BootJSON, Kernel, Schema};
+pub use kernel::{AuxiliaryFile, AuxiliaryKind, BootJSON, Kernel, KernelVersion, MICROCODE_INITRDS, Schema};
 
 mod bootenv;
-pub use bootenv::{BootEnvironment, Firmware};
+pub use bootenv::{BootDevice, BootEnvironment, Firmware, VarAccess};
 pub mod bootloader;
 pub mod os_release;
 
+mod architecture;
+pub use architecture::{Architecture, UnknownArchitecture};
+
+mod cmdline;
+pub use cmdline::Cmdline;
+
+mod boot_state;
+pub use boot_state::{BootRecord, BootState, SyncState};
+
 mod manager;
-pub use manager::Manager;
+pub use manager::{LockGuard, Manager, MountIntent, RemoveOptions, ScopedMount};
+
+mod audit;
+pub use audit::{AuditReport, Orphan};
+
+mod esp_survey;
+pub use esp_survey::{EspSurvey, EspSurveyor, VendorDir, VendorKind, VendorRule};
+
+mod report;
+pub use report::{
+    AssetReport, EntryReport, FallbackReport, OsReport, PartitionReport, StatusReport, VersionInfo, version_info,
+};
+
+mod hash_cache;
+
+mod progress;
+pub use progress::{ProgressCallback, ProgressEvent};
+
+mod retention;
+pub use retention::RetentionPolicy;
 
 /// Re-export the topology APIs
 pub use topology::disk;
@@ -26,7 +58,7 @@ pub mod file_utils;
 
 mod entry;
 
-pub use entry::{CmdlineEntry, Entry};
+pub use entry::{CmdlineEntry, EfiAppEntry, Entry};
 
 /// Core error type for blsforme
 #[derive(Debug, Snafu)]
@@ -52,6 +84,9 @@ pub enum Error {
     #[snafu(display("generic i/o error"))]
     Io { source: std::io::Error },
 
+    #[snafu(display("json: {source}"))]
+    Json { source: serde_json::Error },
+
     #[snafu(display("GPT error"))]
     Gpt { source: GptError },
 
@@ -63,9 +98,57 @@ pub enum Error {
 
     #[snafu(display("unsupported usage"))]
     Unsupported,
+
+    #[snafu(display("kernel {version} is not installed"))]
+    UnknownKernel { version: String },
+
+    #[snafu(display("ambiguous kernel {version}, could be any of: {}", candidates.join(", ")))]
+    AmbiguousKernel { version: String, candidates: Vec<String> },
+
+    #[snafu(display("refusing to remove the running kernel {version}"))]
+    RunningKernel { version: String },
+
+    #[snafu(display("refusing to remove the last installed kernel {version}"))]
+    LastKernel { version: String },
+
+    #[snafu(display("refusing to remove the last known-good kernel {version}"))]
+    KnownGoodKernel { version: String },
+
+    #[snafu(display("invalid loader entry {path:?}: missing {field}"))]
+    InvalidLoaderEntry { path: PathBuf, field: &'static str },
+
+    #[snafu(display("refusing to remove {path:?}, which is not orphaned"))]
+    NotOrphaned { path: PathBuf },
+
+    #[snafu(display("no such path: {path:?}"))]
+    InvalidPath { path: PathBuf },
+
+    #[snafu(display("{partition} is mounted read-only at {path:?}, remount it read-write to make changes"))]
+    BootReadOnly { partition: &'static str, path: PathBuf },
+
+    #[snafu(display("failed to lock {path:?}: {source}"))]
+    Lock { path: PathBuf, source: nix::errno::Errno },
+
+    #[snafu(display("timed out after {}s waiting for another instance to release {path:?}", timeout.as_secs()))]
+    LockTimeout { path: PathBuf, timeout: Duration },
+
+    #[snafu(display("missing kernel files: {missing:?}"))]
+    MissingKernelFiles { missing: Vec<PathBuf> },
+
+    /// Staging or renaming a file into place failed. `from` is the copied file, or the
+    /// staging file when writing contents
+    #[snafu(display("failed to write {} from {}: {source}", to.display(), from.display()))]
+    VfatWrite {
+        from: PathBuf,
+        to: PathBuf,
+        source: std::io::Error,
+    },
 }
 
 /// Core configuration for boot management
+///
+/// Prefer [`Configuration::builder`] over constructing this directly, so that
+/// new fields don't break existing code.
 #[derive(Debug)]
 pub struct Configuration {
     /// Root of all operations
@@ -73,8 +156,53 @@ pub struct Configuration {
 
     /// Where we can find `sysfs` `proc` etc
     pub vfs: PathBuf,
+
+    /// Report changes without modifying anything
+    pub dry_run: bool,
+
+    /// Override the EFI architecture, i.e. for cross-architecture image generation
+    pub arch: Option<Architecture>,
+
+    /// Where kernels and loader entries are installed
+    pub layout: BootLayout,
+
+    /// How long to wait for another instance to finish modifying `$BOOT`
+    pub lock_timeout: Duration,
+
+    /// Directory used as the ESP, bypassing partition discovery
+    pub esp_path: Option<PathBuf>,
+}
+
+/// Selects `$BOOT`, where kernels and loader entries are installed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BootLayout {
+    /// XBOOTLDR when present, otherwise the ESP
+    #[default]
+    Auto,
+
+    /// Always the ESP, even when an XBOOTLDR partition is present
+    EspOnly,
+
+    /// Always XBOOTLDR, failing if there isn't one
+    Xbootldr,
+
+    /// A directory relative to the root (i.e. `boot`), bypassing partition discovery.
+    /// systemd-boot can't read this, so it suits GRUB and image generation.
+    /// The root device isn't probed either, so `root=` must come from `cmdline.d`
+    BootDirectory(PathBuf),
+}
+
+impl BootLayout {
+    /// Whether a discovered XBOOTLDR partition is used as `$BOOT`
+    pub fn uses_xbootldr(&self) -> bool {
+        matches!(self, Self::Auto | Self::Xbootldr)
+    }
 }
 
+/// Environment variables overriding the root, in order of precedence. `CBM_ROOT`
+/// is honoured for compatibility with `clr-boot-manager`
+pub const ROOT_ENV_VARS: [&str; 2] = ["BLSFORME_ROOT", "CBM_ROOT"];
+
 /// Wrap a root into a strong type to avoid confusion
 #[derive(Debug)]
 pub enum Root {
@@ -85,7 +213,133 @@ pub enum Root {
     Image(PathBuf),
 }
 
+impl Configuration {
+    /// Build a new configuration, by default for the native root `/`
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder::default()
+    }
+
+    /// Build a configuration for the root named by [`ROOT_ENV_VARS`], otherwise `/`
+    pub fn from_env() -> Result<Configuration, Error> {
+        Self::builder().root(Root::from_env()).build()
+    }
+
+    /// Whether the boot directories were given explicitly, rather than discovered
+    pub fn has_boot_paths(&self) -> bool {
+        self.esp_path.is_some() || matches!(self.layout, BootLayout::BootDirectory(_))
+    }
+}
+
+/// Builder pattern for a [`Configuration`]
+#[derive(Debug)]
+pub struct ConfigurationBuilder {
+    root: Root,
+    vfs: PathBuf,
+    dry_run: bool,
+    arch: Option<Architecture>,
+    layout: BootLayout,
+    lock_timeout: Duration,
+    esp_path: Option<PathBuf>,
+}
+
+impl Default for ConfigurationBuilder {
+    fn default() -> Self {
+        Self {
+            root: Root::Native("/".into()),
+            vfs: "/".into(),
+            dry_run: false,
+            arch: None,
+            layout: BootLayout::Auto,
+            lock_timeout: Duration::from_secs(30),
+            esp_path: None,
+        }
+    }
+}
+
+impl ConfigurationBuilder {
+    /// Root of all operations
+    pub fn root(self, root: Root) -> Self {
+        Self { root, ..self }
+    }
+
+    /// Where we can find `sysfs`, `proc` etc (default: `/`)
+    pub fn vfs(self, vfs: impl Into<PathBuf>) -> Self {
+        Self {
+            vfs: vfs.into(),
+            ..self
+        }
+    }
+
+    /// Report changes without modifying anything (default: false)
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    /// Override the EFI architecture
+    pub fn arch(self, arch: Option<Architecture>) -> Self {
+        Self { arch, ..self }
+    }
+
+    /// Where kernels and loader entries are installed (default: [`BootLayout::Auto`])
+    pub fn layout(self, layout: BootLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// How long to wait for another instance to finish modifying `$BOOT` (default: 30 seconds)
+    pub fn lock_timeout(self, lock_timeout: Duration) -> Self {
+        Self { lock_timeout, ..self }
+    }
+
+    /// Use a directory as the ESP, i.e. staged image contents rather than a mounted partition
+    ///
+    /// Setting this, like [`BootLayout::BootDirectory`], skips probing the root device,
+    /// partition discovery, mounting and all EFI variable access.
+    pub fn esp_path(self, esp_path: Option<PathBuf>) -> Self {
+        Self { esp_path, ..self }
+    }
+
+    /// Return the configuration
+    /// Note: The root, vfs and any ESP path must exist
+    pub fn build(self) -> Result<Configuration, Error> {
+        for path in [self.root.path(), &self.vfs].into_iter().chain(self.esp_path.iter()) {
+            ensure!(path.exists(), InvalidPathSnafu { path: path.clone() });
+        }
+        Ok(Configuration {
+            root: self.root,
+            vfs: self.vfs,
+            dry_run: self.dry_run,
+            arch: self.arch,
+            layout: self.layout,
+            lock_timeout: self.lock_timeout,
+            esp_path: self.esp_path,
+        })
+    }
+}
+
 impl Root {
+    /// Native when the path is `/`, otherwise an image
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        if path == Path::new("/") {
+            Root::Native(path)
+        } else {
+            Root::Image(path)
+        }
+    }
+
+    /// The root named by the first non-empty [`ROOT_ENV_VARS`], otherwise `/`
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::var_os)
+    }
+
+    fn from_vars(var: impl Fn(&'static str) -> Option<OsString>) -> Self {
+        ROOT_ENV_VARS
+            .into_iter()
+            .filter_map(var)
+            .find(|v| !v.is_empty())
+            .map_or_else(|| Root::Native("/".into()), Root::new)
+    }
+
     /// When we don't need the type of the root..
     pub fn path(&self) -> &PathBuf {
         match self {
@@ -93,4 +347,58 @@ impl Root {
             Root::Image(p) => p,
         }
     }
+
+    /// Whether we're generating an image
+    pub fn is_image(&self) -> bool {
+        matches!(self, Root::Image(_))
+    }
+
+    /// Whether we're managing the running system
+    pub fn is_native(&self) -> bool {
+        matches!(self, Root::Native(_))
+    }
+
+    /// The image path, or the root itself if native
+    pub fn try_into_image(self) -> Result<PathBuf, Self> {
+        match self {
+            Root::Image(p) => Ok(p),
+            root => Err(root),
+        }
+    }
+
+    /// The native root path, or the root itself if an image
+    pub fn try_into_native(self) -> Result<PathBuf, Self> {
+        match self {
+            Root::Native(p) => Ok(p),
+            root => Err(root),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Root;
+
+    #[test]
+    fn test_root_from_env() {
+        let root = |vars: &[(&str, &str)]| {
+            let vars = vars.to_vec();
+            Root::from_vars(move |key| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.into()))
+        };
+
+        assert!(matches!(root(&[]), Root::Native(p) if p == Path::new("/")));
+        assert!(matches!(root(&[("CBM_ROOT", "/")]), Root::Native(_)));
+        assert!(matches!(root(&[("CBM_ROOT", "//")]), Root::Native(_)));
+        assert!(matches!(root(&[("CBM_ROOT", "/mnt")]), Root::Image(p) if p == Path::new("/mnt")));
+        assert!(matches!(
+            root(&[("BLSFORME_ROOT", "/target"), ("CBM_ROOT", "/mnt")]),
+            Root::Image(p) if p == Path::new("/target")
+        ));
+        assert!(matches!(
+            root(&[("BLSFORME_ROOT", ""), ("CBM_ROOT", "/mnt")]),
+            Root::Image(p) if p == Path::new("/mnt")
+        ));
+    }
 }
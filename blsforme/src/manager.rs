@@ -4,24 +4,169 @@
 
 //! Boot loader management entry APIs
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
 use fs_err as fs;
-use nix::mount::{MsFlags, mount, umount};
-use snafu::{ResultExt as _, ensure};
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+    mount::{MsFlags, mount, umount},
+    sys::{
+        statvfs::{FsFlags, statvfs},
+        utsname::uname,
+    },
+};
+use snafu::{OptionExt as _, ResultExt as _, ensure};
 use topology::disk;
 
 use crate::{
-    BootEnvironment, Configuration, Entry, Error, IoSnafu, Kernel, NixSnafu, Root, Schema, UnmountedEspSnafu,
-    bootloader::Bootloader, file_utils::cmdline_snippet,
+    AmbiguousKernelSnafu, AuditReport, BootEnvironment, BootLayout, BootReadOnlySnafu, BootState, Configuration,
+    EfiAppEntry, Entry, Error, Firmware, IoSnafu, Kernel, KnownGoodKernelSnafu, LastKernelSnafu, LockTimeoutSnafu,
+    NixSnafu, NoXbootldrSnafu, NotOrphanedSnafu, Orphan, ProgressCallback, ProgressEvent, RetentionPolicy,
+    RunningKernelSnafu, Schema, SyncState, UnknownKernelSnafu, UnmountedEspSnafu, VarAccess, VendorRule,
+    bootloader::{
+        self, Bootloader, LoaderConf, LoaderEntry, SyncOptions, Timeout,
+        systemd_boot::interface::{BootLoaderInterface, VariableName},
+    },
+    file_utils::{CaseCollision, Change, ChangeDetection, SyncPlan, WriteMode, Writer, case_collisions, format_size},
+    report::{AssetReport, EntryReport, FallbackReport, OsReport, PartitionReport, StatusReport},
+    retention::newest_kernels,
 };
 
+/// Warn when a boot partition has less than this percentage of free space
+const LOW_SPACE_PERCENT: u64 = 20;
+
 #[derive(Debug)]
 pub(crate) struct Mounts {
     pub(crate) xbootldr: Option<PathBuf>,
     pub(crate) esp: Option<PathBuf>,
 }
 
+impl Mounts {
+    /// Select the mountpoints for the boot environment, as chosen by the configured [`BootLayout`]
+    pub(crate) fn new(boot_env: &BootEnvironment, config: &Configuration) -> Result<Self, Error> {
+        let mut mounts = Mounts {
+            xbootldr: boot_env.xboot_mountpoint.clone().or_else(|| {
+                // Legacy systems without a dedicated partition simply use `/boot`
+                if boot_env.firmware == Firmware::Bios && boot_env.boot_partition().is_none() {
+                    return Some(config.root.path().join("boot"));
+                }
+                _ = boot_env.xbootldr()?;
+                Some(config.root.path().join("boot"))
+            }),
+            esp: boot_env.esp_mountpoint.clone().or_else(|| {
+                _ = boot_env.esp()?;
+                // Prefer `/efi`, unless the system only provides `/boot/efi`
+                let efi = config.root.path().join("efi");
+                let boot_efi = config.root.path().join("boot").join("efi");
+                if !efi.exists() && boot_efi.exists() {
+                    Some(boot_efi)
+                } else {
+                    Some(efi)
+                }
+            }),
+        };
+
+        log::trace!("selected mountpoints: {mounts:?}");
+
+        // So, we got a `/boot` mount for ESP, legacy style. We can't stick xbootldr there...
+        if let Some(xbootldr) = mounts.xbootldr.as_ref() {
+            if let Some(esp) = mounts.esp.as_ref() {
+                if esp == xbootldr && boot_env.xbootldr().is_none() {
+                    mounts.xbootldr = Some(config.root.path().join("xboot"))
+                }
+            }
+        }
+
+        match &config.layout {
+            BootLayout::Auto => {}
+            BootLayout::EspOnly => mounts.xbootldr = None,
+            BootLayout::Xbootldr => ensure!(
+                boot_env.xbootldr().is_some() || boot_env.xboot_mountpoint.is_some(),
+                NoXbootldrSnafu
+            ),
+            BootLayout::BootDirectory(dir) => {
+                let dir = dir.strip_prefix("/").unwrap_or(dir);
+                mounts.xbootldr = Some(config.root.path().join(dir));
+            }
+        }
+        log::trace!("mountpoints for {:?} layout: {mounts:?}", config.layout);
+
+        Ok(mounts)
+    }
+
+    /// `$BOOT`: XBOOTLDR when available, otherwise the ESP
+    pub(crate) fn boot_root(&self) -> Option<&PathBuf> {
+        self.xbootldr.as_ref().or(self.esp.as_ref())
+    }
+}
+
+/// How often to retry a contended lock
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How partitions should be mounted by [`Manager::mount_partitions`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MountIntent {
+    /// Inspection only, partitions we mount are mounted `ro`
+    #[default]
+    ReadOnly,
+
+    /// We'll be writing to `$BOOT`, partitions we mount are mounted `rw`
+    ReadWrite,
+}
+
+impl MountIntent {
+    /// Flags for mounting a partition with this intent
+    fn mount_flags(self) -> MsFlags {
+        match self {
+            MountIntent::ReadOnly => MsFlags::MS_MGC_VAL | MsFlags::MS_RDONLY,
+            MountIntent::ReadWrite => MsFlags::MS_MGC_VAL,
+        }
+    }
+}
+
+/// Options controlling kernel removal
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RemoveOptions {
+    /// Also remove the kernel from the rootfs (`/usr/lib/kernel/$version`)
+    pub purge: bool,
+
+    /// Permit removing the currently running or last known-good kernel
+    pub force: bool,
+}
+
+/// Find the installed kernel version matching the query
+///
+/// An exact version wins, otherwise the query may be a version prefix
+/// or a variant name, so long as it matches exactly one kernel.
+fn match_kernel<'k>(installed: &'k [Kernel], query: &str) -> Result<&'k str, Error> {
+    if let Some(kernel) = installed.iter().find(|k| k.version == query) {
+        return Ok(&kernel.version);
+    }
+
+    let candidates = installed
+        .iter()
+        .filter(|k| {
+            k.version.starts_with(query)
+                || k.variant.as_deref() == Some(query)
+                || k.version.ends_with(&format!(".{query}"))
+        })
+        .collect::<Vec<_>>();
+    match candidates.as_slice() {
+        [] => UnknownKernelSnafu { version: query }.fail(),
+        [kernel] => Ok(&kernel.version),
+        _ => AmbiguousKernelSnafu {
+            version: query,
+            candidates: candidates.iter().map(|k| k.version.clone()).collect::<Vec<_>>(),
+        }
+        .fail(),
+    }
+}
+
 /// Encapsulate the entirety of the boot management core APIs
 #[derive(Debug)]
 pub struct Manager<'a> {
@@ -40,78 +185,61 @@ pub struct Manager<'a> {
 
     cmdline: Vec<String>,
 
-    system_excluded_snippets: Vec<String>,
+    /// Whether we're permitted to update EFI variables
+    efi_updates: bool,
+
+    /// Optional limit on the kernels kept on `$BOOT`
+    retention: Option<RetentionPolicy>,
+
+    /// Maximum number of kernels to keep on `$BOOT`, across all variants
+    max_kernels: Option<usize>,
+
+    /// How installed files are compared against their sources
+    change_detection: ChangeDetection,
+
+    /// How the bootloader itself is installed
+    bootloader_options: SyncOptions,
+
+    /// Machine ID of the sysroot, when naming entries by it
+    machine_id: Option<String>,
+
+    /// Remove stale entries before installing, when otherwise short on space
+    cleanup_first: bool,
+
+    /// EFI applications to install alongside the kernels, if configured
+    efi_apps: Option<Vec<EfiAppEntry>>,
+
+    /// All modifications go through here, honouring dry-run mode
+    writer: Writer,
 }
 
 impl<'a> Manager<'a> {
     /// Construct a new blsforme::Manager with the given configuration
     pub fn new(config: &'a Configuration) -> Result<Self, Error> {
-        // Probe the rootfs device managements
-        let probe = disk::Builder::default().build()?;
-        let root = probe.get_rootfs_device(config.root.path())?;
-        log::info!("root = {:?}", root.cmd_line());
-
-        // Right now we assume `rw` for the rootfs
-        let cmdline = [root.cmd_line(), "rw".to_string()];
-        let mut local_cmdline = vec![];
-
-        let etc_cmdline_d = config.root.path().join("etc").join("kernel").join("cmdline.d");
-        let etc_entries = fs::read_dir(&etc_cmdline_d)
-            .map(|i| {
-                i.filter_map(|p| p.ok())
-                    .filter(|d| d.path().extension().is_some_and(|e| e == "cmdline"))
-                    .map(|d| d.path().clone())
-            })
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
-        let mut system_excludes = vec![];
-
-        for entry in etc_entries {
-            // For anything that's a symlink to /dev/null, we'll exclude the matching system-wide cmdline
-            if entry.is_symlink() {
-                if let Ok(target) = entry.read_link() {
-                    if target == PathBuf::from("/dev/null") {
-                        log::trace!("excluding system-wide cmdline.d entry {entry:?}");
-                        system_excludes.push(entry.file_name().unwrap_or_default().to_string_lossy().to_string());
-                        continue;
-                    }
-                }
-            }
-            // Ensure /etc cmdline.d entries are added to the end of the generated cmdline
-            if let Ok(c) = cmdline_snippet(entry) {
-                local_cmdline.push(c);
-            }
-        }
+        let (mut cmdline, boot_env) = if config.has_boot_paths() {
+            // Nothing to probe, i.e. building an image without its devices
+            log::info!("Using explicit boot directories, root= must come from cmdline.d");
+            (
+                vec![],
+                BootEnvironment::from_boot_paths(Firmware::detect(config), config),
+            )
+        } else {
+            // Probe the rootfs device managements
+            let probe = disk::Builder::default().build()?;
+            let root = probe.get_rootfs_device(config.root.path())?;
+            log::info!("root = {:?}", root.cmd_line());
 
-        // Grab parent disk, establish disk environment setup
-        let disk_parent = probe.get_device_parent(root.path);
-        let boot_env = BootEnvironment::new(&probe, disk_parent, config)?;
-        log::trace!("boot env: {boot_env:?}");
-
-        let mut mounts = Mounts {
-            xbootldr: boot_env.xboot_mountpoint.clone().or_else(|| {
-                _ = boot_env.xbootldr()?;
-                Some(config.root.path().join("boot"))
-            }),
-            esp: boot_env.esp_mountpoint.clone().or_else(|| {
-                _ = boot_env.esp()?;
-                Some(config.root.path().join("efi"))
-            }),
+            // Grab parent disk, establish disk environment setup
+            let cmdline = root.cmdline_for_root();
+            let disk_parent = probe.get_device_parent(root.path);
+            (cmdline, BootEnvironment::new(&probe, disk_parent, config)?)
         };
+        log::trace!("boot env: {boot_env:?}");
 
-        log::trace!("selected mountpoints: {mounts:?}");
-
-        // So, we got a `/boot` mount for ESP, legacy style. We can't stick xbootldr there...
-        if let Some(xbootldr) = mounts.xbootldr.as_ref() {
-            if let Some(esp) = mounts.esp.as_ref() {
-                if esp == xbootldr && boot_env.xbootldr().is_none() {
-                    mounts.xbootldr = Some(config.root.path().join("xboot"))
-                }
-            }
-        }
+        // Right now we assume `rw` for the rootfs
+        cmdline.push("rw".to_string());
 
-        let cmdline_joined = cmdline.into_iter().chain(local_cmdline).collect::<Vec<_>>();
+        let mounts = Mounts::new(&boot_env, config)?;
 
         Ok(Self {
             config,
@@ -119,11 +247,110 @@ impl<'a> Manager<'a> {
             bootloader_assets: vec![],
             boot_env,
             mounts,
-            cmdline: cmdline_joined,
-            system_excluded_snippets: system_excludes,
+            cmdline,
+            efi_updates: true,
+            retention: None,
+            max_kernels: None,
+            change_detection: ChangeDetection::default(),
+            bootloader_options: SyncOptions::default(),
+            machine_id: None,
+            cleanup_first: false,
+            efi_apps: None,
+            writer: Writer::new(if config.dry_run {
+                WriteMode::Plan
+            } else {
+                WriteMode::Commit
+            })
+            .with_reproducible(config.root.is_image()),
         })
     }
 
+    /// Report on the boot environment, kernels and bootloader configuration
+    ///
+    /// Anything that can't be determined is logged and left empty, so this works
+    /// in image mode or with `$BOOT` unmounted.
+    pub fn status(&self, schema: &Schema) -> StatusReport {
+        let bootloader = self
+            .bootloader(schema)
+            .inspect_err(|e| log::warn!("No bootloader: {e}"))
+            .ok();
+        let installed_kernels = bootloader
+            .as_ref()
+            .and_then(|b| {
+                b.installed_kernels()
+                    .inspect_err(|e| log::warn!("No installed kernels: {e}"))
+                    .ok()
+            })
+            .unwrap_or_default();
+        let survey = bootloader
+            .as_ref()
+            .and_then(|b| b.esp_survey(&self.bootloader_options))
+            .unwrap_or_default();
+        let windows_hidden = survey.windows_boot_manager.is_some()
+            && self
+                .loader_conf(schema)
+                .inspect_err(|e| log::warn!("No loader.conf: {e}"))
+                .is_ok_and(|conf| conf.hides_windows());
+        let default_entry = self
+            .efi_interface()
+            .and_then(|bli| bli.get_ucs2_string(VariableName::EntryDefault).ok())
+            .or_else(|| bootloader.as_ref()?.default_entry().ok()?);
+
+        StatusReport {
+            firmware: self.boot_env.firmware,
+            efi_vars: self.boot_env.efi_vars,
+            secure_boot: self.boot_env.secure_boot_enabled(),
+            esp: PartitionReport::new(
+                self.boot_env.esp(),
+                self.boot_env.esp_model.as_ref(),
+                self.mounts.esp.as_ref(),
+                self.boot_env.esp_size,
+            ),
+            xbootldr: PartitionReport::new(
+                self.boot_env.xbootldr(),
+                self.boot_env.xbootldr_model.as_ref(),
+                self.mounts.xbootldr.as_ref(),
+                self.boot_env.xbootldr_size,
+            ),
+            os: OsReport::from(schema),
+            cmdline: self.cmdline.clone(),
+            available_kernels: self.entries.iter().map(|e| e.kernel().clone()).collect(),
+            installed_kernels,
+            installed_entries: self
+                .installed_entries(schema)
+                .inspect_err(|e| log::warn!("No installed entries: {e}"))
+                .unwrap_or_default()
+                .iter()
+                .map(EntryReport::from)
+                .collect(),
+            bootloader_assets: self.bootloader_assets.iter().map(|p| AssetReport::new(p)).collect(),
+            installed_bootloaders: bootloader
+                .as_ref()
+                .and_then(|b| b.installed_bootloaders(&self.bootloader_options).ok())
+                .unwrap_or_default()
+                .iter()
+                .map(|p| AssetReport::new(p))
+                .collect(),
+            shim: bootloader
+                .as_ref()
+                .is_some_and(|b| b.shim_installed(&self.bootloader_options)),
+            fallback: bootloader
+                .as_ref()
+                .and_then(|b| b.fallback_bootloader(&self.bootloader_options))
+                .map(|(path, owned)| FallbackReport::new(&path, owned)),
+            foreign_entries: bootloader.as_ref().map(|b| b.foreign_entries()).unwrap_or_default(),
+            esp_vendors: survey.vendors,
+            windows_boot_manager: survey.windows_boot_manager,
+            windows_hidden,
+            default_entry,
+            timeout: self
+                .timeout(schema)
+                .inspect_err(|e| log::warn!("No timeout: {e}"))
+                .ok()
+                .flatten(),
+        }
+    }
+
     /// Access the automatic cmdline
     pub fn cmdline(&self) -> &[String] {
         &self.cmdline
@@ -131,8 +358,35 @@ impl<'a> Manager<'a> {
 
     /// Set the system kernels to use for sync operations
     pub fn with_entries(self, entries: impl Iterator<Item = Entry<'a>>) -> Self {
+        let entries = match &self.machine_id {
+            Some(machine_id) => entries.map(|e| e.with_machine_id(machine_id)).collect(),
+            None => entries.collect::<Vec<_>>(),
+        };
+        Self { entries, ..self }
+    }
+
+    /// Name entries `$machine_id-$id-$version`, so that installations of the same OS
+    /// can share `$BOOT` (default: false)
+    ///
+    /// The machine ID is read from `/etc/machine-id` in the sysroot. If it isn't set
+    /// yet, as on first boot of an image, entries keep their usual names.
+    pub fn with_machine_id(self, enabled: bool) -> Self {
+        let machine_id = if enabled {
+            read_machine_id(self.config.root.path())
+        } else {
+            None
+        };
+        let entries = self
+            .entries
+            .into_iter()
+            .map(|e| match &machine_id {
+                Some(machine_id) => e.with_machine_id(machine_id),
+                None => Entry { machine_id: None, ..e },
+            })
+            .collect();
         Self {
-            entries: entries.collect::<Vec<_>>(),
+            entries,
+            machine_id,
             ..self
         }
     }
@@ -145,12 +399,168 @@ impl<'a> Manager<'a> {
         }
     }
 
+    /// Report the progress of [`Manager::sync`] to the given callback
+    ///
+    /// The callback may be invoked from several threads (one at a time), but never
+    /// once `sync` has returned.
+    pub fn with_progress(self, callback: ProgressCallback) -> Self {
+        Self {
+            writer: self.writer.with_progress(callback),
+            ..self
+        }
+    }
+
+    /// Allow or forbid updating EFI variables (default: allowed)
+    pub fn with_efi_updates(self, efi_updates: bool) -> Self {
+        Self { efi_updates, ..self }
+    }
+
+    /// Only plan changes, without modifying anything
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        let mode = if dry_run { WriteMode::Plan } else { WriteMode::Commit };
+        Self {
+            writer: self.writer.with_mode(mode),
+            ..self
+        }
+    }
+
+    /// Limit the kernels kept on `$BOOT` during [`Manager::sync`]
+    pub fn with_retention_policy(self, policy: RetentionPolicy) -> Self {
+        Self {
+            retention: Some(policy),
+            ..self
+        }
+    }
+
+    /// Keep only the `max` most recent kernels on `$BOOT` during [`Manager::sync`],
+    /// pruning any older ones. Unlimited by default.
+    pub fn with_max_kernels(self, max: usize) -> Self {
+        Self {
+            max_kernels: Some(max),
+            ..self
+        }
+    }
+
+    /// Select how installed files are compared against their sources (default: metadata)
+    ///
+    /// [`ChangeDetection::Hash`] also verifies every copy once written.
+    pub fn with_change_detection(self, change_detection: ChangeDetection) -> Self {
+        Self {
+            change_detection,
+            ..self
+        }
+    }
+
+    /// When `$BOOT` lacks space for the new entries, remove stale ones before installing
+    /// rather than after (default: false)
+    ///
+    /// This is riskier: an interrupted sync may leave fewer bootable entries behind.
+    pub fn with_cleanup_first(self, cleanup_first: bool) -> Self {
+        Self { cleanup_first, ..self }
+    }
+
+    /// EFI applications (i.e. memtest86+) to install with their own loader entries
+    ///
+    /// Applications we previously installed that aren't listed are removed on sync. Without
+    /// this, installed applications are left untouched.
+    pub fn with_efi_apps(self, efi_apps: Vec<EfiAppEntry>) -> Self {
+        Self {
+            efi_apps: Some(efi_apps),
+            ..self
+        }
+    }
+
+    /// Menu timeout to write to `loader.conf` during [`Manager::sync`]
+    ///
+    /// `Timeout::Seconds(0)` boots the default entry immediately. Unless set, any
+    /// existing timeout is preserved.
+    pub fn with_timeout(self, timeout: Timeout) -> Self {
+        Self {
+            bootloader_options: SyncOptions {
+                timeout: Some(timeout),
+                ..self.bootloader_options
+            },
+            ..self
+        }
+    }
+
+    /// Replace the installed systemd-boot even when it is newer than ours (default: false)
+    pub fn with_force_bootloader(self, force: bool) -> Self {
+        Self {
+            bootloader_options: SyncOptions {
+                force,
+                ..self.bootloader_options
+            },
+            ..self
+        }
+    }
+
+    /// Install the fallback bootloader and our default entry pattern even when `$BOOT`
+    /// is shared with another distribution (default: false)
+    ///
+    /// Otherwise, when foreign loader entries are present and `loader.conf` defaults
+    /// to one of them, only our own entries are updated.
+    pub fn with_take_ownership(self, take_ownership: bool) -> Self {
+        Self {
+            bootloader_options: SyncOptions {
+                take_ownership,
+                ..self.bootloader_options
+            },
+            ..self
+        }
+    }
+
+    /// Install systemd-boot under the given name for shim to chain load (default: `grub$ARCH.efi`)
+    pub fn with_shim_loader(self, name: impl Into<String>) -> Self {
+        Self {
+            bootloader_options: SyncOptions {
+                shim_loader: Some(name.into()),
+                ..self.bootloader_options
+            },
+            ..self
+        }
+    }
+
+    /// Recognise another vendor directory on the ESP, in preference to the built-in rules
+    pub fn with_vendor_rule(self, rule: VendorRule) -> Self {
+        Self {
+            bootloader_options: SyncOptions {
+                vendors: self.bootloader_options.vendors.with_rule(rule),
+                ..self.bootloader_options
+            },
+            ..self
+        }
+    }
+
+    /// Mount `$BOOT` (ESP and XBOOTLDR) where not already mounted
+    ///
+    /// Unless `persistent`, the partitions are unmounted again when the returned
+    /// guards are dropped. Already mounted partitions are left untouched.
+    pub fn mount_boot(&self, persistent: bool) -> Result<Vec<ScopedMount>, Error> {
+        if let Some(point) = self.boot_env.esp_mountpoint.as_ref() {
+            log::info!("ESP already mounted at {}", point.display());
+        }
+        if let Some(point) = self.boot_env.xboot_mountpoint.as_ref() {
+            log::info!("XBOOTLDR already mounted at {}", point.display());
+        }
+
+        let mounts = self.mount_partitions(MountIntent::ReadWrite)?;
+        if persistent {
+            Ok(mounts.into_iter().map(ScopedMount::persist).collect())
+        } else {
+            Ok(mounts)
+        }
+    }
+
     /// Mount any required partitions (ESP/XBOOTLDR)
-    pub fn mount_partitions(&self) -> Result<Vec<ScopedMount>, Error> {
+    ///
+    /// Already mounted partitions are left untouched in their existing mode, so any
+    /// later write to a partition mounted `ro` fails with [`Error::BootReadOnly`].
+    pub fn mount_partitions(&self, intent: MountIntent) -> Result<Vec<ScopedMount>, Error> {
         let mut mounted_paths = vec![];
 
         // Stop silly buggers with image based mounting
-        if let Root::Image(_) = self.config.root {
+        if self.config.root.is_image() {
             log::warn!("Refusing to auto-mount partitions in image mode");
             return Ok(mounted_paths);
         }
@@ -159,14 +569,14 @@ impl<'a> Manager<'a> {
         if let Some(hw) = self.boot_env.esp() {
             if self.boot_env.esp_mountpoint.is_none() {
                 let mount_point = self.mounts.esp.clone().ok_or(Error::NoEsp)?;
-                mounted_paths.insert(0, self.mount_vfat_partition(hw, &mount_point)?);
+                mounted_paths.insert(0, self.mount_vfat_partition(hw, &mount_point, intent)?);
             }
         }
-        // Got an XBOOTLDR, not mounted..
-        if let Some(hw) = self.boot_env.xbootldr() {
+        // Got an XBOOTLDR, not mounted, and we're using it..
+        if let Some(hw) = self.boot_env.xbootldr().filter(|_| self.config.layout.uses_xbootldr()) {
             if self.boot_env.xboot_mountpoint.is_none() {
                 let mount_point = self.mounts.xbootldr.clone().ok_or(Error::NoXbootldr)?;
-                mounted_paths.insert(0, self.mount_vfat_partition(hw, &mount_point)?);
+                mounted_paths.insert(0, self.mount_vfat_partition(hw, &mount_point, intent)?);
             }
         }
 
@@ -185,15 +595,446 @@ impl<'a> Manager<'a> {
         Ok(results)
     }
 
+    /// Parse all loader entries owned by us on `$BOOT`
+    ///
+    /// Unparseable entries are logged and skipped.
+    pub fn installed_entries(&self, schema: &Schema) -> Result<Vec<Entry<'static>>, Error> {
+        let bootloader = self.bootloader(schema)?;
+        let mut entries = bootloader
+            .loader_entry_files()
+            .into_iter()
+            .filter_map(|path| match Entry::from_loader_conf(&path, schema) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::warn!("Skipping loader entry: {e}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.kernel.cmp(&b.kernel));
+        Ok(entries)
+    }
+
+    /// All type #1 loader entries on `$BOOT`, including those from other installations
+    pub fn loader_entries(&self, schema: &Schema) -> Result<Vec<(PathBuf, LoaderEntry)>, Error> {
+        Ok(self.bootloader(schema)?.installed_entries())
+    }
+
+    /// Re-read the installed files of every installed system kernel, returning any
+    /// that are missing or differ from their source
+    pub fn verify(&self, schema: &Schema) -> Result<Vec<PathBuf>, Error> {
+        let entries = self.entries.iter().collect::<Vec<_>>();
+        Ok(self.bootloader(schema)?.verify_entries(&entries)?)
+    }
+
+    /// Cross-check every loader entry on `$BOOT` against the files present, reporting
+    /// dangling entries and unreferenced kernel directories
+    ///
+    /// Nothing is removed, and orphans from other installations are included. See [`Manager::prune`].
+    pub fn audit_boot(&self, schema: &Schema) -> Result<AuditReport, Error> {
+        Ok(self.bootloader(schema)?.audit())
+    }
+
+    /// Remove the selected orphans, as previously reported by [`Manager::audit_boot`]
+    ///
+    /// The audit is repeated first, and any selection that is no longer orphaned is refused.
+    pub fn prune(&self, schema: &Schema, selections: &[Orphan]) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+
+        let report = self.audit_boot(schema)?;
+        for selection in selections {
+            ensure!(
+                report.orphans.iter().any(|o| o.path() == selection.path()),
+                NotOrphanedSnafu { path: selection.path() }
+            );
+        }
+
+        for selection in selections {
+            log::info!("Removing orphan: {}", selection.path().display());
+            match selection {
+                Orphan::DanglingEntry { path, .. } => self.writer.remove_file(path),
+                Orphan::UnreferencedDir { path, .. } => self.writer.remove_dir_all(path),
+            }
+            .context(IoSnafu)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove a kernel from the boot partition
+    ///
+    /// The running kernel is only removed when [`RemoveOptions::force`] is set, and
+    /// we'll never remove the last installed kernel.
+    pub fn remove_kernel(&self, schema: &Schema, version: &str, options: RemoveOptions) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+
+        let bootloader = self.bootloader(schema)?;
+        let installed = bootloader.installed_kernels()?;
+
+        let kernel = installed
+            .iter()
+            .find(|k| k.version == version)
+            .context(UnknownKernelSnafu { version })?;
+        ensure!(installed.len() > 1, LastKernelSnafu { version });
+
+        if self.config.root.is_native() {
+            ensure!(
+                options.force || !kernel.matches_running()?,
+                RunningKernelSnafu { version }
+            );
+        }
+
+        let boot_root = self.boot_root()?;
+        let mut state = BootState::load(boot_root)?;
+        ensure!(
+            options.force || state.last_known_good() != Some(version),
+            KnownGoodKernelSnafu { version }
+        );
+
+        bootloader.remove_kernel(version)?;
+        if state.forget(version) {
+            state.save(boot_root, &self.writer)?;
+        }
+
+        if options.purge {
+            if let Schema::Legacy { .. } = schema {
+                log::warn!("Purging kernels from the rootfs is unsupported for legacy schemas");
+            } else {
+                let tree = self
+                    .config
+                    .root
+                    .path()
+                    .join("usr")
+                    .join("lib")
+                    .join("kernel")
+                    .join(version);
+                if tree.is_dir() {
+                    log::info!("Purging kernel tree: {}", tree.display());
+                    self.writer.remove_dir_all(&tree).context(IoSnafu)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark the currently booted entry as successfully booted
+    ///
+    /// The booted entry is determined via the `LoaderEntrySelected` EFI variable, falling
+    /// back to matching `BOOT_IMAGE=` from `/proc/cmdline` against the installed kernels.
+    /// Returns the version of the booted kernel when it could be determined.
+    pub fn mark_booted(&self, schema: &Schema) -> Result<Option<String>, Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+        let bootloader = self.bootloader(schema)?;
+        let installed = bootloader.installed_kernels()?;
+
+        if let Some(selected) = self
+            .efi_interface()
+            .and_then(|bli| bli.get_ucs2_string(VariableName::EntrySelected).ok())
+        {
+            log::trace!("LoaderEntrySelected: {selected}");
+            if !bootloader.bless_entry(&selected)? {
+                log::warn!("Booted entry {selected} is not managed by us");
+                return Ok(None);
+            }
+            return Ok(installed
+                .into_iter()
+                .find(|k| bootloader.entry_matches_version(&selected, &k.version))
+                .map(|k| k.version));
+        }
+
+        // No Boot Loader Interface, best effort via the kernel cmdline
+        if self.config.root.is_image() {
+            return Ok(None);
+        }
+        let cmdline = fs::read_to_string(self.config.vfs.join("proc").join("cmdline")).context(IoSnafu)?;
+        let Some(boot_image) = cmdline
+            .split_ascii_whitespace()
+            .find_map(|p| p.strip_prefix("BOOT_IMAGE="))
+        else {
+            return Ok(None);
+        };
+        log::trace!("BOOT_IMAGE: {boot_image}");
+
+        Ok(installed
+            .into_iter()
+            .find(|k| {
+                boot_image
+                    .split(['/', '\\'])
+                    .any(|c| c == k.version || c.ends_with(&format!(".{}", k.version)))
+            })
+            .map(|k| k.version))
+    }
+
+    /// Set the default boot entry
+    ///
+    /// The `kernel` may be a full entry ID (as produced by [`Entry::id`]), a kernel version,
+    /// a unique version prefix or a variant name. Passing `None` restores the "newest wins"
+    /// behaviour. When permitted the `LoaderEntryDefault` EFI variable is updated to match.
+    ///
+    /// Returns the resolved entry ID.
+    pub fn set_default_entry(&self, schema: &Schema, kernel: Option<&str>) -> Result<Option<String>, Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+        let bootloader = self.bootloader(schema)?;
+        let bli = self.writable_efi_interface();
+
+        let Some(kernel) = kernel else {
+            bootloader.reset_default_entry()?;
+            if let Some(bli) = bli {
+                self.remove_efi_variable(&bli, VariableName::EntryDefault)?;
+            }
+            return Ok(None);
+        };
+
+        let kernel = kernel.strip_suffix(".conf").unwrap_or(kernel);
+        let ids = bootloader.installed_entry_ids();
+        let entry_id = if ids.iter().any(|id| id == kernel) {
+            kernel.to_string()
+        } else {
+            let installed = bootloader.installed_kernels()?;
+            let version = match_kernel(&installed, kernel)?;
+            let candidates = ids
+                .into_iter()
+                .filter(|id| bootloader.entry_matches_version(id, version))
+                .collect::<Vec<_>>();
+            match candidates.as_slice() {
+                [] => return UnknownKernelSnafu { version }.fail(),
+                [id] => id.clone(),
+                _ => {
+                    return AmbiguousKernelSnafu {
+                        version: kernel,
+                        candidates,
+                    }
+                    .fail();
+                }
+            }
+        };
+
+        log::info!("Setting default entry to {entry_id}");
+        bootloader.set_default_entry(&entry_id)?;
+        if let Some(bli) = bli {
+            self.set_efi_variable(&bli, VariableName::EntryDefault, &format!("{entry_id}.conf"))?;
+        }
+
+        Ok(Some(entry_id))
+    }
+
+    /// Query the bootloader menu timeout
+    ///
+    /// The `LoaderConfigTimeout` EFI variable takes precedence when available,
+    /// otherwise we fall back to `loader.conf`
+    pub fn timeout(&self, schema: &Schema) -> Result<Option<Timeout>, Error> {
+        if let Some(timeout) = self
+            .efi_interface()
+            .and_then(|bli| bli.get_ucs2_string(VariableName::ConfigTimeout).ok())
+            .and_then(|t| t.trim().parse().ok())
+        {
+            return Ok(Some(timeout));
+        }
+
+        let bootloader = self.bootloader(schema)?;
+        Ok(bootloader.timeout()?)
+    }
+
+    /// Update the bootloader menu timeout
+    ///
+    /// A timeout of `0` will skip the menu entirely. `loader.conf` is always
+    /// updated, and the `LoaderConfigTimeout` EFI variable is updated when permitted.
+    pub fn set_timeout(&self, schema: &Schema, timeout: Timeout) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+        let bootloader = self.bootloader(schema)?;
+        bootloader.set_timeout(timeout)?;
+
+        if let Some(bli) = self.writable_efi_interface() {
+            self.set_efi_variable(&bli, VariableName::ConfigTimeout, &timeout.to_string())?;
+        } else if self
+            .efi_interface()
+            .is_some_and(|bli| bli.get_ucs2_string(VariableName::ConfigTimeout).is_ok())
+        {
+            log::warn!("LoaderConfigTimeout EFI variable is set and will override loader.conf");
+        }
+
+        Ok(())
+    }
+
+    /// Read the current `loader.conf`
+    pub fn loader_conf(&self, schema: &Schema) -> Result<LoaderConf, Error> {
+        Ok(self.bootloader(schema)?.loader_conf()?)
+    }
+
+    /// Modify `loader.conf` (i.e. to disable the editor on kiosk systems)
+    ///
+    /// Comments and keys left untouched by `update` are preserved, and the file
+    /// is only written when something changed.
+    pub fn update_loader_conf(&self, schema: &Schema, update: impl FnOnce(&mut LoaderConf)) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+        Ok(self.bootloader(schema)?.update_loader_conf(update)?)
+    }
+
+    /// Record the given kernel version as successfully booted
+    ///
+    /// The state is kept on `$BOOT` and consulted by [`Manager::remove_kernel`]
+    /// so that the most recent known-good kernel is never removed.
+    pub fn report_booted(&self, running_version: &str) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let _lock = self.acquire_lock()?;
+        let boot_root = self.boot_root()?;
+        let mut state = BootState::load(boot_root)?;
+        state.record(running_version);
+        state.save(boot_root, &self.writer)
+    }
+
+    /// Retrieve the recorded boot success state
+    pub fn boot_state(&self) -> Result<BootState, Error> {
+        BootState::load(self.boot_root()?)
+    }
+
+    /// Retrieve the state recorded by the most recent sync, if any
+    ///
+    /// Compare with the current schema via [`SyncState::is_migration`] to detect
+    /// a change of OS namespace since then.
+    pub fn last_sync_state(&self) -> Result<Option<SyncState>, Error> {
+        Ok(self.boot_state()?.sync)
+    }
+
+    /// Report directories on the ESP and XBOOTLDR that differ only by case
+    ///
+    /// Such layouts (ie both `EFI` and `efi`) are typically left behind by
+    /// other tools. When `merge` is set, the contents of each duplicate are
+    /// moved into the directory that we'd otherwise resolve to.
+    pub fn check_boot_layout(&self, merge: bool) -> Result<Vec<CaseCollision>, Error> {
+        let mut roots = vec![];
+        for root in [self.mounts.esp.as_ref(), self.mounts.xbootldr.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if root.exists() && !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+
+        let mut collisions = vec![];
+        for root in roots {
+            for collision in case_collisions(root).context(IoSnafu)? {
+                log::warn!("Case-insensitive duplicate directories: {collision}");
+                if merge {
+                    collision.merge(&self.writer).context(IoSnafu)?;
+                }
+                collisions.push(collision);
+            }
+        }
+
+        Ok(collisions)
+    }
+
+    /// `$BOOT` mountpoint
+    fn boot_root(&self) -> Result<&PathBuf, Error> {
+        self.mounts.boot_root().ok_or(Error::NoEsp)
+    }
+
+    /// Set an EFI variable, unless it already has the given value
+    fn set_efi_variable(&self, bli: &BootLoaderInterface, var: VariableName, value: &str) -> Result<(), Error> {
+        if bli.get_ucs2_string(var).is_ok_and(|v| v == value) {
+            return Ok(());
+        }
+        let change = Change::EfiVariable {
+            name: var.to_string(),
+            value: Some(value.to_string()),
+        };
+        if self.writer.commit(change) {
+            bli.set_ucs2_string(var, value)?;
+        }
+        Ok(())
+    }
+
+    /// Remove an EFI variable, if set
+    fn remove_efi_variable(&self, bli: &BootLoaderInterface, var: VariableName) -> Result<(), Error> {
+        if bli.get_ucs2_string(var).is_err() {
+            return Ok(());
+        }
+        let change = Change::EfiVariable {
+            name: var.to_string(),
+            value: None,
+        };
+        if self.writer.commit(change) {
+            bli.remove_variable(var)?;
+        }
+        Ok(())
+    }
+
+    /// Changes made (or, in dry-run mode, planned) by this manager so far
+    pub fn changes(&self) -> SyncPlan {
+        self.writer.plan()
+    }
+
+    /// Access the Boot Loader Interface, only when running natively on UEFI
+    fn efi_interface(&self) -> Option<BootLoaderInterface> {
+        if self.boot_env.efi_vars == VarAccess::Unavailable || self.config.root.is_image() {
+            return None;
+        }
+        BootLoaderInterface::new(&self.config.vfs).ok()
+    }
+
+    /// Access the Boot Loader Interface for updating variables, when permitted and possible
+    fn writable_efi_interface(&self) -> Option<BootLoaderInterface> {
+        if !self.efi_updates {
+            return None;
+        }
+        let bli = self.efi_interface()?;
+        if self.boot_env.efi_vars != VarAccess::ReadWrite {
+            log::warn!("EFI variables are read-only, only updating loader.conf");
+            return None;
+        }
+        Some(bli)
+    }
+
+    /// Take the exclusive lock serializing modifications to `$BOOT`
+    ///
+    /// Waits up to [`Configuration::lock_timeout`] for any other instance to finish.
+    /// The lock is released when the returned guard is dropped.
+    pub fn acquire_lock(&self) -> Result<LockGuard, Error> {
+        let dir = self.config.vfs.join("run").join("lock");
+        fs::create_dir_all(&dir).context(IoSnafu)?;
+        lock_file(dir.join("blsforme.lock"), self.config.lock_timeout)
+    }
+
+    /// Ensure the ESP and XBOOTLDR mountpoints in use are writable, prior to changing `$BOOT`
+    fn ensure_writable(&self) -> Result<(), Error> {
+        if self.writer.dry_run() {
+            return Ok(());
+        }
+        let partitions = [
+            ("ESP", self.mounts.esp.as_ref()),
+            ("XBOOTLDR", self.mounts.xbootldr.as_ref()),
+        ];
+        for (partition, path) in partitions {
+            if let Some(path) = path.filter(|p| p.exists()) {
+                ensure_writable_at(partition, path)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Mount an fat filesystem
     #[inline]
-    fn mount_vfat_partition(&self, source: &Path, target: &Path) -> Result<ScopedMount, Error> {
+    fn mount_vfat_partition(&self, source: &Path, target: &Path, intent: MountIntent) -> Result<ScopedMount, Error> {
         let options: Option<&str> = None;
         if !target.exists() {
             fs::create_dir_all(target).context(IoSnafu)?;
         }
-        mount(Some(source), target, Some("vfat"), MsFlags::MS_MGC_VAL, options).context(NixSnafu)?;
-        log::info!("Mounted vfat partition {} at {}", source.display(), target.display());
+        mount(Some(source), target, Some("vfat"), intent.mount_flags(), options).context(NixSnafu)?;
+        log::info!(
+            "Mounted vfat partition {} at {} ({})",
+            source.display(),
+            target.display(),
+            if intent == MountIntent::ReadOnly { "ro" } else { "rw" }
+        );
         Ok(ScopedMount {
             point: target.into(),
             mounted: true,
@@ -205,42 +1046,217 @@ impl<'a> Manager<'a> {
     /// Any already installed kernels will be skipped, and this step
     /// is not responsible for *deleting* any unused kernels
     pub fn sync(&self, schema: &Schema) -> Result<(), Error> {
-        if let Root::Image(_) = self.config.root {
+        self.sync_with(schema, &self.writer)
+    }
+
+    /// Compute the changes [`Manager::sync`] would make, without modifying anything
+    ///
+    /// This runs the exact same code paths as a real sync.
+    pub fn plan(&self, schema: &Schema) -> Result<SyncPlan, Error> {
+        let writer = self.writer.planner();
+        self.sync_with(schema, &writer)?;
+        Ok(writer.plan())
+    }
+
+    /// Warn when a boot partition is running low on space
+    fn check_capacity(&self) {
+        let partitions = [
+            ("ESP", self.mounts.esp.as_ref(), self.boot_env.esp_size),
+            ("XBOOTLDR", self.mounts.xbootldr.as_ref(), self.boot_env.xbootldr_size),
+        ];
+        for (name, mountpoint, size) in partitions {
+            let (Some(mountpoint), Some(size)) = (mountpoint, size) else {
+                continue;
+            };
+            let Ok(available) = BootEnvironment::available_space_at(mountpoint) else {
+                continue;
+            };
+            if available * 100 < size * LOW_SPACE_PERCENT {
+                log::warn!(
+                    "{name} at {} is running low on space: {} free of {}",
+                    mountpoint.display(),
+                    format_size(available),
+                    format_size(size)
+                );
+            }
+        }
+    }
+
+    /// Sync the bootloader and entries, with all changes going through the given writer
+    fn sync_with(&self, schema: &Schema, writer: &Writer) -> Result<(), Error> {
+        if self.config.root.is_image() {
             if let Some(esp) = self.boot_env.esp() {
                 ensure!(self.boot_env.esp_mountpoint.is_some(), UnmountedEspSnafu { path: esp });
             }
         }
+        let _lock = if writer.dry_run() {
+            None
+        } else {
+            self.ensure_writable()?;
+            Some(self.acquire_lock()?)
+        };
+        writer.progress(ProgressEvent::Discovery);
+        self.check_capacity();
+
         // Firstly, get the bootloader updated.
-        let bootloader = self.bootloader(schema)?;
-        bootloader.sync()?;
+        let bootloader = self.bootloader_with(schema, writer)?;
+        writer.progress(ProgressEvent::BootloaderSync);
+        match bootloader.sync(&self.bootloader_options) {
+            Err(bootloader::Error::Unsupported { operation }) => {
+                log::warn!(
+                    "Skipping bootloader update, unsupported on {:?}: {operation}",
+                    self.boot_env.firmware
+                )
+            }
+            Err(e @ bootloader::Error::UnsupportedFirmware { .. }) => {
+                log::warn!("Skipping bootloader update: {e}, only maintaining entries")
+            }
+            Err(bootloader::Error::MissingMount { description })
+                if matches!(self.config.layout, BootLayout::BootDirectory(_)) =>
+            {
+                log::info!("Skipping bootloader update, no {description} mounted")
+            }
+            result => result?,
+        }
 
-        // Sync the entries
-        bootloader.sync_entries(
-            self.cmdline.iter().map(String::as_str),
-            &self.entries,
-            self.system_excluded_snippets.iter().map(String::as_str),
-        )?;
+        // Sync the entries, trimmed to the retention policy and kernel limit
+        let entries = match &self.retention {
+            Some(policy) => {
+                let running = if self.config.root.is_native() {
+                    Some(uname().context(NixSnafu)?.release().to_string_lossy().to_string())
+                } else {
+                    None
+                };
+                let state = self.boot_state()?;
+                policy.apply(&self.entries, running.as_deref(), state.last_known_good())
+            }
+            None => self.entries.iter().collect(),
+        };
+        let entries = match self.max_kernels {
+            Some(max) => newest_kernels(entries, max),
+            None => entries,
+        };
+        for entry in entries.iter() {
+            entry.validate(entry.sysroot.as_deref().unwrap_or(self.config.root.path()))?;
+        }
+        bootloader.sync_entries(self.cmdline.iter().map(String::as_str), &entries)?;
+
+        // Record which schema produced this layout, when that's changed
+        if let Some(boot_root) = self.mounts.boot_root() {
+            let machine_id = read_machine_id(self.config.root.path());
+            let mut state = BootState::load(boot_root)?;
+            if state.record_sync(SyncState::new(schema, machine_id, writer.now())) {
+                state.save(boot_root, writer)?;
+            }
+        }
 
         Ok(())
     }
 
     /// factory - create bootloader instance
     fn bootloader(&'a self, schema: &'a Schema) -> Result<Bootloader<'a, 'a>, Error> {
+        self.bootloader_with(schema, &self.writer)
+    }
+
+    /// Bootloader for the schema, making all changes through the given writer
+    fn bootloader_with<'s>(&'s self, schema: &'s Schema, writer: &'s Writer) -> Result<Bootloader<'s, 's>, Error> {
         Ok(Bootloader::new(
             schema,
             &self.bootloader_assets,
             &self.mounts,
-            &self.boot_env.firmware,
-        )?)
+            &self.boot_env,
+            self.config.arch,
+            writer,
+            self.change_detection,
+        )?
+        .with_machine_id(self.machine_id.as_deref())
+        .with_cleanup_first(self.cleanup_first)
+        .with_efi_apps(self.efi_apps.as_deref()))
+    }
+}
+
+/// Read `/etc/machine-id` from the sysroot, if initialised
+fn read_machine_id(root: &Path) -> Option<String> {
+    let path = root.join("etc").join("machine-id");
+    let machine_id = match fs::read_to_string(&path) {
+        Ok(text) => text.trim().to_ascii_lowercase(),
+        Err(e) => {
+            log::debug!("No machine ID, using unprefixed entry names: {e}");
+            return None;
+        }
+    };
+    if machine_id.len() == 32 && machine_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(machine_id)
+    } else {
+        // i.e. `uninitialized` during first boot
+        log::debug!("Ignoring unset machine ID in {}: {machine_id:?}", path.display());
+        None
     }
 }
 
+/// Fail with [`Error::BootReadOnly`] when the filesystem holding `path` is mounted read-only
+fn ensure_writable_at(partition: &'static str, path: &Path) -> Result<(), Error> {
+    let stat = statvfs(path).context(NixSnafu)?;
+    ensure!(
+        !stat.flags().contains(FsFlags::ST_RDONLY),
+        BootReadOnlySnafu { partition, path }
+    );
+    Ok(())
+}
+
+/// Exclusively lock `path`, retrying until `timeout` elapses
+fn lock_file(path: PathBuf, timeout: Duration) -> Result<LockGuard, Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .context(IoSnafu)?;
+
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(lock) => return Ok(LockGuard { _lock: lock }),
+            Err((returned, Errno::EWOULDBLOCK)) => {
+                ensure!(start.elapsed() < timeout, LockTimeoutSnafu { path, timeout });
+                if !waiting {
+                    log::info!("Waiting for another instance to release {}", path.display());
+                    waiting = true;
+                }
+                file = returned;
+                thread::sleep(LOCK_RETRY_INTERVAL);
+            }
+            Err((_, source)) => return Err(Error::Lock { path, source }),
+        }
+    }
+}
+
+/// Exclusive lock on `$BOOT` modifications, released (`LOCK_UN`) when dropped
+#[derive(Debug)]
+pub struct LockGuard {
+    _lock: Flock<std::fs::File>,
+}
+
 /// Encapsulated mountpoint to ensure auto-unmount (Scoped)
 pub struct ScopedMount {
     point: PathBuf,
     mounted: bool,
 }
 
+impl ScopedMount {
+    /// Where the partition is mounted
+    pub fn path(&self) -> &Path {
+        &self.point
+    }
+
+    /// Intentionally leave the partition mounted once dropped
+    pub fn persist(mut self) -> Self {
+        self.mounted = false;
+        self
+    }
+}
+
 impl Drop for ScopedMount {
     fn drop(&mut self) {
         if !self.mounted {
@@ -253,3 +1269,163 @@ impl Drop for ScopedMount {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use fs_err as fs;
+    use topology::disk::Builder;
+
+    use nix::mount::{MsFlags, mount};
+
+    use super::{Manager, MountIntent, Mounts, ScopedMount, ensure_writable_at, lock_file};
+    use crate::{BootEnvironment, BootLayout, Configuration, Error, ProgressEvent, Root};
+
+    #[test]
+    fn test_lock_file() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = dir.path().join("blsforme.lock");
+
+        let guard = lock_file(path.clone(), Duration::ZERO).expect("Failed to take lock");
+        assert!(matches!(
+            lock_file(path.clone(), Duration::ZERO),
+            Err(Error::LockTimeout { .. })
+        ));
+
+        drop(guard);
+        lock_file(path, Duration::ZERO).expect("Failed to retake lock");
+    }
+
+    /// Mounts chosen by `layout`, for an ESP at `efi`
+    fn layout_mounts(root: &Path, layout: BootLayout) -> Result<Mounts, Error> {
+        let config = Configuration::builder()
+            .root(Root::Image(root.to_path_buf()))
+            .esp_path(Some(root.join("efi")))
+            .layout(layout)
+            .build()
+            .unwrap();
+        let probe = Builder::default().build().unwrap();
+        let boot_env = BootEnvironment::new(&probe, None, &config)?;
+        Mounts::new(&boot_env, &config)
+    }
+
+    #[test]
+    fn test_boot_layout() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let root = root.path();
+        for dir in ["efi", "boot"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+
+        let mounts = layout_mounts(root, BootLayout::Auto).unwrap();
+        assert_eq!(mounts.boot_root(), Some(&root.join("efi")));
+        assert_eq!(mounts.xbootldr, None);
+
+        let mounts = layout_mounts(root, BootLayout::EspOnly).unwrap();
+        assert_eq!(mounts.boot_root(), Some(&root.join("efi")));
+
+        assert!(matches!(
+            layout_mounts(root, BootLayout::Xbootldr),
+            Err(Error::NoXbootldr)
+        ));
+
+        // Relative to the root, whether or not given as absolute
+        let mounts = layout_mounts(root, BootLayout::BootDirectory("/boot".into())).unwrap();
+        assert_eq!(mounts.boot_root(), Some(&root.join("boot")));
+        assert_eq!(mounts.esp, Some(root.join("efi")));
+
+        // Discovered XBOOTLDR partitions are only mounted when used
+        assert!(BootLayout::Auto.uses_xbootldr());
+        assert!(BootLayout::Xbootldr.uses_xbootldr());
+        assert!(!BootLayout::EspOnly.uses_xbootldr());
+        assert!(!BootLayout::BootDirectory("boot".into()).uses_xbootldr());
+    }
+
+    #[test]
+    fn test_manager_boot_paths() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let root = root.path();
+        for dir in ["efi", "boot"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+
+        // Nothing is probed, so there's no `root=` to derive
+        let config = Configuration::builder()
+            .root(Root::Image(root.to_path_buf()))
+            .esp_path(Some(root.join("efi")))
+            .build()
+            .unwrap();
+        let manager = Manager::new(&config).unwrap();
+        assert_eq!(manager.cmdline(), ["rw"]);
+        assert_eq!(manager.mounts.boot_root(), Some(&root.join("efi")));
+
+        let config = Configuration::builder()
+            .root(Root::Image(root.to_path_buf()))
+            .layout(BootLayout::BootDirectory("boot".into()))
+            .build()
+            .unwrap();
+        let manager = Manager::new(&config).unwrap();
+        assert_eq!(manager.cmdline(), ["rw"]);
+        assert_eq!(manager.mounts.boot_root(), Some(&root.join("boot")));
+    }
+
+    #[test]
+    fn test_dry_run_keeps_writer() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        fs::create_dir(root.path().join("efi")).unwrap();
+        let config = Configuration::builder()
+            .root(Root::Image(root.path().to_path_buf()))
+            .esp_path(Some(root.path().join("efi")))
+            .build()
+            .unwrap();
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let record = seen.clone();
+        let epoch = UNIX_EPOCH + Duration::from_secs(1700000000);
+        let mut manager = Manager::new(&config)
+            .unwrap()
+            .with_progress(Box::new(move |event| record.lock().unwrap().push(event)));
+        manager.writer = manager.writer.with_source_date_epoch(Some(epoch));
+
+        // Only the mode changes, the observer and timestamps survive
+        let manager = manager.with_dry_run(true);
+        assert!(manager.writer.dry_run());
+        assert_eq!(manager.writer.now(), epoch);
+        manager.writer.progress(ProgressEvent::Discovery);
+        assert_eq!(*seen.lock().unwrap(), vec![ProgressEvent::Discovery]);
+    }
+
+    #[test]
+    fn test_mount_intent() {
+        assert!(MountIntent::ReadOnly.mount_flags().contains(MsFlags::MS_RDONLY));
+        assert!(!MountIntent::ReadWrite.mount_flags().contains(MsFlags::MS_RDONLY));
+        assert_eq!(MountIntent::default(), MountIntent::ReadOnly);
+    }
+
+    #[test]
+    fn test_ensure_writable() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        ensure_writable_at("ESP", dir.path()).unwrap();
+
+        // Mounting needs privileges, only exercise the read-only path when we have them
+        let ro = dir.path().join("ro");
+        fs::create_dir(&ro).unwrap();
+        let flags = MountIntent::ReadOnly.mount_flags();
+        if mount(Some("tmpfs"), &ro, Some("tmpfs"), flags, None::<&str>).is_err() {
+            return;
+        }
+        let _mounted = ScopedMount {
+            point: ro.clone(),
+            mounted: true,
+        };
+        assert!(matches!(
+            ensure_writable_at("XBOOTLDR", &ro),
+            Err(Error::BootReadOnly { partition: "XBOOTLDR", path }) if path == ro
+        ));
+    }
+}
@@ -8,11 +8,15 @@
 //! for more information.
 //!
 //! This crate supports fields pertaining to the use of os-release files within the context
-//! of moss-managed distribution, and currently does not process any fields specifically
-//! intended for container image builds.
+//! of moss-managed distribution. Values are unquoted and unescaped per the shell-compatible
+//! rules of os-release(5), and any other fields remain available via [`OsRelease::extra`].
 
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 
 #[derive(Debug, Snafu)]
@@ -25,11 +29,38 @@ pub enum Error {
 /// Ok it's not the most efficient way, we could use Cow on a Read...
 /// It just gets the job done.
 trait MapDecode: Sized {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, self::Error>;
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, self::Error>;
 }
 
+/// Keys decoded into the [`OsRelease`] fields, all others are kept as [`OsRelease::extra`]
+const KNOWN_KEYS: [&str; 23] = [
+    "NAME",
+    "ID",
+    "ID_LIKE",
+    "PRETTY_NAME",
+    "CPE_NAME",
+    "VERSION",
+    "VERSION_ID",
+    "VERSION_CODENAME",
+    "BUILD_ID",
+    "VARIANT",
+    "VARIANT_ID",
+    "IMAGE_ID",
+    "IMAGE_VERSION",
+    "HOME_URL",
+    "DOCUMENTATION_URL",
+    "SUPPORT_URL",
+    "BUG_REPORT_URL",
+    "PRIVACY_POLICY_URL",
+    "SUPPORT_END",
+    "LOGO",
+    "ANSI_COLOR",
+    "VENDOR_NAME",
+    "VENDOR_URL",
+];
+
 /// General structure of the `os-release` file used by Linux distributionss
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OsRelease {
     /// Name of the operating system
     pub name: String,
@@ -46,6 +77,9 @@ pub struct OsRelease {
     /// Useful project/OS links
     pub urls: Urls,
 
+    /// Image this OS was deployed from, when built as an image
+    pub image: Image,
+
     /// When does support end? ISO-8601
     pub support_ends: Option<String>,
 
@@ -54,41 +88,103 @@ pub struct OsRelease {
 
     /// Vendor details
     pub vendor: Vendor,
+
+    /// Any fields not covered above
+    extra: BTreeMap<String, String>,
+}
+
+impl OsRelease {
+    /// Value of a field not otherwise decoded, i.e. `DEFAULT_HOSTNAME`
+    pub fn extra(&self, key: &str) -> Option<&str> {
+        self.extra.get(key).map(String::as_str)
+    }
+
+    /// IDs of the operating systems this one derives from, closest first, per `ID_LIKE`
+    pub fn like_ids(&self) -> impl Iterator<Item = &str> {
+        self.meta.like.iter().flat_map(|like| like.split_whitespace())
+    }
+
+    /// Display name, preferring `PRETTY_NAME` over `NAME` with any `VERSION_CODENAME`
+    pub fn display_name(&self) -> Option<String> {
+        self.meta.pretty_name.clone().or_else(|| {
+            self.version
+                .codename
+                .as_ref()
+                .map(|codename| format!("{} {codename}", self.name))
+        })
+    }
 }
 
 impl FromStr for OsRelease {
     type Err = self::Error;
 
+    /// Parse `KEY=value` assignments, skipping blank lines and comments
+    ///
+    /// Empty values are treated as unset.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let map = s
             .lines()
             .map(|l| l.trim())
             .filter(|l| !l.starts_with('#'))
             .filter_map(|s| s.split_once('='))
-            .map(|(k, v)| (k, v.trim_matches(|c| c == '\'' || c == '\"')))
+            .map(|(k, v)| (k.trim().to_string(), unquote(v)))
+            .filter(|(k, v)| !k.is_empty() && !v.is_empty())
             .collect::<HashMap<_, _>>();
 
         Self::map_decode(&map)
     }
 }
 
+/// Unquote a shell-compatible value: single quotes are literal, while double quotes and
+/// unquoted text support backslash escapes. Unquoted text ends at a trailing comment
+fn unquote(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.trim().chars();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => result.push(c),
+            (Some('"'), '\\') => match chars.next() {
+                Some(e @ ('$' | '"' | '\\' | '`')) => result.push(e),
+                Some(e) => {
+                    result.push('\\');
+                    result.push(e);
+                }
+                None => result.push('\\'),
+            },
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '\\') => result.extend(chars.next()),
+            (None, '#') if result.ends_with(char::is_whitespace) => break,
+            _ => result.push(c),
+        }
+    }
+    result.trim_end().to_string()
+}
+
 impl MapDecode for OsRelease {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
             name: o.get("NAME").ok_or(Error::MissingKey { key: "NAME" })?.to_string(),
             id: o.get("ID").ok_or(Error::MissingKey { key: "ID" })?.to_string(),
             meta: Metadata::map_decode(o)?,
             version: Version::map_decode(o)?,
             urls: Urls::map_decode(o)?,
-            support_ends: o.get("SUPPORT_ENDS").map(|s| s.to_string()),
+            image: Image::map_decode(o)?,
+            support_ends: o.get("SUPPORT_END").map(|s| s.to_string()),
             brand: Brand::map_decode(o)?,
             vendor: Vendor::map_decode(o)?,
+            extra: o
+                .iter()
+                .filter(|(k, _)| !KNOWN_KEYS.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
         })
     }
 }
 
 /// Logical grouping of metadata fields to assist in queries
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Metadata {
     /// What [`OsRelease::id`] is this OS like?
     pub like: Option<String>,
@@ -101,7 +197,7 @@ pub struct Metadata {
 }
 
 impl MapDecode for Metadata {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
             like: o.get("ID_LIKE").map(|s| s.to_string()),
             pretty_name: o.get("PRETTY_NAME").map(|s| s.to_string()),
@@ -111,7 +207,7 @@ impl MapDecode for Metadata {
 }
 
 /// Logical grouping of the distribution version data
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Version {
     /// Human readable display of version
     pub name: Option<String>,
@@ -133,7 +229,7 @@ pub struct Version {
 }
 
 impl MapDecode for Version {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
             name: o.get("VERSION").map(|s| s.to_string()),
             id: o.get("VERSION_ID").map(|s| s.to_string()),
@@ -146,7 +242,7 @@ impl MapDecode for Version {
 }
 
 /// Various URLs specific to the project
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Urls {
     /// public homepage
     pub homepage: Option<String>,
@@ -165,7 +261,7 @@ pub struct Urls {
 }
 
 impl MapDecode for Urls {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
             homepage: o.get("HOME_URL").map(|s| s.to_string()),
             documentation: o.get("DOCUMENTATION_URL").map(|s| s.to_string()),
@@ -176,8 +272,27 @@ impl MapDecode for Urls {
     }
 }
 
+/// Image details, for OS images deployed as a whole
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    /// Unique ID for the image
+    pub id: Option<String>,
+
+    /// Version of the image
+    pub version: Option<String>,
+}
+
+impl MapDecode for Image {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
+        Ok(Self {
+            id: o.get("IMAGE_ID").map(|s| s.to_string()),
+            version: o.get("IMAGE_VERSION").map(|s| s.to_string()),
+        })
+    }
+}
+
 /// Basic branding details (limited)
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Brand {
     /// A freedesktop icon naming spec compatible string for the distro logo
     pub logo: Option<String>,
@@ -187,7 +302,7 @@ pub struct Brand {
 }
 
 impl MapDecode for Brand {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
             logo: o.get("LOGO").map(|s| s.to_string()),
             ansi_color: o.get("ANSI_COLOR").map(|s| s.to_string()),
@@ -196,7 +311,7 @@ impl MapDecode for Brand {
 }
 
 /// Vendor specific information
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Vendor {
     /// The shipping vendor's name
     pub name: Option<String>,
@@ -206,10 +321,158 @@ pub struct Vendor {
 }
 
 impl MapDecode for Vendor {
-    fn map_decode(o: &HashMap<&str, &str>) -> Result<Self, Error> {
+    fn map_decode(o: &HashMap<String, String>) -> Result<Self, Error> {
         Ok(Self {
-            name: o.get("name").map(|s| s.to_string()),
-            url: o.get("url").map(|s| s.to_string()),
+            name: o.get("VENDOR_NAME").map(|s| s.to_string()),
+            url: o.get("VENDOR_URL").map(|s| s.to_string()),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::OsRelease;
+
+    #[test]
+    fn test_debian() {
+        let os = OsRelease::from_str(
+            r#"PRETTY_NAME="Debian GNU/Linux 12 (bookworm)"
+NAME="Debian GNU/Linux"
+VERSION_ID="12"
+VERSION="12 (bookworm)"
+VERSION_CODENAME=bookworm
+ID=debian
+HOME_URL="https://www.debian.org/"
+SUPPORT_URL="https://www.debian.org/support"
+BUG_REPORT_URL="https://bugs.debian.org/"
+"#,
+        )
+        .unwrap();
+        assert_eq!(os.name, "Debian GNU/Linux");
+        assert_eq!(os.id, "debian");
+        assert_eq!(os.version.codename.as_deref(), Some("bookworm"));
+        assert_eq!(os.urls.support.as_deref(), Some("https://www.debian.org/support"));
+        assert_eq!(os.display_name().as_deref(), Some("Debian GNU/Linux 12 (bookworm)"));
+    }
+
+    #[test]
+    fn test_fedora() {
+        let os = OsRelease::from_str(
+            r#"NAME="Fedora Linux"
+VERSION="40 (Workstation Edition)"
+ID=fedora
+VERSION_ID=40
+VERSION_CODENAME=""
+PLATFORM_ID="platform:f40"
+PRETTY_NAME="Fedora Linux 40 (Workstation Edition)"
+ANSI_COLOR="0;38;2;60;110;180"
+LOGO=fedora-logo-icon
+CPE_NAME="cpe:/o:fedoraproject:fedora:40"
+DEFAULT_HOSTNAME="fedora"
+HOME_URL="https://fedoraproject.org/"
+DOCUMENTATION_URL="https://docs.fedoraproject.org/en-US/fedora/f40/system-administrators-guide/"
+SUPPORT_URL="https://ask.fedoraproject.org/"
+BUG_REPORT_URL="https://bugzilla.redhat.com/"
+REDHAT_BUGZILLA_PRODUCT="Fedora"
+REDHAT_BUGZILLA_PRODUCT_VERSION=40
+REDHAT_SUPPORT_PRODUCT="Fedora"
+REDHAT_SUPPORT_PRODUCT_VERSION=40
+SUPPORT_END=2025-05-13
+VARIANT="Workstation Edition"
+VARIANT_ID=workstation
+"#,
+        )
+        .unwrap();
+        assert_eq!(os.version.codename, None);
+        assert_eq!(os.version.variant_id.as_deref(), Some("workstation"));
+        assert_eq!(os.support_ends.as_deref(), Some("2025-05-13"));
+        assert_eq!(os.brand.logo.as_deref(), Some("fedora-logo-icon"));
+        assert_eq!(os.extra("PLATFORM_ID"), Some("platform:f40"));
+        assert_eq!(os.extra("REDHAT_BUGZILLA_PRODUCT_VERSION"), Some("40"));
+        assert_eq!(os.extra("NAME"), None);
+    }
+
+    #[test]
+    fn test_nixos() {
+        let os = OsRelease::from_str(
+            r#"ANSI_COLOR="1;34"
+BUG_REPORT_URL="https://github.com/NixOS/nixpkgs/issues"
+BUILD_ID="24.05.20240612.cc54fb4"
+CPE_NAME="cpe:/o:nixos:nixos:24.05"
+DEFAULT_HOSTNAME=nixos
+DOCUMENTATION_URL="https://nixos.org/learn.html"
+HOME_URL="https://nixos.org/"
+ID=nixos
+IMAGE_ID=""
+IMAGE_VERSION=""
+LOGO="nix-snowflake"
+NAME=NixOS
+PRETTY_NAME="NixOS 24.05 (Uakari)"
+SUPPORT_END="2024-12-31"
+SUPPORT_URL="https://nixos.org/community.html"
+VERSION="24.05 (Uakari)"
+VERSION_CODENAME=uakari
+VERSION_ID="24.05"
+"#,
+        )
+        .unwrap();
+        assert_eq!(os.name, "NixOS");
+        assert_eq!(os.image.id, None);
+        assert_eq!(os.version.build_id.as_deref(), Some("24.05.20240612.cc54fb4"));
+        assert_eq!(os.extra("DEFAULT_HOSTNAME"), Some("nixos"));
+    }
+
+    #[test]
+    fn test_id_like() {
+        let os = OsRelease::from_str(
+            r#"NAME="Linux Mint"
+ID=linuxmint
+ID_LIKE="ubuntu debian"
+VARIANT_ID=cinnamon
+BUILD_ID=20240725
+IMAGE_ID=mint-live
+"#,
+        )
+        .unwrap();
+        assert_eq!(os.like_ids().collect::<Vec<_>>(), ["ubuntu", "debian"]);
+        assert_eq!(os.version.variant_id.as_deref(), Some("cinnamon"));
+        assert_eq!(os.version.build_id.as_deref(), Some("20240725"));
+        assert_eq!(os.image.id.as_deref(), Some("mint-live"));
+
+        let os = OsRelease::from_str("NAME=Debian\nID=debian\n").unwrap();
+        assert_eq!(os.like_ids().count(), 0);
+    }
+
+    #[test]
+    fn test_quoting() {
+        let os = OsRelease::from_str(
+            r#"# Hand-written, shell-style
+
+  NAME='Weird "Linux"'
+ID=weird   # trailing comment
+PRETTY_NAME="Weird \"Linux\" \$HOME \\o/ \`x\` \n"
+VERSION_CODENAME=it\'s' 'here
+IMAGE_ID=edge'-'"image"
+VENDOR_NAME="Weird#1"
+HOME_URL=https://example.com/#top
+not an assignment
+"#,
+        )
+        .unwrap();
+        assert_eq!(os.name, r#"Weird "Linux""#);
+        assert_eq!(os.id, "weird");
+        assert_eq!(
+            os.meta.pretty_name.as_deref(),
+            Some(r#"Weird "Linux" $HOME \o/ `x` \n"#)
+        );
+        assert_eq!(os.version.codename.as_deref(), Some("it's here"));
+        assert_eq!(os.image.id.as_deref(), Some("edge-image"));
+        assert_eq!(os.vendor.name.as_deref(), Some("Weird#1"));
+        assert_eq!(os.urls.homepage.as_deref(), Some("https://example.com/#top"));
+
+        let os = OsRelease::from_str("NAME=Plain\nID=plain\nVERSION_CODENAME=rolling").unwrap();
+        assert_eq!(os.display_name().as_deref(), Some("Plain rolling"));
+    }
+}
@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Progress reporting for long running operations
+//!
+//! Syncing to a slow ESP can take a while, so callers may observe the phases of
+//! [`crate::Manager::sync`] and the progress of each copy via
+//! [`crate::Manager::with_progress`]. Events are only emitted from within the
+//! call, never after it returns.
+
+use std::{
+    fmt::Debug,
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+/// Callback receiving [`ProgressEvent`]s
+pub type ProgressCallback = Box<dyn Fn(ProgressEvent) + Send>;
+
+/// A step of a long running operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Inspecting `$BOOT` and the entries to be installed
+    Discovery,
+
+    /// Installing or updating the bootloader itself
+    BootloaderSync,
+
+    /// Installing the files and loader entry for the given entry
+    EntryInstall { entry_id: String },
+
+    /// Copying a file, reported in chunks until `bytes_done == bytes_total`
+    FileCopy {
+        path: PathBuf,
+        bytes_done: u64,
+        bytes_total: u64,
+    },
+
+    /// Removing entries and kernels that are no longer wanted
+    Cleanup,
+}
+
+/// Invokes a [`ProgressCallback`], possibly from several threads
+///
+/// Calls are serialized, and a panicking callback is logged and otherwise ignored
+/// so that it can't interrupt (or poison) an operation in progress. With
+/// `panic = "abort"` a panicking callback still aborts the process.
+pub(crate) struct ProgressObserver(Mutex<ProgressCallback>);
+
+impl ProgressObserver {
+    pub(crate) fn new(callback: ProgressCallback) -> Self {
+        Self(Mutex::new(callback))
+    }
+
+    /// Deliver the event to the callback
+    pub(crate) fn emit(&self, event: ProgressEvent) {
+        let callback = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if catch_unwind(AssertUnwindSafe(|| callback(event))).is_err() {
+            log::warn!("Progress callback panicked, ignoring");
+        }
+    }
+}
+
+impl Debug for ProgressObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{ProgressEvent, ProgressObserver};
+
+    #[test]
+    fn test_panicking_callback() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let record = seen.clone();
+        let observer = ProgressObserver::new(Box::new(move |event| {
+            if event == ProgressEvent::Discovery {
+                panic!("boom");
+            }
+            record.lock().unwrap().push(event);
+        }));
+
+        observer.emit(ProgressEvent::Discovery);
+        observer.emit(ProgressEvent::Cleanup);
+        assert_eq!(*seen.lock().unwrap(), vec![ProgressEvent::Cleanup]);
+    }
+}
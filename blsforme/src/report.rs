@@ -0,0 +1,284 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Structured report of the boot environment, as shown by `blsctl status`, and
+//! of the library itself, as shown by `blsctl version`
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::{
+    Architecture, BootEnvironment, Entry, Firmware, Kernel, Schema, VarAccess, VendorDir,
+    bootloader::{LoaderVersion, Timeout},
+};
+
+/// Snapshot of the boot environment
+///
+/// Anything that couldn't be determined (i.e. in image mode, or with `$BOOT`
+/// unmounted) is left empty rather than failing the whole report.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    /// Firmware in use
+    pub firmware: Firmware,
+
+    /// Access to EFI variables
+    pub efi_vars: VarAccess,
+
+    /// Whether Secure Boot is enabled
+    pub secure_boot: bool,
+
+    /// The EFI System Partition
+    pub esp: PartitionReport,
+
+    /// The Extended Boot Loader partition
+    pub xbootldr: PartitionReport,
+
+    /// Identity of the OS in the rootfs
+    pub os: OsReport,
+
+    /// The automatic cmdline for the rootfs
+    pub cmdline: Vec<String>,
+
+    /// Kernels discovered in the rootfs
+    pub available_kernels: Vec<Kernel>,
+
+    /// Kernels installed to `$BOOT`
+    pub installed_kernels: Vec<Kernel>,
+
+    /// Loader entries installed to `$BOOT`
+    pub installed_entries: Vec<EntryReport>,
+
+    /// Bootloader binaries available in the rootfs
+    pub bootloader_assets: Vec<AssetReport>,
+
+    /// Bootloader binaries installed to the ESP
+    pub installed_bootloaders: Vec<AssetReport>,
+
+    /// Whether systemd-boot is chain loaded by shim, for Secure Boot
+    pub shim: bool,
+
+    /// The removable media fallback bootloader (i.e. `EFI/Boot/BOOTX64.EFI`), when present
+    pub fallback: Option<FallbackReport>,
+
+    /// Loader entries belonging to other installations sharing `$BOOT`
+    pub foreign_entries: Vec<PathBuf>,
+
+    /// Directories beneath `EFI/` on the ESP, classified by vendor
+    pub esp_vendors: Vec<VendorDir>,
+
+    /// The Windows Boot Manager, when present on the ESP
+    pub windows_boot_manager: Option<PathBuf>,
+
+    /// Whether `loader.conf` hides the Windows Boot Manager from the menu
+    pub windows_hidden: bool,
+
+    /// The default boot entry, from EFI variables or `loader.conf`
+    pub default_entry: Option<String>,
+
+    /// The menu timeout, from EFI variables or `loader.conf`
+    pub timeout: Option<Timeout>,
+}
+
+/// Version and build metadata of the library
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    /// Version of the blsforme library
+    pub version: &'static str,
+
+    /// Supported kernel discovery schemas
+    pub schemas: Vec<&'static str>,
+
+    /// Bootloaders compiled in
+    pub bootloaders: Vec<&'static str>,
+
+    /// Supported EFI architectures, by EFI name
+    pub architectures: Vec<&'static str>,
+
+    /// Optional crate features compiled in
+    pub features: Vec<&'static str>,
+}
+
+/// Describe this build of blsforme
+pub fn version_info() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        schemas: vec!["legacy", "blsforme", "os-info"],
+        bootloaders: [Some("systemd-boot"), cfg!(feature = "grub2").then_some("grub2")]
+            .into_iter()
+            .flatten()
+            .collect(),
+        architectures: Architecture::ALL.iter().map(Architecture::efi_name).collect(),
+        features: [
+            cfg!(feature = "par").then_some("par"),
+            cfg!(feature = "grub2").then_some("grub2"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    }
+}
+
+/// A boot partition and where it's mounted
+#[derive(Debug, Default, Serialize)]
+pub struct PartitionReport {
+    /// Block device
+    pub device: Option<PathBuf>,
+
+    /// Model name of the disk holding the partition
+    pub model: Option<String>,
+
+    /// Where the partition is (or would be) mounted
+    pub mountpoint: Option<PathBuf>,
+
+    /// Whether the partition is currently mounted there
+    pub mounted: bool,
+
+    /// Total capacity in bytes
+    pub size: Option<u64>,
+
+    /// Free space in bytes, when mounted
+    pub available: Option<u64>,
+}
+
+impl PartitionReport {
+    pub(crate) fn new(
+        device: Option<&PathBuf>,
+        model: Option<&String>,
+        mountpoint: Option<&PathBuf>,
+        size: Option<u64>,
+    ) -> Self {
+        let mounted = mountpoint.is_some_and(|m| is_mountpoint(m));
+        Self {
+            device: device.cloned(),
+            model: model.cloned(),
+            mountpoint: mountpoint.cloned(),
+            mounted,
+            size,
+            available: mountpoint
+                .filter(|_| mounted)
+                .and_then(|m| BootEnvironment::available_space_at(m).ok()),
+        }
+    }
+}
+
+/// Identity of the OS in the rootfs
+#[derive(Debug, Serialize)]
+pub struct OsReport {
+    /// Kernel discovery schema in use
+    pub schema: &'static str,
+
+    /// OS ID, i.e. `aerynos`
+    pub id: String,
+
+    /// OS name
+    pub name: String,
+
+    /// Pretty name, for display
+    pub display_name: Option<String>,
+}
+
+impl From<&Schema> for OsReport {
+    fn from(schema: &Schema) -> Self {
+        Self {
+            schema: schema.kind(),
+            id: schema.os_id(),
+            name: schema.os_name(),
+            display_name: schema.os_display_name(),
+        }
+    }
+}
+
+/// A loader entry installed to `$BOOT`
+#[derive(Debug, Serialize)]
+pub struct EntryReport {
+    /// Title shown in the boot menu
+    pub title: Option<String>,
+
+    /// Version of the kernel booted by the entry
+    pub version: String,
+
+    /// The kernel command line, when set
+    pub cmdline: Option<String>,
+}
+
+impl From<&Entry<'_>> for EntryReport {
+    fn from(entry: &Entry<'_>) -> Self {
+        Self {
+            title: entry.title().map(str::to_string),
+            version: entry.kernel().version.clone(),
+            cmdline: entry.cmdline().first().map(|c| c.snippet.clone()),
+        }
+    }
+}
+
+/// A bootloader binary and its embedded version
+#[derive(Debug, Serialize)]
+pub struct AssetReport {
+    pub path: PathBuf,
+    pub version: Option<String>,
+}
+
+impl AssetReport {
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            version: LoaderVersion::from_file(path).map(|v| v.to_string()),
+        }
+    }
+}
+
+/// The removable media fallback bootloader and who owns it
+#[derive(Debug, Serialize)]
+pub struct FallbackReport {
+    pub path: PathBuf,
+
+    /// Embedded `LoaderInfo`, when systemd-boot
+    pub version: Option<String>,
+
+    /// Whether it's identical to the binary we'd install
+    pub owned: bool,
+}
+
+impl FallbackReport {
+    pub(crate) fn new(path: &Path, owned: bool) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            version: LoaderVersion::from_file(path).map(|v| v.to_string()),
+            owned,
+        }
+    }
+}
+
+/// Whether the path is the root of a mounted filesystem
+fn is_mountpoint(path: &Path) -> bool {
+    let (Ok(meta), Ok(parent)) = (fs::metadata(path), fs::metadata(path.join(".."))) else {
+        return false;
+    };
+    meta.dev() != parent.dev() || meta.ino() == parent.ino()
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::AssetReport;
+
+    #[test]
+    fn test_asset_report() {
+        let tmp = tempfile::tempdir().unwrap();
+        let loader = tmp.path().join("systemd-bootx64.efi");
+        fs::write(&loader, b"\0\0#### LoaderInfo: systemd-boot 257.5 ####\0\0").unwrap();
+        assert_eq!(AssetReport::new(&loader).version.as_deref(), Some("systemd-boot 257.5"));
+
+        let unknown = tmp.path().join("BOOTX64.EFI");
+        fs::write(&unknown, b"MZ\0\0").unwrap();
+        assert_eq!(AssetReport::new(&unknown).version, None);
+        assert_eq!(AssetReport::new(&tmp.path().join("missing.efi")).version, None);
+    }
+}
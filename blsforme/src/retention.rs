@@ -0,0 +1,166 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Kernel retention policy, to avoid filling `$BOOT` on rolling systems
+
+use std::collections::BTreeMap;
+
+use crate::{Entry, kernel::compare_versions};
+
+/// Controls how many kernels are kept on `$BOOT` during a sync
+///
+/// Retention is applied per kernel variant, so that (for example) a
+/// stream of `lts` updates never evicts the `desktop` kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Number of the newest kernels to keep, per variant
+    pub keep_latest: usize,
+
+    /// Always keep the most recent known-good kernel
+    pub keep_booted: bool,
+
+    /// Always keep the currently running kernel
+    pub keep_running: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_latest: 3,
+            keep_booted: true,
+            keep_running: true,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Filter the entries down to those permitted by the policy
+    ///
+    /// `running` and `booted` are the running and last known-good kernel versions, if known.
+    pub(crate) fn apply<'e, 'k>(
+        &self,
+        entries: &'e [Entry<'k>],
+        running: Option<&str>,
+        booted: Option<&str>,
+    ) -> Vec<&'e Entry<'k>> {
+        // Distinct versions, grouped by variant
+        let mut variants = BTreeMap::<Option<&str>, Vec<&str>>::new();
+        for entry in entries {
+            let versions = variants.entry(entry.kernel.variant.as_deref()).or_default();
+            if !versions.contains(&entry.kernel.version.as_str()) {
+                versions.push(&entry.kernel.version);
+            }
+        }
+
+        let mut retained = vec![];
+        for (variant, mut versions) in variants {
+            versions.sort_by(|a, b| compare_versions(b, a));
+            for (index, version) in versions.into_iter().enumerate() {
+                let keep = index < self.keep_latest
+                    || (self.keep_running && running == Some(version))
+                    || (self.keep_booted && booted == Some(version));
+                if keep {
+                    retained.push(version);
+                } else {
+                    log::info!(
+                        "Retention policy excludes kernel {version} (variant: {})",
+                        variant.unwrap_or("none")
+                    );
+                }
+            }
+        }
+
+        entries
+            .iter()
+            .filter(|e| retained.contains(&e.kernel.version.as_str()))
+            .collect()
+    }
+}
+
+/// Restrict the entries to those for the `max` most recent kernel versions, across all variants
+pub(crate) fn newest_kernels<'e, 'k>(entries: Vec<&'e Entry<'k>>, max: usize) -> Vec<&'e Entry<'k>> {
+    let mut versions = entries.iter().map(|e| e.kernel.version.as_str()).collect::<Vec<_>>();
+    versions.sort_by(|a, b| compare_versions(b, a));
+    versions.dedup();
+    for version in versions.iter().skip(max) {
+        log::info!("Kernel limit of {max} excludes kernel {version}");
+    }
+    versions.truncate(max);
+
+    entries
+        .into_iter()
+        .filter(|e| versions.contains(&e.kernel.version.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{RetentionPolicy, newest_kernels};
+    use crate::{Entry, Kernel};
+
+    fn kernel(version: &str, variant: &str) -> Kernel {
+        Kernel {
+            version: version.to_string(),
+            image: PathBuf::from("vmlinuz"),
+            initrd: vec![],
+            extras: vec![],
+            variant: Some(variant.to_string()),
+            boot_meta: None,
+        }
+    }
+
+    #[test]
+    fn test_per_variant() {
+        let kernels = [
+            kernel("6.9.1-30.desktop", "desktop"),
+            kernel("6.10.2-31.desktop", "desktop"),
+            kernel("6.8.2-25.desktop", "desktop"),
+            kernel("6.6.30-10.lts", "lts"),
+            kernel("6.6.31-11.lts", "lts"),
+        ];
+        let entries = kernels.iter().map(Entry::new).collect::<Vec<_>>();
+        let policy = RetentionPolicy {
+            keep_latest: 1,
+            ..Default::default()
+        };
+
+        let versions = |running, booted| {
+            let mut versions = policy
+                .apply(&entries, running, booted)
+                .into_iter()
+                .map(|e| e.kernel.version.as_str())
+                .collect::<Vec<_>>();
+            versions.sort();
+            versions
+        };
+
+        assert_eq!(versions(None, None), ["6.10.2-31.desktop", "6.6.31-11.lts"]);
+        assert_eq!(
+            versions(Some("6.8.2-25.desktop"), Some("6.6.30-10.lts")),
+            [
+                "6.10.2-31.desktop",
+                "6.6.30-10.lts",
+                "6.6.31-11.lts",
+                "6.8.2-25.desktop"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_newest_kernels() {
+        let kernels = [
+            kernel("6.9.1-30.desktop", "desktop"),
+            kernel("6.10.2-31.desktop", "desktop"),
+            kernel("6.6.31-11.lts", "lts"),
+        ];
+        let entries = kernels.iter().map(Entry::new).collect::<Vec<_>>();
+        let versions = newest_kernels(entries.iter().collect(), 2)
+            .into_iter()
+            .map(|e| e.kernel.version.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["6.9.1-30.desktop", "6.10.2-31.desktop"]);
+    }
+}
@@ -6,16 +6,64 @@
 //! These APIs need to exist as a safety mechanism in order to not load
 //! a bunch of superblocks dynamically allocated into memory..
 
-use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
-use crate::disk::mounts::MountOption;
+use fs_err as fs;
 
 use super::probe;
 
-pub struct BlockDevice<'a> {
+/// What a device in a storage stack is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceLayer {
+    /// A partition or disk holding the filesystem (or another layer) directly
+    PlainPartition,
+
+    /// A LUKS container, by its crypto UUID. An open container is the
+    /// device-mapper device for its plaintext, otherwise the device holding it
+    LuksContainer { uuid: String },
+
+    /// An LVM logical volume
+    LvmLv { vg: String, lv: String },
+
+    /// A software RAID array, i.e. `raid1`
+    Raid { level: String },
+}
+
+impl DeviceLayer {
+    /// Classify the device from sysfs, or its superblock when that is all there is
+    fn detect(probe: &probe::Probe, path: &Path, superblock: Option<(&superblock::Kind, &str)>) -> Self {
+        if let Some(level) = probe.get_raid_level(path) {
+            return DeviceLayer::Raid { level };
+        }
+
+        let Some(name) = fs::canonicalize(path)
+            .ok()
+            .and_then(|p| p.file_name().map(|f| f.to_owned()))
+        else {
+            return DeviceLayer::PlainPartition;
+        };
+        let sysfs_path = probe.sysfs.join("class").join("block").join(name);
+        let dm = sysfs_path.join("dm");
+        let dm_uuid = fs::read_to_string(dm.join("uuid")).unwrap_or_default();
+        if dm_uuid.starts_with("LVM-") {
+            let dm_name = fs::read_to_string(dm.join("name")).unwrap_or_default();
+            if let Some((vg, lv)) = split_lvm_name(dm_name.trim()) {
+                return DeviceLayer::LvmLv { vg, lv };
+            }
+        } else if let Some(uuid) = luks_uuid(dm_uuid.trim()) {
+            return DeviceLayer::LuksContainer { uuid };
+        }
+
+        // A closed container, as an open one is represented by its mapping
+        let held = fs::read_dir(sysfs_path.join("holders")).is_ok_and(|mut h| h.next().is_some());
+        match superblock {
+            Some((superblock::Kind::Luks2, uuid)) if !held => DeviceLayer::LuksContainer { uuid: uuid.to_string() },
+            _ => DeviceLayer::PlainPartition,
+        }
+    }
+}
+
+pub struct BlockDevice {
     pub kind: Option<superblock::Kind>,
 
     // Actively mounted somewhere?
@@ -25,10 +73,7 @@ pub struct BlockDevice<'a> {
     pub path: String,
 
     /// Block devices living under this device..
-    pub children: Vec<BlockDevice<'a>>,
-
-    /// What owns us, precious.
-    pub(super) probe: &'a probe::Probe,
+    pub children: Vec<BlockDevice>,
 
     // Superblock's UUID
     pub(super) uuid: Option<String>,
@@ -36,28 +81,72 @@ pub struct BlockDevice<'a> {
     // GPT partition GUID
     pub(super) guid: Option<String>,
 
+    /// Active btrfs subvolume, when mounted from one
+    pub btrfs_subvol_id: Option<u64>,
+
+    /// Path of the mounted btrfs subvolume (i.e. `/@`), per the mount options
+    pub btrfs_subvol: Option<String>,
+
+    /// Software RAID level (i.e. `raid1`), for md arrays
+    pub raid_level: Option<String>,
+
+    /// What this device is within the storage stack
+    pub layer: DeviceLayer,
+
+    /// Leading [`Self::children`] stacked between the filesystem and us
+    pub(super) stacked: usize,
+
     // Auxiliary (ignored) device
     pub(super) aux: bool,
 }
 
-impl<'a> BlockDevice<'a> {
+impl BlockDevice {
     pub(super) fn new(
-        probe: &'a probe::Probe,
+        probe: &probe::Probe,
         path: impl AsRef<Path>,
         mount: Option<PathBuf>,
         aux: bool,
     ) -> Result<Self, super::Error> {
         let path = path.as_ref();
 
+        let raid_level = probe.get_raid_level(path);
+        if let Some(level) = &raid_level {
+            let members = probe.get_device_chain(path).unwrap_or_default();
+            log::warn!(
+                "{} is a software RAID ({level}) of: {}",
+                path.display(),
+                members
+                    .iter()
+                    .map(|m| m.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
         let block = if let Result::Ok(sb) = probe.get_device_superblock(path) {
+            let uuid = sb.uuid()?;
+            let (btrfs_subvol_id, btrfs_subvol) = match (sb.kind(), &mount) {
+                (superblock::Kind::Btrfs, Some(mount)) => (
+                    probe.get_btrfs_subvol_id(mount),
+                    probe
+                        .mounts
+                        .get_by_mountpoint(mount)
+                        .and_then(|m| m.subvolume().map(str::to_string)),
+                ),
+                _ => (None, None),
+            };
             BlockDevice {
                 kind: Some(sb.kind()),
                 mountpoint: mount.clone(),
                 path: path.to_string_lossy().to_string(),
                 children: vec![],
-                probe,
-                uuid: Some(sb.uuid()?),
+                layer: DeviceLayer::detect(probe, path, Some((&sb.kind(), &uuid))),
+                uuid: Some(uuid),
                 guid: None,
+                btrfs_subvol_id,
+                btrfs_subvol,
+                raid_level,
+                stacked: 0,
                 aux,
             }
         } else {
@@ -66,66 +155,135 @@ impl<'a> BlockDevice<'a> {
                 mountpoint: mount.clone(),
                 path: path.to_string_lossy().to_string(),
                 children: vec![],
-                probe,
                 uuid: None,
                 guid: None,
+                btrfs_subvol_id: None,
+                btrfs_subvol: None,
+                raid_level,
+                layer: DeviceLayer::detect(probe, path, None),
+                stacked: 0,
                 aux,
             }
         };
         Ok(block)
     }
 
+    /// The storage stack, from the device holding the filesystem down to the disk
+    pub fn layers(&self) -> impl Iterator<Item = &BlockDevice> {
+        self.children.iter().take(self.stacked).chain(std::iter::once(self))
+    }
+
+    /// Whether any layer of the stack is a LUKS container
+    pub fn is_encrypted(&self) -> bool {
+        self.layers()
+            .any(|l| matches!(l.layer, DeviceLayer::LuksContainer { .. }))
+    }
+
     /// Generate a working "root=" style boot line
     pub fn cmd_line(&self) -> String {
-        let children = self.children.iter().map(|c| c.cmd_line()).collect::<Vec<_>>().join(" ");
-        let mounts = self
-            .probe
-            .mounts
-            .iter()
-            .map(|m| (PathBuf::from(&m.mountpoint), m))
-            .collect::<HashMap<_, _>>();
-        let mount = self.mountpoint.as_ref().and_then(|m| mounts.get(m));
-        let mount_options = if let Some(mp) = mount {
-            mp.options()
-                .filter_map(|o| match o {
-                    MountOption::Option(k, v) => Some((k, v)),
-                    _ => None,
-                })
-                .collect::<HashMap<_, _>>()
-        } else {
-            HashMap::new()
-        };
+        self.cmdline_for_root().join(" ")
+    }
 
-        let local = if let Some(kind) = &self.kind {
+    /// Kernel parameters required to mount this device as the rootfs
+    ///
+    /// GPT partitions are identified by `root=PARTUUID=`, falling back to the
+    /// filesystem `root=UUID=`, along with `rootfstype=`. Btrfs roots also
+    /// carry their subvolume in `rootflags=`. Any LUKS or LVM
+    /// devices in the chain yield `rd.luks.uuid=` and `rd.lvm.lv=` hints.
+    pub fn cmdline_for_root(&self) -> Vec<String> {
+        let mut params = vec![];
+        if let Some(kind) = &self.kind {
             match kind {
                 superblock::Kind::Btrfs => {
                     let uuid = self.uuid.as_ref().expect("cannot have btrfs without uuid..");
-                    if let Some(subvol) = mount_options.get("subvol") {
-                        format!("root=UUID={uuid} rootfsflags=subvol={subvol}")
-                    } else {
-                        format!("root=UUID={uuid}")
+                    params.push(format!("root=UUID={uuid}"));
+                    params.push(format!("rootfstype={}", fs_type(kind)));
+                    // Subvolume IDs remain stable when snapshots are renamed into place
+                    if let Some(id) = self.btrfs_subvol_id {
+                        params.push(format!("rootflags=subvolid={id}"));
+                    } else if let Some(subvol) = &self.btrfs_subvol {
+                        params.push(format!("rootflags=subvol={subvol}"));
                     }
                 }
                 superblock::Kind::Luks2 => {
                     let uuid = self.uuid.as_ref().expect("cannot have luks2 without uuid");
-                    format!("rd.luks.uuid={uuid}")
+                    params.push(format!("rd.luks.uuid={uuid}"));
                 }
                 _ => {
                     if let Some(guid) = self.guid.as_ref() {
-                        format!("root=PARTUUID={guid}")
+                        params.push(format!("root=PARTUUID={guid}"));
                     } else if let Some(uuid) = self.uuid.as_ref() {
-                        format!("root=UUID={uuid}")
-                    } else {
-                        String::new()
+                        params.push(format!("root=UUID={uuid}"));
                     }
+                    params.push(format!("rootfstype={}", fs_type(kind)));
                 }
             }
         } else if !self.aux {
-            format!("root={}", &self.path)
-        } else {
-            String::new()
-        };
+            params.push(format!("root={}", &self.path));
+        }
+
+        if let Some(lv) = self.lvm_name() {
+            params.push(format!("rd.lvm.lv={lv}"));
+        }
+
+        // Further members of a multi-device filesystem share our UUID
+        params.extend(
+            self.children
+                .iter()
+                .filter(|c| c.uuid.is_none() || c.uuid != self.uuid)
+                .flat_map(|c| c.cmdline_for_root()),
+        );
+        params
+    }
 
-        format!("{local} {children}").trim().to_owned()
+    /// `VG/LV` name when this device is an LVM logical volume
+    fn lvm_name(&self) -> Option<String> {
+        match &self.layer {
+            DeviceLayer::LvmLv { vg, lv } => Some(format!("{vg}/{lv}")),
+            _ => None,
+        }
+    }
+}
+
+/// Split a device-mapper name into VG and LV, which are joined by `-` with
+/// any `-` within the names doubled
+fn split_lvm_name(dm_name: &str) -> Option<(String, String)> {
+    let mut vg = String::new();
+    let mut chars = dm_name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                vg.push('-');
+            }
+            '-' => {
+                let lv = chars.collect::<String>().replace("--", "-");
+                return Some((vg, lv));
+            }
+            c => vg.push(c),
+        }
     }
+    None
+}
+
+/// Crypto UUID from the device-mapper UUID of an open LUKS container,
+/// i.e. `CRYPT-LUKS2-<uuid without dashes>-<name>`
+fn luks_uuid(dm_uuid: &str) -> Option<String> {
+    let rest = dm_uuid
+        .strip_prefix("CRYPT-LUKS2-")
+        .or_else(|| dm_uuid.strip_prefix("CRYPT-LUKS1-"))?;
+    let hex = rest.get(..32).filter(|h| h.chars().all(|c| c.is_ascii_hexdigit()))?;
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Filesystem type name, as used by `rootfstype=`
+fn fs_type(kind: &superblock::Kind) -> String {
+    kind.to_string().to_lowercase()
 }
@@ -12,7 +12,10 @@ mod builder;
 pub use builder::Builder;
 pub mod device;
 pub mod mounts;
+pub mod partition;
 pub mod probe;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -31,6 +34,12 @@ pub enum Error {
     #[snafu(display("no such device: {path:?}"))]
     InvalidDevice { path: PathBuf },
 
+    #[snafu(display("failed to read partition table: {source}"))]
+    Gpt { source: gpt::GptError },
+
+    #[snafu(display("ZFS root detected (pool {pool}), GPT PARTUUID resolution unsupported"))]
+    UnsupportedZfs { pool: String },
+
     #[snafu(context(false), display("failed to read superblock: {source}"))]
     Superblock { source: superblock::Error },
 
@@ -4,7 +4,10 @@
 
 //! Processing of `/proc/self/mounts` & `/etc/mtab`
 
-use std::{io, path::Path};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use fs_err as fs;
 
@@ -49,7 +52,86 @@ impl MountOption<'_> {
     }
 }
 
+/// What a mount is backed by, per [`Mount::source`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountSource {
+    /// Single device, i.e. `/dev/sda1`
+    Device(PathBuf),
+
+    /// Multi-device filesystem, i.e. bcachefs `/dev/nvme0n1p3:/dev/sda1`
+    Devices(Vec<PathBuf>),
+
+    /// ZFS dataset, i.e. `rpool/ROOT/default`
+    ZfsDataset(String),
+}
+
+impl MountSource {
+    /// Pool name of a ZFS dataset
+    pub fn zfs_pool(&self) -> Option<&str> {
+        match self {
+            MountSource::ZfsDataset(dataset) => dataset.split('/').next().filter(|p| !p.is_empty()),
+            _ => None,
+        }
+    }
+}
+
 impl Mount<'_> {
+    /// Parse [`Mount::device`], given as `ZFS=pool/dataset`, a plain ZFS dataset,
+    /// colon separated devices or a single device
+    pub fn source(&self) -> MountSource {
+        if let Some(dataset) = self.device.strip_prefix("ZFS=") {
+            return MountSource::ZfsDataset(dataset.to_string());
+        }
+        if self.filesystem == "zfs" {
+            return MountSource::ZfsDataset(self.device.to_string());
+        }
+
+        // Only absolute paths, so that `host:/export` style sources are left alone
+        let devices = self.device.split(':').collect::<Vec<_>>();
+        if devices.len() > 1 && devices.iter().all(|d| d.starts_with('/')) {
+            MountSource::Devices(devices.into_iter().map(PathBuf::from).collect())
+        } else {
+            MountSource::Device(self.device.into())
+        }
+    }
+
+    /// The filesystem type, i.e. `ext4` or `overlay`
+    pub fn fstype(&self) -> &str {
+        self.filesystem
+    }
+
+    /// Value of a `key=value` option, the last given winning
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options()
+            .filter_map(|o| match o {
+                MountOption::Option(k, v) if k == key => Some(v),
+                _ => None,
+            })
+            .last()
+    }
+
+    /// The mounted btrfs subvolume path, i.e. `/@`
+    pub fn subvolume(&self) -> Option<&str> {
+        self.option("subvol").filter(|_| self.filesystem == "btrfs")
+    }
+
+    /// Directories layered by an overlayfs mount, the upper layer first
+    ///
+    /// Lower layers are given either colon separated in `lowerdir=`, or one per
+    /// `lowerdir+=` option when mounted via the new mount API.
+    pub fn overlay_dirs(&self) -> Vec<&str> {
+        if self.filesystem != "overlay" {
+            return vec![];
+        }
+        let upper = self.option("upperdir");
+        let lower = self.options().flat_map(|o| match o {
+            MountOption::Option("lowerdir", v) => v.split(':').collect(),
+            MountOption::Option("lowerdir+", v) => vec![v],
+            _ => vec![],
+        });
+        upper.into_iter().chain(lower).filter(|d| !d.is_empty()).collect()
+    }
+
     /// Convert [`Mount::opts`] into an iterator of typed options
     pub fn options(&self) -> impl Iterator<Item = MountOption<'_>> {
         self.opts.split(',').map(|o| {
@@ -91,6 +173,29 @@ impl Table {
         })
     }
 
+    /// The mount at the given mountpoint, the most recent when stacked
+    pub fn get_by_mountpoint(&self, mountpoint: impl AsRef<Path>) -> Option<Mount<'_>> {
+        let mountpoint = mountpoint.as_ref();
+        self.iter().filter(|m| Path::new(m.mountpoint) == mountpoint).last()
+    }
+
+    /// The most recent mount of the given device, comparing canonical paths where they exist
+    pub fn get_by_device(&self, device: impl AsRef<Path>) -> Option<Mount<'_>> {
+        let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let device = canonical(device.as_ref());
+        self.iter().filter(|m| canonical(Path::new(m.device)) == device).last()
+    }
+
+    /// All mounts of the given filesystem type (i.e. `vfat`)
+    pub fn filter_by_fstype<'a>(&'a self, fstype: &'a str) -> impl Iterator<Item = Mount<'a>> {
+        self.iter().filter(move |m| m.filesystem == fstype)
+    }
+
+    /// Whether anything is mounted at the given mountpoint
+    pub fn contains_mountpoint(&self, mountpoint: impl AsRef<Path>) -> bool {
+        self.get_by_mountpoint(mountpoint).is_some()
+    }
+
     /// New MountTable parser for file
     ///
     /// Arguments:
@@ -100,3 +205,101 @@ impl Table {
         Ok(Self::new(fs::read_to_string(path)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{MountSource, Table};
+
+    #[test]
+    fn test_mount_source() {
+        let table = Table::new(
+            "ZFS=rpool/ROOT/default / zfs rw 0 0
+rpool/home /home zfs rw 0 0
+/dev/nvme0n1p3:/dev/sda1 /srv bcachefs rw 0 0
+/dev/sda2 /boot ext4 rw 0 0
+server:/export /mnt nfs rw 0 0
+"
+            .into(),
+        );
+        let sources = table.iter().map(|m| m.source()).collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![
+                MountSource::ZfsDataset("rpool/ROOT/default".into()),
+                MountSource::ZfsDataset("rpool/home".into()),
+                MountSource::Devices(vec![PathBuf::from("/dev/nvme0n1p3"), PathBuf::from("/dev/sda1")]),
+                MountSource::Device("/dev/sda2".into()),
+                MountSource::Device("server:/export".into()),
+            ]
+        );
+        assert_eq!(sources[0].zfs_pool(), Some("rpool"));
+        assert_eq!(sources[3].zfs_pool(), None);
+    }
+
+    /// Captured from a btrfs root, an immutable overlay root and a bind mount
+    const LAYERED_MOUNTS: &str = "/dev/nvme0n1p2 / btrfs rw,noatime,compress=zstd:3,ssd,space_cache=v2,subvolid=256,subvol=/@ 0 0
+/dev/nvme0n1p2 /home btrfs rw,noatime,subvolid=257,subvol=/@home 0 0
+overlay / overlay rw,relatime,lowerdir=/run/rootfsbase:/run/base2,upperdir=/run/overlay/upper,workdir=/run/overlay/work 0 0
+overlay /sysroot overlay ro,relatime,lowerdir+=/run/layer1,lowerdir+=/run/layer2,datadir+=/run/data 0 0
+/dev/sda1 /srv/data ext4 rw,relatime 0 0
+";
+
+    #[test]
+    fn test_mount_options() {
+        let table = Table::new(LAYERED_MOUNTS.into());
+        let mounts = table.iter().collect::<Vec<_>>();
+
+        assert_eq!(mounts[0].fstype(), "btrfs");
+        assert_eq!(mounts[0].option("compress"), Some("zstd:3"));
+        assert_eq!(mounts[0].option("ssd"), None);
+        assert_eq!(mounts[0].subvolume(), Some("/@"));
+        assert_eq!(mounts[1].subvolume(), Some("/@home"));
+        assert!(mounts[0].overlay_dirs().is_empty());
+
+        assert_eq!(mounts[2].fstype(), "overlay");
+        assert_eq!(mounts[2].subvolume(), None);
+        assert_eq!(
+            mounts[2].overlay_dirs(),
+            ["/run/overlay/upper", "/run/rootfsbase", "/run/base2"]
+        );
+        assert_eq!(mounts[3].overlay_dirs(), ["/run/layer1", "/run/layer2"]);
+
+        assert_eq!(mounts[4].subvolume(), None);
+        assert_eq!(mounts[4].option("relatime"), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let table = Table::new(LAYERED_MOUNTS.into());
+
+        // The overlay is stacked over the btrfs root
+        assert_eq!(table.get_by_mountpoint("/").map(|m| m.filesystem), Some("overlay"));
+        assert_eq!(
+            table.get_by_mountpoint("/srv/data").map(|m| m.device),
+            Some("/dev/sda1")
+        );
+        assert!(table.get_by_mountpoint("/srv").is_none());
+        assert_eq!(
+            table.get_by_device("/dev/nvme0n1p2").map(|m| m.mountpoint),
+            Some("/home")
+        );
+        assert!(table.get_by_device("/dev/sdb1").is_none());
+    }
+
+    #[test]
+    fn test_filter_by_fstype() {
+        let table = Table::new(LAYERED_MOUNTS.into());
+        assert_eq!(
+            table
+                .filter_by_fstype("overlay")
+                .map(|m| m.mountpoint)
+                .collect::<Vec<_>>(),
+            ["/", "/sysroot"]
+        );
+        assert_eq!(table.filter_by_fstype("vfat").count(), 0);
+        assert!(table.contains_mountpoint("/sysroot"));
+        assert!(!table.contains_mountpoint("/boot"));
+    }
+}
@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Partition table entries
+
+/// A single GPT partition on a disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// Partition number, as used by the kernel (i.e. `2` for `sda2`)
+    pub number: u32,
+
+    /// Unique partition GUID, as used by `root=PARTUUID=`
+    pub part_guid: String,
+
+    /// Partition type GUID, i.e. ESP or XBOOTLDR
+    pub part_type_guid: String,
+
+    /// Partition label
+    pub name: String,
+
+    /// First logical block
+    pub start_lba: u64,
+
+    /// Last logical block (inclusive)
+    pub end_lba: u64,
+
+    /// Size in bytes, as reported by sysfs where available
+    pub size: u64,
+}
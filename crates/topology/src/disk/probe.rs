@@ -4,14 +4,23 @@
 
 //! Disk probe/query APIs
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use fs_err as fs;
 use nix::sys::stat;
-use snafu::{OptionExt, ResultExt as _};
+use snafu::{OptionExt, ResultExt as _, ensure};
 use superblock::Superblock;
 
-use super::{CanonicalizeSnafu, InvalidDeviceSnafu, IoSnafu, NixSnafu, device::BlockDevice, mounts::Table};
+use super::{
+    CanonicalizeSnafu, GptSnafu, InvalidDeviceSnafu, IoSnafu, NixSnafu, UnsupportedZfsSnafu,
+    device::BlockDevice,
+    mounts::{MountSource, Table},
+    partition::PartitionInfo,
+};
 
 /// A Disk probe to query disks
 #[derive(Debug)]
@@ -39,8 +48,8 @@ impl Probe {
         Ok(())
     }
 
-    /// Resolve a device by mountpoint
-    pub fn get_device_from_mountpoint(&self, mountpoint: impl AsRef<Path>) -> Result<PathBuf, super::Error> {
+    /// Resolve what backs the given mountpoint
+    pub fn get_mount_source(&self, mountpoint: impl AsRef<Path>) -> Result<MountSource, super::Error> {
         let mountpoint = fs::canonicalize(mountpoint.as_ref()).context(IoSnafu)?;
 
         // Attempt to stat the device
@@ -52,33 +61,109 @@ impl Probe {
 
         // Return by stat path if possible, otherwise fallback to mountpoint device
         if device_path.exists() {
-            Ok(fs::canonicalize(&device_path).context(CanonicalizeSnafu)?)
+            Ok(MountSource::Device(
+                fs::canonicalize(&device_path).context(CanonicalizeSnafu)?,
+            ))
         } else {
             // Find matching mountpoint
             let matching_device = self
                 .mounts
-                .iter()
-                .find(|m| PathBuf::from(m.mountpoint) == mountpoint)
-                .ok_or(super::Error::UnknownMount { path: mountpoint })?;
-            // TODO: Handle `ZFS=`, and composite bcachefs mounts (dev:dev1:dev2)
-            Ok(matching_device.device.into())
+                .get_by_mountpoint(&mountpoint)
+                .ok_or(super::Error::UnknownMount {
+                    path: mountpoint.clone(),
+                })?;
+
+            // Overlays have no device of their own, so use the first layer that does
+            let layers = matching_device.overlay_dirs();
+            if layers.is_empty() {
+                return Ok(matching_device.source());
+            }
+            layers
+                .into_iter()
+                .find_map(|dir| {
+                    let source = self.get_mount_source(dir).ok()?;
+                    log::debug!("Resolved overlay {} via layer {dir}", mountpoint.display());
+                    Some(source)
+                })
+                .ok_or(super::Error::UnknownMount { path: mountpoint })
+        }
+    }
+
+    /// Resolve a device by mountpoint
+    ///
+    /// Multi-device filesystems resolve to their first present member, and ZFS
+    /// datasets to the first leaf vdev of their pool.
+    pub fn get_device_from_mountpoint(&self, mountpoint: impl AsRef<Path>) -> Result<PathBuf, super::Error> {
+        self.resolve_mount_source(&self.get_mount_source(mountpoint)?)
+    }
+
+    /// Pick a single device for the [`MountSource`]
+    fn resolve_mount_source(&self, source: &MountSource) -> Result<PathBuf, super::Error> {
+        match source {
+            MountSource::Device(device) => Ok(device.clone()),
+            MountSource::Devices(devices) => {
+                let resolved = self.resolve_members(devices);
+                resolved.into_iter().next().context(InvalidDeviceSnafu {
+                    path: devices.first().cloned().unwrap_or_default(),
+                })
+            }
+            MountSource::ZfsDataset(dataset) => {
+                let pool = source.zfs_pool().context(UnsupportedZfsSnafu { pool: dataset })?;
+                zfs_leaf_vdev(pool)
+            }
         }
     }
 
+    /// Canonical paths of the members of a multi-device filesystem that are present
+    fn resolve_members(&self, devices: &[PathBuf]) -> Vec<PathBuf> {
+        devices
+            .iter()
+            .filter_map(|d| match fs::canonicalize(d) {
+                Ok(device) => Some(device),
+                Err(_) => {
+                    log::warn!("Member device {} not found", d.display());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Resolve a device by GPT partition UUID, via `/dev/disk/by-partuuid`
+    pub fn get_device_by_partuuid(&self, uuid: &str) -> Result<PathBuf, super::Error> {
+        self.get_device_by_link("by-partuuid", &uuid.to_lowercase())
+    }
+
+    /// Resolve a device by filesystem UUID, via `/dev/disk/by-uuid`
+    pub fn get_device_by_uuid(&self, uuid: &str) -> Result<PathBuf, super::Error> {
+        self.get_device_by_link("by-uuid", &uuid.to_lowercase())
+    }
+
+    /// Resolve a device by filesystem label, via `/dev/disk/by-label`
+    pub fn get_device_by_label(&self, label: &str) -> Result<PathBuf, super::Error> {
+        self.get_device_by_link("by-label", &udev_escape(label))
+    }
+
+    /// Canonicalize a udev symlink within `/dev/disk`
+    fn get_device_by_link(&self, kind: &str, name: &str) -> Result<PathBuf, super::Error> {
+        let link = self.devfs.join("disk").join(kind).join(name);
+        ensure!(link.exists(), InvalidDeviceSnafu { path: link });
+        fs::canonicalize(&link).context(CanonicalizeSnafu)
+    }
+
     /// Retrieve the parent device, such as the disk of a partition, if possible
+    ///
+    /// The sysfs parent of a whole disk isn't necessarily `block`: NVMe namespaces
+    /// (`nvme0n1`) live beneath their controller (`nvme0`), so the parent must itself
+    /// be a block device.
     pub fn get_device_parent(&self, device: impl AsRef<Path>) -> Option<PathBuf> {
         let device = fs::canonicalize(device.as_ref()).ok()?;
-        let child = fs::canonicalize(
-            device
-                .file_name()
-                .map(|f| self.sysfs.join("class").join("block").join(f))?,
-        )
-        .ok()?;
+        let class = self.sysfs.join("class").join("block");
+        let child = fs::canonicalize(device.file_name().map(|f| class.join(f))?).ok()?;
         let parent = child.parent()?.file_name()?;
-        if parent == "block" {
-            None
-        } else {
+        if class.join(parent).exists() {
             fs::canonicalize(self.devfs.join(parent)).ok()
+        } else {
+            None
         }
     }
 
@@ -90,21 +175,42 @@ impl Probe {
             device
                 .file_name()
                 .map(|f| self.sysfs.join("class").join("block").join(f))
-                .context(InvalidDeviceSnafu { path: device })?,
+                .context(InvalidDeviceSnafu { path: &device })?,
         )
         .context(CanonicalizeSnafu)?;
 
         let mut ret = vec![];
-        // no backing devices
-        let dir = sysfs_path.join("slaves");
-        if !dir.exists() {
-            return Ok(ret);
-        }
+        let slaves = sysfs_path.join("slaves");
+        let backings = if slaves.exists() {
+            fs::read_dir(&slaves)
+                .context(IoSnafu)?
+                .map(|entry| entry.map(|e| self.devfs.join(e.file_name())))
+                .collect::<Result<Vec<_>, _>>()
+                .context(IoSnafu)?
+        } else {
+            vec![]
+        };
+
+        // md arrays list members in `md/dev-*` too, even when `slaves` is empty
+        let backings = if backings.is_empty() && sysfs_path.join("md").exists() {
+            let members = self.get_raid_members(&sysfs_path);
+            if members.is_empty() {
+                log::warn!("Software RAID {} has no member devices", device.display());
+            }
+            members
+        } else {
+            backings
+        };
+
+        // device-mapper devices without slaves: resolve through `/dev/mapper`
+        let backings = if backings.is_empty() {
+            self.get_mapper_backings(&device, &sysfs_path)
+        } else {
+            backings
+        };
 
         // Build a recursive set of device backings
-        for dir in fs::read_dir(dir).context(IoSnafu)? {
-            let entry = dir.context(IoSnafu)?;
-            let name = self.devfs.join(entry.file_name());
+        for name in backings {
             ret.push(name.clone());
             ret.extend(self.get_device_chain(&name)?);
         }
@@ -112,6 +218,85 @@ impl Probe {
         Ok(ret)
     }
 
+    /// RAID level (i.e. `raid1`) when the device is a Linux software RAID array
+    pub fn get_raid_level(&self, device: impl AsRef<Path>) -> Option<String> {
+        let device = fs::canonicalize(device.as_ref()).ok()?;
+        let name = device.file_name()?;
+        let level = fs::read_to_string(
+            self.sysfs
+                .join("class")
+                .join("block")
+                .join(name)
+                .join("md")
+                .join("level"),
+        )
+        .ok()?;
+        let level = level.trim();
+        (!level.is_empty()).then(|| level.to_string())
+    }
+
+    /// Member devices of an md array, via the `md/dev-*/block` links
+    fn get_raid_members(&self, sysfs_path: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(sysfs_path.join("md")) else {
+            return vec![];
+        };
+        let mut members = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("dev-"))
+            .filter_map(|e| fs::canonicalize(e.path().join("block")).ok())
+            .filter_map(|block| Some(self.devfs.join(block.file_name()?)))
+            .collect::<Vec<_>>();
+        members.sort();
+        members
+    }
+
+    /// For a device-mapper device, resolve its `dm/name` through `/dev/mapper`
+    /// to the `dm-N` node and list the backings from `/sys/block/dm-N/slaves`
+    ///
+    /// Never yields the device given, to avoid cycles.
+    fn get_mapper_backings(&self, device: &Path, sysfs_path: &Path) -> Vec<PathBuf> {
+        let Ok(name) = fs::read_to_string(sysfs_path.join("dm").join("name")) else {
+            return vec![];
+        };
+        let Some(node) = fs::canonicalize(self.devfs.join("mapper").join(name.trim()))
+            .ok()
+            .and_then(|mapped| mapped.file_name().map(ToOwned::to_owned))
+        else {
+            return vec![];
+        };
+        let Ok(entries) = fs::read_dir(self.sysfs.join("block").join(node).join("slaves")) else {
+            return vec![];
+        };
+        let mut backings = entries
+            .filter_map(|e| e.ok())
+            .map(|e| self.devfs.join(e.file_name()))
+            .filter(|backing| backing != device)
+            .collect::<Vec<_>>();
+        backings.sort();
+        for backing in &backings {
+            log::debug!("Resolved {} via /dev/mapper to {}", device.display(), backing.display());
+        }
+        backings
+    }
+
+    /// Active btrfs subvolume ID for the mountpoint, per `/proc/self/mountinfo`
+    ///
+    /// When stacked, the last mount at the mountpoint is the visible one.
+    pub(super) fn get_btrfs_subvol_id(&self, mountpoint: &Path) -> Option<u64> {
+        let mountinfo = fs::read_to_string(self.procfs.join("self").join("mountinfo")).ok()?;
+        mountinfo.lines().rev().find_map(|line| {
+            // Per-mount fields, then the superblock fields after the ` - ` separator
+            let (mount, sb) = line.split_once(" - ")?;
+            if Path::new(mount.split_ascii_whitespace().nth(4)?) != mountpoint {
+                return None;
+            }
+            let options = sb.split_ascii_whitespace().nth(2)?;
+            options
+                .split(',')
+                .find_map(|o| o.strip_prefix("subvolid=")?.parse().ok())
+        })
+    }
+
     /// Scan superblock of the device for `UUID=` parameter
     pub fn get_device_superblock(&self, path: impl AsRef<Path>) -> Result<Superblock, super::Error> {
         let path = path.as_ref();
@@ -125,9 +310,10 @@ impl Probe {
 
     /// Determine the composite rootfs device for the given mountpoint,
     /// building a set of superblocks and necessary `/proc/cmdline` arguments
-    pub fn get_rootfs_device(&self, path: impl AsRef<Path>) -> Result<BlockDevice<'_>, super::Error> {
+    pub fn get_rootfs_device(&self, path: impl AsRef<Path>) -> Result<BlockDevice, super::Error> {
         let path = path.as_ref();
-        let device = self.get_device_from_mountpoint(path)?;
+        let source = self.get_mount_source(path)?;
+        let device = self.resolve_mount_source(&source)?;
 
         // Scan GPT for PartUUID
         let guid = self
@@ -152,11 +338,115 @@ impl Probe {
                 }
             })
             .collect::<Vec<_>>();
+        block.stacked = block.children.len();
+
+        // Remaining members of a multi-device filesystem
+        if let MountSource::Devices(devices) = &source {
+            block.children.extend(
+                self.resolve_members(devices)
+                    .into_iter()
+                    .filter(|m| *m != device)
+                    .flat_map(|m| BlockDevice::new(self, m, None, true)),
+            );
+        }
         block.guid = guid;
 
         Ok(block)
     }
 
+    /// Enumerate all GPT partitions on the given disk
+    ///
+    /// Sizes are taken from sysfs when the disk is known there, otherwise
+    /// derived from the partition table.
+    pub fn list_partitions(&self, disk: &Path) -> Result<Vec<PartitionInfo>, super::Error> {
+        let fi = fs::File::open(disk).context(IoSnafu)?;
+        let table = gpt::GptConfig::new()
+            .writable(false)
+            .open_from_device(Box::new(fi))
+            .context(GptSnafu)?;
+        let block_size = u64::from(*table.logical_block_size());
+        let sysfs_sizes = self.get_partition_sizes(disk);
+
+        Ok(table
+            .partitions()
+            .iter()
+            .map(|(number, partition)| PartitionInfo {
+                number: *number,
+                part_guid: partition.part_guid.hyphenated().to_string(),
+                part_type_guid: partition.part_type_guid.guid.hyphenated().to_string(),
+                name: partition.name.clone(),
+                start_lba: partition.first_lba,
+                end_lba: partition.last_lba,
+                size: sysfs_sizes
+                    .get(number)
+                    .copied()
+                    .unwrap_or_else(|| (partition.last_lba + 1).saturating_sub(partition.first_lba) * block_size),
+            })
+            .collect())
+    }
+
+    /// Total capacity of the block device in bytes, from sysfs
+    pub fn get_device_size(&self, device: &Path) -> Result<u64, super::Error> {
+        let device = fs::canonicalize(device).context(CanonicalizeSnafu)?;
+        let name = device.file_name().context(InvalidDeviceSnafu { path: &device })?;
+        let size =
+            fs::read_to_string(self.sysfs.join("class").join("block").join(name).join("size")).context(IoSnafu)?;
+        // sysfs always counts 512 byte sectors
+        let sectors = size
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .context(InvalidDeviceSnafu { path: &device })?;
+        Ok(sectors * 512)
+    }
+
+    /// Partition number of the device within its disk, i.e. `1` for `sda1`
+    pub fn get_partition_number(&self, device: impl AsRef<Path>) -> Option<u32> {
+        let device = fs::canonicalize(device.as_ref()).ok()?;
+        let name = device.file_name()?;
+        let number = fs::read_to_string(self.sysfs.join("class").join("block").join(name).join("partition")).ok()?;
+        number.trim().parse().ok()
+    }
+
+    /// Model name of the disk holding the device, i.e. `Samsung SSD 970 EVO 1TB`
+    ///
+    /// SATA/SAS disks expose `device/model`, NVMe `device/device/model` on older
+    /// kernels and MMC `device/name`.
+    pub fn get_device_model(&self, device: &Path) -> Option<String> {
+        let disk = self
+            .get_device_parent(device)
+            .or_else(|| fs::canonicalize(device).ok())?;
+        let sysfs_disk = self.sysfs.join("class").join("block").join(disk.file_name()?);
+        ["device/model", "device/device/model", "device/name"]
+            .iter()
+            .filter_map(|p| fs::read_to_string(sysfs_disk.join(p)).ok())
+            .map(|m| m.trim().to_string())
+            .find(|m| !m.is_empty())
+    }
+
+    /// Partition sizes in bytes, keyed by partition number, from sysfs
+    fn get_partition_sizes(&self, disk: &Path) -> BTreeMap<u32, u64> {
+        let Some(sysfs_disk) = fs::canonicalize(disk)
+            .ok()
+            .and_then(|d| Some(self.sysfs.join("class").join("block").join(d.file_name()?)))
+        else {
+            return BTreeMap::new();
+        };
+        let Ok(entries) = fs::read_dir(&sysfs_disk) else {
+            return BTreeMap::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let number = fs::read_to_string(path.join("partition")).ok()?.trim().parse().ok()?;
+                // sysfs always counts 512 byte sectors
+                let sectors = fs::read_to_string(path.join("size")).ok()?.trim().parse::<u64>().ok()?;
+                Some((number, sectors * 512))
+            })
+            .collect()
+    }
+
     /// For GPT disks return the PartUUID (GUID)
     pub fn get_device_guid(&self, parent: PathBuf, path: &Path) -> Option<String> {
         let device = fs::canonicalize(path).ok()?;
@@ -178,3 +468,90 @@ impl Probe {
             .map(|partition| partition.part_guid.hyphenated().to_string())
     }
 }
+
+/// Escape a name as udev does for `/dev/disk` symlinks, i.e. ` ` becomes `\x20`
+fn udev_escape(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || "#+-.:=@_".contains(c) || !c.is_ascii() => c.to_string(),
+            c => format!("\\x{:02x}", c as u32),
+        })
+        .collect()
+}
+
+/// Resolve the first leaf vdev of the pool via `zpool list`
+///
+/// ZFS doesn't expose pool membership in sysfs, so without a working `zpool`
+/// we can only refuse rather than return a dataset name as a device.
+fn zfs_leaf_vdev(pool: &str) -> Result<PathBuf, super::Error> {
+    let output = Command::new("zpool")
+        .args(["list", "-v", "-H", "-P", "-L", pool])
+        .output()
+        .ok()
+        .filter(|o| o.status.success());
+    let vdev = output.and_then(|o| {
+        String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .flat_map(|line| line.split('\t'))
+            .find(|field| field.starts_with("/dev/"))
+            .map(PathBuf::from)
+    });
+    match vdev {
+        Some(vdev) => {
+            log::debug!("ZFS pool {pool} resolved to {}", vdev.display());
+            Ok(vdev)
+        }
+        None => UnsupportedZfsSnafu { pool }.fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_err as fs;
+
+    use super::udev_escape;
+    use crate::disk::{Error, mounts::MountSource, testing::MockBuilder};
+
+    #[test]
+    fn test_udev_escape() {
+        assert_eq!(udev_escape("AERYNOS_ROOT"), "AERYNOS_ROOT");
+        assert_eq!(udev_escape("My Disk/1"), "My\\x20Disk\\x2f1");
+    }
+
+    #[test]
+    fn test_overlay_mount_source() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let root = root.path().canonicalize().unwrap();
+        let [lower, upper, work, merged] = ["lower", "upper", "work", "merged"].map(|d| root.join(d));
+        for dir in [&lower, &upper, &work, &merged] {
+            fs::create_dir(dir).unwrap();
+        }
+        let options = format!(
+            "rw,lowerdir={},upperdir={},workdir={}",
+            lower.display(),
+            upper.display(),
+            work.display()
+        );
+
+        // The upper layer isn't a mount of its own, so the lower one provides the device
+        let probe = MockBuilder::new(&root)
+            .with_mount("/dev/sda2", &lower, "ext4")
+            .with_mount_options("overlay", &merged, "overlay", &options)
+            .build()
+            .unwrap();
+        assert_eq!(
+            probe.get_mount_source(&merged).unwrap(),
+            MountSource::Device("/dev/sda2".into())
+        );
+
+        // No layer resolves to a device
+        let probe = MockBuilder::new(&root)
+            .with_mount_options("overlay", &merged, "overlay", &options)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            probe.get_mount_source(&merged),
+            Err(Error::UnknownMount { .. })
+        ));
+    }
+}
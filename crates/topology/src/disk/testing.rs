@@ -0,0 +1,273 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Mock sysfs, devfs and procfs trees for testing a [`Probe`]
+//!
+//! [`MockBuilder`] lays out the same structure as the fixtures under `tests/`
+//! beneath a given (typically temporary) directory, with real GPT images for
+//! the disks, so that no root access or block devices are required.
+
+use std::{
+    collections::BTreeMap,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use gpt::partition_types;
+use snafu::ResultExt as _;
+
+use super::{Builder, GptSnafu, IoSnafu, probe::Probe};
+
+/// Partitions are aligned to 1MiB, leaving room for the partition tables either side
+const ALIGNMENT: u64 = 1024 * 1024;
+
+/// A partition to create on a mock disk
+#[derive(Debug, Clone)]
+pub struct MockPartition {
+    /// Partition label
+    pub name: String,
+
+    /// Size in bytes
+    pub size: u64,
+
+    /// Partition type, i.e. [`partition_types::EFI`]
+    pub part_type: partition_types::Type,
+}
+
+impl MockPartition {
+    /// A Linux filesystem partition of the given size in bytes
+    pub fn new(name: impl Into<String>, size: u64) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            part_type: partition_types::LINUX_FS,
+        }
+    }
+
+    /// Use the given partition type
+    pub fn with_type(self, part_type: partition_types::Type) -> Self {
+        Self { part_type, ..self }
+    }
+}
+
+/// A GPT disk with its partitions
+#[derive(Debug, Clone)]
+struct MockDisk {
+    name: String,
+    partitions: Vec<MockPartition>,
+}
+
+/// Builds a [`Probe`] over mock sysfs, devfs and procfs trees
+///
+/// Each disk `sda` gets a GPT image at `dev/sda` and partitions `dev/sda1`, ...,
+/// with sysfs entries beneath `sys/class/block` and `/dev/disk/by-partuuid`
+/// links. Mounts are listed in `proc/self/mounts`.
+#[derive(Debug, Clone)]
+pub struct MockBuilder {
+    root: PathBuf,
+    disks: Vec<MockDisk>,
+    mounts: Vec<String>,
+}
+
+impl MockBuilder {
+    /// Lay out the trees beneath `root`, which must exist
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            disks: vec![],
+            mounts: vec![],
+        }
+    }
+
+    /// Add a GPT disk named `name` (i.e. `sda`) with the given partitions
+    pub fn with_disk(mut self, name: impl Into<String>, partitions: impl IntoIterator<Item = MockPartition>) -> Self {
+        self.disks.push(MockDisk {
+            name: name.into(),
+            partitions: partitions.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Add a mount of `source` (i.e. `/dev/sda1`) at `mountpoint`
+    pub fn with_mount(self, source: impl AsRef<Path>, mountpoint: impl AsRef<Path>, fstype: &str) -> Self {
+        self.with_mount_options(source, mountpoint, fstype, "rw,relatime")
+    }
+
+    /// Add a mount as for [`Self::with_mount`], with the given options (i.e. `ro,nosuid`)
+    pub fn with_mount_options(
+        mut self,
+        source: impl AsRef<Path>,
+        mountpoint: impl AsRef<Path>,
+        fstype: &str,
+        options: &str,
+    ) -> Self {
+        self.mounts.push(format!(
+            "{} {} {fstype} {options} 0 0",
+            source.as_ref().display(),
+            mountpoint.as_ref().display()
+        ));
+        self
+    }
+
+    /// Location of the mock devfs
+    pub fn devfs(&self) -> PathBuf {
+        self.root.join("dev")
+    }
+
+    /// Location of the mock sysfs
+    pub fn sysfs(&self) -> PathBuf {
+        self.root.join("sys")
+    }
+
+    /// Location of the mock procfs
+    pub fn procfs(&self) -> PathBuf {
+        self.root.join("proc")
+    }
+
+    /// Write out the trees and return a Probe over them
+    pub fn build(self) -> Result<Probe, super::Error> {
+        let (devfs, sysfs, procfs) = (self.devfs(), self.sysfs(), self.procfs());
+        for dir in [
+            devfs.join("disk").join("by-partuuid"),
+            sysfs.join("class").join("block"),
+            procfs.join("self"),
+        ] {
+            fs::create_dir_all(dir).context(IoSnafu)?;
+        }
+
+        for disk in self.disks.iter() {
+            self.write_disk(disk)?;
+        }
+
+        let mounts = self.mounts.iter().map(|m| format!("{m}\n")).collect::<String>();
+        fs::write(procfs.join("self").join("mounts"), mounts).context(IoSnafu)?;
+
+        Builder::default()
+            .with_devfs(devfs)
+            .with_sysfs(sysfs)
+            .with_procfs(procfs)
+            .build()
+    }
+
+    /// Write the GPT image and the device nodes, sysfs entries and links for the disk
+    fn write_disk(&self, disk: &MockDisk) -> Result<(), super::Error> {
+        let devfs = self.devfs();
+        let image = devfs.join(&disk.name);
+        let size = 2 * ALIGNMENT
+            + disk
+                .partitions
+                .iter()
+                .map(|p| p.size.next_multiple_of(ALIGNMENT))
+                .sum::<u64>();
+        let file = fs::File::create(&image).context(IoSnafu)?;
+        file.set_len(size).context(IoSnafu)?;
+
+        let mut table = gpt::GptConfig::new()
+            .writable(true)
+            .create_from_device(Box::new(file), None)
+            .context(GptSnafu)?;
+        table.update_partitions(BTreeMap::new()).context(GptSnafu)?;
+        for partition in disk.partitions.iter() {
+            table
+                .add_partition(&partition.name, partition.size, partition.part_type.clone(), 0, None)
+                .context(GptSnafu)?;
+        }
+        let partitions = table.partitions().clone();
+        table.write().context(GptSnafu)?;
+
+        // Partitions live beneath their disk, with `sys/class/block` linking to both
+        let disk_dir = self.sysfs().join("devices").join("mock").join("block").join(&disk.name);
+        fs::create_dir_all(&disk_dir).context(IoSnafu)?;
+        fs::write(disk_dir.join("size"), format!("{}\n", size / 512)).context(IoSnafu)?;
+        self.link_class(&disk.name, &disk_dir)?;
+
+        for (number, partition) in partitions.iter() {
+            let name = partition_name(&disk.name, *number);
+            fs::write(devfs.join(&name), "").context(IoSnafu)?;
+
+            let part_dir = disk_dir.join(&name);
+            fs::create_dir_all(&part_dir).context(IoSnafu)?;
+            fs::write(part_dir.join("partition"), format!("{number}\n")).context(IoSnafu)?;
+            let sectors = (partition.last_lba + 1).saturating_sub(partition.first_lba);
+            fs::write(part_dir.join("size"), format!("{sectors}\n")).context(IoSnafu)?;
+            self.link_class(&name, &part_dir)?;
+
+            let uuid = partition.part_guid.hyphenated().to_string();
+            symlink(
+                Path::new("../..").join(&name),
+                devfs.join("disk").join("by-partuuid").join(uuid),
+            )
+            .context(IoSnafu)?;
+        }
+
+        Ok(())
+    }
+
+    /// Link `sys/class/block/$name` to the device directory
+    fn link_class(&self, name: &str, dir: &Path) -> Result<(), super::Error> {
+        let relative = dir.strip_prefix(self.sysfs()).unwrap_or(dir);
+        symlink(
+            Path::new("../..").join(relative),
+            self.sysfs().join("class").join("block").join(name),
+        )
+        .context(IoSnafu)
+    }
+}
+
+/// Kernel name of the partition, i.e. `sda1` or `nvme0n1p1`
+fn partition_name(disk: &str, number: u32) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{disk}p{number}")
+    } else {
+        format!("{disk}{number}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gpt::partition_types;
+
+    use super::{MockBuilder, MockPartition, partition_name};
+
+    #[test]
+    fn test_partition_name() {
+        assert_eq!(partition_name("sda", 2), "sda2");
+        assert_eq!(partition_name("nvme0n1", 1), "nvme0n1p1");
+    }
+
+    #[test]
+    fn test_mock_probe() {
+        let root = tempfile::tempdir().expect("Failed to create tempdir");
+        let dev = root.path().canonicalize().unwrap().join("dev");
+        let probe = MockBuilder::new(root.path())
+            .with_disk(
+                "sda",
+                [
+                    MockPartition::new("ESP", 4 * 1024 * 1024).with_type(partition_types::EFI),
+                    MockPartition::new("root", 8 * 1024 * 1024),
+                ],
+            )
+            .with_mount("/dev/sda2", "/", "ext4")
+            .with_mount("/dev/sda1", "/efi", "vfat")
+            .build()
+            .expect("Failed to create Probe");
+
+        assert_eq!(probe.get_partition_number(dev.join("sda2")), Some(2));
+        assert_eq!(probe.get_device_parent(dev.join("sda1")), Some(dev.join("sda")));
+        assert_eq!(probe.get_device_parent(dev.join("sda")), None);
+        assert!(probe.mounts.contains_mountpoint("/efi"));
+
+        let partitions = probe.list_partitions(&dev.join("sda")).expect("Failed to read GPT");
+        assert_eq!(
+            partitions.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            ["ESP", "root"]
+        );
+        assert_eq!(partitions[0].size, 4 * 1024 * 1024);
+        assert_eq!(
+            probe.get_device_by_partuuid(&partitions[1].part_guid).unwrap(),
+            dev.join("sda2")
+        );
+    }
+}
@@ -4,9 +4,12 @@
 
 //! Testing BTRFS volume on LVk atop LUKS...
 
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
-use topology::disk::Builder;
+use topology::disk::{Builder, device::DeviceLayer};
 
 #[test]
 fn topology_test() {
@@ -31,6 +34,59 @@ fn topology_test() {
     // PartUUID is the only one we want.
     assert_eq!(
         cmdline,
-        "rd.luks.uuid=b6b31f26-39f4-48f7-bed5-6faaff96cca4 root=UUID=2a78a4da-f110-4441-8839-dbd97ab87cda rootfsflags=subvol=/"
+        "rd.luks.uuid=b6b31f26-39f4-48f7-bed5-6faaff96cca4 root=UUID=2a78a4da-f110-4441-8839-dbd97ab87cda rootfstype=btrfs rootflags=subvolid=5 rd.lvm.lv=BogusInstall/root"
     );
 }
+
+#[test]
+fn device_size_test() {
+    let topo = Builder::default()
+        .with_devfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/dev"))
+        .with_sysfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/sys"))
+        .with_procfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/proc"))
+        .build()
+        .expect("Failed to create Probe");
+
+    // 4096 sectors of 512 bytes
+    let size = topo
+        .get_device_size(Path::new("tests/btrfs_gpt_lvm_on_luks/dev/sda1"))
+        .expect("Cannot read device size");
+    assert_eq!(size, 2 * 1024 * 1024);
+}
+
+#[test]
+fn device_layers_test() {
+    let topo = Builder::default()
+        .with_devfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/dev"))
+        .with_sysfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/sys"))
+        .with_procfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/btrfs_gpt_lvm_on_luks/proc"))
+        .build()
+        .expect("Failed to create Probe");
+    let block = topo.get_rootfs_device("/").expect("Failed to determine block device");
+
+    // The open LUKS container is its mapping, the partition beneath is plain
+    let layers = block
+        .layers()
+        .map(|l| (Path::new(&l.path).file_name().unwrap(), &l.layer))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        layers,
+        [
+            (
+                "BogusInstall-root".as_ref(),
+                &DeviceLayer::LvmLv {
+                    vg: "BogusInstall".into(),
+                    lv: "root".into()
+                }
+            ),
+            (
+                "dm-0".as_ref(),
+                &DeviceLayer::LuksContainer {
+                    uuid: "b6b31f26-39f4-48f7-bed5-6faaff96cca4".into()
+                }
+            ),
+            ("sda1".as_ref(), &DeviceLayer::PlainPartition),
+        ]
+    );
+    assert!(block.is_encrypted());
+}
@@ -0,0 +1,41 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ensure partitions resolve to their disk across SCSI, NVMe, MMC and virtio-blk naming,
+//! and that the disk model is found for each
+
+use std::{env, path::Path};
+
+use topology::disk::Builder;
+
+#[test]
+fn device_parent_test() {
+    let topo = Builder::default()
+        .with_devfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/device_parent/dev"))
+        .with_sysfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/device_parent/sys"))
+        .with_procfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/device_parent/proc"))
+        .build()
+        .expect("Failed to create Probe");
+
+    let dev = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/device_parent/dev"));
+    for (partition, disk, model) in [
+        ("sda1", "sda", Some("Samsung SSD 860")),
+        ("nvme0n1p1", "nvme0n1", Some("Samsung SSD 970 EVO 1TB")),
+        ("mmcblk0p1", "mmcblk0", Some("SD32G")),
+        ("vda1", "vda", None),
+    ] {
+        assert_eq!(
+            topo.get_device_parent(dev.join(partition)),
+            Some(dev.join(disk)),
+            "parent of {partition}"
+        );
+        assert_eq!(topo.get_partition_number(dev.join(partition)), Some(1));
+        assert_eq!(topo.get_partition_number(dev.join(disk)), None);
+        assert_eq!(topo.get_device_model(&dev.join(partition)).as_deref(), model);
+        assert_eq!(topo.get_device_model(&dev.join(disk)).as_deref(), model);
+
+        // Whole disks have no parent, even though NVMe namespaces sit beneath their controller
+        assert_eq!(topo.get_device_parent(dev.join(disk)), None, "parent of {disk}");
+    }
+}
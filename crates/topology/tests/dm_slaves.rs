@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ensure LVM volumes without `slaves` in their class entry still resolve their PVs
+
+use std::{env, path::Path};
+
+use topology::disk::Builder;
+
+#[test]
+fn dm_slaves_test() {
+    let topo = Builder::default()
+        .with_devfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dm_slaves/dev"))
+        .with_sysfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dm_slaves/sys"))
+        .with_procfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dm_slaves/proc"))
+        .build()
+        .expect("Failed to create Probe");
+    let dev = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dm_slaves/dev"));
+
+    // `vg-root` is `dm-0`, backed by the `sda2` PV
+    let chain = topo
+        .get_device_chain(dev.join("mapper").join("vg-root"))
+        .expect("Failed to resolve vg-root");
+    assert_eq!(chain, vec![dev.join("sda2")]);
+
+    assert!(topo.get_device_chain(dev.join("sda2")).unwrap().is_empty());
+}
@@ -0,0 +1,35 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ensure software RAID arrays resolve to their member devices
+
+use std::{env, path::Path};
+
+use topology::disk::Builder;
+
+#[test]
+fn md_raid_test() {
+    let topo = Builder::default()
+        .with_devfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/md_raid/dev"))
+        .with_sysfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/md_raid/sys"))
+        .with_procfs(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/md_raid/proc"))
+        .build()
+        .expect("Failed to create Probe");
+    let dev = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/md_raid/dev"));
+
+    // Assembled under a name, i.e. `/dev/md/root` -> `md127`
+    let array = dev.join("md").join("root");
+    assert_eq!(topo.get_raid_level(&array).as_deref(), Some("raid1"));
+    let mut chain = topo.get_device_chain(&array).expect("Failed to resolve md127");
+    chain.sort();
+    assert_eq!(chain, vec![dev.join("sda1"), dev.join("sdb1")]);
+
+    // Degraded arrays may lack `slaves`, but still list their members
+    let chain = topo
+        .get_device_chain(dev.join("md126"))
+        .expect("Failed to resolve md126");
+    assert_eq!(chain, vec![dev.join("sdc1")]);
+
+    assert_eq!(topo.get_raid_level(dev.join("sda1")), None);
+}